@@ -191,8 +191,10 @@ function sidebarGuide() {
         { text: 'Wireless', link: 'features/wireless' },
         { text: 'Low-Power', link: 'features/low_power' },
         { text: 'Storage', link: 'features/storage' },
+        { text: 'Bootloader', link: 'features/bootloader' },
         { text: 'Split Keyboard', link: 'features/split_keyboard' },
         { text: 'USB Logging', link: 'features/usb_logging' },
+        { text: 'USB Console and WebUSB', link: 'features/usb_console' },
         {
           text: 'Binary Size Optimization',
           link: 'features/binary_size_optimization'
@@ -204,7 +206,9 @@ function sidebarGuide() {
       text: 'Input Devices',
       items: [
         { text: 'Rotary Encoder', link: 'input_devices/encoder' },
-        { text: 'Joystick', link: 'input_devices/joystick' }
+        { text: 'Joystick', link: 'input_devices/joystick' },
+        { text: 'Touchpad', link: 'input_devices/touchpad' },
+        { text: 'Analog Matrix', link: 'input_devices/analog_matrix' }
       ]
     },
     { text: 'Controller', link: 'features/controller' },
@@ -61,15 +61,22 @@ fn generate_vial_config() {
     let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("config_generated.rs");
 
     let p = Path::new("vial.json");
+    if !p.exists() {
+        // Remove the output of previous builds, RMK generates the Vial definition from `keyboard.toml` instead
+        fs::remove_file(&out_file).ok();
+        return;
+    }
     let mut content = String::new();
-    match File::open(p) {
-        Ok(mut file) => {
-            file.read_to_string(&mut content).expect("Cannot read vial.json");
-        }
-        Err(e) => println!("Cannot find vial.json {:?}: {}", p, e),
-    };
+    File::open(p)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .unwrap_or_else(|e| {
+            panic!(
+                "Cannot read {:?}, it's required to generate the Vial definition: {}",
+                p, e
+            )
+        });
 
-    let vial_cfg = json::stringify(json::parse(&content).unwrap());
+    let vial_cfg = json::stringify(json::parse(&content).unwrap_or_else(|e| panic!("Invalid {:?}: {}", p, e)));
     let mut keyboard_def_compressed: Vec<u8> = Vec::new();
     XzEncoder::new(vial_cfg.as_bytes(), 6)
         .read_to_end(&mut keyboard_def_compressed)
@@ -68,6 +68,7 @@ async fn main(_spawner: Spawner) {
     let mut behavior_config = BehaviorConfig::default();
     let (keymap, mut storage) =
         initialize_keymap_and_storage(&mut default_keymap, flash, &storage_config, &mut behavior_config).await;
+    keymap.borrow_mut().set_default_keymap(&keymap::DEFAULT_KEYMAP, None);
 
     // Initialize the matrix + keyboard
     let debouncer = DefaultDebouncer::<ROW, COL>::new();
@@ -190,6 +190,7 @@ async fn main(spawner: Spawner) {
         &mut behavior_config,
     )
     .await;
+    keymap.borrow_mut().set_default_keymap(&keymap::DEFAULT_KEYMAP, Some(&keymap::DEFAULT_ENCODER_MAP));
 
     // Initialize the matrix and keyboard
     let debouncer = DefaultDebouncer::<ROW, COL>::new();
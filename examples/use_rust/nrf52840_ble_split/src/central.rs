@@ -196,6 +196,7 @@ async fn main(spawner: Spawner) {
         &mut behavior_config,
     )
     .await;
+    keymap.borrow_mut().set_default_keymap(&keymap::DEFAULT_KEYMAP, Some(&keymap::DEFAULT_ENCODER_MAP));
 
     let pin_a = Input::new(p.P1_06, embassy_nrf::gpio::Pull::None);
     let pin_b = Input::new(p.P1_04, embassy_nrf::gpio::Pull::None);
@@ -4,6 +4,9 @@ pub(crate) const COL: usize = 7;
 pub(crate) const ROW: usize = 8;
 pub(crate) const NUM_LAYER: usize = 4;
 pub(crate) const NUM_ENCODER: usize = 2;
+/// The default keymap, which is restored by the VIA keymap reset
+pub static DEFAULT_KEYMAP: [[[KeyAction; COL]; ROW]; NUM_LAYER] = get_default_keymap();
+
 #[rustfmt::skip]
 pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
     [
@@ -50,6 +53,9 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
     ]
 }
 
+/// The default encoder map, which is restored by the VIA keymap reset
+pub static DEFAULT_ENCODER_MAP: [[EncoderAction; NUM_ENCODER]; NUM_LAYER] = get_default_encoder_map();
+
 pub const fn get_default_encoder_map() -> [[EncoderAction; NUM_ENCODER]; NUM_LAYER] {
     [
         [
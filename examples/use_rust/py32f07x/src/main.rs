@@ -74,6 +74,7 @@ async fn main(_spawner: Spawner) {
     let mut default_keymap = keymap::get_default_keymap();
     let mut behavior_config = BehaviorConfig::default();
     let keymap = rmk::initialize_keymap(&mut default_keymap, &mut behavior_config).await;
+    keymap.borrow_mut().set_default_keymap(&keymap::DEFAULT_KEYMAP, None);
     // let (keymap, mut storage) = initialize_keymap_and_storage(
     //     &mut default_keymap,
     //     async_flash_wrapper(f),
@@ -5,6 +5,9 @@ pub(crate) const ROW: usize = 4;
 pub(crate) const SIZE: usize = 4;
 pub(crate) const NUM_LAYER: usize = 2;
 
+/// The default keymap, which is restored by the VIA keymap reset
+pub static DEFAULT_KEYMAP: [[[KeyAction; COL]; ROW]; NUM_LAYER] = get_default_keymap();
+
 #[rustfmt::skip]
 pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
     [
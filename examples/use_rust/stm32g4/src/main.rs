@@ -55,6 +55,7 @@ async fn main(_spawner: Spawner) {
     // let storage_config = StorageConfig::default();
 
     let keymap = initialize_keymap(&mut default_keymap, &mut behavior_config).await;
+    keymap.borrow_mut().set_default_keymap(&keymap::DEFAULT_KEYMAP, None);
 
     // Initialize the matrix + keyboard
     let debouncer = DefaultDebouncer::<ROW, COL>::new();
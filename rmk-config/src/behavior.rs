@@ -3,8 +3,13 @@ use crate::{BehaviorConfig, MacroOperation};
 impl crate::KeyboardTomlConfig {
     pub fn get_behavior_config(&self) -> Result<BehaviorConfig, String> {
         let default = self.behavior.clone().unwrap_or_default();
-        let layout = self.get_layout_config().unwrap();
-        match self.behavior.clone() {
+        let (layout, inline) = self.get_layout_config_with_inline_behaviors().unwrap();
+        let mut behavior = self.behavior.clone();
+        if !inline.is_empty() {
+            // Macros and morses defined inline in the keymap are appended to the ones in `[behavior]`
+            inline.append_to(behavior.get_or_insert_default());
+        }
+        match behavior {
             Some(mut behavior) => {
                 behavior.tri_layer = match behavior.tri_layer {
                     Some(tri_layer) => {
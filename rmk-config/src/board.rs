@@ -1,4 +1,4 @@
-use crate::{InputDeviceConfig, KeyboardTomlConfig, MatrixConfig, MatrixType, SplitConfig};
+use crate::{EncoderConfig, InputDeviceConfig, KeyboardTomlConfig, MatrixConfig, MatrixType, SplitConfig};
 
 #[derive(Clone, Debug)]
 pub enum BoardConfig {
@@ -58,6 +58,19 @@ impl BoardConfig {
         };
         num_encoder
     }
+
+    /// Get the encoders of all boards, ordered by the encoder id
+    ///
+    /// The encoders on the central come first, followed by the encoders on each peripheral.
+    pub fn get_encoders(&self) -> Vec<EncoderConfig> {
+        match self {
+            BoardConfig::Split(split) => core::iter::once(&split.central.input_device)
+                .chain(split.peripheral.iter().map(|p| &p.input_device))
+                .flat_map(|d| d.clone().unwrap_or_default().encoder.unwrap_or_default())
+                .collect(),
+            BoardConfig::UniBody(uni_body_config) => uni_body_config.input_device.encoder.clone().unwrap_or_default(),
+        }
+    }
 }
 
 impl KeyboardTomlConfig {
@@ -81,6 +94,11 @@ impl KeyboardTomlConfig {
                             return Err("`direct_pins` is required for direct pin matrix".to_string());
                         }
                     },
+                    MatrixType::analog => {
+                        if m.adc_pins.is_none() || m.select_pins.is_none() {
+                            return Err("`adc_pins` and `select_pins` is required for analog matrix".to_string());
+                        }
+                    },
                 }
                 // FIXME: input device for split keyboard is not supported yet
                 Ok(BoardConfig::UniBody(UniBodyConfig{matrix: m, input_device: input_device.unwrap_or_default()}))
@@ -1,4 +1,4 @@
-use crate::{DependencyConfig, KeyboardTomlConfig};
+use crate::{BootloaderConfig, DependencyConfig, KeyboardTomlConfig};
 
 /// Keyboard's basic info
 #[derive(Clone, Debug)]
@@ -30,6 +30,14 @@ impl Default for Basic {
     }
 }
 
+/// Keys held at power-up to trigger bootmagic actions, in `[row, col]`
+#[derive(Clone, Debug, Default)]
+pub struct Bootmagic {
+    pub bootloader_keys: Vec<[u8; 2]>,
+    pub clear_storage_keys: Vec<[u8; 2]>,
+    pub clear_bonds_keys: Vec<[u8; 2]>,
+}
+
 impl KeyboardTomlConfig {
     pub fn get_basic_info(&self) -> Basic {
         let default = Basic::default();
@@ -44,6 +52,29 @@ impl KeyboardTomlConfig {
         }
     }
 
+    pub fn get_bootmagic(&self) -> Bootmagic {
+        let storage = self.get_storage_config();
+        Bootmagic {
+            bootloader_keys: self
+                .keyboard
+                .as_ref()
+                .and_then(|k| k.bootloader_keys.clone())
+                .unwrap_or_default(),
+            clear_storage_keys: storage.clear_storage_keys.unwrap_or_default(),
+            clear_bonds_keys: storage.clear_bonds_keys.unwrap_or_default(),
+        }
+    }
+
+    /// Get the firmware version in `keyboard.toml`, `None` if it's not set
+    pub fn get_firmware_version(&self) -> Option<Result<[u16; 3], String>> {
+        let version = self.keyboard.as_ref()?.version.as_ref()?;
+        Some(parse_version(version))
+    }
+
+    pub fn get_bootloader_config(&self) -> Option<BootloaderConfig> {
+        self.bootloader.clone()
+    }
+
     pub fn get_dependency_config(&self) -> DependencyConfig {
         if let Some(dependency) = &self.dependency {
             dependency.clone()
@@ -52,3 +83,16 @@ impl KeyboardTomlConfig {
         }
     }
 }
+
+/// Parse a semantic version `major.minor.patch`, the pre-release and build metadata are ignored
+pub fn parse_version(version: &str) -> Result<[u16; 3], String> {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<_> = core.split('.').map(|p| p.parse::<u16>()).collect();
+    match parts.as_slice() {
+        [Ok(major), Ok(minor), Ok(patch)] => Ok([*major, *minor, *patch]),
+        _ => Err(format!(
+            "Invalid version `{}`, it should be `major.minor.patch` with numbers up to 65535",
+            version
+        )),
+    }
+}
@@ -2,48 +2,20 @@ use std::collections::HashMap;
 
 use once_cell::sync::Lazy;
 
-pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
-    let mut m = HashMap::new();
+use crate::keycode_names::KEYCODE_NAMES;
+
+/// Lowercase names of keycodes, and aliases of them
+pub static KEYCODE_ALIAS: Lazy<HashMap<String, &str>> = Lazy::new(|| {
+    let mut m: HashMap<String, &str> = KEYCODE_NAMES.iter().map(|&name| (name.to_lowercase(), name)).collect();
 
     macro_rules! add_alias {
-        ($keycode:tt) => {
-            m.insert(paste::paste!{ stringify!([<$keycode:lower>]) }, $keycode);
-        };
         ($keycode:tt = $( $alias:expr),*) => {
-            add_alias!($keycode);
             $(
-                m.insert($alias, $keycode);
+                m.insert($alias.to_string(), $keycode);
             )*
         };
     }
 
-    add_alias!("No");
-    add_alias!("A");
-    add_alias!("B");
-    add_alias!("C");
-    add_alias!("D");
-    add_alias!("E");
-    add_alias!("F");
-    add_alias!("G");
-    add_alias!("H");
-    add_alias!("I");
-    add_alias!("J");
-    add_alias!("K");
-    add_alias!("L");
-    add_alias!("M");
-    add_alias!("N");
-    add_alias!("O");
-    add_alias!("P");
-    add_alias!("Q");
-    add_alias!("R");
-    add_alias!("S");
-    add_alias!("T");
-    add_alias!("U");
-    add_alias!("V");
-    add_alias!("W");
-    add_alias!("X");
-    add_alias!("Y");
-    add_alias!("Z");
     add_alias!("Kc1" = "1");
     add_alias!("Kc2" = "2");
     add_alias!("Kc3" = "3");
@@ -57,7 +29,6 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("Enter" = "ent");
     add_alias!("Escape" = "esc");
     add_alias!("Backspace" = "bspc");
-    add_alias!("Tab");
     add_alias!("Space" = "spc");
     add_alias!("Minus" = "mins", "-");
     add_alias!("Equal" = "eql", "=");
@@ -72,31 +43,14 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("Dot" = ".");
     add_alias!("Slash" = "slsh", "/");
     add_alias!("CapsLock" = "caps_lock", "caps");
-    add_alias!("F1");
-    add_alias!("F2");
-    add_alias!("F3");
-    add_alias!("F4");
-    add_alias!("F5");
-    add_alias!("F6");
-    add_alias!("F7");
-    add_alias!("F8");
-    add_alias!("F9");
-    add_alias!("F10");
-    add_alias!("F11");
-    add_alias!("F12");
     add_alias!("PrintScreen" = "print_screen", "pscr");
     add_alias!("ScrollLock" = "scroll_lock", "scrl", "brmd");
     add_alias!("Pause" = "paus", "brk", "brmu");
     add_alias!("Insert" = "ins");
-    add_alias!("Home");
     add_alias!("PageUp" = "page_up", "pgup");
     add_alias!("Delete" = "del");
-    add_alias!("End");
     add_alias!("PageDown" = "page_down", "pgdn");
     add_alias!("Right" = "rght");
-    add_alias!("Left");
-    add_alias!("Down");
-    add_alias!("Up");
     add_alias!("NumLock" = "num_lock", "num");
     add_alias!("KpSlash" = "kp_slash", "psls");
     add_alias!("KpAsterisk" = "kp_asterisk", "past");
@@ -118,29 +72,10 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("Application" = "app");
     add_alias!("KbPower" = "kb_power");
     add_alias!("KpEqual" = "kp_equal", "peql");
-    add_alias!("F13");
-    add_alias!("F14");
-    add_alias!("F15");
-    add_alias!("F16");
-    add_alias!("F17");
-    add_alias!("F18");
-    add_alias!("F19");
-    add_alias!("F20");
-    add_alias!("F21");
-    add_alias!("F22");
-    add_alias!("F23");
-    add_alias!("F24");
     add_alias!("Execute" = "exec");
-    add_alias!("Help");
-    add_alias!("Menu");
     add_alias!("Select" = "slct");
-    add_alias!("Stop");
     add_alias!("Again" = "agin");
-    add_alias!("Undo");
-    add_alias!("Cut");
-    add_alias!("Copy");
     add_alias!("Paste" = "pste");
-    add_alias!("Find");
     add_alias!("KbMute" = "kb_mute");
     add_alias!("KbVolumeUp" = "kb_volume_up");
     add_alias!("KbVolumeDown" = "kb_volume_down");
@@ -174,8 +109,6 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("Prior" = "prir");
     add_alias!("Return" = "retn");
     add_alias!("Separator" = "sepr");
-    add_alias!("Out");
-    add_alias!("Oper");
     add_alias!("ClearAgain" = "clear_again", "clag");
     add_alias!("Crsel" = "crsl");
     add_alias!("Exsel" = "exsl");
@@ -191,7 +124,6 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("MediaPlayPause" = "media_play_pause", "mply");
     add_alias!("MediaSelect" = "media_select", "msel");
     add_alias!("MediaEject" = "media_eject", "ejct");
-    add_alias!("Mail");
     add_alias!("Calculator" = "calc");
     add_alias!("MyComputer" = "my_computer", "mycm");
     add_alias!("WwwSearch" = "www_search", "wsch");
@@ -252,5 +184,24 @@ pub static KEYCODE_ALIAS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     add_alias!("RAlt" = "r_alt", "rightalt", "right_alt", "ropt", "algr");
     add_alias!("RGui" = "r_gui", "rightgui", "right_gui", "rcmd", "rwin");
 
+    add_alias!("CapsWordToggle" = "capsword", "caps_word", "cw_togg");
+
     m
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keycode_alias() {
+        // Names of keycodes are case insensitive
+        assert_eq!(KEYCODE_ALIAS.get("a"), Some(&"A"));
+        assert_eq!(KEYCODE_ALIAS.get("mouseaccel0"), Some(&"MouseAccel0"));
+        assert_eq!(KEYCODE_ALIAS.get("joystickbutton31"), Some(&"JoystickButton31"));
+        assert_eq!(KEYCODE_ALIAS.get("capsword"), Some(&"CapsWordToggle"));
+        assert_eq!(KEYCODE_ALIAS.get("1"), Some(&"Kc1"));
+        assert_eq!(KEYCODE_ALIAS.get("foo"), None);
+        assert!(KEYCODE_NAMES.len() > 600);
+    }
+}
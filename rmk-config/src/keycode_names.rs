@@ -0,0 +1,703 @@
+//! Names of all `KeyCode` variants in `rmk/src/keycode.rs`.
+//!
+//! The list is generated from `KeyCode`, `test_keycode_names` in rmk fails with the updated list when they're out of sync.
+
+/// Names of all `KeyCode` variants, in the order of their values
+pub const KEYCODE_NAMES: &[&str] = &[
+    "No",
+    "ErrorRollover",
+    "PostFail",
+    "ErrorUndefined",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "Kc1",
+    "Kc2",
+    "Kc3",
+    "Kc4",
+    "Kc5",
+    "Kc6",
+    "Kc7",
+    "Kc8",
+    "Kc9",
+    "Kc0",
+    "Enter",
+    "Escape",
+    "Backspace",
+    "Tab",
+    "Space",
+    "Minus",
+    "Equal",
+    "LeftBracket",
+    "RightBracket",
+    "Backslash",
+    "NonusHash",
+    "Semicolon",
+    "Quote",
+    "Grave",
+    "Comma",
+    "Dot",
+    "Slash",
+    "CapsLock",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "PrintScreen",
+    "ScrollLock",
+    "Pause",
+    "Insert",
+    "Home",
+    "PageUp",
+    "Delete",
+    "End",
+    "PageDown",
+    "Right",
+    "Left",
+    "Down",
+    "Up",
+    "NumLock",
+    "KpSlash",
+    "KpAsterisk",
+    "KpMinus",
+    "KpPlus",
+    "KpEnter",
+    "Kp1",
+    "Kp2",
+    "Kp3",
+    "Kp4",
+    "Kp5",
+    "Kp6",
+    "Kp7",
+    "Kp8",
+    "Kp9",
+    "Kp0",
+    "KpDot",
+    "NonusBackslash",
+    "Application",
+    "KbPower",
+    "KpEqual",
+    "F13",
+    "F14",
+    "F15",
+    "F16",
+    "F17",
+    "F18",
+    "F19",
+    "F20",
+    "F21",
+    "F22",
+    "F23",
+    "F24",
+    "Execute",
+    "Help",
+    "Menu",
+    "Select",
+    "Stop",
+    "Again",
+    "Undo",
+    "Cut",
+    "Copy",
+    "Paste",
+    "Find",
+    "KbMute",
+    "KbVolumeUp",
+    "KbVolumeDown",
+    "LockingCapsLock",
+    "LockingNumLock",
+    "LockingScrollLock",
+    "KpComma",
+    "KpEqualAs400",
+    "International1",
+    "International2",
+    "International3",
+    "International4",
+    "International5",
+    "International6",
+    "International7",
+    "International8",
+    "International9",
+    "Language1",
+    "Language2",
+    "Language3",
+    "Language4",
+    "Language5",
+    "Language6",
+    "Language7",
+    "Language8",
+    "Language9",
+    "AlternateErase",
+    "SystemRequest",
+    "Cancel",
+    "Clear",
+    "Prior",
+    "Return",
+    "Separator",
+    "Out",
+    "Oper",
+    "ClearAgain",
+    "Crsel",
+    "Exsel",
+    "SystemPower",
+    "SystemSleep",
+    "SystemWake",
+    "AudioMute",
+    "AudioVolUp",
+    "AudioVolDown",
+    "MediaNextTrack",
+    "MediaPrevTrack",
+    "MediaStop",
+    "MediaPlayPause",
+    "MediaSelect",
+    "MediaEject",
+    "Mail",
+    "Calculator",
+    "MyComputer",
+    "WwwSearch",
+    "WwwHome",
+    "WwwBack",
+    "WwwForward",
+    "WwwStop",
+    "WwwRefresh",
+    "WwwFavorites",
+    "MediaFastForward",
+    "MediaRewind",
+    "BrightnessUp",
+    "BrightnessDown",
+    "ControlPanel",
+    "Assistant",
+    "MissionControl",
+    "Launchpad",
+    "MouseUp",
+    "MouseDown",
+    "MouseLeft",
+    "MouseRight",
+    "MouseBtn1",
+    "MouseBtn2",
+    "MouseBtn3",
+    "MouseBtn4",
+    "MouseBtn5",
+    "MouseBtn6",
+    "MouseBtn7",
+    "MouseBtn8",
+    "MouseWheelUp",
+    "MouseWheelDown",
+    "MouseWheelLeft",
+    "MouseWheelRight",
+    "MouseAccel0",
+    "MouseAccel1",
+    "MouseAccel2",
+    "LCtrl",
+    "LShift",
+    "LAlt",
+    "LGui",
+    "RCtrl",
+    "RShift",
+    "RAlt",
+    "RGui",
+    "MagicSwapControlCapsLock",
+    "MagicUnswapControlCapsLock",
+    "MagicToggleControlCapsLock",
+    "MagicCapsLockAsControlOff",
+    "MagicCapsLockAsControlOn",
+    "MagicSwapLaltLGui",
+    "MagicUnswapLaltLGui",
+    "MagicSwapRaltRGui",
+    "MagicUnswapRaltRGui",
+    "MagicGuiOn",
+    "MagicGuiOff",
+    "MagicToggleGui",
+    "MagicSwapGraveEsc",
+    "MagicUnswapGraveEsc",
+    "MagicSwapBackslashBackspace",
+    "MagicUnswapBackslashBackspace",
+    "MagicToggleBackslashBackspace",
+    "MagicNkroOn",
+    "MagicNkroOff",
+    "MagicToggleNkro",
+    "MagicSwapAltGui",
+    "MagicUnswapAltGui",
+    "MagicToggleAltGui",
+    "MagicSwapLctlLGui",
+    "MagicUnswapLctlLGui",
+    "MagicSwapRctlRGui",
+    "MagicUnswapRctlRGui",
+    "MagicSwapCtlGui",
+    "MagicUnswapCtlGui",
+    "MagicToggleCtlGui",
+    "MagicEeHandsLeft",
+    "MagicEeHandsRight",
+    "MagicSwapEscapeCapsLock",
+    "MagicUnswapEscapeCapsLock",
+    "MagicToggleEscapeCapsLock",
+    "MidiOn",
+    "MidiOff",
+    "MidiToggle",
+    "MidiNoteC0",
+    "MidiNoteCSharp0",
+    "MidiNoteD0",
+    "MidiNoteDSharp0",
+    "MidiNoteE0",
+    "MidiNoteF0",
+    "MidiNoteFSharp0",
+    "MidiNoteG0",
+    "MidiNoteGSharp0",
+    "MidiNoteA0",
+    "MidiNoteASharp0",
+    "MidiNoteB0",
+    "MidiNoteC1",
+    "MidiNoteCSharp1",
+    "MidiNoteD1",
+    "MidiNoteDSharp1",
+    "MidiNoteE1",
+    "MidiNoteF1",
+    "MidiNoteFSharp1",
+    "MidiNoteG1",
+    "MidiNoteGSharp1",
+    "MidiNoteA1",
+    "MidiNoteASharp1",
+    "MidiNoteB1",
+    "MidiNoteC2",
+    "MidiNoteCSharp2",
+    "MidiNoteD2",
+    "MidiNoteDSharp2",
+    "MidiNoteE2",
+    "MidiNoteF2",
+    "MidiNoteFSharp2",
+    "MidiNoteG2",
+    "MidiNoteGSharp2",
+    "MidiNoteA2",
+    "MidiNoteASharp2",
+    "MidiNoteB2",
+    "MidiNoteC3",
+    "MidiNoteCSharp3",
+    "MidiNoteD3",
+    "MidiNoteDSharp3",
+    "MidiNoteE3",
+    "MidiNoteF3",
+    "MidiNoteFSharp3",
+    "MidiNoteG3",
+    "MidiNoteGSharp3",
+    "MidiNoteA3",
+    "MidiNoteASharp3",
+    "MidiNoteB3",
+    "MidiNoteC4",
+    "MidiNoteCSharp4",
+    "MidiNoteD4",
+    "MidiNoteDSharp4",
+    "MidiNoteE4",
+    "MidiNoteF4",
+    "MidiNoteFSharp4",
+    "MidiNoteG4",
+    "MidiNoteGSharp4",
+    "MidiNoteA4",
+    "MidiNoteASharp4",
+    "MidiNoteB4",
+    "MidiNoteC5",
+    "MidiNoteCSharp5",
+    "MidiNoteD5",
+    "MidiNoteDSharp5",
+    "MidiNoteE5",
+    "MidiNoteF5",
+    "MidiNoteFSharp5",
+    "MidiNoteG5",
+    "MidiNoteGSharp5",
+    "MidiNoteA5",
+    "MidiNoteASharp5",
+    "MidiNoteB5",
+    "MidiOctaveN2",
+    "MidiOctaveN1",
+    "MidiOctave0",
+    "MidiOctave1",
+    "MidiOctave2",
+    "MidiOctave3",
+    "MidiOctave4",
+    "MidiOctave5",
+    "MidiOctave6",
+    "MidiOctave7",
+    "MidiOctaveDOWN",
+    "MidiOctaveUP",
+    "MidiTransposeN6",
+    "MidiTransposeN5",
+    "MidiTransposeN4",
+    "MidiTransposeN3",
+    "MidiTransposeN2",
+    "MidiTransposeN1",
+    "MidiTranspose0",
+    "MidiTranspose1",
+    "MidiTranspose2",
+    "MidiTranspose3",
+    "MidiTranspose4",
+    "MidiTranspose5",
+    "MidiTranspose6",
+    "MidiTransposeDown",
+    "MidiTransposeUp",
+    "MidiVelocity0",
+    "MidiVelocity1",
+    "MidiVelocity2",
+    "MidiVelocity3",
+    "MidiVelocity4",
+    "MidiVelocity5",
+    "MidiVelocity6",
+    "MidiVelocity7",
+    "MidiVelocity8",
+    "MidiVelocity9",
+    "MidiVelocity10",
+    "MidiVelocityDOWN",
+    "MidiVelocityUP",
+    "MidiChannel1",
+    "MidiChannel2",
+    "MidiChannel3",
+    "MidiChannel4",
+    "MidiChannel5",
+    "MidiChannel6",
+    "MidiChannel7",
+    "MidiChannel8",
+    "MidiChannel9",
+    "MidiChannel10",
+    "MidiChannel11",
+    "MidiChannel12",
+    "MidiChannel13",
+    "MidiChannel14",
+    "MidiChannel15",
+    "MidiChannel16",
+    "MidiChannelDOWN",
+    "MidiChannelUP",
+    "MidiAllNotesOff",
+    "MidiSustain",
+    "MidiPortamento",
+    "MidiSostenuto",
+    "MidiSoft",
+    "MidiLegato",
+    "MidiModulation",
+    "MidiModulationSpeedDown",
+    "MidiModulationSpeedUp",
+    "MidiPitchBendDown",
+    "MidiPitchBendUp",
+    "SequencerOn",
+    "SequencerOff",
+    "SequencerToggle",
+    "SequencerTempoDown",
+    "SequencerTempoUp",
+    "SequencerResolutionDown",
+    "SequencerResolutionUp",
+    "SequencerStepsAll",
+    "SequencerStepsClear",
+    "JoystickButton0",
+    "JoystickButton1",
+    "JoystickButton2",
+    "JoystickButton3",
+    "JoystickButton4",
+    "JoystickButton5",
+    "JoystickButton6",
+    "JoystickButton7",
+    "JoystickButton8",
+    "JoystickButton9",
+    "JoystickButton10",
+    "JoystickButton11",
+    "JoystickButton12",
+    "JoystickButton13",
+    "JoystickButton14",
+    "JoystickButton15",
+    "JoystickButton16",
+    "JoystickButton17",
+    "JoystickButton18",
+    "JoystickButton19",
+    "JoystickButton20",
+    "JoystickButton21",
+    "JoystickButton22",
+    "JoystickButton23",
+    "JoystickButton24",
+    "JoystickButton25",
+    "JoystickButton26",
+    "JoystickButton27",
+    "JoystickButton28",
+    "JoystickButton29",
+    "JoystickButton30",
+    "JoystickButton31",
+    "ProgrammableButton1",
+    "ProgrammableButton2",
+    "ProgrammableButton3",
+    "ProgrammableButton4",
+    "ProgrammableButton5",
+    "ProgrammableButton6",
+    "ProgrammableButton7",
+    "ProgrammableButton8",
+    "ProgrammableButton9",
+    "ProgrammableButton10",
+    "ProgrammableButton11",
+    "ProgrammableButton12",
+    "ProgrammableButton13",
+    "ProgrammableButton14",
+    "ProgrammableButton15",
+    "ProgrammableButton16",
+    "ProgrammableButton17",
+    "ProgrammableButton18",
+    "ProgrammableButton19",
+    "ProgrammableButton20",
+    "ProgrammableButton21",
+    "ProgrammableButton22",
+    "ProgrammableButton23",
+    "ProgrammableButton24",
+    "ProgrammableButton25",
+    "ProgrammableButton26",
+    "ProgrammableButton27",
+    "ProgrammableButton28",
+    "ProgrammableButton29",
+    "ProgrammableButton30",
+    "ProgrammableButton31",
+    "ProgrammableButton32",
+    "JoystickHatUp",
+    "JoystickHatRight",
+    "JoystickHatDown",
+    "JoystickHatLeft",
+    "AudioOn",
+    "AudioOff",
+    "AudioToggle",
+    "AudioClickyToggle",
+    "AudioClickyOn",
+    "AudioClickyOff",
+    "AudioClickyUp",
+    "AudioClickyDown",
+    "AudioClickyReset",
+    "MusicOn",
+    "MusicOff",
+    "MusicToggle",
+    "MusicModeNext",
+    "AudioVoiceNext",
+    "AudioVoicePrevious",
+    "StenoBolt",
+    "StenoGemini",
+    "StenoComb",
+    "StenoCombMax",
+    "Macro0",
+    "Macro1",
+    "Macro2",
+    "Macro3",
+    "Macro4",
+    "Macro5",
+    "Macro6",
+    "Macro7",
+    "Macro8",
+    "Macro9",
+    "Macro10",
+    "Macro11",
+    "Macro12",
+    "Macro13",
+    "Macro14",
+    "Macro15",
+    "Macro16",
+    "Macro17",
+    "Macro18",
+    "Macro19",
+    "Macro20",
+    "Macro21",
+    "Macro22",
+    "Macro23",
+    "Macro24",
+    "Macro25",
+    "Macro26",
+    "Macro27",
+    "Macro28",
+    "Macro29",
+    "Macro30",
+    "Macro31",
+    "BacklightOn",
+    "BacklightOff",
+    "BacklightToggle",
+    "BacklightDown",
+    "BacklightUp",
+    "BacklightStep",
+    "BacklightToggleBreathing",
+    "RgbTog",
+    "RgbModeForward",
+    "RgbModeReverse",
+    "RgbHui",
+    "RgbHud",
+    "RgbSai",
+    "RgbSad",
+    "RgbVai",
+    "RgbVad",
+    "RgbSpi",
+    "RgbSpd",
+    "RgbModePlain",
+    "RgbModeBreathe",
+    "RgbModeRainbow",
+    "RgbModeSwirl",
+    "RgbModeSnake",
+    "RgbModeKnight",
+    "RgbModeXmas",
+    "RgbModeGradient",
+    "RgbModeRgbtest",
+    "RgbModeTwinkle",
+    "Bootloader",
+    "Reboot",
+    "DebugToggle",
+    "ClearEeprom",
+    "Make",
+    "AutoShiftDown",
+    "AutoShiftUp",
+    "AutoShiftReport",
+    "AutoShiftOn",
+    "AutoShiftOff",
+    "AutoShiftToggle",
+    "GraveEscape",
+    "VelocikeyToggle",
+    "SpaceCadetLCtrlParenthesisOpen",
+    "SpaceCadetRCtrlParenthesisClose",
+    "SpaceCadetLShiftParenthesisOpen",
+    "SpaceCadetRShiftParenthesisClose",
+    "SpaceCadetLAltParenthesisOpen",
+    "SpaceCadetRAltParenthesisClose",
+    "SpaceCadetRShiftEnter",
+    "OutputAuto",
+    "OutputUsb",
+    "OutputBluetooth",
+    "UnicodeModeNext",
+    "UnicodeModePrevious",
+    "UnicodeModeMacos",
+    "UnicodeModeLinux",
+    "UnicodeModeWindows",
+    "UnicodeModeBsd",
+    "UnicodeModeWincompose",
+    "UnicodeModeEmacs",
+    "HapticOn",
+    "HapticOff",
+    "HapticToggle",
+    "HapticReset",
+    "HapticFeedbackToggle",
+    "HapticBuzzToggle",
+    "HapticModeNext",
+    "HapticModePrevious",
+    "HapticContinuousToggle",
+    "HapticContinuousUp",
+    "HapticContinuousDown",
+    "HapticDwellUp",
+    "HapticDwellDown",
+    "ComboOn",
+    "ComboOff",
+    "ComboToggle",
+    "DynamicMacroRecordStart1",
+    "DynamicMacroRecordStart2",
+    "DynamicMacroRecordStop",
+    "DynamicMacroPlay1",
+    "DynamicMacroPlay2",
+    "Leader",
+    "Lock",
+    "OneShotOn",
+    "OneShotOff",
+    "OneShotToggle",
+    "KeyOverrideToggle",
+    "KeyOverrideOn",
+    "KeyOverrideOff",
+    "SecureLock",
+    "SecureUnlock",
+    "SecureToggle",
+    "SecureRequest",
+    "DynamicTappingTermPrint",
+    "DynamicTappingTermUp",
+    "DynamicTappingTermDown",
+    "CapsWordToggle",
+    "AutocorrectOn",
+    "AutocorrectOff",
+    "AutocorrectToggle",
+    "TriLayerLower",
+    "TriLayerUpper",
+    "RepeatKey",
+    "AltRepeatKey",
+    "Kb0",
+    "Kb1",
+    "Kb2",
+    "Kb3",
+    "Kb4",
+    "Kb5",
+    "Kb6",
+    "Kb7",
+    "Kb8",
+    "Kb9",
+    "Kb10",
+    "Kb11",
+    "Kb12",
+    "Kb13",
+    "Kb14",
+    "Kb15",
+    "Kb16",
+    "Kb17",
+    "Kb18",
+    "Kb19",
+    "Kb20",
+    "Kb21",
+    "Kb22",
+    "Kb23",
+    "Kb24",
+    "Kb25",
+    "Kb26",
+    "Kb27",
+    "Kb28",
+    "Kb29",
+    "Kb30",
+    "Kb31",
+    "User0",
+    "User1",
+    "User2",
+    "User3",
+    "User4",
+    "User5",
+    "User6",
+    "User7",
+    "User8",
+    "User9",
+    "User10",
+    "User11",
+    "User12",
+    "User13",
+    "User14",
+    "User15",
+    "User16",
+    "User17",
+    "User18",
+    "User19",
+    "User20",
+    "User21",
+    "User22",
+    "User23",
+    "User24",
+    "User25",
+    "User26",
+    "User27",
+    "User28",
+    "User29",
+    "User30",
+    "User31",
+];
@@ -3,19 +3,55 @@ use std::collections::HashMap;
 use pest::Parser;
 use pest_derive::Parser;
 
-use crate::{KeyboardTomlConfig, LayoutConfig};
+use crate::{
+    BehaviorConfig, DurationMillis, KeyboardTomlConfig, LayoutConfig, LayoutOptionConfig, MacroConfig, MacroOperation,
+    MorseConfig, parse_duration,
+};
 
 // Pest parser using the grammar files
 #[derive(Parser)]
 #[grammar = "keymap.pest"]
-struct ConfigParser;
+pub(crate) struct ConfigParser;
 
 // Max alias resolution depth to prevent infinite loops
 const MAX_ALIAS_RESOLUTION_DEPTH: usize = 10;
 
+// Max number of taps in a morse pattern
+const MAX_MORSE_TAPS: usize = 15;
+
+/// Macros and morses which are defined inline in the keymap
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InlineBehaviors {
+    pub(crate) macros: Vec<MacroConfig>,
+    pub(crate) morses: Vec<MorseConfig>,
+}
+
+impl InlineBehaviors {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.macros.is_empty() && self.morses.is_empty()
+    }
+
+    /// Append the inline macros and morses after the ones defined in `[behavior]`
+    pub(crate) fn append_to(self, behavior: &mut BehaviorConfig) {
+        if !self.macros.is_empty() {
+            behavior.macros.get_or_insert_default().macros.extend(self.macros);
+        }
+        if !self.morses.is_empty() {
+            behavior.morse.get_or_insert_default().morses.extend(self.morses);
+        }
+    }
+}
+
 impl KeyboardTomlConfig {
     /// Layout is a mandatory field in toml, so we mainly check the sizes
     pub fn get_layout_config(&self) -> Result<LayoutConfig, String> {
+        self.get_layout_config_with_inline_behaviors().map(|(layout, _)| layout)
+    }
+
+    /// Get the layout config, and the macros and morses which are defined inline in the keymap.
+    ///
+    /// Inline actions in the returned keymap are replaced by `Macro(n)` or `TD(n)`.
+    pub(crate) fn get_layout_config_with_inline_behaviors(&self) -> Result<(LayoutConfig, InlineBehaviors), String> {
         let aliases = self.aliases.clone().unwrap_or_default();
         let layers = self.layer.clone().unwrap_or_default();
         let mut layout = self.layout.clone().expect("layout config is required");
@@ -70,27 +106,27 @@ impl KeyboardTomlConfig {
         } else if !layers.is_empty() {
             return Err("layout.matrix_map is need to be defined to process [[layer]] based key maps".to_string());
         }
-        if let Some(sequence_to_grid) = &sequence_to_grid {
-            // collect layer names first
-            let mut layer_names = HashMap::<String, u32>::new();
-            for (layer_number, layer) in layers.iter().enumerate() {
-                if let Some(name) = &layer.name {
-                    if layer_names.contains_key(name) {
-                        return Err(format!(
-                            "keyboard.toml: Duplicate layer name '{}' found in `layout.keymap`",
-                            name
-                        ));
-                    }
-                    layer_names.insert(name.clone(), layer_number as u32);
+        // collect layer names first
+        let mut layer_names = HashMap::<String, u32>::new();
+        for (layer_number, layer) in layers.iter().enumerate() {
+            if let Some(name) = &layer.name {
+                if layer_names.contains_key(name) {
+                    return Err(format!(
+                        "keyboard.toml: Duplicate layer name '{}' found in `layout.keymap`",
+                        name
+                    ));
                 }
+                layer_names.insert(name.clone(), layer_number as u32);
             }
+        }
+        let layer_names = layer_names;
+        if let Some(sequence_to_grid) = &sequence_to_grid {
             if layers.len() > layout.layers as usize {
                 return Err("keyboard.toml: Number of [[layer]] entries is larger than layout.layers".to_string());
             }
             // Parse each explicitly defined [[layer]] with pest into the final_layers vector
             // using the previously defined sequence_to_grid mapping to fill in the
             // grid shaped classic keymaps
-            let layer_names = layer_names;
             for (layer_number, layer) in layers.iter().enumerate() {
                 // each layer should contain a sequence of keymap entries
                 // their number and order should match the number and order of the above parsed matrix map
@@ -147,17 +183,290 @@ impl KeyboardTomlConfig {
         {
             return Err("keyboard.toml: Col number in keymap doesn't match with [layout.col]".to_string());
         }
-        Ok(LayoutConfig {
-            rows: layout.rows,
-            cols: layout.cols,
-            layers: layout.layers,
-            keymap: final_layers,
-        })
+        let mut options = self.get_layout_options(&aliases, &layer_names)?;
+        let keymap_keys = final_layers.iter_mut().enumerate().flat_map(|(layer, rows)| {
+            rows.iter_mut().enumerate().flat_map(move |(row, cols)| {
+                cols.iter_mut()
+                    .enumerate()
+                    .map(move |(col, key)| ((layer as u8, row as u8, col as u8), key))
+            })
+        });
+        let option_keys = options.iter_mut().flat_map(|o| {
+            o.keys
+                .iter_mut()
+                .map(|(layer, row, col, key)| ((*layer, *row, *col), key))
+        });
+        let inline = self.lower_inline_behaviors(keymap_keys.chain(option_keys))?;
+        Ok((
+            LayoutConfig {
+                rows: layout.rows,
+                cols: layout.cols,
+                layers: layout.layers,
+                keymap: final_layers,
+                option_bits: self.get_layout_option_bits(),
+                options,
+            },
+            inline,
+        ))
+    }
+
+    /// Number of bits of each layout option in the VIA layout options value.
+    ///
+    /// A toggle option takes 1 bit, a select option takes enough bits for all of its choices.
+    fn get_layout_option_bits(&self) -> Vec<u8> {
+        self.get_layout_option_choices()
+            .into_iter()
+            .map(|choices| (usize::BITS - choices.saturating_sub(1).leading_zeros()) as u8)
+            .collect()
+    }
+
+    /// Number of choices of each layout option in `layout.physical.options`
+    fn get_layout_option_choices(&self) -> Vec<usize> {
+        self.layout
+            .as_ref()
+            .and_then(|l| l.physical.as_ref())
+            .and_then(|p| p.options.as_ref())
+            .map(|options| {
+                options
+                    .iter()
+                    .map(|labels| {
+                        if labels.len() == 1 {
+                            2
+                        } else {
+                            labels.len().saturating_sub(1)
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Parse the keymap changes of layout options in `[[layout.option]]`
+    fn get_layout_options(
+        &self,
+        aliases: &HashMap<String, String>,
+        layer_names: &HashMap<String, u32>,
+    ) -> Result<Vec<LayoutOptionConfig>, String> {
+        let layout = self.layout.as_ref().expect("layout config is required");
+        let choices = self.get_layout_option_choices();
+        let check_bounds = |row: u8, col: u8, field: &str| {
+            if row >= layout.rows || col >= layout.cols {
+                Err(format!(
+                    "keyboard.toml: Coordinate ({},{}) in `layout.option.{}` is out of bounds: ([0..{}], [0..{}]) is the expected range",
+                    row,
+                    col,
+                    field,
+                    layout.rows - 1,
+                    layout.cols - 1
+                ))
+            } else {
+                Ok(())
+            }
+        };
+
+        let mut options = Vec::new();
+        for option in layout.option.iter().flatten() {
+            let Some(&num_choices) = choices.get(option.option as usize) else {
+                return Err(format!(
+                    "keyboard.toml: Layout option {} in `layout.option` is not defined in `layout.physical.options`",
+                    option.option
+                ));
+            };
+            let choice = option.choice.unwrap_or(1);
+            if choice as usize >= num_choices {
+                return Err(format!(
+                    "keyboard.toml: Choice {} of layout option {} is out of range, the option has {} choices",
+                    choice, option.option, num_choices
+                ));
+            }
+
+            let mut remaps = Vec::new();
+            if let Some(remap) = &option.remap {
+                let coords = Self::parse_matrix_map(remap)
+                    .map_err(|e| format!("keyboard.toml: Error in `layout.option.remap`: {}", e))?;
+                if coords.len() % 2 != 0 {
+                    return Err("keyboard.toml: `layout.option.remap` must contain pairs of coordinates".to_string());
+                }
+                for pair in coords.chunks(2) {
+                    check_bounds(pair[0].0, pair[0].1, "remap")?;
+                    check_bounds(pair[1].0, pair[1].1, "remap")?;
+                    remaps.push((pair[0], pair[1]));
+                }
+            }
+
+            let mut keys = Vec::new();
+            if let Some(layer_keys) = &option.keys {
+                let Some(matrix_map) = &option.matrix_map else {
+                    return Err(
+                        "keyboard.toml: `layout.option.matrix_map` is required to set `layout.option.keys`".to_string(),
+                    );
+                };
+                let coords = Self::parse_matrix_map(matrix_map)
+                    .map_err(|e| format!("keyboard.toml: Error in `layout.option.matrix_map`: {}", e))?;
+                if layer_keys.len() > layout.layers as usize {
+                    return Err(
+                        "keyboard.toml: Number of layers in `layout.option.keys` is larger than layout.layers"
+                            .to_string(),
+                    );
+                }
+                for (layer, layer_keys) in layer_keys.iter().enumerate() {
+                    let actions = Self::keymap_parser(layer_keys, aliases, layer_names)
+                        .map_err(|e| format!("keyboard.toml: Error in `layout.option.keys`: {}", e))?;
+                    if actions.len() != coords.len() {
+                        return Err(format!(
+                            "keyboard.toml: Layer #{} of layout option {} has {} keys, but {} keys are defined in `layout.option.matrix_map`",
+                            layer,
+                            option.option,
+                            actions.len(),
+                            coords.len()
+                        ));
+                    }
+                    for (&(row, col), action) in coords.iter().zip(actions) {
+                        check_bounds(row, col, "matrix_map")?;
+                        keys.push((layer as u8, row, col, action));
+                    }
+                }
+            }
+
+            options.push(LayoutOptionConfig {
+                option: option.option,
+                choice,
+                remaps,
+                keys,
+            });
+        }
+
+        // Events are remapped once when they are received, so a remapped position can't be remapped again
+        let sources: Vec<(u8, u8)> = options
+            .iter()
+            .flat_map(|o| o.remaps.iter().map(|(from, _)| *from))
+            .collect();
+        if let Some((row, col)) = options
+            .iter()
+            .flat_map(|o| o.remaps.iter().map(|(_, to)| *to))
+            .find(|to| sources.contains(to))
+        {
+            return Err(format!(
+                "keyboard.toml: Coordinate ({},{}) in `layout.option.remap` can't be both remapped and the target of a remap",
+                row, col
+            ));
+        }
+        Ok(options)
+    }
+
+    /// Replace the key actions which can't be represented by a single `KeyAction` with `Macro(n)` or `TD(n)`.
+    ///
+    /// Inline text macros become macros, `TT` with a tap count and tap-hold actions with a per-key timeout
+    /// become morses. They are numbered after the macros and morses defined in `[behavior]`,
+    /// identical key actions share the same macro or morse.
+    fn lower_inline_behaviors<'a>(
+        &self,
+        keys: impl Iterator<Item = ((u8, u8, u8), &'a mut String)>,
+    ) -> Result<InlineBehaviors, String> {
+        let behavior = self.behavior.clone().unwrap_or_default();
+        let num_macros = behavior.macros.map(|m| m.macros.len()).unwrap_or_default();
+        let num_morses = behavior.morse.map(|m| m.morses.len()).unwrap_or_default();
+        let default_timeout = behavior.tap_hold.and_then(|t| t.hold_timeout);
+
+        let mut inline = InlineBehaviors::default();
+        let mut lowered = HashMap::<String, String>::new();
+        for ((layer, row, col), key) in keys {
+            if let Some(action) = lowered.get(key.as_str()) {
+                *key = action.clone();
+                continue;
+            }
+            let action = if let Some(text) = key.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+                inline.macros.push(MacroConfig {
+                    operations: vec![MacroOperation::Text { text: text.to_string() }],
+                });
+                format!("Macro({})", num_macros + inline.macros.len() - 1)
+            } else if let Some(morse) = Self::inline_morse(key, &default_timeout).map_err(|e| {
+                format!(
+                    "keyboard.toml: Error in key ({}, {}) of layer {}: {}",
+                    row, col, layer, e
+                )
+            })? {
+                inline.morses.push(morse);
+                format!("TD({})", num_morses + inline.morses.len() - 1)
+            } else {
+                continue;
+            };
+            lowered.insert(key.clone(), action.clone());
+            *key = action;
+        }
+        Ok(inline)
+    }
+
+    /// Get the morse of `TT(n, taps)` or of a tap-hold action with a timeout, like `MT(A, LShift, 250ms)`.
+    ///
+    /// Returns `None` if the key action doesn't need a morse.
+    fn inline_morse(key: &str, default_timeout: &Option<DurationMillis>) -> Result<Option<MorseConfig>, String> {
+        let Some((name, args)) = key.split_once('(') else {
+            return Ok(None);
+        };
+        let Some(args) = args.trim_end().strip_suffix(')') else {
+            return Ok(None);
+        };
+        let mut args = split_arguments(args);
+        // The last argument can be the timeout
+        let timeout = match args.last().map(|arg| parse_duration(arg)) {
+            Some(Ok(millis)) => {
+                args.pop();
+                Some(DurationMillis(millis))
+            }
+            _ => None,
+        };
+
+        let morse = match (name.trim().to_lowercase().as_str(), args.as_slice()) {
+            ("tt", [layer, taps @ ..]) if taps.len() == 1 || (taps.is_empty() && timeout.is_some()) => {
+                let taps = match taps.first() {
+                    Some(taps) => taps
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid tap count `{}` in `{}`", taps, key))?,
+                    None => 1,
+                };
+                if taps == 0 || taps > MAX_MORSE_TAPS {
+                    return Err(format!(
+                        "Tap count in `{}` must be between 1 and {}",
+                        key, MAX_MORSE_TAPS
+                    ));
+                }
+                // Holding activates the layer, tapping `taps` times toggles the layer
+                let mut tap_actions = vec!["No".to_string(); taps - 1];
+                tap_actions.push(format!("TG({})", layer));
+                MorseConfig {
+                    tap_actions: Some(tap_actions),
+                    hold_actions: Some(vec![format!("MO({})", layer); taps]),
+                    ..Default::default()
+                }
+            }
+            ("lt", [layer, tap]) if timeout.is_some() => MorseConfig {
+                tap: Some(tap.to_string()),
+                hold: Some(format!("MO({})", layer)),
+                ..Default::default()
+            },
+            ("mt", [tap, modifiers]) if timeout.is_some() => MorseConfig {
+                tap: Some(tap.to_string()),
+                hold: Some(format!("MOD({})", modifiers)),
+                ..Default::default()
+            },
+            ("th", [tap, hold]) if timeout.is_some() => MorseConfig {
+                tap: Some(tap.to_string()),
+                hold: Some(hold.to_string()),
+                ..Default::default()
+            },
+            _ => return Ok(None),
+        };
+
+        Ok(Some(MorseConfig {
+            timeout: timeout.or_else(|| default_timeout.clone()),
+            ..morse
+        }))
     }
 
     /// Parses and validates a matrix_map string using Pest.
     /// Ensures the string contains only valid coordinates and whitespace.
-    fn parse_matrix_map(matrix_map: &str) -> Result<Vec<(u8, u8)>, String> {
+    pub(crate) fn parse_matrix_map(matrix_map: &str) -> Result<Vec<(u8, u8)>, String> {
         match ConfigParser::parse(Rule::matrix_map, matrix_map) {
             Ok(pairs) => {
                 let mut coordinates = Vec::new();
@@ -218,6 +527,13 @@ impl KeyboardTomlConfig {
                 // Append the text before the '@'
                 next_keys.push_str(&current_keys[last_index..start_index]);
 
+                // '@' in an inline text macro is not an alias
+                if current_keys[..start_index].matches('"').count() % 2 == 1 {
+                    next_keys.push('@');
+                    last_index = start_index + 1;
+                    continue;
+                }
+
                 // Check if it's a valid alias start (@ followed by a non whitespace)
                 if let Some(first_char) = current_keys.as_bytes().get(start_index + 1) {
                     if !first_char.is_ascii_whitespace() {
@@ -313,7 +629,7 @@ impl KeyboardTomlConfig {
         Ok(action)
     }
 
-    fn keymap_parser(
+    pub(crate) fn keymap_parser(
         layer_keys: &str,
         aliases: &HashMap<String, String>,
         layer_names: &HashMap<String, u32>,
@@ -362,6 +678,21 @@ impl KeyboardTomlConfig {
                                     key_action_sequence.push(action);
                                 }
 
+                                Rule::mod_action => {
+                                    let action = inner_pair.as_str().to_string();
+                                    key_action_sequence.push(action);
+                                }
+
+                                Rule::osk_action => {
+                                    let action = inner_pair.as_str().to_string();
+                                    key_action_sequence.push(action);
+                                }
+
+                                Rule::tap_action => {
+                                    let action = inner_pair.as_str().to_string();
+                                    key_action_sequence.push(action);
+                                }
+
                                 //layer actions:
                                 Rule::df_action => {
                                     key_action_sequence.push(Self::layer_name_resolver("DF", inner_pair, layer_names)?);
@@ -413,15 +744,20 @@ impl KeyboardTomlConfig {
                                     key_action_sequence.push(action);
                                 }
 
+                                Rule::text_action => {
+                                    let action = inner_pair.as_str().to_string();
+                                    key_action_sequence.push(action);
+                                }
+
                                 Rule::EOI | Rule::WHITESPACE => {
                                     // Ignore End of input marker
                                 }
                                 _ => {
                                     // This case should not be reached
-                                    panic!(
-                                        "Unexpected rule encountered during layer.keys processing:{:?}",
+                                    return Err(format!(
+                                        "Unexpected rule encountered during layer.keys processing: {:?}",
                                         inner_pair.as_rule()
-                                    );
+                                    ));
                                 }
                             }
                         }
@@ -429,7 +765,7 @@ impl KeyboardTomlConfig {
                 }
             }
             Err(e) => {
-                panic!("Invalid keymap format: {}", e);
+                return Err(format!("Invalid keymap format: {}", e));
             }
         }
 
@@ -437,6 +773,26 @@ impl KeyboardTomlConfig {
     }
 }
 
+/// Split the arguments of a key action at the top level commas, like `1, WM(A, RShift), 200ms`
+fn split_arguments(args: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    arguments.push(args[start..].trim());
+    arguments
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -645,4 +1001,200 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_extended_action_grammar() {
+        let test_cases = vec![
+            ("OSK(A)", Rule::osk_action),
+            ("TAP(Enter)", Rule::tap_action),
+            ("MOD(LCtrl | LShift)", Rule::mod_action),
+            ("\"hello world\"", Rule::text_action),
+            ("TT(1, 3)", Rule::tt_action),
+            ("TT(1, 300ms)", Rule::tt_action),
+            ("TT(1, 3, 300ms)", Rule::tt_action),
+            ("LT(1, WM(A, RShift))", Rule::lt_action),
+            ("LT(1, A, 200ms)", Rule::lt_action),
+            ("MT(A, LShift, 250ms)", Rule::mt_action),
+            ("TH(A, B, 1s)", Rule::th_action),
+            ("CapsWord", Rule::simple_keycode),
+            ("Tab", Rule::simple_keycode),
+        ];
+
+        for (input, expected_rule) in test_cases {
+            let result = ConfigParser::parse(Rule::key_map, input);
+            assert!(result.is_ok(), "Failed to parse: {}", input);
+
+            let rules: Vec<_> = result
+                .unwrap()
+                .flat_map(|pair| pair.into_inner())
+                .map(|pair| pair.as_rule())
+                .filter(|rule| *rule != Rule::EOI)
+                .collect();
+            assert_eq!(rules, vec![expected_rule], "Input: {}", input);
+        }
+
+        // Invalid tap counts, durations and keys with modifier
+        for input in ["TT(1, 3x)", "MT(A, LShift, 250)", "LT(1, WM(A))"] {
+            assert!(
+                ConfigParser::parse(Rule::key_map, input).is_err(),
+                "Input: {} should be invalid",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_keymap_parser_with_extended_actions() {
+        let aliases = HashMap::from([("mail".to_string(), "\"me@rmk.rs\"".to_string())]);
+        let layer_names = HashMap::from([("nav".to_string(), 1)]);
+
+        let keymap =
+            "OSK(A) TAP(B) \"hello world\" @mail TT(nav, 3) LT(nav, WM(A, RShift), 200ms) MT(A, LShift, 250ms)";
+        let result = KeyboardTomlConfig::keymap_parser(keymap, &aliases, &layer_names);
+
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                "OSK(A)",
+                "TAP(B)",
+                "\"hello world\"",
+                "\"me@rmk.rs\"",
+                "TT(1, 3)",
+                "LT(1, WM(A, RShift), 200ms)",
+                "MT(A, LShift, 250ms)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_behaviors() {
+        let config: KeyboardTomlConfig = toml::from_str(
+            r#"
+            [keyboard]
+            name = "Test Keyboard"
+            vendor_id = 0x4c4b
+            product_id = 0x4643
+            chip = "nrf52840"
+
+            [layout]
+            rows = 1
+            cols = 5
+            layers = 2
+            matrix_map = "(0,0) (0,1) (0,2) (0,3) (0,4)"
+
+            [[layer]]
+            keys = '"hi@rmk" TT(1, 3) MT(A, LShift | LGui, 250ms) "hi@rmk" TT(1)'
+
+            [behavior.morse]
+            morses = [{ tap = "A", hold = "B" }]
+            "#,
+        )
+        .unwrap();
+
+        let layout = config.get_layout_config().unwrap();
+        assert_eq!(
+            layout.keymap[0][0],
+            vec!["Macro(0)", "TD(1)", "TD(2)", "Macro(0)", "TT(1)"]
+        );
+
+        let behavior = config.get_behavior_config().unwrap();
+        let macros = behavior.macros.unwrap().macros;
+        assert_eq!(macros.len(), 1);
+        assert!(matches!(&macros[0].operations[..], [MacroOperation::Text { text }] if text == "hi@rmk"));
+
+        let morses = behavior.morse.unwrap().morses;
+        assert_eq!(morses.len(), 3);
+        assert_eq!(
+            morses[1].tap_actions,
+            Some(vec!["No".to_string(), "No".to_string(), "TG(1)".to_string()])
+        );
+        assert_eq!(morses[1].hold_actions, Some(vec!["MO(1)".to_string(); 3]));
+        assert!(morses[1].timeout.is_none());
+        assert_eq!(morses[2].tap.as_deref(), Some("A"));
+        assert_eq!(morses[2].hold.as_deref(), Some("MOD(LShift | LGui)"));
+        assert_eq!(morses[2].timeout.as_ref().map(|t| t.0), Some(250));
+
+        // Errors of inline actions contain the position of the key
+        let mut config = config;
+        config.layer.as_mut().unwrap()[0].keys = "A B TT(1, 16) C D".to_string();
+        assert_eq!(
+            config.get_layout_config().unwrap_err(),
+            "keyboard.toml: Error in key (0, 2) of layer 0: Tap count in `TT(1, 16)` must be between 1 and 15"
+        );
+    }
+
+    #[test]
+    fn test_layout_options() {
+        let config: KeyboardTomlConfig = toml::from_str(
+            r#"
+            [keyboard]
+            name = "Test Keyboard"
+            vendor_id = 0x4c4b
+            product_id = 0x4643
+            chip = "nrf52840"
+
+            [layout]
+            rows = 1
+            cols = 3
+            layers = 2
+            matrix_map = "(0,0) (0,1) (0,2)"
+
+            [layout.physical]
+            options = [["Split Space"], ["Bottom Row", "ANSI", "Tsangan", "WKL"]]
+
+            [[layer]]
+            name = "base"
+            keys = "A B C"
+
+            [[layout.option]]
+            option = 0
+            remap = "(0,2) (0,1)"
+
+            [[layout.option]]
+            option = 1
+            choice = 2
+            matrix_map = "(0,0) (0,2)"
+            keys = ['"hi" TO(base)', "_ No"]
+            "#,
+        )
+        .unwrap();
+
+        let layout = config.get_layout_config().unwrap();
+        assert_eq!(layout.option_bits, vec![1, 2]);
+        assert_eq!(layout.options.len(), 2);
+        assert_eq!((layout.options[0].option, layout.options[0].choice), (0, 1));
+        assert_eq!(layout.options[0].remaps, vec![((0, 2), (0, 1))]);
+        assert!(layout.options[0].keys.is_empty());
+        assert_eq!((layout.options[1].option, layout.options[1].choice), (1, 2));
+        assert_eq!(
+            layout.options[1].keys,
+            vec![
+                (0, 0, 0, "Macro(0)".to_string()),
+                (0, 0, 2, "TO(0)".to_string()),
+                (1, 0, 0, "_".to_string()),
+                (1, 0, 2, "No".to_string()),
+            ]
+        );
+        let behavior = config.get_behavior_config().unwrap();
+        assert_eq!(behavior.macros.unwrap().macros.len(), 1);
+
+        // Choice out of range
+        let mut config = config;
+        config.layout.as_mut().unwrap().option.as_mut().unwrap()[1].choice = Some(3);
+        assert!(config.get_layout_config().is_err());
+
+        // A remapped position can't be remapped again
+        config.layout.as_mut().unwrap().option.as_mut().unwrap()[1].choice = Some(2);
+        config.layout.as_mut().unwrap().option.as_mut().unwrap()[0].remap = Some("(0,2) (0,1) (0,1) (0,0)".to_string());
+        assert!(config.get_layout_config().unwrap_err().contains("Coordinate (0,1)"));
+    }
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(
+            split_arguments("1, WM(A, RShift), 200ms"),
+            vec!["1", "WM(A, RShift)", "200ms"]
+        );
+        assert_eq!(split_arguments("A"), vec!["A"]);
+    }
 }
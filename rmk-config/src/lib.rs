@@ -14,15 +14,19 @@ pub mod usb_interrupt_map;
 pub mod behavior;
 pub mod board;
 pub mod keycode_alias;
+pub mod keycode_names;
 pub mod layout;
 pub mod light;
 pub mod storage;
+pub mod validate;
+pub mod vial;
 
 pub use board::{BoardConfig, UniBodyConfig};
 pub use chip::{ChipModel, ChipSeries};
 pub use communication::{CommunicationConfig, UsbInfo};
-pub use keyboard::Basic;
+pub use keyboard::{Basic, Bootmagic, parse_version};
 pub use keycode_alias::KEYCODE_ALIAS;
+pub use validate::{ConfigError, ConfigErrors};
 
 /// Keyboard constants configuration for performance and hardware limits
 #[serde_inline_default]
@@ -101,7 +105,9 @@ where
 {
     let value = SerdeDeserialize::deserialize(deserializer)?;
     if value > 256 {
-        panic!("❌ Parse `keyboard.toml` error: combo_max_num must be between 0 and 256, got {value}");
+        return Err(de::Error::custom(format!(
+            "combo_max_num must be between 0 and 256, got {value}"
+        )));
     }
     Ok(value)
 }
@@ -112,7 +118,9 @@ where
 {
     let value = SerdeDeserialize::deserialize(deserializer)?;
     if value > 256 {
-        panic!("❌ Parse `keyboard.toml` error: morse_max_num must be between 0 and 256, got {value}");
+        return Err(de::Error::custom(format!(
+            "morse_max_num must be between 0 and 256, got {value}"
+        )));
     }
     Ok(value)
 }
@@ -123,7 +131,9 @@ where
 {
     let value = SerdeDeserialize::deserialize(deserializer)?;
     if value < 4 || value > 65536 {
-        panic!("❌ Parse `keyboard.toml` error: max_patterns_per_key must be between 4 and 65566, got {value}");
+        return Err(de::Error::custom(format!(
+            "max_patterns_per_key must be between 4 and 65566, got {value}"
+        )));
     }
     Ok(value)
 }
@@ -134,7 +144,9 @@ where
 {
     let value = SerdeDeserialize::deserialize(deserializer)?;
     if value > 256 {
-        panic!("❌ Parse `keyboard.toml` error: fork_max_num must be between 0 and 256, got {value}");
+        return Err(de::Error::custom(format!(
+            "fork_max_num must be between 0 and 256, got {value}"
+        )));
     }
     Ok(value)
 }
@@ -198,6 +210,8 @@ pub struct KeyboardTomlConfig {
     input_device: Option<InputDeviceConfig>,
     /// Unlock keys for the keyboard
     pub security: Option<SecurityConfig>,
+    /// Bootloader config
+    bootloader: Option<BootloaderConfig>,
     /// RMK config constants
     #[serde(default)]
     pub rmk: RmkConstantsConfig,
@@ -205,28 +219,51 @@ pub struct KeyboardTomlConfig {
 
 impl KeyboardTomlConfig {
     pub fn new_from_toml_path<P: AsRef<Path>>(config_toml_path: P) -> Self {
+        Self::try_new_from_toml_path(config_toml_path).unwrap_or_else(|e| panic!("\n{}", e))
+    }
+
+    /// Load and validate `keyboard.toml`, all errors found in the file are returned with their locations
+    pub fn try_new_from_toml_path<P: AsRef<Path>>(config_toml_path: P) -> Result<Self, ConfigErrors> {
+        let path = config_toml_path.as_ref().to_string_lossy().to_string();
+        let source = std::fs::read_to_string(config_toml_path.as_ref()).map_err(|e| {
+            ConfigErrors::new(
+                &path,
+                "",
+                vec![ConfigError::new(
+                    format!("Read keyboard config file error: {}", e),
+                    None,
+                )],
+            )
+        })?;
+        let errors = |errors: Vec<ConfigError>| ConfigErrors::new(&path, &source, errors);
+
         // The first run, load chip model only
-        let user_config = match std::fs::read_to_string(config_toml_path.as_ref()) {
-            Ok(s) => match toml::from_str::<KeyboardTomlConfig>(&s) {
-                Ok(c) => c,
-                Err(e) => panic!("Parse {:?} error: {}", config_toml_path.as_ref(), e.message()),
-            },
-            Err(e) => panic!("Read keyboard config file {:?} error: {}", config_toml_path.as_ref(), e),
-        };
-        let default_config_str = user_config.get_chip_model().unwrap().get_default_config_str().unwrap();
+        let user_config = toml::from_str::<KeyboardTomlConfig>(&source)
+            .map_err(|e| errors(vec![ConfigError::new(e.message().to_string(), e.span())]))?;
+        if user_config.keyboard.is_none() {
+            return Err(errors(vec![ConfigError::new("[keyboard] section is required", None)]));
+        }
+        let default_config_str = user_config
+            .get_chip_model()
+            .and_then(|chip| chip.get_default_config_str())
+            .map_err(|e| errors(vec![ConfigError::new(e, validate::span_of(&source, "keyboard"))]))?;
 
         // The second run, load the user config and merge with the default config
         let mut config: KeyboardTomlConfig = Config::builder()
             .add_source(File::from_str(default_config_str, FileFormat::Toml))
-            .add_source(File::with_name(config_toml_path.as_ref().to_str().unwrap()))
+            .add_source(File::from_str(&source, FileFormat::Toml))
             .build()
-            .unwrap()
-            .try_deserialize()
-            .unwrap();
+            .and_then(|c| c.try_deserialize())
+            .map_err(|e| errors(vec![ConfigError::new(e.to_string(), None)]))?;
 
         config.auto_calculate_parameters();
 
-        config
+        let validation_errors = config.validate(&source);
+        if !validation_errors.is_empty() {
+            return Err(errors(validation_errors));
+        }
+
+        Ok(config)
     }
 
     /// Auto calculate some parameters in toml:
@@ -256,11 +293,6 @@ impl KeyboardTomlConfig {
                     let tap_actions_len = morse.tap_actions.as_ref().map(|v| v.len()).unwrap_or(0);
                     let hold_actions_len = morse.hold_actions.as_ref().map(|v| v.len()).unwrap_or(0);
 
-                    let n = tap_actions_len.max(hold_actions_len);
-                    if n > 15 {
-                        panic!("The number of taps per morse is too large, the max number of taps is 15, got {n}");
-                    }
-
                     let morse_actions_len = morse.morse_actions.as_ref().map(|v| v.len()).unwrap_or(0);
 
                     max_required_patterns =
@@ -284,6 +316,42 @@ pub struct LayoutTomlConfig {
     pub layers: u8,
     pub keymap: Option<Vec<Vec<Vec<String>>>>, // will be deprecated in the future
     pub matrix_map: Option<String>,            //temporarily allow both matrix_map and keymap to be set
+    // Physical layout, the Vial definition is generated from the toml if it's set
+    pub physical: Option<PhysicalLayoutConfig>,
+    // Keymap changes of layout options
+    pub option: Option<Vec<LayoutOptionTomlConfig>>,
+}
+
+/// Keymap changes of a layout option, which are applied when the choice is selected in Vial
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutOptionTomlConfig {
+    // Index of the layout option in `layout.physical.options`
+    pub option: u8,
+    // The choice which enables the changes, default is 1, which means enabled for toggle options
+    pub choice: Option<u8>,
+    // Pairs of `(row, col)` coordinates, pressing the first position triggers the key at the second position
+    pub remap: Option<String>,
+    // Matrix positions of `keys`, in the same format as `layout.matrix_map`
+    pub matrix_map: Option<String>,
+    // Default keys of each layer at the positions in `matrix_map`, in the same format as `layer.keys`
+    pub keys: Option<Vec<String>>,
+}
+
+/// Physical layout of the keyboard, which is used to generate the Vial definition
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicalLayoutConfig {
+    // Positions of keys in units of 1u, in the same order as `matrix_map`.
+    // Each key is `(x, y)`, `(x, y, w)`, `(x, y, w, h)` or `(x, y, w, h, option, choice)`.
+    // If not set, each line of `matrix_map` is used as a row of 1u keys
+    pub keys: Option<String>,
+    // Positions of encoders, `(x, y)` for each encoder.
+    // Each encoder takes two 1u keys, for counter-clockwise and clockwise rotation
+    pub encoders: Option<String>,
+    // Labels of layout options.
+    // A single label is a toggle option, multiple labels are the title and the choices of a select option
+    pub options: Option<Vec<Vec<String>>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -314,6 +382,10 @@ pub struct KeyboardInfo {
     pub chip: Option<String>,
     /// enable usb
     pub usb_enable: Option<bool>,
+    /// Bootmagic, hold these keys at power-up to enter the bootloader
+    pub bootloader_keys: Option<Vec<[u8; 2]>>,
+    /// Firmware version in `major.minor.patch`, if not set, the version of the firmware crate is used
+    pub version: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -322,6 +394,7 @@ pub enum MatrixType {
     #[default]
     normal,
     direct_pin,
+    analog,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -335,10 +408,54 @@ pub struct MatrixConfig {
     pub direct_pin_low_active: bool,
     #[serde(default = "default_false")]
     pub row2col: bool,
+    /// ADC pins of the analog matrix, each row is connected to one of them via a multiplexer
+    pub adc_pins: Option<Vec<String>>,
+    /// Select pins of the multiplexers of the analog matrix, the first pin is the least significant bit of the column
+    pub select_pins: Option<Vec<String>>,
+    /// Travel, calibration and key config of the analog matrix
+    pub analog: Option<AnalogMatrixConfig>,
+}
+
+/// Config of the analog matrix, all travel values are in 0.01mm
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalogMatrixConfig {
+    /// Total travel of the switch
+    pub total_travel: Option<u16>,
+    /// Estimated difference of raw values between the rest and bottom-out positions, negative if the value decreases when pressed
+    pub default_range: Option<i16>,
+    /// Minimal change of the bottom-out raw value which triggers saving the calibration
+    pub save_threshold: Option<u16>,
+    /// Time for the outputs of multiplexers to settle after switching the column, in microseconds
+    pub settle_time_us: Option<u32>,
+    /// Travel at which keys are pressed
+    pub actuation_point: Option<u16>,
+    /// Travel at which keys are released
+    pub release_point: Option<u16>,
+    pub rapid_trigger: Option<AnalogRapidTriggerConfig>,
+    pub dual_actions: Option<Vec<AnalogDualActionConfig>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalogRapidTriggerConfig {
+    pub press_sensitivity: u16,
+    pub release_sensitivity: u16,
+}
+
+/// Second action of a key, triggered when the key is pressed deeper than `actuation_point`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalogDualActionConfig {
+    /// Position of the key in the matrix
+    pub key: [u8; 2],
+    pub actuation_point: u16,
+    /// Position of the second action in the keymap
+    pub action_key: [u8; 2],
 }
 
 /// Config for storage
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct StorageConfig {
     /// Start address of local storage, MUST BE start of a sector.
@@ -350,6 +467,10 @@ pub struct StorageConfig {
     pub enabled: bool,
     // Clear on the storage at reboot, set this to true if you want to reset the keymap
     pub clear_storage: Option<bool>,
+    // Bootmagic, hold these keys at power-up to clear the storage
+    pub clear_storage_keys: Option<Vec<[u8; 2]>>,
+    // Bootmagic, hold these keys at power-up to clear BLE bonds
+    pub clear_bonds_keys: Option<Vec<[u8; 2]>>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
@@ -362,6 +483,14 @@ pub struct BleConfig {
     pub adc_divider_measured: Option<u32>,
     pub adc_divider_total: Option<u32>,
     pub default_tx_power: Option<i8>,
+    // Send the reports to both USB and BLE, when a BLE host is in use
+    pub output_mirror: Option<bool>,
+    // Interval of undirected advertising
+    pub advertising_interval: Option<DurationMillis>,
+    // Timeout of undirected advertising, the keyboard goes to sleep after it
+    pub advertising_timeout: Option<DurationMillis>,
+    // Reconnect to the last bonded host with directed advertising first
+    pub directed_advertising: Option<bool>,
 }
 
 /// Config for lights
@@ -381,6 +510,41 @@ pub struct PinConfig {
     pub low_active: bool,
 }
 
+/// Configurations for the bootloader
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootloaderConfig {
+    #[serde(rename = "type")]
+    pub bootloader_type: BootloaderType,
+    // Address of the STM32 system memory, required by `stm32_dfu`
+    pub system_memory: Option<u32>,
+    // Start address of the embassy-boot state partition, required by `embassy_boot`
+    pub state_start_addr: Option<u32>,
+    // Size of the embassy-boot state partition, required by `embassy_boot`
+    pub state_size: Option<u32>,
+    // Start address of the embassy-boot DFU partition, enables BLE DFU with `dfu_size` and `dfu_public_key`
+    pub dfu_start_addr: Option<u32>,
+    // Size of the embassy-boot DFU partition
+    pub dfu_size: Option<u32>,
+    // Path of the raw 32-byte Ed25519 public key which verifies the firmware images, relative to `Cargo.toml`
+    pub dfu_public_key: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootloaderType {
+    /// STM32 system memory DFU bootloader
+    Stm32Dfu,
+    /// USB boot mode in the ROM of RP2040
+    RpRom,
+    /// Adafruit nRF52 UF2 bootloader
+    AdafruitUf2,
+    /// ESP32 ROM download mode
+    Esp32Download,
+    /// embassy-boot with double-bank updates
+    EmbassyBoot,
+}
+
 /// Configurations for dependencies
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -404,6 +568,21 @@ pub struct LayoutConfig {
     pub cols: u8,
     pub layers: u8,
     pub keymap: Vec<Vec<Vec<String>>>,
+    // Number of bits of each layout option in the VIA layout options value
+    pub option_bits: Vec<u8>,
+    pub options: Vec<LayoutOptionConfig>,
+}
+
+/// Keymap changes of a layout option
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutOptionConfig {
+    pub option: u8,
+    pub choice: u8,
+    // Remapped matrix positions `((row, col), (to_row, to_col))`
+    pub remaps: Vec<((u8, u8), (u8, u8))>,
+    // Default keys `(layer, row, col, action)`
+    pub keys: Vec<(u8, u8, u8, String)>,
 }
 
 /// Configurations for actions behavior
@@ -465,7 +644,7 @@ pub struct ComboConfig {
 }
 
 /// Configurations for macros
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MacrosConfig {
     pub macros: Vec<MacroConfig>,
@@ -508,14 +687,14 @@ pub struct ForkConfig {
 }
 
 /// Configurations for morse keys
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MorsesConfig {
     pub morses: Vec<MorseConfig>,
 }
 
 /// Configurations for morse
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct MorseConfig {
     pub tap: Option<String>,
     pub hold: Option<String>,
@@ -566,6 +745,8 @@ pub struct SplitBoardConfig {
     pub ble_addr: Option<[u8; 6]>,
     /// Serial config, the vector length should be 1 for peripheral
     pub serial: Option<Vec<SerialConfig>>,
+    /// Address of the peripheral on the split bus, default is the index of the peripheral + 1
+    pub bus_addr: Option<u8>,
     /// Matrix config for the split
     pub matrix: MatrixConfig,
     /// Input device config for the split
@@ -578,6 +759,14 @@ pub struct SerialConfig {
     pub instance: String,
     pub tx_pin: String,
     pub rx_pin: String,
+    // Use a single wire for both directions, default is true if `tx_pin` and `rx_pin` are the same
+    pub half_duplex: Option<bool>,
+}
+
+impl SerialConfig {
+    pub fn is_half_duplex(&self) -> bool {
+        self.half_duplex.unwrap_or(self.tx_pin == self.rx_pin)
+    }
 }
 
 /// Duration in milliseconds
@@ -594,20 +783,23 @@ const fn default_false() -> bool {
 
 fn parse_duration_millis<'de, D: de::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
     let input: String = de::Deserialize::deserialize(deserializer)?;
+    parse_duration(&input).map_err(de::Error::custom)
+}
+
+/// Parse a duration string like `200ms` or `1s` into milliseconds
+pub(crate) fn parse_duration(input: &str) -> Result<u64, String> {
     let num = input.trim_end_matches(|c: char| !c.is_numeric());
     let unit = &input[num.len()..];
-    let num: u64 = num.parse().map_err(|_| {
-        de::Error::custom(format!(
-            "Invalid number \"{num}\" in duration: number part must be a u64"
-        ))
-    })?;
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("Invalid number \"{num}\" in duration: number part must be a u64"))?;
 
     match unit {
         "s" => Ok(num * 1000),
         "ms" => Ok(num),
-        other => Err(de::Error::custom(format!(
+        other => Err(format!(
             "Invalid duration unit \"{other}\": unit part must be either \"s\" or \"ms\""
-        ))),
+        )),
     }
 }
 
@@ -615,6 +807,8 @@ fn parse_duration_millis<'de, D: de::Deserializer<'de>>(deserializer: D) -> Resu
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct SecurityConfig {
     pub unlock_keys: Vec<[u8; 2]>,
+    /// Relock Vial if no VIA/Vial command is received within the timeout
+    pub lock_timeout: Option<DurationMillis>,
 }
 
 /// Configurations for input devices
@@ -643,6 +837,17 @@ pub struct JoystickConfig {
     pub transform: Vec<Vec<i16>>,
     pub bias: Vec<i16>,
     pub resolution: u16,
+    // Output mode of the joystick
+    // Available mode:
+    // - mouse: move the mouse cursor(default)
+    // - gamepad: report axes of the gamepad, requires the `gamepad` feature of RMK
+    // - scroll: scroll the mouse wheel
+    // - arrow_keys: press `keys` when the axis exceeds `threshold`
+    pub mode: Option<String>,
+    // Threshold of the `arrow_keys` mode
+    pub threshold: Option<i16>,
+    // (row, col) of the up, down, left and right keys in the keymap, required by the `arrow_keys` mode
+    pub keys: Option<[[u8; 2]; 4]>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -666,6 +871,30 @@ pub struct EncoderConfig {
     // Use MCU's internal pull-up resistor or not
     #[serde(default = "default_false")]
     pub internal_pullup: bool,
+    // Acceleration curve of the encoder, fast rotation repeats the detent
+    pub acceleration: Option<EncoderAccelerationConfig>,
+    // Output mode of the encoder
+    // Available mode:
+    // - key: press and release the encoder position for each detent(default)
+    // - hold: keep the encoder position pressed until the encoder is idle for `idle_timeout`
+    // - scroll: send mouse wheel movements, `scroll_axis` and `scroll_units` can be specified
+    pub mode: Option<String>,
+    // Idle timeout of the `hold` mode
+    pub idle_timeout: Option<DurationMillis>,
+    // Scroll axis of the `scroll` mode, "vertical"(default) or "horizontal"
+    pub scroll_axis: Option<String>,
+    // Scroll units per detent of the `scroll` mode, 120 units is a full notch
+    pub scroll_units: Option<i16>,
+}
+
+/// Acceleration config of rotary encoders
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderAccelerationConfig {
+    /// Detents closer than this interval are accelerated
+    pub threshold: DurationMillis,
+    /// Maximum number of events generated by one detent
+    pub max_multiplier: u8,
 }
 
 /// Pointing device config
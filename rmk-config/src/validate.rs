@@ -0,0 +1,1477 @@
+//! Validation of `keyboard.toml`.
+//!
+//! All errors in the config are collected with their spans in the file, so that they can be reported at once.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use pest::Parser;
+use toml_edit::{ImDocument, Item};
+
+use crate::layout::{ConfigParser, Rule};
+use crate::{
+    BootloaderType, ChipModel, ChipSeries, InputDeviceConfig, KEYCODE_ALIAS, KeyboardTomlConfig, MatrixConfig,
+    MatrixType, parse_duration,
+};
+
+/// The max number of taps of a morse key
+const MORSE_MAX_TAPS: usize = 15;
+
+/// An error in `keyboard.toml`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigError {
+    pub message: String,
+    /// Byte range in `keyboard.toml`
+    pub span: Option<Range<usize>>,
+}
+
+impl ConfigError {
+    pub fn new(message: impl Into<String>, span: Option<Range<usize>>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+/// All errors found in a `keyboard.toml`
+#[derive(Clone, Debug)]
+pub struct ConfigErrors {
+    path: String,
+    source: String,
+    errors: Vec<ConfigError>,
+}
+
+impl ConfigErrors {
+    pub fn new(path: &str, source: &str, errors: Vec<ConfigError>) -> Self {
+        Self {
+            path: path.to_string(),
+            source: source.to_string(),
+            errors,
+        }
+    }
+
+    pub fn errors(&self) -> &[ConfigError] {
+        &self.errors
+    }
+
+    /// Render each error with its location and the source line, in the style of rustc
+    pub fn messages(&self) -> Vec<String> {
+        self.errors.iter().map(|e| self.render(e)).collect()
+    }
+
+    fn render(&self, error: &ConfigError) -> String {
+        let Some(span) = error.span.clone().filter(|s| s.start <= self.source.len()) else {
+            return format!("{}: {}", self.path, error.message);
+        };
+        let line_start = self.source[..span.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.source[span.start..]
+            .find('\n')
+            .map(|i| i + span.start)
+            .unwrap_or(self.source.len());
+        let line = self.source[..span.start].matches('\n').count() + 1;
+        let col = self.source[line_start..span.start].chars().count() + 1;
+        let width = self.source[span.start..span.end.clamp(span.start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let line_number = line.to_string();
+        let padding = " ".repeat(line_number.len());
+        format!(
+            "{}\n{padding}--> {}:{}:{}\n{padding} |\n{} | {}\n{padding} | {}{}",
+            error.message,
+            self.path,
+            line,
+            col,
+            line_number,
+            self.source[line_start..line_end].trim_end_matches('\r'),
+            " ".repeat(col - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for message in self.messages() {
+            writeln!(f, "❌ Parse `keyboard.toml` error: {}\n", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the span of the item at `path` in `source`, the path is dot separated and numbers are array indices,
+/// such as `layer.0.keys`. If the item isn't found, the span of its nearest parent is returned.
+pub(crate) fn span_of(source: &str, path: &str) -> Option<Range<usize>> {
+    let doc = ImDocument::parse(source).ok()?;
+    find_span(doc.as_item(), path)
+}
+
+fn find_span(root: &Item, path: &str) -> Option<Range<usize>> {
+    let mut item = root;
+    let mut span = None;
+    for segment in path.split('.') {
+        let next = match segment.parse::<usize>() {
+            Ok(index) => item.get(index),
+            Err(_) => item.get(segment),
+        };
+        match next {
+            Some(next) => {
+                item = next;
+                span = item.span().or(span);
+            }
+            None => break,
+        }
+    }
+    span
+}
+
+/// Offset of the content of a string value in the source, assuming that there's no escape in the string
+fn string_content_start(source: &str, span: &Range<usize>) -> usize {
+    let raw = &source[span.clone()];
+    for delimiter in ["\"\"\"", "'''", "\"", "'"] {
+        if let Some(rest) = raw.strip_prefix(delimiter) {
+            let mut start = span.start + delimiter.len();
+            // A newline immediately following the opening delimiter of a multi-line string is trimmed
+            if delimiter.len() == 3 {
+                if rest.starts_with("\r\n") {
+                    start += 2;
+                } else if rest.starts_with('\n') {
+                    start += 1;
+                }
+            }
+            return start;
+        }
+    }
+    span.start
+}
+
+/// Split `layer.keys` into key actions with their byte ranges, whitespaces in parentheses are kept
+fn split_key_actions(keys: &str) -> Vec<(Range<usize>, &str)> {
+    let mut actions = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut in_text = false;
+    let mut chars = keys.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if in_text {
+            // Whitespaces and comments are part of the inline text macro
+            in_text = c != '"';
+            continue;
+        }
+        if c == '/' && depth == 0 && chars.peek().is_some_and(|(_, next)| *next == '/') {
+            // Skip the comment until the end of the line
+            if let Some(s) = start.take() {
+                actions.push((s..i, &keys[s..i]));
+            }
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            continue;
+        }
+        match c {
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(s) = start.take() {
+                    actions.push((s..i, &keys[s..i]));
+                }
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => depth = (depth - 1).max(0),
+            '"' => in_text = true,
+            _ => (),
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        actions.push((s..keys.len(), &keys[s..]));
+    }
+    actions
+}
+
+/// Check the pin name for the chip
+fn check_pin(chip: &ChipModel, pin: &str) -> Result<(), String> {
+    // Pin numbers without leading zeros
+    let number = |s: &str| {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) && (s.len() == 1 || !s.starts_with('0')) {
+            s.parse::<u8>().ok()
+        } else {
+            None
+        }
+    };
+    // Pin numbers of nRF are always 2 digits
+    let nrf_number = |s: &str| {
+        if s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse::<u8>().ok()
+        } else {
+            None
+        }
+    };
+    let valid = match chip.series {
+        ChipSeries::Nrf52 => {
+            pin.strip_prefix("P0_").and_then(nrf_number).is_some_and(|n| n < 32)
+                || (matches!(chip.chip.as_str(), "nrf52833" | "nrf52840")
+                    && pin.strip_prefix("P1_").and_then(nrf_number).is_some_and(|n| n < 16))
+        }
+        ChipSeries::Rp2040 => pin.strip_prefix("PIN_").and_then(number).is_some_and(|n| n < 30),
+        ChipSeries::Stm32 => {
+            let mut chars = pin.chars();
+            chars.next() == Some('P')
+                && chars.next().is_some_and(|port| ('A'..='K').contains(&port))
+                && number(chars.as_str()).is_some_and(|n| n < 16)
+        }
+        ChipSeries::Esp32 => pin.strip_prefix("GPIO").and_then(number).is_some_and(|n| n < 49),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid pin `{}` for {}", pin, chip.chip))
+    }
+}
+
+struct Validator<'a> {
+    config: &'a KeyboardTomlConfig,
+    source: &'a str,
+    doc: Option<ImDocument<&'a str>>,
+    errors: Vec<ConfigError>,
+}
+
+impl<'a> Validator<'a> {
+    fn span(&self, path: &str) -> Option<Range<usize>> {
+        self.doc.as_ref().and_then(|doc| find_span(doc.as_item(), path))
+    }
+
+    fn error(&mut self, message: impl Into<String>, path: &str) {
+        let span = self.span(path);
+        self.errors.push(ConfigError::new(message, span));
+    }
+
+    /// Report an error at the `range` of the string value at `path`
+    fn error_in_string(&mut self, message: impl Into<String>, path: &str, range: Range<usize>) {
+        let span = self.span(path).map(|span| {
+            let start = string_content_start(self.source, &span);
+            (start + range.start).min(span.end)..(start + range.end).min(span.end)
+        });
+        self.errors.push(ConfigError::new(message, span));
+    }
+
+    fn num_layers(&self) -> u8 {
+        self.config.layout.as_ref().map(|l| l.layers).unwrap_or_default()
+    }
+
+    /// Whether BLE is enabled, an invalid chip or communication config is reported by other checks
+    fn ble_enabled(&self) -> bool {
+        if self.config.get_chip_model().is_err() {
+            return true;
+        }
+        match self.config.get_communication_config() {
+            Ok(communication) => communication.ble_enabled(),
+            Err(_) => true,
+        }
+    }
+
+    /// Check a single key action, layer names should be resolved before
+    fn check_key_action(&self, action: &str) -> Result<(), String> {
+        let pairs =
+            ConfigParser::parse(Rule::key_map, action).map_err(|_| format!("Invalid key action `{}`", action))?;
+        let mut actions = pairs.flat_map(|p| p.into_inner()).filter(|p| p.as_rule() != Rule::EOI);
+        let (Some(pair), None) = (actions.next(), actions.next()) else {
+            return Err(format!("Expected a single key action, got `{}`", action));
+        };
+
+        let is_morse = pair.as_rule() == Rule::morse_action;
+        if pair.as_rule() == Rule::text_action && pair.as_str().len() <= 2 {
+            return Err("Inline text macro cannot be empty".to_string());
+        }
+        for inner in pair.into_inner().flatten() {
+            match inner.as_rule() {
+                Rule::keycode_name => {
+                    let Some(&keycode) = KEYCODE_ALIAS.get(inner.as_str().to_lowercase().as_str()) else {
+                        return Err(format!("Unknown keycode `{}`", inner.as_str()));
+                    };
+                    if matches!(keycode, "OutputAuto" | "OutputUsb" | "OutputBluetooth") && !self.ble_enabled() {
+                        return Err(format!("Keycode `{}` requires BLE to be enabled", inner.as_str()));
+                    }
+                }
+                Rule::layer_number => {
+                    let layer = inner.as_str().parse::<u8>().unwrap_or(u8::MAX);
+                    if layer >= self.num_layers() {
+                        return Err(format!(
+                            "Layer {} is out of range, the number of layers is {}",
+                            inner.as_str(),
+                            self.num_layers()
+                        ));
+                    }
+                }
+                Rule::layer_name => {
+                    return Err(format!(
+                        "Layer name `{}` can only be used in [[layer]], use the layer number instead",
+                        inner.as_str()
+                    ));
+                }
+                Rule::number => {
+                    let index = inner.as_str().parse::<u8>().map_err(|_| {
+                        format!(
+                            "Index {} in `{}` is out of range, the max is 255",
+                            inner.as_str(),
+                            action
+                        )
+                    })?;
+                    if is_morse && index as usize >= self.config.rmk.morse_max_num {
+                        return Err(format!(
+                            "Morse index {} is out of range, `morse_max_num` is {}",
+                            index, self.config.rmk.morse_max_num
+                        ));
+                    }
+                }
+                Rule::tap_count => {
+                    let taps = inner.as_str().parse::<usize>().unwrap_or(usize::MAX);
+                    if taps == 0 || taps > 15 {
+                        return Err(format!("Tap count in `{}` must be between 1 and 15", action));
+                    }
+                }
+                Rule::duration => {
+                    let timeout = parse_duration(inner.as_str()).unwrap_or(u64::MAX);
+                    if timeout > u16::MAX as u64 {
+                        return Err(format!(
+                            "Timeout in `{}` is out of range, the max is {}ms",
+                            action,
+                            u16::MAX
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn check_key_action_at(&mut self, action: &str, path: &str) {
+        if let Err(e) = self.check_key_action(action) {
+            self.error(e, path);
+        }
+    }
+
+    fn check_layers(&mut self) {
+        let config = self.config;
+        let Some(layout) = &config.layout else {
+            self.error("[layout] section is required", "layout");
+            return;
+        };
+        let num_errors = self.errors.len();
+        let aliases = config.aliases.clone().unwrap_or_default();
+        let layers = config.layer.clone().unwrap_or_default();
+        let layer_names: HashMap<String, u32> = layers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, layer)| layer.name.clone().map(|name| (name, i as u32)))
+            .collect();
+        let num_keys = layout
+            .matrix_map
+            .as_ref()
+            .and_then(|m| KeyboardTomlConfig::parse_matrix_map(m).ok())
+            .map(|coords| coords.len());
+
+        for (i, layer) in layers.iter().enumerate() {
+            let path = format!("layer.{}.keys", i);
+            let mut count = 0;
+            let mut parsed = true;
+            for (range, token) in split_key_actions(&layer.keys) {
+                match KeyboardTomlConfig::keymap_parser(token, &aliases, &layer_names) {
+                    Ok(actions) => {
+                        count += actions.len();
+                        for action in actions {
+                            if let Err(e) = self.check_key_action(&action) {
+                                self.error_in_string(e, &path, range.clone());
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        parsed = false;
+                        let message = if e.starts_with("Invalid keymap format") {
+                            format!("Invalid key action `{}`", token)
+                        } else {
+                            e
+                        };
+                        self.error_in_string(message, &path, range);
+                    }
+                }
+            }
+            if let Some(num_keys) = num_keys {
+                if parsed && count != num_keys {
+                    self.error(
+                        format!(
+                            "Layer #{} has {} keys, but {} keys are defined in `layout.matrix_map`",
+                            i, count, num_keys
+                        ),
+                        &path,
+                    );
+                }
+            }
+        }
+
+        // The deprecated `layout.keymap`
+        for (l, layer) in layout.keymap.iter().flatten().enumerate() {
+            for (r, row) in layer.iter().enumerate() {
+                for (c, key) in row.iter().enumerate() {
+                    self.check_key_action_at(key, &format!("layout.keymap.{}.{}.{}", l, r, c));
+                }
+            }
+        }
+
+        // Default keys of layout options
+        for (i, option) in layout.option.iter().flatten().enumerate() {
+            for (l, keys) in option.keys.iter().flatten().enumerate() {
+                let path = format!("layout.option.{}.keys.{}", i, l);
+                for (range, token) in split_key_actions(keys) {
+                    let result = KeyboardTomlConfig::keymap_parser(token, &aliases, &layer_names)
+                        .map_err(|_| format!("Invalid key action `{}`", token))
+                        .and_then(|actions| actions.iter().try_for_each(|action| self.check_key_action(action)));
+                    if let Err(e) = result {
+                        self.error_in_string(e, &path, range);
+                    }
+                }
+            }
+        }
+
+        // Other errors of the layout, such as an invalid `matrix_map`
+        if self.errors.len() == num_errors {
+            match config.get_layout_config_with_inline_behaviors() {
+                Ok((_, inline)) => {
+                    // Morses defined inline in the keymap count towards `morse_max_num` too
+                    let num_morses = config
+                        .behavior
+                        .as_ref()
+                        .and_then(|b| b.morse.as_ref())
+                        .map(|m| m.morses.len())
+                        .unwrap_or_default()
+                        + inline.morses.len();
+                    if !inline.morses.is_empty() && num_morses > config.rmk.morse_max_num {
+                        self.error(
+                            format!(
+                                "The number of morses is {}, including {} defined in the keymap, which exceeds `morse_max_num`({})",
+                                num_morses,
+                                inline.morses.len(),
+                                config.rmk.morse_max_num
+                            ),
+                            "layout",
+                        );
+                    }
+                }
+                Err(e) => self.error(e.trim_start_matches("keyboard.toml: "), "layout"),
+            }
+        }
+    }
+
+    fn check_behavior(&mut self) {
+        let config = self.config;
+        let Some(behavior) = &config.behavior else {
+            return;
+        };
+        let rmk = &config.rmk;
+
+        if let Some(tri_layer) = &behavior.tri_layer {
+            for (name, layer) in [
+                ("upper", tri_layer.upper),
+                ("lower", tri_layer.lower),
+                ("adjust", tri_layer.adjust),
+            ] {
+                if layer >= self.num_layers() {
+                    self.error(
+                        format!(
+                            "Layer {} is out of range, the number of layers is {}",
+                            layer,
+                            self.num_layers()
+                        ),
+                        &format!("behavior.tri_layer.{}", name),
+                    );
+                }
+            }
+        }
+
+        if let Some(combo) = &behavior.combo {
+            if combo.combos.len() > rmk.combo_max_num {
+                self.error(
+                    format!(
+                        "The number of combos is {}, which exceeds `combo_max_num`({})",
+                        combo.combos.len(),
+                        rmk.combo_max_num
+                    ),
+                    "behavior.combo.combos",
+                );
+            }
+            for (i, c) in combo.combos.iter().enumerate() {
+                let path = format!("behavior.combo.combos.{}", i);
+                if c.actions.len() > rmk.combo_max_length {
+                    self.error(
+                        format!(
+                            "The number of keys in combo is {}, which exceeds `combo_max_length`({})",
+                            c.actions.len(),
+                            rmk.combo_max_length
+                        ),
+                        &format!("{}.actions", path),
+                    );
+                }
+                for (j, action) in c.actions.iter().enumerate() {
+                    self.check_key_action_at(action, &format!("{}.actions.{}", path, j));
+                }
+                self.check_key_action_at(&c.output, &format!("{}.output", path));
+                if c.layer.is_some_and(|layer| layer >= self.num_layers()) {
+                    self.error(
+                        format!(
+                            "Layer {} is out of range, the number of layers is {}",
+                            c.layer.unwrap_or_default(),
+                            self.num_layers()
+                        ),
+                        &format!("{}.layer", path),
+                    );
+                }
+            }
+        }
+
+        if let Some(fork) = &behavior.fork {
+            if fork.forks.len() > rmk.fork_max_num {
+                self.error(
+                    format!(
+                        "The number of forks is {}, which exceeds `fork_max_num`({})",
+                        fork.forks.len(),
+                        rmk.fork_max_num
+                    ),
+                    "behavior.fork.forks",
+                );
+            }
+            for (i, f) in fork.forks.iter().enumerate() {
+                let path = format!("behavior.fork.forks.{}", i);
+                self.check_key_action_at(&f.trigger, &format!("{}.trigger", path));
+                self.check_key_action_at(&f.negative_output, &format!("{}.negative_output", path));
+                self.check_key_action_at(&f.positive_output, &format!("{}.positive_output", path));
+            }
+        }
+
+        if let Some(morse) = &behavior.morse {
+            if morse.morses.len() > 256 {
+                self.error(
+                    format!("The number of morses is {}, the max is 256", morse.morses.len()),
+                    "behavior.morse.morses",
+                );
+            }
+            for (i, m) in morse.morses.iter().enumerate() {
+                let path = format!("behavior.morse.morses.{}", i);
+                for (name, action) in [
+                    ("tap", &m.tap),
+                    ("hold", &m.hold),
+                    ("hold_after_tap", &m.hold_after_tap),
+                    ("double_tap", &m.double_tap),
+                ] {
+                    if let Some(action) = action {
+                        self.check_key_action_at(action, &format!("{}.{}", path, name));
+                    }
+                }
+                for (name, actions) in [("tap_actions", &m.tap_actions), ("hold_actions", &m.hold_actions)] {
+                    let Some(actions) = actions else {
+                        continue;
+                    };
+                    if actions.len() > MORSE_MAX_TAPS {
+                        self.error(
+                            format!(
+                                "The number of taps per morse is too large, the max number of taps is {}, got {}",
+                                MORSE_MAX_TAPS,
+                                actions.len()
+                            ),
+                            &format!("{}.{}", path, name),
+                        );
+                    }
+                    for (j, action) in actions.iter().enumerate() {
+                        self.check_key_action_at(action, &format!("{}.{}.{}", path, name, j));
+                    }
+                }
+                for (j, pair) in m.morse_actions.iter().flatten().enumerate() {
+                    self.check_key_action_at(&pair.action, &format!("{}.morse_actions.{}.action", path, j));
+                }
+            }
+        }
+    }
+
+    fn check_pins(&mut self) {
+        let config = self.config;
+        let Ok(chip) = config.get_chip_model() else {
+            return;
+        };
+        if let Some(matrix) = &config.matrix {
+            self.check_matrix_pins(&chip, matrix, "matrix");
+        }
+        if let Some(input_device) = &config.input_device {
+            self.check_input_device_pins(&chip, input_device, "input_device");
+        }
+        if let Some(split) = &config.split {
+            let boards = std::iter::once((split.central.clone(), "split.central".to_string())).chain(
+                split
+                    .peripheral
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (p.clone(), format!("split.peripheral.{}", i))),
+            );
+            for (board, path) in boards {
+                self.check_matrix_pins(&chip, &board.matrix, &format!("{}.matrix", path));
+                for (i, serial) in board.serial.iter().flatten().enumerate() {
+                    let serial_path = format!("{}.serial.{}", path, i);
+                    if !serial.is_half_duplex() {
+                        if serial.tx_pin == serial.rx_pin {
+                            self.error(
+                                format!(
+                                    "`tx_pin` and `rx_pin` are both {}, remove `half_duplex = false` to use the pin as a half-duplex serial",
+                                    serial.tx_pin
+                                ),
+                                &serial_path,
+                            );
+                        }
+                        continue;
+                    }
+                    // The half-duplex serial is generated for the PIO of RP2040, the USART of STM32 and the UARTE of nRF52
+                    match chip.series {
+                        ChipSeries::Rp2040 if !serial.instance.starts_with("PIO") => self.error(
+                            format!(
+                                "Half-duplex serial on RP2040 requires a PIO instance, but {} is given",
+                                serial.instance
+                            ),
+                            &serial_path,
+                        ),
+                        ChipSeries::Rp2040 | ChipSeries::Stm32 | ChipSeries::Nrf52 => {}
+                        _ => self.error(
+                            format!(
+                                "Half-duplex serial isn't supported on {:?} in keyboard.toml, wrap the serial port with `HalfDuplexSerial` in the Rust API instead",
+                                chip.series
+                            ),
+                            &serial_path,
+                        ),
+                    }
+                }
+                if let Some(input_device) = &board.input_device {
+                    self.check_input_device_pins(&chip, input_device, &format!("{}.input_device", path));
+                }
+            }
+        }
+    }
+
+    fn check_pin_at(&mut self, chip: &ChipModel, pin: &str, path: &str) {
+        if let Err(e) = check_pin(chip, pin) {
+            self.error(e, path);
+        }
+    }
+
+    fn check_matrix_pins(&mut self, chip: &ChipModel, matrix: &MatrixConfig, path: &str) {
+        for (name, pins) in [("input_pins", &matrix.input_pins), ("output_pins", &matrix.output_pins)] {
+            for (i, pin) in pins.iter().flatten().enumerate() {
+                self.check_pin_at(chip, pin, &format!("{}.{}.{}", path, name, i));
+            }
+        }
+        for (name, pins) in [("adc_pins", &matrix.adc_pins), ("select_pins", &matrix.select_pins)] {
+            for (i, pin) in pins.iter().flatten().enumerate() {
+                self.check_pin_at(chip, pin, &format!("{}.{}.{}", path, name, i));
+            }
+        }
+        for (row, pins) in matrix.direct_pins.iter().flatten().enumerate() {
+            for (col, pin) in pins.iter().enumerate() {
+                // `_` or `trns` means that there's no pin at this position
+                if pin != "_" && pin.to_lowercase() != "trns" {
+                    self.check_pin_at(chip, pin, &format!("{}.direct_pins.{}.{}", path, row, col));
+                }
+            }
+        }
+    }
+
+    fn check_analog_matrix(&mut self) {
+        let config = self.config;
+        if let Some(split) = &config.split {
+            let boards = std::iter::once((&split.central, "split.central".to_string())).chain(
+                split
+                    .peripheral
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (p, format!("split.peripheral.{}", i))),
+            );
+            for (board, path) in boards {
+                if matches!(board.matrix.matrix_type, MatrixType::analog) {
+                    self.error(
+                        "The analog matrix isn't supported on split keyboards in keyboard.toml, use the Rust API instead",
+                        &format!("{}.matrix.matrix_type", path),
+                    );
+                }
+            }
+        }
+        let Some(matrix) = &config.matrix else {
+            return;
+        };
+        if !matches!(matrix.matrix_type, MatrixType::analog) {
+            return;
+        }
+        // The analog source is generated for the SAADC of nRF52 only
+        if let Ok(chip) = config.get_chip_model()
+            && chip.series != ChipSeries::Nrf52
+        {
+            self.error(
+                format!(
+                    "The analog matrix isn't supported on {:?} in keyboard.toml, implement `AnalogSource` in the Rust API instead",
+                    chip.series
+                ),
+                "matrix.matrix_type",
+            );
+        }
+        // The SAADC samples all channels at once, so it can't be shared with other ADC devices
+        if config.ble.as_ref().is_some_and(|ble| ble.battery_adc_pin.is_some()) {
+            self.error(
+                "The analog matrix uses the SAADC, which can't be shared with `battery_adc_pin`",
+                "ble.battery_adc_pin",
+            );
+        }
+        if config
+            .input_device
+            .as_ref()
+            .is_some_and(|d| d.joystick.as_ref().is_some_and(|j| !j.is_empty()))
+        {
+            self.error(
+                "The analog matrix uses the SAADC, which can't be shared with joysticks",
+                "input_device.joystick",
+            );
+        }
+        if let (Some(layout), Some(adc_pins)) = (&config.layout, &matrix.adc_pins) {
+            if adc_pins.len() != layout.rows as usize {
+                self.error(
+                    format!(
+                        "The analog matrix has {} rows, but {} `adc_pins` are given",
+                        layout.rows,
+                        adc_pins.len()
+                    ),
+                    "matrix.adc_pins",
+                );
+            } else if adc_pins.len() > 8 {
+                self.error("The SAADC has at most 8 channels", "matrix.adc_pins");
+            }
+        }
+        if let (Some(layout), Some(select_pins)) = (&config.layout, &matrix.select_pins)
+            && (layout.cols as usize) > 1usize.checked_shl(select_pins.len() as u32).unwrap_or(usize::MAX)
+        {
+            self.error(
+                format!(
+                    "{} `select_pins` can select at most {} columns, but the analog matrix has {} columns",
+                    select_pins.len(),
+                    1usize << select_pins.len(),
+                    layout.cols
+                ),
+                "matrix.select_pins",
+            );
+        }
+        let Some(analog) = &matrix.analog else {
+            return;
+        };
+        if analog.default_range == Some(0) {
+            self.error("`default_range` must not be 0", "matrix.analog.default_range");
+        }
+        // Compare with the default points of `AnalogKeyConfig` when one of them is not set
+        let actuation_point = analog.actuation_point.unwrap_or(200);
+        let release_point = analog.release_point.unwrap_or(150);
+        if release_point >= actuation_point {
+            self.error(
+                format!(
+                    "`release_point` {} should be less than `actuation_point` {}",
+                    release_point, actuation_point
+                ),
+                "matrix.analog",
+            );
+        }
+        for (i, dual_action) in analog.dual_actions.iter().flatten().enumerate() {
+            let path = format!("matrix.analog.dual_actions.{}", i);
+            let [row, col] = dual_action.key;
+            self.check_key_pos(row, col, &format!("{}.key", path));
+            let [row, col] = dual_action.action_key;
+            self.check_key_pos(row, col, &format!("{}.action_key", path));
+            if dual_action.actuation_point <= actuation_point {
+                self.error(
+                    format!(
+                        "The actuation point of the second action should be deeper than `actuation_point` {}",
+                        actuation_point
+                    ),
+                    &format!("{}.actuation_point", path),
+                );
+            }
+        }
+    }
+
+    fn check_bootloader(&mut self) {
+        let Some(bootloader) = self.config.get_bootloader_config() else {
+            return;
+        };
+        let Ok(chip) = self.config.get_chip_model() else {
+            return;
+        };
+        let supported = match bootloader.bootloader_type {
+            BootloaderType::Stm32Dfu => chip.series == ChipSeries::Stm32,
+            BootloaderType::RpRom => chip.series == ChipSeries::Rp2040,
+            BootloaderType::AdafruitUf2 => chip.series == ChipSeries::Nrf52,
+            BootloaderType::Esp32Download => chip.series == ChipSeries::Esp32,
+            // The state partition is written by the blocking flash of STM32, or the flash of MPSL on nRF52
+            BootloaderType::EmbassyBoot => matches!(chip.series, ChipSeries::Stm32 | ChipSeries::Nrf52),
+        };
+        if !supported {
+            self.error(
+                format!(
+                    "Bootloader {:?} is not supported on {}",
+                    bootloader.bootloader_type, chip.chip
+                ),
+                "bootloader.type",
+            );
+        }
+        match bootloader.bootloader_type {
+            BootloaderType::Stm32Dfu if bootloader.system_memory.is_none() => {
+                self.error("`system_memory` is required by the stm32_dfu bootloader", "bootloader")
+            }
+            BootloaderType::EmbassyBoot if bootloader.state_start_addr.is_none() || bootloader.state_size.is_none() => {
+                self.error(
+                    "`state_start_addr` and `state_size` are required by the embassy_boot bootloader",
+                    "bootloader",
+                )
+            }
+            // The flash is accessed through MPSL, which is initialized with BLE
+            BootloaderType::EmbassyBoot if chip.series == ChipSeries::Nrf52 && !self.ble_enabled() => {
+                self.error("The embassy_boot bootloader requires BLE on nRF52", "bootloader.type")
+            }
+            _ => (),
+        }
+        // BLE DFU writes the DFU partition of embassy-boot
+        let dfu_fields = [
+            (bootloader.dfu_start_addr.is_some(), "bootloader.dfu_start_addr"),
+            (bootloader.dfu_size.is_some(), "bootloader.dfu_size"),
+            (bootloader.dfu_public_key.is_some(), "bootloader.dfu_public_key"),
+        ];
+        if let Some(&(_, path)) = dfu_fields.iter().find(|(set, _)| *set) {
+            if bootloader.bootloader_type != BootloaderType::EmbassyBoot {
+                self.error("BLE DFU requires the embassy_boot bootloader", path);
+            } else if dfu_fields.iter().any(|(set, _)| !set) {
+                self.error(
+                    "`dfu_start_addr`, `dfu_size` and `dfu_public_key` are all required by BLE DFU",
+                    path,
+                );
+            }
+        }
+    }
+
+    /// Check that the key at (row, col) is in the matrix
+    fn check_key_pos(&mut self, row: u8, col: u8, path: &str) {
+        let Some(layout) = &self.config.layout else {
+            return;
+        };
+        let (rows, cols) = (layout.rows, layout.cols);
+        if row >= rows || col >= cols {
+            self.error(
+                format!("Key ({}, {}) is out of the {}x{} matrix", row, col, rows, cols),
+                path,
+            );
+        }
+    }
+
+    fn check_bootmagic(&mut self) {
+        let bootmagic = self.config.get_bootmagic();
+        for (keys, path) in [
+            (&bootmagic.bootloader_keys, "keyboard.bootloader_keys"),
+            (&bootmagic.clear_storage_keys, "storage.clear_storage_keys"),
+            (&bootmagic.clear_bonds_keys, "storage.clear_bonds_keys"),
+        ] {
+            for (i, [row, col]) in keys.iter().enumerate() {
+                self.check_key_pos(*row, *col, &format!("{}.{}", path, i));
+            }
+            // Each half of a split keyboard checks its own keys, so a combination must be on a single half
+            if let Some(split) = &self.config.split {
+                let boards: Vec<_> = std::iter::once(&split.central).chain(split.peripheral.iter()).collect();
+                let board_of = |&[row, col]: &[u8; 2]| {
+                    let (row, col) = (row as usize, col as usize);
+                    boards.iter().position(|b| {
+                        (b.row_offset..b.row_offset + b.rows).contains(&row)
+                            && (b.col_offset..b.col_offset + b.cols).contains(&col)
+                    })
+                };
+                let mut halves: Vec<_> = keys.iter().filter_map(board_of).collect();
+                halves.dedup();
+                if halves.len() > 1 {
+                    self.error(
+                        "The keys of a bootmagic combination must be on the same split half",
+                        path,
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_split_bus(&mut self) {
+        let Some(split) = &self.config.split else {
+            return;
+        };
+        if split.connection != "serial_bus" {
+            return;
+        }
+        let mut addrs: Vec<u8> = Vec::new();
+        for (i, peripheral) in split.peripheral.iter().enumerate() {
+            let path = match peripheral.bus_addr {
+                Some(_) => format!("split.peripheral.{}.bus_addr", i),
+                None => format!("split.peripheral.{}", i),
+            };
+            let addr = peripheral.bus_addr.unwrap_or(i as u8 + 1);
+            // The highest bit of the address marks the responses on the bus
+            if addr >= 0x80 {
+                self.error(format!("Split bus address {} must be less than 128", addr), &path);
+            } else if addrs.contains(&addr) {
+                self.error(
+                    format!("Split bus address {} is used by another peripheral", addr),
+                    &path,
+                );
+            }
+            addrs.push(addr);
+        }
+    }
+
+    fn check_version(&mut self) {
+        match self.config.get_firmware_version() {
+            Some(Err(e)) => self.error(e, "keyboard.version"),
+            // VIA reports the major and minor versions in 8 bits
+            Some(Ok([major, minor, _])) if major > 255 || minor > 255 => self.error(
+                "The major and minor versions should be up to 255, which are reported by VIA in 8 bits",
+                "keyboard.version",
+            ),
+            _ => (),
+        }
+    }
+
+    fn check_input_device_pins(&mut self, chip: &ChipModel, input_device: &InputDeviceConfig, path: &str) {
+        for (i, encoder) in input_device.encoder.iter().flatten().enumerate() {
+            self.check_pin_at(chip, &encoder.pin_a, &format!("{}.encoder.{}.pin_a", path, i));
+            self.check_pin_at(chip, &encoder.pin_b, &format!("{}.encoder.{}.pin_b", path, i));
+        }
+    }
+
+    fn check_input_devices(&mut self) {
+        let config = self.config;
+        let boards = config.split.iter().flat_map(|split| {
+            std::iter::once((&split.central, "split.central".to_string())).chain(
+                split
+                    .peripheral
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (p, format!("split.peripheral.{}", i))),
+            )
+        });
+        let input_devices = config
+            .input_device
+            .iter()
+            .map(|input_device| (input_device, "input_device".to_string()))
+            .chain(boards.filter_map(|(board, path)| {
+                board
+                    .input_device
+                    .as_ref()
+                    .map(|input_device| (input_device, format!("{}.input_device", path)))
+            }));
+        for (input_device, path) in input_devices {
+            for (i, joystick) in input_device.joystick.iter().flatten().enumerate() {
+                for (j, [row, col]) in joystick.keys.iter().flatten().enumerate() {
+                    self.check_key_pos(*row, *col, &format!("{}.joystick.{}.keys.{}", path, i, j));
+                }
+            }
+        }
+    }
+}
+
+impl KeyboardTomlConfig {
+    /// Validate the config, `source` is the content of `keyboard.toml`, which is used to locate errors.
+    ///
+    /// All found errors are returned, an empty vector means the config is valid.
+    pub fn validate(&self, source: &str) -> Vec<ConfigError> {
+        let mut validator = Validator {
+            config: self,
+            source,
+            doc: ImDocument::parse(source).ok(),
+            errors: Vec::new(),
+        };
+        if self.keyboard.is_none() {
+            validator.error("[keyboard] section is required", "keyboard");
+            return validator.errors;
+        }
+        if let Err(e) = self.get_chip_model() {
+            validator.error(e, "keyboard");
+        }
+        validator.check_layers();
+        validator.check_behavior();
+        validator.check_pins();
+        validator.check_analog_matrix();
+        validator.check_input_devices();
+        validator.check_bootloader();
+        validator.check_bootmagic();
+        validator.check_split_bus();
+        validator.check_version();
+        validator.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYBOARD: &str = r#"[keyboard]
+name = "Test Keyboard"
+vendor_id = 0x4c4b
+product_id = 0x4643
+chip = "nrf52840"
+
+[layout]
+rows = 1
+cols = 2
+layers = 2
+matrix_map = "(0,0) (0,1)"
+"#;
+
+    fn validate(toml: &str) -> (String, Vec<ConfigError>) {
+        let source = format!("{KEYBOARD}{toml}");
+        let config: KeyboardTomlConfig = toml::from_str(&source).unwrap();
+        let errors = config.validate(&source);
+        (source, errors)
+    }
+
+    /// (name, toml, part of the error message, source text at the error span)
+    const CASES: &[(&str, &str, &str, &str)] = &[
+        (
+            "unknown keycode",
+            "[[layer]]\nkeys = \"A Foo\"",
+            "Unknown keycode `Foo`",
+            "Foo",
+        ),
+        (
+            "unknown keycode in action",
+            "[[layer]]\nkeys = \"LT(1, Foo) A\"",
+            "Unknown keycode `Foo`",
+            "LT(1, Foo)",
+        ),
+        (
+            "unknown keycode in multi-line string",
+            "[[layer]]\nkeys = \"\"\"\nA\n  Foo // comment\n\"\"\"",
+            "Unknown keycode `Foo`",
+            "Foo",
+        ),
+        (
+            "layer out of range",
+            "[[layer]]\nkeys = \"MO(2) A\"",
+            "Layer 2 is out of range",
+            "MO(2)",
+        ),
+        (
+            "undefined layer name",
+            "[[layer]]\nname = \"base\"\nkeys = \"MO(nav) A\"",
+            "Invalid layer name: nav",
+            "MO(nav)",
+        ),
+        (
+            "undefined alias",
+            "[[layer]]\nkeys = \"@copy A\"",
+            "Undefined alias: copy",
+            "@copy",
+        ),
+        (
+            "invalid key action",
+            "[[layer]]\nkeys = \"A WM(B)\"",
+            "Invalid key action `WM(B)`",
+            "WM(B)",
+        ),
+        (
+            "morse index out of range",
+            "[[layer]]\nkeys = \"A TD(8)\"",
+            "Morse index 8 is out of range",
+            "TD(8)",
+        ),
+        (
+            "tap count out of range",
+            "[[layer]]\nkeys = \"A TT(1, 16)\"",
+            "Tap count in `TT(1, 16)` must be between 1 and 15",
+            "TT(1, 16)",
+        ),
+        (
+            "empty inline text macro",
+            "[[layer]]\nkeys = 'A \"\"'",
+            "Inline text macro cannot be empty",
+            "\"\"",
+        ),
+        (
+            "too few keys in layer",
+            "[[layer]]\nkeys = \"A\"",
+            "Layer #0 has 1 keys, but 2 keys",
+            "\"A\"",
+        ),
+        (
+            "too many keys in layer",
+            "[[layer]]\nkeys = \"A B C\"",
+            "Layer #0 has 3 keys, but 2 keys",
+            "\"A B C\"",
+        ),
+        (
+            "unknown keycode in layout.keymap",
+            "keymap = [[[\"A\", \"Foo\"]]]",
+            "Unknown keycode `Foo`",
+            "\"Foo\"",
+        ),
+        (
+            "invalid nrf pin",
+            "[matrix]\ninput_pins = [\"P0_02\", \"P0_2\"]\noutput_pins = [\"P0_03\"]",
+            "Invalid pin `P0_2` for nrf52840",
+            "\"P0_2\"",
+        ),
+        (
+            "invalid direct pin",
+            "[matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\", \"_\"], [\"PIN_3\", \"P1_01\"]]",
+            "Invalid pin `PIN_3` for nrf52840",
+            "\"PIN_3\"",
+        ),
+        (
+            "invalid encoder pin",
+            "[[input_device.encoder]]\npin_a = \"P0_02\"\npin_b = \"P2_01\"",
+            "Invalid pin `P2_01` for nrf52840",
+            "\"P2_01\"",
+        ),
+        (
+            "too many keys in combo",
+            "[behavior.combo]\ncombos = [{ actions = [\"A\", \"B\", \"C\", \"D\", \"E\"], output = \"F\" }]",
+            "exceeds `combo_max_length`(4)",
+            "[\"A\", \"B\", \"C\", \"D\", \"E\"]",
+        ),
+        (
+            "unknown keycode in combo",
+            "[behavior.combo]\ncombos = [{ actions = [\"A\", \"B\"], output = \"Foo\" }]",
+            "Unknown keycode `Foo`",
+            "\"Foo\"",
+        ),
+        (
+            "layer name in combo",
+            "[behavior.combo]\ncombos = [{ actions = [\"A\", \"B\"], output = \"MO(nav)\" }]",
+            "Layer name `nav` can only be used in [[layer]]",
+            "\"MO(nav)\"",
+        ),
+        (
+            "too many taps in morse",
+            "[behavior.morse]\nmorses = [{ tap_actions = [\"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\"] }]",
+            "the max number of taps is 15, got 16",
+            "[\"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\", \"A\"]",
+        ),
+        (
+            "unknown keycode in fork",
+            "[behavior.fork]\nforks = [{ trigger = \"Dot\", negative_output = \"Dot\", positive_output = \"Foo\" }]",
+            "Unknown keycode `Foo`",
+            "\"Foo\"",
+        ),
+        (
+            "tri layer out of range",
+            "[behavior.tri_layer]\nupper = 1\nlower = 1\nadjust = 2",
+            "Layer 2 is out of range",
+            "2",
+        ),
+        (
+            "unsupported bootloader",
+            "[bootloader]\ntype = \"stm32_dfu\"\nsystem_memory = 0x1FFF0000",
+            "Bootloader Stm32Dfu is not supported on nrf52840",
+            "\"stm32_dfu\"",
+        ),
+        (
+            "incomplete ble dfu",
+            "[bootloader]\ntype = \"embassy_boot\"\nstate_start_addr = 0x6000\nstate_size = 0x1000\ndfu_start_addr = 0x7C000",
+            "`dfu_start_addr`, `dfu_size` and `dfu_public_key` are all required by BLE DFU",
+            "0x7C000",
+        ),
+        (
+            "ble dfu without embassy_boot",
+            "[bootloader]\ntype = \"adafruit_uf2\"\ndfu_public_key = \"dfu.pub\"",
+            "BLE DFU requires the embassy_boot bootloader",
+            "\"dfu.pub\"",
+        ),
+        (
+            "bootmagic key out of range",
+            "[storage]\nclear_storage_keys = [[0, 0], [1, 0]]",
+            "Key (1, 0) is out of the 1x2 matrix",
+            "[1, 0]",
+        ),
+        (
+            "joystick key out of range",
+            "[[input_device.joystick]]\nname = \"joystick\"\npin_x = \"P0_02\"\npin_y = \"P0_03\"\npin_z = \"_\"\ntransform = [[1, 0], [0, 1]]\nbias = [0, 0]\nresolution = 1\nmode = \"arrow_keys\"\nkeys = [[0, 0], [0, 1], [0, 2], [0, 1]]",
+            "Key (0, 2) is out of the 1x2 matrix",
+            "[0, 2]",
+        ),
+        (
+            "full-duplex serial on a single pin",
+            "[split]\nconnection = \"serial\"\nperipheral = []\n[split.central]\nrows = 1\ncols = 2\nrow_offset = 0\ncol_offset = 0\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_06\", half_duplex = false }]\n[split.central.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\", \"P0_03\"]]",
+            "`tx_pin` and `rx_pin` are both P0_06",
+            "{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_06\", half_duplex = false }",
+        ),
+        (
+            "split bus address out of range",
+            "[split]\nconnection = \"serial_bus\"\n[split.central]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 0\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.central.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]\n[[split.peripheral]]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 1\nbus_addr = 200\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.peripheral.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]",
+            "Split bus address 200 must be less than 128",
+            "200",
+        ),
+        (
+            "duplicated split bus address",
+            "[split]\nconnection = \"serial_bus\"\n[split.central]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 0\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.central.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]\n[[split.peripheral]]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 1\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.peripheral.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]\n[[split.peripheral]]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 1\nbus_addr = 1\nserial = [{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.peripheral.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]",
+            "Split bus address 1 is used by another peripheral",
+            "1",
+        ),
+        (
+            "analog matrix adc pins",
+            "[matrix]\nmatrix_type = \"analog\"\nadc_pins = [\"P0_02\", \"P0_03\"]\nselect_pins = [\"P0_06\"]",
+            "The analog matrix has 1 rows, but 2 `adc_pins` are given",
+            "[\"P0_02\", \"P0_03\"]",
+        ),
+        (
+            "analog matrix select pins",
+            "[matrix]\nmatrix_type = \"analog\"\nadc_pins = [\"P0_02\"]\nselect_pins = []",
+            "0 `select_pins` can select at most 1 columns, but the analog matrix has 2 columns",
+            "[]",
+        ),
+        (
+            "analog matrix with battery adc",
+            "[matrix]\nmatrix_type = \"analog\"\nadc_pins = [\"P0_02\"]\nselect_pins = [\"P0_06\"]\n[ble]\nenabled = true\nbattery_adc_pin = \"P0_04\"",
+            "can't be shared with `battery_adc_pin`",
+            "\"P0_04\"",
+        ),
+        (
+            "analog dual action out of range",
+            "[matrix]\nmatrix_type = \"analog\"\nadc_pins = [\"P0_02\"]\nselect_pins = [\"P0_06\"]\n[matrix.analog]\ndual_actions = [{ key = [0, 0], actuation_point = 350, action_key = [1, 0] }]",
+            "Key (1, 0) is out of the 1x2 matrix",
+            "[1, 0]",
+        ),
+        (
+            "bootmagic across split halves",
+            "[storage]\nclear_storage_keys = [[0, 0], [0, 1]]\n[split]\nconnection = \"serial\"\n[split.central]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 0\nserial = [{ instance = \"UART0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.central.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]\n[[split.peripheral]]\nrows = 1\ncols = 1\nrow_offset = 0\ncol_offset = 1\nserial = [{ instance = \"UART0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }]\n[split.peripheral.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\"]]",
+            "The keys of a bootmagic combination must be on the same split half",
+            "[[0, 0], [0, 1]]",
+        ),
+    ];
+
+    #[test]
+    fn test_validate_errors() {
+        for (name, toml, message, text) in CASES {
+            let (source, errors) = validate(toml);
+            assert_eq!(errors.len(), 1, "{}: {:?}", name, errors);
+            assert!(
+                errors[0].message.contains(message),
+                "{}: unexpected message `{}`",
+                name,
+                errors[0].message
+            );
+            let span = errors[0].span.clone().unwrap_or_else(|| panic!("{}: no span", name));
+            assert_eq!(&source[span], *text, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_version() {
+        let with_version = |version: &str| {
+            let source = KEYBOARD.replacen("chip =", &format!("version = \"{version}\"\nchip ="), 1);
+            let config: KeyboardTomlConfig = toml::from_str(&source).unwrap();
+            (config.get_firmware_version(), config.validate(&source))
+        };
+        let (version, errors) = with_version("1.20.3-beta.1");
+        assert_eq!(version, Some(Ok([1, 20, 3])));
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        for invalid in ["1.2", "1.2.3.4", "1.x.3", "70000.0.0"] {
+            let (_, errors) = with_version(invalid);
+            assert_eq!(errors.len(), 1, "{}: {:?}", invalid, errors);
+            assert!(errors[0].message.contains("Invalid version"), "{}", invalid);
+        }
+
+        for too_large in ["256.0.0", "1.300.0"] {
+            let (_, errors) = with_version(too_large);
+            assert_eq!(errors.len(), 1, "{}: {:?}", too_large, errors);
+            assert!(errors[0].message.contains("up to 255"), "{}", too_large);
+        }
+        let (_, errors) = with_version("255.255.65535");
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_validate_valid_config() {
+        let (_, errors) = validate(
+            r#"
+            [aliases]
+            copy = "WM(C, LCtrl)"
+
+            [[layer]]
+            name = "base"
+            keys = "@copy MO(nav)"
+
+            [[layer]]
+            name = "nav"
+            keys = """
+            // Comments are allowed
+            MT(A, LShift) TO(base)
+            """
+
+            [matrix]
+            input_pins = ["P0_02", "P1_15"]
+            output_pins = ["P0_31"]
+
+            [behavior.combo]
+            combos = [{ actions = ["A", "User0"], output = "TD(0)", layer = 1 }]
+
+            [bootloader]
+            type = "adafruit_uf2"
+
+            [storage]
+            clear_bonds_keys = [[0, 0], [0, 1]]
+            "#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_validate_half_duplex_serial() {
+        let split = |serial: &str| {
+            format!(
+                "[split]\nconnection = \"serial\"\nperipheral = []\n[split.central]\nrows = 1\ncols = 2\nrow_offset = 0\ncol_offset = 0\nserial = [{serial}]\n[split.central.matrix]\nmatrix_type = \"direct_pin\"\ndirect_pins = [[\"P0_02\", \"P0_03\"]]"
+            )
+        };
+        for serial in [
+            "{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_06\" }",
+            "{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\", half_duplex = true }",
+            "{ instance = \"UARTE0\", tx_pin = \"P0_06\", rx_pin = \"P0_08\" }",
+        ] {
+            let (_, errors) = validate(&split(serial));
+            assert!(errors.is_empty(), "{}: {:?}", serial, errors);
+        }
+    }
+
+    #[test]
+    fn test_validate_output_keys() {
+        let usb_only = r#"[keyboard]
+name = "Test Keyboard"
+vendor_id = 0x4c4b
+product_id = 0x4643
+chip = "rp2040"
+usb_enable = true
+
+[layout]
+rows = 1
+cols = 2
+layers = 1
+matrix_map = "(0,0) (0,1)"
+
+[[layer]]
+keys = "OutputUsb A"
+"#;
+        let config: KeyboardTomlConfig = toml::from_str(usb_only).unwrap();
+        let errors = config.validate(usb_only);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("Keycode `OutputUsb` requires BLE"));
+
+        let (_, errors) = validate("[ble]\nenabled = true\n\n[[layer]]\nkeys = \"OutputUsb OutputAuto\"");
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_validate_collects_all_errors() {
+        let (_, errors) =
+            validate("[[layer]]\nkeys = \"Foo MO(5)\"\n[matrix]\ninput_pins = [\"P0_32\"]\noutput_pins = []");
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Unknown keycode `Foo`",
+                "Layer 5 is out of range, the number of layers is 2",
+                "Invalid pin `P0_32` for nrf52840",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_inline_morses() {
+        let (_, errors) = validate("[rmk]\nmorse_max_num = 1\n[[layer]]\nkeys = \"TT(1, 2) MT(A, LShift, 200ms)\"");
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0]
+                .message
+                .contains("The number of morses is 2, including 2 defined in the keymap")
+        );
+
+        // Identical inline actions share the same morse
+        let (_, errors) = validate("[rmk]\nmorse_max_num = 1\n[[layer]]\nkeys = \"TT(1, 2) TT(1, 2)\"");
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_validate_layout_options() {
+        let options = "[layout.physical]\noptions = [[\"Split Space\"]]\n";
+        let (_, errors) = validate(&format!(
+            "{}[[layout.option]]\noption = 0\nremap = \"(0,1) (0,0)\"\nmatrix_map = \"(0,0)\"\nkeys = [\"B\"]",
+            options
+        ));
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let (_, errors) = validate(&format!(
+            "{}[[layout.option]]\noption = 0\nmatrix_map = \"(0,0)\"\nkeys = [\"Foo\"]",
+            options
+        ));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].message.contains("Unknown keycode `Foo`"));
+
+        let (_, errors) = validate(&format!("{}[[layout.option]]\noption = 1", options));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0]
+                .message
+                .contains("Layout option 1 in `layout.option` is not defined")
+        );
+    }
+
+    #[test]
+    fn test_check_pin() {
+        let chip = |series, chip: &str| ChipModel {
+            series,
+            chip: chip.to_string(),
+            board: None,
+        };
+        let cases = [
+            (chip(ChipSeries::Nrf52, "nrf52840"), "P1_10", true),
+            (chip(ChipSeries::Nrf52, "nrf52832"), "P1_10", false),
+            (chip(ChipSeries::Nrf52, "nrf52840"), "P0_7", false),
+            (chip(ChipSeries::Rp2040, "rp2040"), "PIN_29", true),
+            (chip(ChipSeries::Rp2040, "rp2040"), "PIN_30", false),
+            (chip(ChipSeries::Rp2040, "rp2040"), "PIN_01", false),
+            (chip(ChipSeries::Stm32, "stm32h7b0vb"), "PE15", true),
+            (chip(ChipSeries::Stm32, "stm32h7b0vb"), "PZ1", false),
+            (chip(ChipSeries::Stm32, "stm32h7b0vb"), "PA16", false),
+            (chip(ChipSeries::Esp32, "esp32c3"), "GPIO21", true),
+            (chip(ChipSeries::Esp32, "esp32c3"), "P0_01", false),
+        ];
+        for (chip, pin, valid) in cases {
+            assert_eq!(check_pin(&chip, pin).is_ok(), valid, "{} on {}", pin, chip.chip);
+        }
+    }
+
+    #[test]
+    fn test_render_error() {
+        let source = "[keyboard]\nname = \"x\"\n\n[[layer]]\nkeys = \"A Foo\"\n";
+        let errors = ConfigErrors::new(
+            "keyboard.toml",
+            source,
+            vec![ConfigError::new("Unknown keycode `Foo`", Some(43..46))],
+        );
+        assert_eq!(
+            errors.messages(),
+            vec!["Unknown keycode `Foo`\n --> keyboard.toml:5:11\n  |\n5 | keys = \"A Foo\"\n  |           ^^^"]
+        );
+    }
+
+    #[test]
+    fn test_split_key_actions() {
+        let keys = "A  LT(1, Space)\n// B C\nWM(X, LShift | RGui) @copy";
+        let actions: Vec<_> = split_key_actions(keys).into_iter().map(|(_, a)| a).collect();
+        assert_eq!(actions, vec!["A", "LT(1, Space)", "WM(X, LShift | RGui)", "@copy"]);
+    }
+
+    #[test]
+    fn test_split_key_actions_with_text() {
+        let keys = "A \"hello world // not a comment\" B";
+        let actions: Vec<_> = split_key_actions(keys).into_iter().map(|(_, a)| a).collect();
+        assert_eq!(actions, vec!["A", "\"hello world // not a comment\"", "B"]);
+    }
+}
@@ -0,0 +1,677 @@
+use pest::Parser;
+use serde_json::{Map, Value, json};
+
+use crate::KeyboardTomlConfig;
+use crate::layout::{ConfigParser, Rule};
+
+/// VIA custom channel used by RMK settings
+pub const RMK_SETTINGS_CHANNEL: u8 = 0;
+
+/// Type of a value in the VIA custom menus, which decides the control shown in VIA and the size of the value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CustomValueType {
+    /// On/off switch, the value is 1 byte
+    Toggle,
+    /// Slider with min and max, the value is 2 bytes(big endian) if max > 255, otherwise 1 byte
+    Range(u16, u16),
+    /// Dropdown of labels, the value is the 1 byte index of the selected label
+    Dropdown(&'static [&'static str]),
+    /// Dropdown of the BLE profiles, whose number is `rmk.ble_profiles_num`
+    BleProfile,
+}
+
+impl CustomValueType {
+    /// Size of the value in VIA packets
+    pub fn size(&self) -> usize {
+        match self {
+            CustomValueType::Range(_, max) if *max > 255 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Min and max of the value, the firmware rejects values out of the range.
+    ///
+    /// The max of `BleProfile` is the max of the 1 byte value, the firmware checks it against the number of profiles.
+    pub fn range(&self) -> (u16, u16) {
+        match self {
+            CustomValueType::Toggle => (0, 1),
+            CustomValueType::Range(min, max) => (*min, *max),
+            CustomValueType::Dropdown(labels) => (0, labels.len().saturating_sub(1) as u16),
+            CustomValueType::BleProfile => (0, u8::MAX as u16),
+        }
+    }
+}
+
+/// A value in the VIA custom menus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CustomValue {
+    /// Value id in the custom channel, the firmware handles values by this id
+    pub id: u8,
+    /// Name of the value in the firmware, which is the variant name of `RmkSetting` in `rmk`
+    pub name: &'static str,
+    pub label: &'static str,
+    pub ty: CustomValueType,
+}
+
+/// A menu of values in the VIA custom menus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CustomMenu {
+    pub label: &'static str,
+    /// Whether the menu is shown only when BLE is enabled
+    pub ble: bool,
+    /// Whether the menu is shown only when the storage is enabled
+    pub storage: bool,
+    pub values: &'static [CustomValue],
+}
+
+const fn value(id: u8, name: &'static str, label: &'static str, ty: CustomValueType) -> CustomValue {
+    CustomValue { id, name, label, ty }
+}
+
+/// RMK settings which are exposed in the VIA custom menus, all of them are in the channel `RMK_SETTINGS_CHANNEL`.
+///
+/// `RmkSetting` in `rmk` is generated from this table by its build script. Behavior settings, such as the tap-hold
+/// timeout, are not here, because they are served as Vial's QMK settings.
+pub const RMK_CUSTOM_MENUS: [CustomMenu; 5] = [
+    CustomMenu {
+        label: "Mouse keys",
+        ble: false,
+        storage: false,
+        values: &[
+            value(
+                0x10,
+                "MouseInitialDelay",
+                "Initial delay(ms)",
+                CustomValueType::Range(0, 1000),
+            ),
+            value(
+                0x11,
+                "MouseRepeatInterval",
+                "Repeat interval(ms)",
+                CustomValueType::Range(1, 100),
+            ),
+            value(0x12, "MouseMoveDelta", "Move delta", CustomValueType::Range(1, 50)),
+            value(0x13, "MouseMaxSpeed", "Max speed", CustomValueType::Range(1, 10)),
+            value(
+                0x14,
+                "MouseTimeToMax",
+                "Time to max speed",
+                CustomValueType::Range(0, 255),
+            ),
+        ],
+    },
+    CustomMenu {
+        label: "Lighting",
+        ble: false,
+        storage: false,
+        values: &[
+            value(0x40, "LightingIndicators", "Lock indicators", CustomValueType::Toggle),
+            value(
+                0x41,
+                "LightingLowBattery",
+                "Low battery warning(%)",
+                CustomValueType::Range(0, 100),
+            ),
+        ],
+    },
+    CustomMenu {
+        label: "Bluetooth",
+        ble: true,
+        storage: false,
+        values: &[
+            value(0x20, "BleProfile", "Active profile", CustomValueType::BleProfile),
+            value(
+                0x21,
+                "BleLatencyMode",
+                "Latency mode",
+                CustomValueType::Dropdown(&["Low latency", "Balanced", "Power saving"]),
+            ),
+            value(
+                0x22,
+                "BleHostOs",
+                "Host OS",
+                CustomValueType::Dropdown(&["Unknown", "Windows", "macOS", "Linux", "iOS", "Android"]),
+            ),
+        ],
+    },
+    CustomMenu {
+        label: "Battery",
+        ble: true,
+        storage: false,
+        values: &[
+            // ADC values of the battery voltage before the voltage divider
+            value(
+                0x50,
+                "BatteryEmpty",
+                "Empty battery ADC value",
+                CustomValueType::Range(0, 10000),
+            ),
+            value(
+                0x51,
+                "BatteryFull",
+                "Full battery ADC value",
+                CustomValueType::Range(0, 10000),
+            ),
+        ],
+    },
+    CustomMenu {
+        label: "Storage",
+        ble: false,
+        storage: true,
+        values: &[
+            // Health of the storage is read-only, changes are ignored by the firmware
+            value(0x30, "StorageUsed", "Used(%)", CustomValueType::Range(0, 100)),
+            value(
+                0x31,
+                "StorageEraseCount",
+                "Erased sectors",
+                CustomValueType::Range(0, 65535),
+            ),
+            value(
+                0x32,
+                "StorageLastError",
+                "Last error",
+                CustomValueType::Dropdown(&["None", "Flash error", "Storage full", "Corrupted", "Other"]),
+            ),
+            value(0x33, "StorageCompact", "Compact storage", CustomValueType::Toggle),
+        ],
+    },
+];
+
+/// A key in the physical layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicalKey {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    /// Layout option and the choice which this key belongs to
+    pub option: Option<(u8, u8)>,
+}
+
+impl PhysicalKey {
+    fn new(x: f64, y: f64) -> Self {
+        Self {
+            x,
+            y,
+            w: 1.0,
+            h: 1.0,
+            option: None,
+        }
+    }
+}
+
+impl KeyboardTomlConfig {
+    /// Whether the Vial definition should be generated from `keyboard.toml`, instead of `vial.json`
+    pub fn has_physical_layout(&self) -> bool {
+        self.layout.as_ref().is_some_and(|l| l.physical.is_some())
+    }
+
+    /// Generate the Vial definition from `[layout]` and `[layout.physical]`
+    pub fn get_vial_definition(&self) -> Result<String, String> {
+        let layout = self.layout.as_ref().ok_or("keyboard.toml: [layout] is required")?;
+        let physical = layout.physical.clone().unwrap_or_default();
+        let keyboard = self.keyboard.as_ref().ok_or("keyboard.toml: [keyboard] is required")?;
+
+        // Matrix coordinates in the same order as physical keys
+        let coords = match &layout.matrix_map {
+            Some(matrix_map) => Self::parse_matrix_map(matrix_map)
+                .map_err(|e| format!("keyboard.toml: Error in `layout.matrix_map`: {}", e))?,
+            None => (0..layout.rows)
+                .flat_map(|row| (0..layout.cols).map(move |col| (row, col)))
+                .collect(),
+        };
+
+        let keys = match &physical.keys {
+            Some(keys) => Self::parse_physical_keys(keys)
+                .map_err(|e| format!("keyboard.toml: Error in `layout.physical.keys`: {}", e))?,
+            None => Self::default_physical_keys(layout.matrix_map.as_deref(), layout.rows, layout.cols)?,
+        };
+        if keys.len() != coords.len() {
+            return Err(format!(
+                "keyboard.toml: The number of keys in `layout.physical.keys` is {}, which doesn't match the {} keys in the matrix",
+                keys.len(),
+                coords.len()
+            ));
+        }
+
+        let num_options = physical.options.as_ref().map(|o| o.len()).unwrap_or_default();
+        let mut labeled_keys = Vec::new();
+        for (key, (row, col)) in keys.into_iter().zip(coords) {
+            let legend = match key.option {
+                Some((option, choice)) => {
+                    if option as usize >= num_options {
+                        return Err(format!(
+                            "keyboard.toml: Layout option {} of key ({},{}) is not defined in `layout.physical.options`",
+                            option, row, col
+                        ));
+                    }
+                    // Legend at position 8 is the layout option
+                    format!("{},{}\n\n\n\n\n\n\n\n{},{}", row, col, option, choice)
+                }
+                None => format!("{},{}", row, col),
+            };
+            labeled_keys.push((key, legend));
+        }
+
+        if let Some(encoders) = &physical.encoders {
+            let encoders = Self::parse_physical_keys(encoders)
+                .map_err(|e| format!("keyboard.toml: Error in `layout.physical.encoders`: {}", e))?;
+            if let Ok(board) = self.get_board_config() {
+                let num_encoder: usize = board.get_num_encoder().iter().sum();
+                if encoders.len() != num_encoder {
+                    return Err(format!(
+                        "keyboard.toml: The number of encoders in `layout.physical.encoders` is {}, but {} encoders are defined",
+                        encoders.len(),
+                        num_encoder
+                    ));
+                }
+            }
+            for (idx, encoder) in encoders.into_iter().enumerate() {
+                // Legend at position 9 marks the key as an encoder
+                for direction in 0..2 {
+                    let key = PhysicalKey::new(encoder.x + direction as f64, encoder.y);
+                    labeled_keys.push((key, format!("{},{}\n\n\n\n\n\n\n\n\ne", idx, direction)));
+                }
+            }
+        }
+
+        let mut layouts = Map::new();
+        if let Some(options) = &physical.options {
+            let labels = options
+                .iter()
+                .map(|o| match o.as_slice() {
+                    [] => Err("keyboard.toml: Empty label in `layout.physical.options`".to_string()),
+                    [label] => Ok(json!(label)),
+                    _ => Ok(json!(o)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            layouts.insert("labels".to_string(), Value::Array(labels));
+        }
+        layouts.insert("keymap".to_string(), json!(Self::to_kle_rows(labeled_keys)));
+
+        let definition = json!({
+            "name": keyboard.product_name.clone().unwrap_or(keyboard.name.clone()),
+            "vendorId": format!("0x{:04X}", keyboard.vendor_id),
+            "productId": format!("0x{:04X}", keyboard.product_id),
+            "lighting": "none",
+            "matrix": {
+                "rows": layout.rows,
+                "cols": layout.cols
+            },
+            "layouts": layouts,
+            "menus": [{
+                "label": "RMK",
+                "content": self.get_custom_menus()
+            }]
+        });
+
+        Ok(definition.to_string())
+    }
+
+    /// Generate the VIA custom menus of RMK settings, menus for BLE or the storage are skipped if they're not enabled
+    fn get_custom_menus(&self) -> Vec<Value> {
+        let ble_enabled = self.ble.as_ref().is_some_and(|b| b.enabled);
+        let storage_enabled = self.get_storage_config().enabled;
+        RMK_CUSTOM_MENUS
+            .iter()
+            .filter(|menu| (!menu.ble || ble_enabled) && (!menu.storage || storage_enabled))
+            .map(|menu| {
+                let values = menu
+                    .values
+                    .iter()
+                    .map(|v| {
+                        let mut item = Map::new();
+                        item.insert("label".to_string(), json!(v.label));
+                        let (ty, options) = match v.ty {
+                            CustomValueType::Toggle => ("toggle", None),
+                            CustomValueType::Range(min, max) => ("range", Some(json!([min, max]))),
+                            CustomValueType::Dropdown(labels) => ("dropdown", Some(json!(labels))),
+                            CustomValueType::BleProfile => {
+                                let profiles = (1..=self.rmk.ble_profiles_num)
+                                    .map(|i| format!("Profile {}", i))
+                                    .collect::<Vec<_>>();
+                                ("dropdown", Some(json!(profiles)))
+                            }
+                        };
+                        item.insert("type".to_string(), json!(ty));
+                        if let Some(options) = options {
+                            item.insert("options".to_string(), options);
+                        }
+                        item.insert(
+                            "content".to_string(),
+                            json!([format!("id_rmk_setting_{}", v.id), RMK_SETTINGS_CHANNEL, v.id]),
+                        );
+                        Value::Object(item)
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "label": menu.label, "content": values })
+            })
+            .collect()
+    }
+
+    /// Generate the Vial keyboard id from the keyboard info, so that it's stable across builds
+    pub fn get_vial_keyboard_id(&self) -> [u8; 8] {
+        let basic = self.get_basic_info();
+        // FNV-1a hash
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for b in basic
+            .name
+            .bytes()
+            .chain(basic.vendor_id.to_le_bytes())
+            .chain(basic.product_id.to_le_bytes())
+        {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash.to_le_bytes()
+    }
+
+    /// Parse positions of keys, each key is `(x, y, [w, [h, [option, choice]]])`
+    fn parse_physical_keys(keys: &str) -> Result<Vec<PhysicalKey>, String> {
+        let pairs = ConfigParser::parse(Rule::physical_keys, keys).map_err(|e| format!("Invalid format: {}", e))?;
+        let mut physical_keys = Vec::new();
+        for pair in pairs.flat_map(|p| p.into_inner()) {
+            if pair.as_rule() != Rule::physical_key {
+                continue;
+            }
+            let text = pair.as_str();
+            let values = pair
+                .into_inner()
+                .map(|v| v.as_str().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Failed to parse key {}: {}", text, e))?;
+            let mut key = PhysicalKey::new(values[0], values[1]);
+            if let Some(w) = values.get(2) {
+                key.w = *w;
+            }
+            if let Some(h) = values.get(3) {
+                key.h = *h;
+            }
+            match values.get(4..) {
+                Some([option, choice]) => key.option = Some((*option as u8, *choice as u8)),
+                Some([]) | None => {}
+                Some(_) => return Err(format!("Both option and choice should be set for key {}", text)),
+            }
+            if key.w <= 0.0 || key.h <= 0.0 {
+                return Err(format!("Size of key {} must be positive", text));
+            }
+            physical_keys.push(key);
+        }
+        Ok(physical_keys)
+    }
+
+    /// Each line of the `matrix_map` is a row of 1u keys, or the whole matrix is used if `matrix_map` is not set
+    fn default_physical_keys(matrix_map: Option<&str>, rows: u8, cols: u8) -> Result<Vec<PhysicalKey>, String> {
+        let Some(matrix_map) = matrix_map else {
+            return Ok((0..rows)
+                .flat_map(|y| (0..cols).map(move |x| PhysicalKey::new(x as f64, y as f64)))
+                .collect());
+        };
+        let mut keys = Vec::new();
+        let mut y = 0.0;
+        for line in matrix_map.lines() {
+            let num_keys = Self::parse_matrix_map(line)?.len();
+            if num_keys == 0 {
+                continue;
+            }
+            keys.extend((0..num_keys).map(|x| PhysicalKey::new(x as f64, y)));
+            y += 1.0;
+        }
+        Ok(keys)
+    }
+
+    /// Convert keys to rows of KLE(keyboard-layout-editor) format, which is used in the Vial definition
+    fn to_kle_rows(mut keys: Vec<(PhysicalKey, String)>) -> Vec<Vec<Value>> {
+        keys.sort_by(|(a, _), (b, _)| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)));
+        let round = |v: f64| (v * 10000.0).round() / 10000.0;
+
+        let mut rows: Vec<Vec<Value>> = Vec::new();
+        // Y of the current row and X of the next key, in KLE each row starts at x = 0 and y = last row + 1
+        let mut current_y = -1.0;
+        let mut current_x = 0.0;
+        for (key, legend) in keys {
+            let mut props = Map::new();
+            if rows.is_empty() || key.y != current_y {
+                let offset = if rows.is_empty() {
+                    key.y
+                } else {
+                    key.y - current_y - 1.0
+                };
+                if round(offset) != 0.0 {
+                    props.insert("y".to_string(), json!(round(offset)));
+                }
+                rows.push(Vec::new());
+                current_y = key.y;
+                current_x = 0.0;
+            }
+            if round(key.x - current_x) != 0.0 {
+                props.insert("x".to_string(), json!(round(key.x - current_x)));
+            }
+            if key.w != 1.0 {
+                props.insert("w".to_string(), json!(round(key.w)));
+            }
+            if key.h != 1.0 {
+                props.insert("h".to_string(), json!(round(key.h)));
+            }
+            let row = rows.last_mut().unwrap();
+            if !props.is_empty() {
+                row.push(Value::Object(props));
+            }
+            row.push(json!(legend));
+            current_x = key.x + key.w;
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> KeyboardTomlConfig {
+        let keyboard = r#"
+            [keyboard]
+            name = "Test Keyboard"
+            vendor_id = 0x4c4b
+            product_id = 0x4643
+        "#;
+        toml::from_str(&format!("{keyboard}\n{toml}")).unwrap()
+    }
+
+    fn keymap(config: &KeyboardTomlConfig) -> Value {
+        let definition: Value = serde_json::from_str(&config.get_vial_definition().unwrap()).unwrap();
+        definition["layouts"]["keymap"].clone()
+    }
+
+    #[test]
+    fn test_parse_physical_keys() {
+        let keys = KeyboardTomlConfig::parse_physical_keys("(0,0) (1, 0, 1.5)\n(0,1,1,2) (2.25,1,1,1,0,1)").unwrap();
+        assert_eq!(keys.len(), 4);
+        assert_eq!(keys[0], PhysicalKey::new(0.0, 0.0));
+        assert_eq!((keys[1].x, keys[1].w, keys[1].h), (1.0, 1.5, 1.0));
+        assert_eq!((keys[2].w, keys[2].h), (1.0, 2.0));
+        assert_eq!((keys[3].x, keys[3].option), (2.25, Some((0, 1))));
+
+        assert!(KeyboardTomlConfig::parse_physical_keys("(0)").is_err());
+        assert!(KeyboardTomlConfig::parse_physical_keys("(0,0,1,1,0)").is_err());
+        assert!(KeyboardTomlConfig::parse_physical_keys("(0,0,0)").is_err());
+        assert!(KeyboardTomlConfig::parse_physical_keys("(0,0) A").is_err());
+    }
+
+    #[test]
+    fn test_default_physical_layout_from_matrix_map() {
+        let config = config(
+            r#"
+            [layout]
+            rows = 2
+            cols = 3
+            layers = 1
+            matrix_map = """
+            (0,0) (0,1) (0,2)
+
+            (1,2) (1,0)
+            """
+            [layout.physical]
+            "#,
+        );
+        assert!(config.has_physical_layout());
+        assert_eq!(keymap(&config), json!([["0,0", "0,1", "0,2"], ["1,2", "1,0"]]));
+    }
+
+    #[test]
+    fn test_kle_offsets() {
+        let config = config(
+            r#"
+            [layout]
+            rows = 2
+            cols = 3
+            layers = 1
+            matrix_map = "(0,0) (0,1) (0,2) (1,0) (1,1) (1,2)"
+            [layout.physical]
+            keys = "(0,0,1.5) (2,0) (3,0,1,2) (0.25,1.5) (1.25,1.5,1.75) (2,3,1,1,0,1)"
+            options = [["Split Space"], ["Bottom Row", "ANSI", "Tsangan"]]
+            "#,
+        );
+        let definition: Value = serde_json::from_str(&config.get_vial_definition().unwrap()).unwrap();
+        assert_eq!(definition["vendorId"], "0x4C4B");
+        assert_eq!(definition["matrix"], json!({"rows": 2, "cols": 3}));
+        assert_eq!(
+            definition["layouts"]["labels"],
+            json!(["Split Space", ["Bottom Row", "ANSI", "Tsangan"]])
+        );
+        assert_eq!(
+            definition["layouts"]["keymap"],
+            json!([
+                [{"w": 1.5}, "0,0", {"x": 0.5}, "0,1", {"h": 2.0}, "0,2"],
+                [{"y": 0.5, "x": 0.25}, "1,0", {"w": 1.75}, "1,1"],
+                [{"y": 0.5, "x": 2.0}, "1,2\n\n\n\n\n\n\n\n0,1"]
+            ])
+        );
+    }
+
+    #[test]
+    fn test_encoders_and_errors() {
+        let config = config(
+            r#"
+            [layout]
+            rows = 1
+            cols = 2
+            layers = 1
+            [layout.physical]
+            encoders = "(3,0)"
+            "#,
+        );
+        assert_eq!(
+            keymap(&config),
+            json!([[
+                "0,0",
+                "0,1",
+                {"x": 1.0},
+                "0,0\n\n\n\n\n\n\n\n\ne",
+                "0,1\n\n\n\n\n\n\n\n\ne"
+            ]])
+        );
+
+        let config = self::config(
+            r#"
+            [layout]
+            rows = 1
+            cols = 2
+            layers = 1
+            [layout.physical]
+            keys = "(0,0)"
+            "#,
+        );
+        assert!(config.get_vial_definition().is_err());
+
+        let config = self::config(
+            r#"
+            [layout]
+            rows = 1
+            cols = 1
+            layers = 1
+            [layout.physical]
+            keys = "(0,0,1,1,1,0)"
+            options = [["Split Space"]]
+            "#,
+        );
+        assert!(config.get_vial_definition().is_err());
+    }
+
+    #[test]
+    fn test_custom_menus() {
+        let layout = r#"
+            [layout]
+            rows = 1
+            cols = 1
+            layers = 1
+            [layout.physical]
+        "#;
+        let menus = |config: &KeyboardTomlConfig| {
+            let definition: Value = serde_json::from_str(&config.get_vial_definition().unwrap()).unwrap();
+            definition["menus"][0]["content"].clone()
+        };
+
+        let menus_without_ble = menus(&config(layout));
+        assert_eq!(menus_without_ble.as_array().unwrap().len(), 2);
+        assert_eq!(menus_without_ble[0]["label"], "Mouse keys");
+        assert_eq!(
+            menus_without_ble[0]["content"][0],
+            json!({
+                "label": "Initial delay(ms)",
+                "type": "range",
+                "options": [0, 1000],
+                "content": ["id_rmk_setting_16", 0, 0x10]
+            })
+        );
+        assert_eq!(
+            menus_without_ble[1]["content"][0],
+            json!({"label": "Lock indicators", "type": "toggle", "content": ["id_rmk_setting_64", 0, 0x40]})
+        );
+
+        let menus_with_storage = menus(&config(&format!("{layout}\n[storage]\nenabled = true")));
+        assert_eq!(menus_with_storage.as_array().unwrap().len(), 3);
+        assert_eq!(menus_with_storage[2]["label"], "Storage");
+        assert_eq!(
+            menus_with_storage[2]["content"][3],
+            json!({"label": "Compact storage", "type": "toggle", "content": ["id_rmk_setting_51", 0, 0x33]})
+        );
+
+        let menus_with_ble = menus(&config(&format!(
+            "{layout}\n[ble]\nenabled = true\n[rmk]\nble_profiles_num = 2"
+        )));
+        assert_eq!(menus_with_ble.as_array().unwrap().len(), 4);
+        let ble = &menus_with_ble[2];
+        assert_eq!(ble["label"], "Bluetooth");
+        assert_eq!(ble["content"][0]["type"], "dropdown");
+        assert_eq!(ble["content"][0]["options"], json!(["Profile 1", "Profile 2"]));
+        assert_eq!(ble["content"][1]["content"], json!(["id_rmk_setting_33", 0, 0x21]));
+        assert_eq!(menus_with_ble[3]["label"], "Battery");
+    }
+
+    #[test]
+    fn test_custom_value_ids_are_unique() {
+        let mut ids: Vec<_> = RMK_CUSTOM_MENUS.iter().flat_map(|m| m.values).map(|v| v.id).collect();
+        let mut names: Vec<_> = RMK_CUSTOM_MENUS.iter().flat_map(|m| m.values).map(|v| v.name).collect();
+        let len = ids.len();
+        ids.sort();
+        ids.dedup();
+        names.sort();
+        names.dedup();
+        assert_eq!(ids.len(), len);
+        assert_eq!(names.len(), len);
+        assert_eq!(CustomValueType::Range(0, 255).size(), 1);
+        assert_eq!(CustomValueType::Range(0, 256).size(), 2);
+        assert_eq!(CustomValueType::Range(1, 10).range(), (1, 10));
+        assert_eq!(CustomValueType::Dropdown(&["a", "b", "c"]).range(), (0, 2));
+    }
+
+    #[test]
+    fn test_vial_keyboard_id_is_stable() {
+        let a = config("");
+        let b = config("");
+        assert_eq!(a.get_vial_keyboard_id(), b.get_vial_keyboard_id());
+        assert!(!a.has_physical_layout());
+    }
+}
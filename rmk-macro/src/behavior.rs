@@ -7,7 +7,8 @@ use rmk_config::{
     TapHoldConfig, TriLayerConfig,
 };
 
-use crate::layout::{get_key_with_alias, parse_key};
+use crate::input_device::encoder::expand_encoders_config;
+use crate::layout::{expand_key, expand_key_at, get_key_with_alias};
 
 fn expand_tri_layer(tri_layer: &Option<TriLayerConfig>) -> proc_macro2::TokenStream {
     match tri_layer {
@@ -54,7 +55,7 @@ fn expand_morse_action_pair(action_pair: &MorseActionPair) -> proc_macro2::Token
             _ => {}
         }
     }
-    let action = parse_key(action_pair.action.to_owned());
+    let action = expand_key(action_pair.action.to_owned());
     quote! { (rmk::morse::MorsePattern::from_u16(#pattern), #action.to_action()) }
 }
 
@@ -123,8 +124,8 @@ fn expand_combos(combos: &Option<CombosConfig>) -> proc_macro2::TokenStream {
     match combos {
         Some(combos) => {
             let combos_def = combos.combos.iter().map(|combo| {
-                let actions = combo.actions.iter().map(|a| parse_key(a.to_owned()));
-                let output = parse_key(combo.output.to_owned());
+                let actions = combo.actions.iter().map(|a| expand_key(a.to_owned()));
+                let output = expand_key(combo.output.to_owned());
                 let layer = match combo.layer {
                     Some(layer) => quote! { ::core::option::Option::Some(#layer) },
                     None => quote! { ::core::option::Option::None },
@@ -227,7 +228,7 @@ fn expand_morse(morse: &Option<MorsesConfig>) -> proc_macro2::TokenStream {
                     let tap_actions_def = match &td.tap_actions {
                         Some(tap_actions) => {
                             let actions = tap_actions.iter().map(|action| {
-                                let parsed_action = parse_key(action.clone());
+                                let parsed_action = expand_key(action.clone());
                                 quote! { #parsed_action }
                             });
                             quote! { ::rmk::heapless::Vec::from_iter([#(#actions.to_action()),*]) }
@@ -238,7 +239,7 @@ fn expand_morse(morse: &Option<MorsesConfig>) -> proc_macro2::TokenStream {
                     let hold_actions_def = match &td.hold_actions {
                         Some(hold_actions) => {
                             let actions = hold_actions.iter().map(|action| {
-                                let parsed_action = parse_key(action.clone());
+                                let parsed_action = expand_key(action.clone());
                                 quote! { #parsed_action }
                             });
                             quote! { ::rmk::heapless::Vec::from_iter([#(#actions.to_action()),*]) }
@@ -254,10 +255,10 @@ fn expand_morse(morse: &Option<MorsesConfig>) -> proc_macro2::TokenStream {
                         )
                     }
                 } else {
-                    let tap = parse_key(td.tap.clone().unwrap_or_else(|| "No".to_string()));
-                    let hold = parse_key(td.hold.clone().unwrap_or_else(|| "No".to_string()));
-                    let hold_after_tap = parse_key(td.hold_after_tap.clone().unwrap_or_else(|| "No".to_string()));
-                    let double_tap = parse_key(td.double_tap.clone().unwrap_or_else(|| "No".to_string()));
+                    let tap = expand_key(td.tap.clone().unwrap_or_else(|| "No".to_string()));
+                    let hold = expand_key(td.hold.clone().unwrap_or_else(|| "No".to_string()));
+                    let hold_after_tap = expand_key(td.hold_after_tap.clone().unwrap_or_else(|| "No".to_string()));
+                    let double_tap = expand_key(td.double_tap.clone().unwrap_or_else(|| "No".to_string()));
 
                     quote! {
                         ::rmk::morse::Morse::new_from_vial(
@@ -411,9 +412,9 @@ fn expand_forks(forks: &Option<ForksConfig>) -> proc_macro2::TokenStream {
     match forks {
         Some(forks) => {
             let forks_def = forks.forks.iter().map(|fork| {
-                let trigger = parse_key(fork.trigger.to_owned());
-                let negative_output = parse_key(fork.negative_output.to_owned());
-                let positive_output = parse_key(fork.positive_output.to_owned());
+                let trigger = expand_key(fork.trigger.to_owned());
+                let negative_output = expand_key(fork.negative_output.to_owned());
+                let positive_output = expand_key(fork.positive_output.to_owned());
                 let match_any  = fork.match_any.as_ref().map(|s| parse_state_combination(s)).unwrap_or_default();
                 let match_none = fork.match_none.as_ref().map(|s| parse_state_combination(s)).unwrap_or_default();
                 let kept = fork.kept_modifiers.as_ref().map(|s| parse_state_combination(s)).unwrap_or_default();
@@ -437,6 +438,44 @@ fn expand_forks(forks: &Option<ForksConfig>) -> proc_macro2::TokenStream {
     }
 }
 
+fn expand_layout_options(keyboard_config: &KeyboardTomlConfig) -> proc_macro2::TokenStream {
+    let layout = keyboard_config.get_layout_config().unwrap();
+    if layout.options.is_empty() {
+        return quote! { ::rmk::config::LayoutOptionsConfig::default() };
+    }
+    let option_bits = layout.option_bits;
+    let options = layout.options.into_iter().map(|option| {
+        let option_index = option.option;
+        let choice = option.choice;
+        let remaps = option
+            .remaps
+            .iter()
+            .map(|((row, col), (to_row, to_col))| quote! { ((#row, #col), (#to_row, #to_col)) });
+        let keys = option.keys.into_iter().map(|(layer, row, col, action)| {
+            let action = expand_key_at(action, layer, row, col);
+            quote! { (#layer, #row, #col, #action) }
+        });
+        quote! {
+            ::rmk::config::LayoutOption {
+                option: #option_index,
+                choice: #choice,
+                remaps: &[#(#remaps),*],
+                keys: &[#(#keys),*],
+            }
+        }
+    });
+
+    quote! {
+        ::rmk::config::LayoutOptionsConfig {
+            option_bits: &[#(#option_bits),*],
+            options: {
+                const LAYOUT_OPTIONS: &[::rmk::config::LayoutOption] = &[#(#options),*];
+                LAYOUT_OPTIONS
+            },
+        }
+    }
+}
+
 pub(crate) fn expand_behavior_config(keyboard_config: &KeyboardTomlConfig) -> proc_macro2::TokenStream {
     let behavior = keyboard_config.get_behavior_config().unwrap();
     let tri_layer = expand_tri_layer(&behavior.tri_layer);
@@ -446,6 +485,8 @@ pub(crate) fn expand_behavior_config(keyboard_config: &KeyboardTomlConfig) -> pr
     let macros = expand_macros(&behavior.macros);
     let forks = expand_forks(&behavior.fork);
     let morse = expand_morse(&behavior.morse);
+    let layout_options = expand_layout_options(keyboard_config);
+    let encoders = expand_encoders_config(keyboard_config);
 
     quote! {
         let mut behavior_config = ::rmk::config::BehaviorConfig {
@@ -459,6 +500,8 @@ pub(crate) fn expand_behavior_config(keyboard_config: &KeyboardTomlConfig) -> pr
             // keyboard_macros: ::rmk::config::macro_config::KeyboardMacrosConfig::default(),
             mouse_key: ::rmk::config::MouseKeyConfig::default(),
             tap: ::rmk::config::TapConfig::default(),
+            layout_options: #layout_options,
+            encoder: #encoders,
         };
     }
 }
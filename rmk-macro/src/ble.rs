@@ -10,6 +10,32 @@ pub(crate) fn expand_ble_config(keyboard_config: &KeyboardTomlConfig) -> (TokenS
     if !communication.ble_enabled() {
         return (quote! {}, quote! {});
     }
+    let ble_toml_config = communication.get_ble_config();
+    let output_mirror = ble_toml_config
+        .as_ref()
+        .and_then(|ble| ble.output_mirror)
+        .unwrap_or(false);
+    let mut ble_fields = quote! { output_mirror: #output_mirror, };
+    if let Some(interval) = ble_toml_config
+        .as_ref()
+        .and_then(|ble| ble.advertising_interval.as_ref())
+    {
+        let interval = interval.0;
+        ble_fields.extend(quote! { advertising_interval: ::embassy_time::Duration::from_millis(#interval), });
+    }
+    if let Some(timeout) = ble_toml_config
+        .as_ref()
+        .and_then(|ble| ble.advertising_timeout.as_ref())
+    {
+        let timeout = timeout.0;
+        ble_fields.extend(quote! { advertising_timeout: ::embassy_time::Duration::from_millis(#timeout), });
+    }
+    if let Some(directed) = ble_toml_config.as_ref().and_then(|ble| ble.directed_advertising) {
+        ble_fields.extend(quote! { directed_advertising: #directed, });
+    }
+    let set_ble_output_config = quote! {
+        ble_config: ::rmk::config::BleConfig { #ble_fields ..Default::default() },
+    };
     let chip = keyboard_config.get_chip_model().unwrap();
     // Advanced parameters are only supported for nrf52(for now)
     if chip.series != ChipSeries::Nrf52 {
@@ -19,6 +45,7 @@ pub(crate) fn expand_ble_config(keyboard_config: &KeyboardTomlConfig) -> (TokenS
             },
             quote! {
                 ble_battery_config,
+                #set_ble_output_config
             },
         );
     }
@@ -77,6 +104,7 @@ pub(crate) fn expand_ble_config(keyboard_config: &KeyboardTomlConfig) -> (TokenS
                     ble_config_tokens,
                     quote! {
                         ble_battery_config,
+                        #set_ble_output_config
                     },
                 )
             } else {
@@ -86,6 +114,7 @@ pub(crate) fn expand_ble_config(keyboard_config: &KeyboardTomlConfig) -> (TokenS
                     },
                     quote! {
                         ble_battery_config,
+                        #set_ble_output_config
                     },
                 )
             }
@@ -0,0 +1,251 @@
+//! Initialize the bootloader selected in `keyboard.toml`
+//!
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rmk_config::{BootloaderType, ChipSeries, KeyboardTomlConfig};
+
+use crate::feature::is_feature_enabled;
+
+/// Check that the cargo features of RMK required by the bootloader are enabled
+pub(crate) fn check_bootloader_features(keyboard_config: &KeyboardTomlConfig, rmk_features: &Option<Vec<String>>) {
+    let Some(bootloader) = keyboard_config.get_bootloader_config() else {
+        return;
+    };
+    let dfu_enabled = is_feature_enabled(rmk_features, "dfu");
+    if bootloader.bootloader_type == BootloaderType::EmbassyBoot
+        && !(is_feature_enabled(rmk_features, "embassy_boot") || dfu_enabled)
+    {
+        panic!("The embassy_boot bootloader requires the \"embassy_boot\" cargo feature of RMK");
+    }
+    if bootloader.dfu_start_addr.is_some() && !dfu_enabled {
+        panic!("BLE DFU requires the \"dfu\" cargo feature of RMK");
+    }
+}
+
+/// Expand the bootloader initialization.
+///
+/// The first part is placed at the beginning of `main`, before chips are initialized.
+/// The second part is placed after the flash is initialized, `flash_initialized` is whether `expand_flash_init` is used.
+pub(crate) fn expand_bootloader_init(
+    keyboard_config: &KeyboardTomlConfig,
+    flash_initialized: bool,
+) -> (TokenStream2, TokenStream2) {
+    let Some(bootloader) = keyboard_config.get_bootloader_config() else {
+        return (quote! {}, quote! {});
+    };
+    match bootloader.bootloader_type {
+        BootloaderType::Stm32Dfu => {
+            let Some(system_memory) = bootloader.system_memory else {
+                return (
+                    quote! { compile_error!("`system_memory` is required by the stm32_dfu bootloader"); },
+                    quote! {},
+                );
+            };
+            (
+                quote! {
+                    static BOOTLOADER: ::static_cell::StaticCell<::rmk::boot::Stm32DfuBootloader> = ::static_cell::StaticCell::new();
+                    let bootloader = BOOTLOADER.init(::rmk::boot::Stm32DfuBootloader::new(#system_memory));
+                    // Jump to the system memory bootloader before any peripheral is initialized
+                    bootloader.check();
+                    ::rmk::boot::set_bootloader(bootloader);
+                },
+                quote! {},
+            )
+        }
+        BootloaderType::RpRom => (
+            quote! {},
+            quote! {
+                static BOOTLOADER: ::static_cell::StaticCell<::rmk::boot::RpRomBootloader> = ::static_cell::StaticCell::new();
+                ::rmk::boot::set_bootloader(BOOTLOADER.init(::rmk::boot::RpRomBootloader));
+            },
+        ),
+        BootloaderType::AdafruitUf2 => (
+            quote! {},
+            quote! {
+                static BOOTLOADER: ::static_cell::StaticCell<::rmk::boot::AdafruitUf2Bootloader> = ::static_cell::StaticCell::new();
+                ::rmk::boot::set_bootloader(BOOTLOADER.init(::rmk::boot::AdafruitUf2Bootloader));
+            },
+        ),
+        BootloaderType::Esp32Download => (
+            quote! {},
+            quote! {
+                static BOOTLOADER: ::static_cell::StaticCell<::rmk::boot::Esp32DownloadBootloader> = ::static_cell::StaticCell::new();
+                ::rmk::boot::set_bootloader(BOOTLOADER.init(::rmk::boot::Esp32DownloadBootloader));
+            },
+        ),
+        BootloaderType::EmbassyBoot => {
+            let (Some(state_start), Some(state_size)) = (bootloader.state_start_addr, bootloader.state_size) else {
+                return (
+                    quote! {},
+                    quote! { compile_error!("`state_start_addr` and `state_size` are required by the embassy_boot bootloader"); },
+                );
+            };
+            let series = keyboard_config.get_chip_model().unwrap().series;
+            // The flash is shared with the storage, see `expand_flash_init`
+            let shared_flash_init = if flash_initialized && keyboard_config.get_storage_config().enabled {
+                quote! {}
+            } else {
+                expand_shared_flash(&series)
+            };
+            let bootloader_init = match series {
+                ChipSeries::Stm32 => quote! {
+                    static BOOTLOADER: ::static_cell::StaticCell<
+                        ::rmk::boot::EmbassyBootBootloader<
+                            ::rmk::boot::SharedFlashPartition<::embassy_stm32::flash::Flash<'static, ::embassy_stm32::flash::Blocking>>,
+                        >,
+                    > = ::static_cell::StaticCell::new();
+                    let bootloader = BOOTLOADER.init(::rmk::boot::EmbassyBootBootloader::new(
+                        ::rmk::boot::SharedFlashPartition::new(shared_flash, #state_start, #state_size),
+                    ));
+                    // Confirm the current firmware, otherwise embassy-boot reverts it at next boot.
+                    // The error is logged by RMK
+                    let _ = bootloader.mark_booted();
+                    ::rmk::boot::set_bootloader(bootloader);
+                },
+                // The flash of MPSL is async, so it can't be written when jumping to the bootloader.
+                // The firmware is only confirmed, and updated by BLE DFU
+                ChipSeries::Nrf52 => quote! {
+                    // Confirm the current firmware, otherwise embassy-boot reverts it at next boot.
+                    // The error is logged by RMK
+                    let _ = ::rmk::boot::mark_booted(
+                        ::rmk::boot::SharedAsyncFlashPartition::new(shared_flash, #state_start, #state_size),
+                    ).await;
+                },
+                _ => quote! { compile_error!("The embassy_boot bootloader is only supported on STM32 and nRF52"); },
+            };
+            let dfu_init = expand_dfu_init(keyboard_config, &series, state_start, state_size);
+            (
+                quote! {},
+                quote! {
+                    #shared_flash_init
+                    #bootloader_init
+                    #dfu_init
+                },
+            )
+        }
+    }
+}
+
+/// Expand the BLE DFU updater as `dfu_updater`, which writes the DFU partition of embassy-boot
+fn expand_dfu_init(
+    keyboard_config: &KeyboardTomlConfig,
+    series: &ChipSeries,
+    state_start: u32,
+    state_size: u32,
+) -> TokenStream2 {
+    let bootloader = keyboard_config.get_bootloader_config().unwrap();
+    let (Some(dfu_start), Some(dfu_size), Some(public_key)) = (
+        bootloader.dfu_start_addr,
+        bootloader.dfu_size,
+        bootloader.dfu_public_key,
+    ) else {
+        return quote! {};
+    };
+    let (dfu, state) = match series {
+        ChipSeries::Stm32 => (
+            quote! { ::rmk::storage::async_flash_wrapper(::rmk::boot::SharedFlashPartition::new(shared_flash, #dfu_start, #dfu_size)) },
+            quote! { ::rmk::storage::async_flash_wrapper(::rmk::boot::SharedFlashPartition::new(shared_flash, #state_start, #state_size)) },
+        ),
+        _ => (
+            quote! { ::rmk::boot::SharedAsyncFlashPartition::new(shared_flash, #dfu_start, #dfu_size) },
+            quote! { ::rmk::boot::SharedAsyncFlashPartition::new(shared_flash, #state_start, #state_size) },
+        ),
+    };
+    quote! {
+        let mut dfu_updater = ::rmk::dfu::DfuUpdater::new(
+            #dfu,
+            #state,
+            *include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", #public_key)),
+        );
+    }
+}
+
+/// Expand the BLE DFU task, which is run together with RMK if BLE DFU is configured
+pub(crate) fn expand_dfu_task(keyboard_config: &KeyboardTomlConfig) -> Option<TokenStream2> {
+    keyboard_config
+        .get_bootloader_config()
+        .filter(|b| b.bootloader_type == BootloaderType::EmbassyBoot && b.dfu_start_addr.is_some())
+        .map(|_| quote! { ::rmk::dfu::run_dfu(&mut dfu_updater) })
+}
+
+/// Whether the flash is shared between the storage and the bootloader
+pub(crate) fn is_flash_shared(keyboard_config: &KeyboardTomlConfig) -> bool {
+    keyboard_config
+        .get_bootloader_config()
+        .is_some_and(|b| b.bootloader_type == BootloaderType::EmbassyBoot)
+}
+
+/// Expand the flash shared by the storage and embassy-boot, as `shared_flash`.
+///
+/// The blocking flash is used on STM32, and the async flash of MPSL is used on nRF52.
+pub(crate) fn expand_shared_flash(series: &ChipSeries) -> TokenStream2 {
+    match series {
+        ChipSeries::Nrf52 => quote! {
+            static SHARED_FLASH: ::static_cell::StaticCell<::rmk::boot::SharedAsyncFlash<::nrf_mpsl::Flash<'static>>> =
+                ::static_cell::StaticCell::new();
+            let shared_flash = &*SHARED_FLASH.init(::rmk::boot::SharedAsyncFlash::new(::nrf_mpsl::Flash::take(mpsl, p.NVMC)));
+        },
+        _ => quote! {
+            static SHARED_FLASH: ::static_cell::StaticCell<
+                ::rmk::boot::SharedFlash<::embassy_stm32::flash::Flash<'static, ::embassy_stm32::flash::Blocking>>,
+            > = ::static_cell::StaticCell::new();
+            let shared_flash = &*SHARED_FLASH.init(::rmk::boot::SharedFlash::new(::core::cell::RefCell::new(
+                ::embassy_stm32::flash::Flash::new_blocking(p.FLASH),
+            )));
+        },
+    }
+}
+
+/// Expand the bootmagic config.
+///
+/// `area` is `(row_offset, col_offset, rows, cols)` of a split peripheral, whose keys are converted to local positions.
+/// Combinations on other halves are ignored, combinations across halves are rejected by the validator of `keyboard.toml`.
+pub(crate) fn expand_bootmagic_config(
+    keyboard_config: &KeyboardTomlConfig,
+    area: Option<(usize, usize, usize, usize)>,
+) -> TokenStream2 {
+    let bootmagic = keyboard_config.get_bootmagic();
+    if bootmagic.bootloader_keys.is_empty()
+        && bootmagic.clear_storage_keys.is_empty()
+        && bootmagic.clear_bonds_keys.is_empty()
+    {
+        return quote! {};
+    }
+    let expand_keys = |keys: &[[u8; 2]]| {
+        let keys = match area {
+            Some((row_offset, col_offset, rows, cols)) => {
+                let local_keys = keys
+                    .iter()
+                    .filter_map(|&[row, col]| {
+                        let (row, col) = (row as usize, col as usize);
+                        if row >= row_offset && row < row_offset + rows && col >= col_offset && col < col_offset + cols
+                        {
+                            Some([(row - row_offset) as u8, (col - col_offset) as u8])
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if local_keys.len() == keys.len() {
+                    local_keys
+                } else {
+                    Vec::new()
+                }
+            }
+            None => keys.to_vec(),
+        };
+        let keys = keys.iter().map(|[row, col]| quote! { (#row, #col) });
+        quote! { &[#(#keys), *] }
+    };
+    let bootloader_keys = expand_keys(&bootmagic.bootloader_keys);
+    let clear_storage_keys = expand_keys(&bootmagic.clear_storage_keys);
+    let clear_bonds_keys = expand_keys(&bootmagic.clear_bonds_keys);
+    quote! {
+        ::rmk::bootmagic::set_bootmagic_config(::rmk::config::BootmagicConfig {
+            bootloader_keys: #bootloader_keys,
+            clear_storage_keys: #clear_storage_keys,
+            clear_bonds_keys: #clear_bonds_keys,
+        });
+    }
+}
@@ -4,6 +4,7 @@ use quote::{format_ident, quote};
 use rmk_config::{BoardConfig, CommunicationConfig, KeyboardTomlConfig};
 use syn::{ItemFn, ItemMod};
 
+use crate::bootloader::expand_dfu_task;
 use crate::keyboard::Overwritten;
 
 pub(crate) fn expand_rmk_entry(
@@ -96,6 +97,7 @@ pub(crate) fn rmk_entry_select(
                     #controller.event_loop(),
                 });
             }
+            tasks.extend(expand_dfu_task(keyboard_config));
             if split_config.connection == "ble" {
                 let rmk_task = quote! {
                     ::rmk::run_rmk(&keymap, #usb_driver_arg &stack, #storage rmk_config),
@@ -152,9 +154,52 @@ pub(crate) fn rmk_entry_select(
                     });
                 });
                 join_all_tasks(tasks)
+            } else if split_config.connection == "serial_bus" {
+                let rmk_task = quote! {
+                    ::rmk::run_rmk(&keymap, #usb_driver_arg #storage rmk_config),
+                };
+                tasks.push(rmk_task);
+                if !processors.is_empty() {
+                    tasks.push(processors_task);
+                };
+                // All peripherals share the only serial bus of central
+                let uart_instance = format_ident!(
+                    "{}",
+                    split_config
+                        .central
+                        .serial
+                        .as_ref()
+                        .and_then(|s| s.first())
+                        .expect("No serial defined for central")
+                        .instance
+                        .to_lowercase()
+                );
+                let peripherals = split_config.peripheral.iter().enumerate().map(|(idx, p)| {
+                    let addr = p.bus_addr.unwrap_or(idx as u8 + 1);
+                    let rows = p.rows as u8;
+                    let cols = p.cols as u8;
+                    let row_offset = p.row_offset as u8;
+                    let col_offset = p.col_offset as u8;
+                    quote! {
+                        ::rmk::split::bus::BusPeripheral {
+                            addr: #addr,
+                            rows: #rows,
+                            cols: #cols,
+                            row_offset: #row_offset,
+                            col_offset: #col_offset,
+                        }
+                    }
+                });
+                tasks.push(quote! {
+                    ::rmk::split::bus::run_bus_peripheral_manager(
+                        ::rmk::split::bus::SerialBus::new(#uart_instance),
+                        [#(#peripherals),*],
+                    )
+                });
+                join_all_tasks(tasks)
             } else {
                 panic!(
-                    "Invalid split connection type: {}, only \"ble\" and \"serial\" are supported",
+                    "Invalid split connection type: {}, only \"ble\", \"serial\" and \"serial_bus\" are supported",
                     split_config.connection
                 );
             }
@@ -189,6 +234,7 @@ pub(crate) fn rmk_entry_default(
             #controller.event_loop()
         });
     }
+    tasks.extend(expand_dfu_task(keyboard_config));
     // Remove the storage argument if disabled in config. The feature also needs to be disabled.
     let storage = if keyboard_config.get_storage_config().enabled {
         quote! {&mut storage,}
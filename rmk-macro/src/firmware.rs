@@ -0,0 +1,34 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rmk_config::{KeyboardTomlConfig, parse_version};
+
+/// Expand the firmware metadata block, which is embedded in the firmware and set as the metadata of the running firmware.
+///
+/// `role` is 0 for the central or a non-split keyboard, `id + 1` for a split peripheral.
+pub(crate) fn expand_firmware_metadata(keyboard_config: &KeyboardTomlConfig, role: u8) -> TokenStream2 {
+    let [major, minor, patch] = match keyboard_config.get_firmware_version() {
+        Some(Ok(version)) => version,
+        // The error is reported by the validator of `keyboard.toml`
+        Some(Err(e)) => return quote! { compile_error!(#e); },
+        // Use the version of the firmware crate, if VIA can report it
+        None => std::env::var("CARGO_PKG_VERSION")
+            .ok()
+            .and_then(|v| parse_version(&v).ok())
+            .filter(|[major, minor, _]| *major <= 255 && *minor <= 255)
+            .unwrap_or_default(),
+    };
+    let keyboard_id = keyboard_config.get_vial_keyboard_id();
+    quote! {
+        // Placed at a fixed offset of the image by `rmk.x` on Cortex-M
+        #[used]
+        #[cfg_attr(all(target_arch = "arm", target_os = "none"), unsafe(link_section = ".rmk_metadata"))]
+        static RMK_FIRMWARE_METADATA: [u8; ::rmk::firmware::FIRMWARE_METADATA_SIZE] =
+            ::rmk::firmware::FirmwareMetadata::new(
+                ::rmk::firmware::FirmwareVersion::new(#major, #minor, #patch),
+                [#(#keyboard_id), *],
+                #role,
+            )
+            .to_bytes();
+        ::rmk::firmware::set_firmware_metadata(&RMK_FIRMWARE_METADATA);
+    }
+}
@@ -5,6 +5,8 @@ use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use rmk_config::{ChipSeries, KeyboardTomlConfig, StorageConfig};
 
+use crate::bootloader::{expand_shared_flash, is_flash_shared};
+
 pub(crate) fn expand_flash_init(keyboard_config: &KeyboardTomlConfig) -> TokenStream2 {
     if !keyboard_config.get_storage_config().enabled {
         // This config actually does nothing if storage is disabled
@@ -17,11 +19,35 @@ pub(crate) fn expand_flash_init(keyboard_config: &KeyboardTomlConfig) -> TokenSt
     let chip = keyboard_config.get_chip_model().unwrap();
     flash_init.extend(
     match chip.series {
+            ChipSeries::Stm32 if is_flash_shared(keyboard_config) => {
+                // Share the flash with embassy-boot
+                let shared_flash_init = expand_shared_flash(&ChipSeries::Stm32);
+                quote! {
+                    #shared_flash_init
+                    let flash = ::rmk::storage::async_flash_wrapper(::rmk::boot::SharedFlashPartition::new(
+                        shared_flash,
+                        0,
+                        ::embassy_stm32::flash::FLASH_SIZE as u32,
+                    ));
+                }
+            }
             ChipSeries::Stm32 => {
                 quote! {
                     let flash = ::rmk::storage::async_flash_wrapper(::embassy_stm32::flash::Flash::new_blocking(p.FLASH));
                 }
             }
+            ChipSeries::Nrf52 if is_flash_shared(keyboard_config) => {
+                // Share the flash with embassy-boot
+                let shared_flash_init = expand_shared_flash(&ChipSeries::Nrf52);
+                quote! {
+                    #shared_flash_init
+                    let flash = ::rmk::boot::SharedAsyncFlashPartition::new(
+                        shared_flash,
+                        0,
+                        ::embassy_nrf::nvmc::FLASH_SIZE as u32,
+                    );
+                }
+            }
             ChipSeries::Nrf52 => {
                 quote! {
                     let flash = ::nrf_mpsl::Flash::take(mpsl, p.NVMC);
@@ -1,6 +1,7 @@
 use quote::{format_ident, quote};
 use rmk_config::{BleConfig, ChipSeries, JoystickConfig};
 
+use crate::feature::{get_rmk_features, is_feature_enabled};
 use crate::input_device::Initializer;
 
 /// Expand the ADC device configuration.
@@ -86,11 +87,15 @@ pub(crate) fn expand_adc_device(
                     transform,
                     bias,
                     resolution,
+                    mode,
+                    threshold,
+                    keys,
                     ..
                 } = joystick;
+                let mode = expand_joystick_mode(mode.as_deref(), threshold, keys);
                 let joystick_processor = Initializer {
                     initializer: quote! {
-                        let mut #joy_ident = rmk::input_device::joystick::JoystickProcessor::new([#([#(#transform),*]),*], [#(#bias),*], #resolution, &keymap);
+                        let mut #joy_ident = rmk::input_device::joystick::JoystickProcessor::new([#([#(#transform),*]),*], [#(#bias),*], #resolution, &keymap).with_mode(#mode);
                     },
                     var_name: joy_ident,
                 };
@@ -136,3 +141,36 @@ pub(crate) fn expand_adc_device(
         _ => (Vec::new(), Vec::new()),
     }
 }
+
+/// Expand the output mode of the joystick
+fn expand_joystick_mode(
+    mode: Option<&str>,
+    threshold: Option<i16>,
+    keys: Option<[[u8; 2]; 4]>,
+) -> proc_macro2::TokenStream {
+    match mode {
+        None | Some("mouse") => quote! { ::rmk::input_device::joystick::JoystickMode::Mouse },
+        Some("gamepad") => {
+            // `JoystickMode::Gamepad` is only available with the `gamepad` feature
+            if !is_feature_enabled(&get_rmk_features(), "gamepad") {
+                return quote! { compile_error!("Joystick mode gamepad requires the `gamepad` feature of RMK") };
+            }
+            quote! { ::rmk::input_device::joystick::JoystickMode::Gamepad }
+        }
+        Some("scroll") => quote! { ::rmk::input_device::joystick::JoystickMode::Scroll },
+        Some("arrow_keys") => {
+            let threshold = threshold.unwrap_or(100);
+            let Some(keys) = keys else {
+                return quote! { compile_error!("Joystick mode arrow_keys requires `keys`") };
+            };
+            let keys = keys
+                .iter()
+                .map(|[row, col]| quote! { ::rmk::event::KeyPos { row: #row, col: #col } });
+            quote! { ::rmk::input_device::joystick::JoystickMode::ArrowKeys { threshold: #threshold, keys: [#(#keys),*] } }
+        }
+        Some(mode) => {
+            let message = format!("Invalid joystick mode {mode}, available mode: mouse, gamepad, scroll, arrow_keys");
+            quote! { compile_error!(#message) }
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use quote::{format_ident, quote};
-use rmk_config::{ChipModel, EncoderConfig};
+use rmk_config::{ChipModel, EncoderConfig, KeyboardTomlConfig};
 
 use super::Initializer;
 use crate::gpio_config::convert_gpio_str_to_input_pin;
@@ -17,6 +17,7 @@ pub(crate) fn expand_encoder_device(
     }
 
     let mut device_initializer = vec![];
+    let mut processor_initializer = vec![];
 
     // Create rotary encoders
     for (idx, encoder) in encoder_config.iter().enumerate() {
@@ -35,12 +36,12 @@ pub(crate) fn expand_encoder_device(
         let encoder_device = match encoder.phase.as_deref() {
             Some("e8h7") => {
                 quote! {
-                    let mut #encoder_name = ::rmk::input_device::rotary_encoder::RotaryEncoder::with_phase(
+                    ::rmk::input_device::rotary_encoder::RotaryEncoder::with_phase(
                         #pin_a,
                         #pin_b,
                         ::rmk::input_device::rotary_encoder::E8H7Phase,
                         #encoder_id
-                    );
+                    )
                 }
             }
             Some("resolution") => {
@@ -51,24 +52,24 @@ pub(crate) fn expand_encoder_device(
                 let reverse = encoder.reverse.unwrap_or(false);
 
                 quote! {
-                    let mut #encoder_name = ::rmk::input_device::rotary_encoder::RotaryEncoder::with_resolution(
+                    ::rmk::input_device::rotary_encoder::RotaryEncoder::with_resolution(
                         #pin_a,
                         #pin_b,
                         #resolution,
                         #reverse,
                         #encoder_id
-                    );
+                    )
                 }
             }
             Some("default") => {
                 // Default phase
                 quote! {
-                    let mut #encoder_name = ::rmk::input_device::rotary_encoder::RotaryEncoder::with_phase(
+                    ::rmk::input_device::rotary_encoder::RotaryEncoder::with_phase(
                         #pin_a,
                         #pin_b,
                         ::rmk::input_device::rotary_encoder::DefaultPhase,
                         #encoder_id
-                    );
+                    )
                 }
             }
             _ => {
@@ -76,11 +77,110 @@ pub(crate) fn expand_encoder_device(
             }
         };
 
+        let behavior = expand_encoder_behavior(encoder);
+        let encoder_device = quote! {
+            let mut #encoder_name = #encoder_device.with_behavior(#behavior);
+        };
+
         device_initializer.push(Initializer {
             initializer: encoder_device,
             var_name: encoder_name,
         });
     }
 
-    (device_initializer, vec![])
+    // Scroll events of encoders are converted to mouse reports by the scroll processor
+    if has_scroll_encoder(&encoder_config) {
+        processor_initializer.push(expand_encoder_scroll_processor());
+    }
+
+    (device_initializer, processor_initializer)
+}
+
+/// Whether any of the encoders works in scroll mode
+pub(crate) fn has_scroll_encoder(encoder_config: &[EncoderConfig]) -> bool {
+    encoder_config
+        .iter()
+        .any(|encoder| encoder.mode.as_deref() == Some("scroll"))
+}
+
+/// Expand the processor which converts encoder scroll events to mouse reports
+pub(crate) fn expand_encoder_scroll_processor() -> Initializer {
+    let processor_name = format_ident!("encoder_scroll_processor");
+    Initializer {
+        initializer: quote! {
+            let mut #processor_name = ::rmk::input_device::rotary_encoder::EncoderScrollProcessor::new(&keymap);
+        },
+        var_name: processor_name,
+    }
+}
+
+/// Expand the encoder config of the behavior config, which is derived from the behaviors of the encoders of all boards
+pub(crate) fn expand_encoders_config(keyboard_config: &KeyboardTomlConfig) -> proc_macro2::TokenStream {
+    let encoders = keyboard_config.get_board_config().unwrap().get_encoders();
+    if encoders.len() > 32 && encoders[32..].iter().any(|e| e.mode.as_deref() == Some("hold")) {
+        return quote! { compile_error!("The `hold` mode is only available for the first 32 encoders") };
+    }
+    let behaviors = encoders.iter().map(expand_encoder_behavior);
+    quote! {
+        ::rmk::config::EncodersConfig::from_behaviors(&[#(#behaviors),*])
+    }
+}
+
+/// Expand the acceleration and output mode of an encoder
+fn expand_encoder_behavior(encoder: &EncoderConfig) -> proc_macro2::TokenStream {
+    let acceleration = match &encoder.acceleration {
+        Some(acceleration) => {
+            let threshold_ms = acceleration.threshold.0.min(u16::MAX as u64) as u16;
+            let max_multiplier = acceleration.max_multiplier;
+            quote! {
+                Some(::rmk::input_device::rotary_encoder::EncoderAcceleration {
+                    threshold_ms: #threshold_ms,
+                    max_multiplier: #max_multiplier,
+                })
+            }
+        }
+        None => quote! { None },
+    };
+
+    let mode = match encoder.mode.as_deref() {
+        None | Some("key") => quote! { ::rmk::input_device::rotary_encoder::EncoderMode::Key },
+        Some("hold") => {
+            let idle_timeout_ms = encoder
+                .idle_timeout
+                .as_ref()
+                .map(|t| t.0.min(u16::MAX as u64) as u16)
+                .unwrap_or(500);
+            quote! {
+                ::rmk::input_device::rotary_encoder::EncoderMode::HoldUntilIdle { idle_timeout_ms: #idle_timeout_ms }
+            }
+        }
+        Some("scroll") => {
+            let horizontal = match encoder.scroll_axis.as_deref() {
+                None | Some("vertical") => false,
+                Some("horizontal") => true,
+                Some(axis) => {
+                    let message = format!("Invalid encoder scroll axis {axis}, available axis: vertical, horizontal");
+                    return quote! { compile_error!(#message) };
+                }
+            };
+            let units_per_detent = encoder.scroll_units.unwrap_or(120);
+            quote! {
+                ::rmk::input_device::rotary_encoder::EncoderMode::Scroll {
+                    horizontal: #horizontal,
+                    units_per_detent: #units_per_detent,
+                }
+            }
+        }
+        Some(mode) => {
+            let message = format!("Invalid rotary encoder mode {mode}, available mode: key, hold, scroll");
+            return quote! { compile_error!(#message) };
+        }
+    };
+
+    quote! {
+        ::rmk::input_device::rotary_encoder::EncoderBehavior {
+            acceleration: #acceleration,
+            mode: #mode,
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use adc::expand_adc_device;
-use encoder::expand_encoder_device;
+use encoder::{expand_encoder_device, expand_encoder_scroll_processor, has_scroll_encoder};
 use proc_macro2::{Ident, TokenStream};
 use quote::quote;
 use rmk_config::{BoardConfig, CommunicationConfig, InputDeviceConfig, KeyboardTomlConfig, UniBodyConfig};
@@ -62,7 +62,7 @@ pub(crate) fn expand_input_device_config(
     }
 
     // generate encoder configuration
-    let (device_initializer, processor_initializer) = match &board {
+    let (device_initializer, mut processor_initializer) = match &board {
         BoardConfig::UniBody(UniBodyConfig { input_device, .. }) => {
             expand_encoder_device(0, input_device.clone().encoder.unwrap_or(Vec::new()), &chip)
         }
@@ -78,6 +78,18 @@ pub(crate) fn expand_input_device_config(
             &chip,
         ),
     };
+    // Scroll events from peripherals' encoders are processed by the central
+    if let BoardConfig::Split(split_config) = &board
+        && processor_initializer.is_empty()
+        && split_config.peripheral.iter().any(|p| {
+            p.input_device
+                .as_ref()
+                .and_then(|d| d.encoder.as_ref())
+                .is_some_and(|e| has_scroll_encoder(e))
+        })
+    {
+        processor_initializer.push(expand_encoder_scroll_processor());
+    }
     for initializer in device_initializer {
         initialization.extend(initializer.initializer);
         let device_name = initializer.var_name;
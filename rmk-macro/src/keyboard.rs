@@ -1,17 +1,19 @@
 use darling::FromMeta;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::quote;
-use rmk_config::{BoardConfig, ChipSeries, KeyboardTomlConfig, MatrixType, UniBodyConfig};
+use quote::{format_ident, quote};
+use rmk_config::{BoardConfig, ChipSeries, KeyboardTomlConfig, MatrixConfig, MatrixType, UniBodyConfig};
 use syn::ItemMod;
 
 use crate::behavior::expand_behavior_config;
 use crate::bind_interrupt::expand_bind_interrupt;
 use crate::ble::expand_ble_config;
+use crate::bootloader::{check_bootloader_features, expand_bootloader_init, expand_bootmagic_config};
 use crate::chip_init::expand_chip_init;
 use crate::comm::expand_usb_init;
 use crate::controller::expand_controller_init;
 use crate::entry::expand_rmk_entry;
 use crate::feature::{get_rmk_features, is_feature_enabled};
+use crate::firmware::expand_firmware_metadata;
 use crate::flash::expand_flash_init;
 use crate::import::expand_custom_imports;
 use crate::input_device::expand_input_device_config;
@@ -33,7 +35,10 @@ pub enum Overwritten {
 pub(crate) fn parse_keyboard_mod(item_mod: ItemMod) -> TokenStream2 {
     let rmk_features = get_rmk_features();
 
-    let keyboard_config = read_keyboard_toml_config();
+    let keyboard_config = match read_keyboard_toml_config() {
+        Ok(config) => config,
+        Err(errors) => return errors,
+    };
 
     if keyboard_config.get_storage_config().enabled != is_feature_enabled(&rmk_features, "storage") {
         if keyboard_config.get_storage_config().enabled {
@@ -47,6 +52,8 @@ pub(crate) fn parse_keyboard_mod(item_mod: ItemMod) -> TokenStream2 {
         }
     }
 
+    check_bootloader_features(&keyboard_config, &rmk_features);
+
     // Generate imports and statics
     let imports_and_statics = expand_imports_and_constants(&keyboard_config);
 
@@ -116,6 +123,9 @@ fn expand_main(
     // Expand components of main function
     let imports = expand_custom_imports(&item_mod);
     let bind_interrupt = expand_bind_interrupt(keyboard_config, &item_mod);
+    let (bootloader_check, bootloader_init) = expand_bootloader_init(keyboard_config, true);
+    let bootmagic_config = expand_bootmagic_config(keyboard_config, None);
+    let firmware_metadata = expand_firmware_metadata(keyboard_config, 0);
     let chip_init = expand_chip_init(keyboard_config, None, &item_mod);
     let usb_init = expand_usb_init(keyboard_config, &item_mod);
     let flash_init = expand_flash_init(keyboard_config);
@@ -168,6 +178,12 @@ fn expand_main(
         #bind_interrupt
 
         #main_function_sig {
+            // Check whether to jump to the bootloader before initializing peripherals
+            #bootloader_check
+
+            // Embed the firmware metadata as `RMK_FIRMWARE_METADATA`
+            #firmware_metadata
+
             // Initialize peripherals as `p`
             #chip_init
 
@@ -183,6 +199,10 @@ fn expand_main(
             // Initialize flash driver as `flash` and storage config as `storage_config`
             #flash_init
 
+            // Set the bootloader used by `KeyCode::Bootloader` and VIA
+            #bootloader_init
+            #bootmagic_config
+
             // Initialize ble config as `ble_battery_config`
             #ble_config
 
@@ -211,6 +231,11 @@ fn expand_main(
 }
 
 pub(crate) fn expand_keymap_and_storage(keyboard_config: &KeyboardTomlConfig) -> TokenStream2 {
+    // The default keymap is kept in flash, which is restored by the VIA keymap reset and layout options
+    let default_keymap = quote! {
+        static DEFAULT_KEYMAP: [[[::rmk::action::KeyAction; COL]; ROW]; NUM_LAYER] = get_default_keymap();
+        let mut default_keymap = get_default_keymap();
+    };
     if keyboard_config.get_storage_config().enabled {
         let num_encoders = keyboard_config.get_board_config().unwrap().get_num_encoder();
         let total_num_encoders = num_encoders.iter().sum::<usize>();
@@ -236,28 +261,110 @@ pub(crate) fn expand_keymap_and_storage(keyboard_config: &KeyboardTomlConfig) ->
                 )
             }
         };
-        let default_encoder_keymap = if total_num_encoders == 0 {
-            quote! {}
+        let (default_encoder_keymap, default_encoders) = if total_num_encoders == 0 {
+            (quote! {}, quote! { None })
         } else {
-            quote! {
-                let mut encoder_keymap = get_default_encoder_map();
-            }
+            (
+                quote! {
+                    static DEFAULT_ENCODER_MAP: [[::rmk::action::EncoderAction; NUM_ENCODER]; NUM_LAYER] = get_default_encoder_map();
+                    let mut encoder_keymap = get_default_encoder_map();
+                },
+                quote! { Some(&DEFAULT_ENCODER_MAP) },
+            )
         };
         // Return the keymap and storage initialization code
         quote! {
-            let mut default_keymap = get_default_keymap();
+            #default_keymap
             #default_encoder_keymap
             let (keymap, mut storage) =  #keymap_storage_init.await;
+            keymap.borrow_mut().set_default_keymap(&DEFAULT_KEYMAP, #default_encoders);
         }
     } else {
         // Return the keymap initialization code
         quote! {
-            let mut default_keymap = get_default_keymap();
+            #default_keymap
             let keymap =  ::rmk::initialize_keymap(
                 &mut default_keymap,
                 &mut behavior_config,
             ).await;
+            keymap.borrow_mut().set_default_keymap(&DEFAULT_KEYMAP, None);
+        }
+    }
+}
+
+/// Create the analog matrix from `analog_source`, with the calibration saved in the storage
+fn expand_analog_matrix(keyboard_config: &KeyboardTomlConfig, matrix_config: &MatrixConfig) -> TokenStream2 {
+    let analog = matrix_config.analog.clone().unwrap_or_default();
+    let optional = |value: Option<TokenStream2>, field: &str| {
+        let field = format_ident!("{}", field);
+        value.map(|v| quote! { #field: #v, })
+    };
+    let config_fields = [
+        optional(analog.total_travel.map(|v| quote! { #v }), "total_travel"),
+        optional(analog.default_range.map(|v| quote! { #v }), "default_range"),
+        optional(analog.save_threshold.map(|v| quote! { #v }), "save_threshold"),
+    ];
+    let key_fields = [
+        optional(analog.actuation_point.map(|v| quote! { #v }), "actuation_point"),
+        optional(analog.release_point.map(|v| quote! { #v }), "release_point"),
+        optional(
+            analog.rapid_trigger.map(|rt| {
+                let (press, release) = (rt.press_sensitivity, rt.release_sensitivity);
+                quote! {
+                    Some(::rmk::input_device::adc::analog_matrix::RapidTrigger {
+                        press_sensitivity: #press,
+                        release_sensitivity: #release,
+                    })
+                }
+            }),
+            "rapid_trigger",
+        ),
+    ];
+    let dual_actions = analog.dual_actions.unwrap_or_default();
+    let matrix_mut = if dual_actions.is_empty() {
+        quote! {}
+    } else {
+        quote! { mut }
+    };
+    let set_dual_actions = dual_actions.iter().map(|dual_action| {
+        let [row, col] = dual_action.key.map(|v| v as usize);
+        let [action_row, action_col] = dual_action.action_key;
+        let actuation_point = dual_action.actuation_point;
+        quote! {
+            matrix.set_key_config(#row, #col, ::rmk::input_device::adc::analog_matrix::AnalogKeyConfig {
+                dual_action: Some(::rmk::input_device::adc::analog_matrix::DualAction {
+                    actuation_point: #actuation_point,
+                    row: #action_row,
+                    col: #action_col,
+                }),
+                ..analog_config.key
+            });
         }
+    });
+    // Keys are calibrated at runtime when the storage is disabled
+    let with_calibration = if keyboard_config.get_storage_config().enabled {
+        quote! {
+            .with_calibration({
+                let mut calibration = [[::rmk::input_device::adc::analog_matrix::KeyCalibration::default(); COL]; ROW];
+                storage.read_analog_calibration(&mut calibration).await.ok();
+                calibration
+            })
+        }
+    } else {
+        quote! {}
+    };
+    quote! {
+        let analog_config = ::rmk::input_device::adc::analog_matrix::AnalogMatrixConfig {
+            #(#config_fields)*
+            key: ::rmk::input_device::adc::analog_matrix::AnalogKeyConfig {
+                #(#key_fields)*
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let #matrix_mut matrix = ::rmk::input_device::adc::analog_matrix::AnalogMatrix::<_, ROW, COL>::new(analog_source, analog_config)
+            #with_calibration;
+        #(#set_dual_actions)*
     }
 }
 
@@ -302,6 +409,7 @@ pub(crate) fn expand_matrix_and_keyboard_init(
                     let mut matrix = ::rmk::direct_pin::DirectPinMatrix::<_, _, #input_output_num, SIZE>::new(direct_pins, debouncer, #low_active);
                 }
             }
+            MatrixType::analog => expand_analog_matrix(keyboard_config, &matrix_config),
         },
         BoardConfig::Split(split_config) => {
             // Matrix config for split central
@@ -330,6 +438,8 @@ pub(crate) fn expand_matrix_and_keyboard_init(
                         let mut matrix = ::rmk::split::central::CentralDirectPinMatrix::<_, _, #central_row_offset, #central_col_offset, #central_row, #central_col, #size>::new(direct_pins, debouncer, #low_active);
                     }
                 }
+                // The error is reported when expanding the matrix config
+                MatrixType::analog => quote! {},
             }
         }
     };
@@ -1,12 +1,22 @@
+use std::io::Read;
+
 use quote::quote;
 use rmk_config::KeyboardTomlConfig;
+use xz2::read::XzEncoder;
 
-pub(crate) fn read_keyboard_toml_config() -> KeyboardTomlConfig {
+/// Read and validate `keyboard.toml`, errors in the file are returned as `compile_error!`s
+pub(crate) fn read_keyboard_toml_config() -> Result<KeyboardTomlConfig, proc_macro2::TokenStream> {
     // Get the path of the keyboard config file from the environment variable
     let config_toml_path =
         std::env::var("KEYBOARD_TOML_PATH").expect("[ERROR]: KEYBOARD_TOML_PATH should be set in `.cargo/config.toml`");
 
-    KeyboardTomlConfig::new_from_toml_path(&config_toml_path)
+    KeyboardTomlConfig::try_new_from_toml_path(&config_toml_path).map_err(|e| {
+        let errors = e
+            .messages()
+            .into_iter()
+            .map(|message| quote! { compile_error!(#message); });
+        quote! { #(#errors)* }
+    })
 }
 
 pub(crate) fn expand_keyboard_info(keyboard_config: &KeyboardTomlConfig) -> proc_macro2::TokenStream {
@@ -40,6 +50,13 @@ pub(crate) fn expand_keyboard_info(keyboard_config: &KeyboardTomlConfig) -> proc
 }
 
 pub(crate) fn expand_vial_config(config: &KeyboardTomlConfig) -> proc_macro2::TokenStream {
+    let lock_timeout = match config.security.as_ref().and_then(|s| s.lock_timeout.as_ref()) {
+        Some(timeout) => {
+            let millis = timeout.0;
+            quote! { Some(::embassy_time::Duration::from_millis(#millis)) }
+        }
+        None => quote! { None },
+    };
     let unlock_keys = if let Some(security_config) = &config.security {
         let keys_expr = security_config
             .unlock_keys
@@ -54,12 +71,38 @@ pub(crate) fn expand_vial_config(config: &KeyboardTomlConfig) -> proc_macro2::To
     } else {
         quote! { &[] }
     };
+    // Generate the Vial definition from `keyboard.toml` if the physical layout is set, otherwise use `vial.json` processed by build.rs.
+    // If build.rs doesn't generate it, e.g. `vial.json` is missing, fall back to the definition from `keyboard.toml`
+    let generated = std::env::var_os("OUT_DIR")
+        .map(|dir| std::path::Path::new(&dir).join("config_generated.rs"))
+        .is_some_and(|path| path.exists());
+    let vial_definition = if config.has_physical_layout() || !generated {
+        let definition = match config.get_vial_definition() {
+            Ok(definition) => definition,
+            Err(e) => return quote! { compile_error!(#e); },
+        };
+        let mut compressed = Vec::new();
+        XzEncoder::new(definition.as_bytes(), 6)
+            .read_to_end(&mut compressed)
+            .expect("Failed to compress the Vial definition");
+        let len = compressed.len();
+        let keyboard_id = config.get_vial_keyboard_id();
+        quote! {
+            const VIAL_KEYBOARD_DEF: [u8; #len] = [#(#compressed), *];
+            const VIAL_KEYBOARD_ID: [u8; 8] = [#(#keyboard_id), *];
+        }
+    } else {
+        quote! {
+            include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
+        }
+    };
     quote! {
-        include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
+        #vial_definition
         const VIAL_CONFIG: ::rmk::config::VialConfig = ::rmk::config::VialConfig {
             vial_keyboard_id: &VIAL_KEYBOARD_ID,
             vial_keyboard_def: &VIAL_KEYBOARD_DEF,
-            unlock_keys: #unlock_keys
+            unlock_keys: #unlock_keys,
+            lock_timeout: #lock_timeout,
         };
     }
 }
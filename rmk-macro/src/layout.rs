@@ -13,8 +13,14 @@ pub(crate) fn expand_default_keymap(keyboard_config: &KeyboardTomlConfig) -> Tok
 
     let mut layers = vec![];
     let mut encoder_map = vec![];
-    for layer in keyboard_config.get_layout_config().unwrap().keymap {
-        layers.push(expand_layer(layer));
+    for (layer_number, layer) in keyboard_config
+        .get_layout_config()
+        .unwrap()
+        .keymap
+        .into_iter()
+        .enumerate()
+    {
+        layers.push(expand_layer(layer_number as u8, layer));
         encoder_map.push(quote! { [#(#encoders), *] });
     }
 
@@ -30,19 +36,19 @@ pub(crate) fn expand_default_keymap(keyboard_config: &KeyboardTomlConfig) -> Tok
 }
 
 /// Push rows in the layer
-fn expand_layer(layer: Vec<Vec<String>>) -> TokenStream2 {
+fn expand_layer(layer_number: u8, layer: Vec<Vec<String>>) -> TokenStream2 {
     let mut rows = vec![];
-    for row in layer {
-        rows.push(expand_row(row));
+    for (row_number, row) in layer.into_iter().enumerate() {
+        rows.push(expand_row(layer_number, row_number as u8, row));
     }
     quote! { [#(#rows), *] }
 }
 
 /// Push keys in the row
-fn expand_row(row: Vec<String>) -> TokenStream2 {
+fn expand_row(layer_number: u8, row_number: u8, row: Vec<String>) -> TokenStream2 {
     let mut keys = vec![];
-    for key in row {
-        keys.push(parse_key(key));
+    for (col_number, key) in row.into_iter().enumerate() {
+        keys.push(expand_key_at(key, layer_number, row_number, col_number as u8));
     }
     quote! { [#(#keys), *] }
 }
@@ -120,15 +126,31 @@ fn parse_modifiers(modifiers_str: &str) -> ModifierCombinationMacro {
     combination
 }
 
+/// Parse the key string at a single position, errors are returned as `compile_error!`
+pub(crate) fn expand_key(key: String) -> TokenStream2 {
+    parse_key(key).unwrap_or_else(|e| quote! { compile_error!(#e) })
+}
+
+/// Same as `expand_key`, but the error message contains the position of the key in the keymap
+pub(crate) fn expand_key_at(key: String, layer: u8, row: u8, col: u8) -> TokenStream2 {
+    parse_key(key).unwrap_or_else(|e| {
+        let message = format!(
+            "keyboard.toml: Error in key ({}, {}) of layer {}: {}",
+            row, col, layer, e
+        );
+        quote! { compile_error!(#message) }
+    })
+}
+
 /// Parse the key string at a single position
-pub(crate) fn parse_key(key: String) -> TokenStream2 {
+pub(crate) fn parse_key(key: String) -> Result<TokenStream2, String> {
     if !key.is_empty() && (key.trim_start_matches("_").is_empty() || key.to_lowercase() == "trns") {
-        return quote! { ::rmk::a!(Transparent) };
+        return Ok(quote! { ::rmk::a!(Transparent) });
     } else if !key.is_empty() && key == "No" {
-        return quote! { ::rmk::a!(No) };
+        return Ok(quote! { ::rmk::a!(No) });
     }
 
-    match key {
+    let action = match key {
         s if s.to_lowercase().starts_with("wm(") => {
             let prefix = s.get(0..3).unwrap();
             if let Some(internal) = s.trim_start_matches(prefix).strip_suffix(")") {
@@ -138,9 +160,7 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     .filter(|w| !w.is_empty())
                     .collect();
                 if keys.len() != 2 {
-                    panic!(
-                        "\n❌ keyboard.toml: WM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: WM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
 
                 let ident = get_key_with_alias(keys[0].to_string());
@@ -148,27 +168,23 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                 let modifiers = parse_modifiers(keys[1]);
 
                 if modifiers.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: modifier in WM(layer, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: modifier in WM(layer, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 quote! {
                     ::rmk::wm!(#ident, #modifiers)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: WM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: WM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("mo(") => {
-            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::mo!(#layer)
             }
         }
         s if s.to_lowercase().starts_with("osl(") => {
-            let layer = get_number(s.clone(), s.get(0..4).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..4).unwrap(), ")")?;
             quote! {
                 ::rmk::osl!(#layer)
             }
@@ -179,19 +195,57 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                 let modifiers = parse_modifiers(internal);
 
                 if modifiers.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: modifier in OSM(modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: modifier in OSM(modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 quote! {
                     ::rmk::osm!(#modifiers)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: OSM(modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: OSM(modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
+            }
+        }
+        s if s.to_lowercase().starts_with("mod(") => {
+            let prefix = s.get(0..4).unwrap();
+            if let Some(internal) = s.trim_start_matches(prefix).strip_suffix(")") {
+                let modifiers = parse_modifiers(internal);
+
+                if modifiers.is_empty() {
+                    return Err("\n❌ keyboard.toml: modifier in MOD(modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
+                }
+                quote! {
+                    ::rmk::modifier!(#modifiers)
+                }
+            } else {
+                return Err("\n❌ keyboard.toml: MOD(modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
+            }
+        }
+        s if s.to_lowercase().starts_with("osk(") => {
+            let prefix = s.get(0..4).unwrap();
+            match s.trim_start_matches(prefix).strip_suffix(")").map(|k| k.trim()) {
+                Some(internal) if !internal.is_empty() => {
+                    let key = get_key_with_alias(internal.to_string());
+                    quote! { ::rmk::osk!(#key) }
+                }
+                _ => return Err("\n❌ keyboard.toml: OSK(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string()),
             }
         }
+        s if s.to_lowercase().starts_with("tap(") => {
+            let prefix = s.get(0..4).unwrap();
+            match s.trim_start_matches(prefix).strip_suffix(")").map(|k| k.trim()) {
+                Some(internal) if !internal.is_empty() => {
+                    let key = get_key_with_alias(internal.to_string());
+                    quote! { ::rmk::tap!(#key) }
+                }
+                _ => return Err("\n❌ keyboard.toml: TAP(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string()),
+            }
+        }
+        s if s.starts_with('"') => {
+            // Inline text macros are converted to `Macro(n)` in the keymap, they are not available elsewhere
+            return Err(format!(
+                "\n❌ keyboard.toml: inline text macro {} can only be used in the keymap, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html",
+                s
+            ));
+        }
         s if s.to_lowercase().starts_with("lm(") => {
             let prefix = s.get(0..3).unwrap();
             if let Some(internal) = s.trim_start_matches(prefix).strip_suffix(")") {
@@ -201,68 +255,81 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     .filter(|w| !w.is_empty())
                     .collect();
                 if keys.len() != 2 {
-                    panic!(
-                        "\n❌ keyboard.toml: LM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: LM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
-                let layer = keys[0].parse::<u8>().unwrap();
+                let layer = keys[0]
+                    .parse::<u8>()
+                    .map_err(|_| format!("\n❌ keyboard.toml: invalid layer number in {}", s))?;
 
                 let modifiers = parse_modifiers(keys[1]);
 
                 if modifiers.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: modifier in LM(layer, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: modifier in LM(layer, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 quote! {
                     ::rmk::lm!(#layer, #modifiers)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: LM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: LM(layer, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("lt(") => {
             let prefix = s.get(0..3).unwrap();
+            // The tapped key can be `WM(key, modifier)`, so only split at the first comma
             let keys: Vec<&str> = s
                 .trim_start_matches(prefix)
-                .trim_end_matches(")")
-                .split_terminator(",")
+                .strip_suffix(")")
+                .unwrap_or_default()
+                .splitn(2, ",")
                 .map(|w| w.trim())
                 .filter(|w| !w.is_empty())
                 .collect();
             if keys.len() != 2 {
-                panic!(
-                    "\n❌ keyboard.toml: LT(layer, key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: LT(layer, key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
-            let layer = keys[0].parse::<u8>().unwrap();
-            let key = get_key_with_alias(keys[1].to_string());
-            quote! {
-                ::rmk::lt!(#layer, #key)
+            let layer = keys[0]
+                .parse::<u8>()
+                .map_err(|_| format!("\n❌ keyboard.toml: invalid layer number in {}", s))?;
+            if keys[1].to_lowercase().starts_with("wm(") {
+                let internal = keys[1].get(3..).and_then(|k| k.strip_suffix(")")).unwrap_or_default();
+                let Some((key, modifiers)) = internal.split_once(",") else {
+                    return Err("\n❌ keyboard.toml: LT(layer, WM(key, modifier)) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
+                };
+                let key = get_key_with_alias(key.trim().to_string());
+                let modifiers = parse_modifiers(modifiers);
+                if modifiers.is_empty() {
+                    return Err("\n❌ keyboard.toml: modifier in LT(layer, WM(key, modifier)) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
+                }
+                quote! {
+                    ::rmk::lt!(#layer, #key, #modifiers)
+                }
+            } else {
+                let key = get_key_with_alias(keys[1].to_string());
+                quote! {
+                    ::rmk::lt!(#layer, #key)
+                }
             }
         }
         s if s.to_lowercase().starts_with("tt(") => {
-            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::tt!(#layer)
             }
         }
         s if s.to_lowercase().starts_with("tg(") => {
-            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::tg!(#layer)
             }
         }
         s if s.to_lowercase().starts_with("to(") => {
-            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::to!(#layer)
             }
         }
         s if s.to_lowercase().starts_with("df(") => {
-            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let layer = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::df!(#layer)
             }
@@ -276,25 +343,19 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     .filter(|w| !w.is_empty())
                     .collect();
                 if keys.len() != 2 {
-                    panic!(
-                        "\n❌ keyboard.toml: MT(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: MT(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 let ident = get_key_with_alias(keys[0].to_string());
                 let modifiers = parse_modifiers(keys[1]);
 
                 if modifiers.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: modifier in MT(key, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: modifier in MT(key, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 quote! {
                     ::rmk::mt!(#ident, #modifiers)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: MT(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: MT(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("hrm(") => {
@@ -306,25 +367,19 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     .filter(|w| !w.is_empty())
                     .collect();
                 if keys.len() != 2 {
-                    panic!(
-                        "\n❌ keyboard.toml: HRM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: HRM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 let ident = get_key_with_alias(keys[0].to_string());
                 let modifiers = parse_modifiers(keys[1]);
 
                 if modifiers.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: modifier in HRM(key, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: modifier in HRM(key, modifier) is not valid! Please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 quote! {
                     ::rmk::hrm!(#ident, #modifiers)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: HRM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: HRM(key, modifier) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("th(") => {
@@ -336,9 +391,7 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     .filter(|w| !w.is_empty())
                     .collect();
                 if keys.len() != 2 {
-                    panic!(
-                        "\n❌ keyboard.toml: TH(key_tap, key_hold) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: TH(key_tap, key_hold) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 let ident1 = get_key_with_alias(keys[0].to_string());
                 let ident2 = get_key_with_alias(keys[1].to_string());
@@ -347,35 +400,29 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
                     ::rmk::th!(#ident1, #ident2)
                 }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: TH(key_tap, key_hold) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: TH(key_tap, key_hold) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("shifted(") => {
             let prefix = s.get(0..8).unwrap();
             if let Some(internal) = s.trim_start_matches(prefix).strip_suffix(")") {
                 if internal.is_empty() {
-                    panic!(
-                        "\n❌ keyboard.toml: SHIFTED(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                    );
+                    return Err("\n❌ keyboard.toml: SHIFTED(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
                 }
                 let key = get_key_with_alias(internal.to_string());
                 quote! { ::rmk::shifted!(#key) }
             } else {
-                panic!(
-                    "\n❌ keyboard.toml: SHIFTED(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html"
-                );
+                return Err("\n❌ keyboard.toml: SHIFTED(key) invalid, please check the documentation: https://rmk.rs/docs/features/configuration/layout.html".to_string());
             }
         }
         s if s.to_lowercase().starts_with("td(") => {
-            let index = get_number(s.clone(), s.get(0..3).unwrap(), ")");
+            let index = get_number(s.clone(), s.get(0..3).unwrap(), ")")?;
             quote! {
                 ::rmk::td!(#index)
             }
         }
         s if s.to_lowercase().starts_with("m(") => {
-            let index = get_number(s.clone(), s.get(0..2).unwrap(), ")");
+            let index = get_number(s.clone(), s.get(0..2).unwrap(), ")")?;
             quote! {
                 ::rmk::m!(#index)
             }
@@ -384,14 +431,17 @@ pub(crate) fn parse_key(key: String) -> TokenStream2 {
             let ident = get_key_with_alias(key);
             quote! { ::rmk::k!(#ident) }
         }
-    }
+    };
+    Ok(action)
 }
 
 /// Parse the string literal like `MO(1)`, `OSL(1)`, `TD(0)`, etc, get the number in it.
 /// The caller should pass the trimmed prefix and suffix
-fn get_number(key: String, prefix: &str, suffix: &str) -> u8 {
+fn get_number(key: String, prefix: &str, suffix: &str) -> Result<u8, String> {
     let layer_str = key.trim_start_matches(prefix).trim_end_matches(suffix);
-    layer_str.parse::<u8>().unwrap()
+    layer_str
+        .parse::<u8>()
+        .map_err(|_| format!("\n❌ keyboard.toml: invalid number in {}", key))
 }
 
 pub(crate) fn get_key_with_alias(key: String) -> Ident {
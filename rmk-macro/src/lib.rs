@@ -1,11 +1,13 @@
 mod behavior;
 mod bind_interrupt;
 mod ble;
+mod bootloader;
 mod chip_init;
 mod comm;
 mod controller;
 mod entry;
 mod feature;
+mod firmware;
 mod flash;
 mod gpio_config;
 mod import;
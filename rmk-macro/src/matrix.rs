@@ -1,12 +1,12 @@
 //! Initialize matrix initialization boilerplate of RMK
 //!
-use quote::quote;
-use rmk_config::{BoardConfig, ChipModel, ChipSeries, KeyboardTomlConfig, MatrixType, UniBodyConfig};
+use quote::{format_ident, quote};
+use rmk_config::{BoardConfig, ChipModel, ChipSeries, KeyboardTomlConfig, MatrixConfig, MatrixType, UniBodyConfig};
 
 use crate::feature::is_feature_enabled;
 use crate::gpio_config::{
-    convert_direct_pins_to_initializers, convert_input_pins_to_initializers, convert_output_pins_to_initializers,
-    get_input_pin_type, get_output_pin_type,
+    convert_direct_pins_to_initializers, convert_gpio_str_to_output_pin, convert_input_pins_to_initializers,
+    convert_output_pins_to_initializers, get_input_pin_type, get_output_pin_type,
 };
 
 pub(crate) fn expand_matrix_config(
@@ -48,6 +48,9 @@ pub(crate) fn expand_matrix_config(
                     let low_active = #low_active;
                 });
             }
+            MatrixType::analog => {
+                matrix_config.extend(expand_analog_source(&keyboard_config.get_chip_model().unwrap(), matrix));
+            }
         },
         BoardConfig::Split(split_config) => {
             // Matrix config for split central
@@ -64,12 +67,51 @@ pub(crate) fn expand_matrix_config(
                     async_matrix,
                     split_config.central.matrix.direct_pin_low_active,
                 )),
+                MatrixType::analog => matrix_config.extend(quote! {
+                    compile_error!("The analog matrix isn't supported on split keyboards in keyboard.toml");
+                }),
             }
         }
     };
     matrix_config
 }
 
+/// Initialize the SAADC and select pins of the analog matrix as `analog_source`
+pub(crate) fn expand_analog_source(chip: &ChipModel, matrix: &MatrixConfig) -> proc_macro2::TokenStream {
+    if chip.series != ChipSeries::Nrf52 {
+        return quote! {
+            compile_error!("The analog matrix is only supported on nRF52 in keyboard.toml");
+        };
+    }
+    let channel_cfg = matrix.adc_pins.clone().unwrap().into_iter().map(|pin| {
+        let pin = format_ident!("{}", pin);
+        quote! { saadc::ChannelConfig::single_ended(p.#pin.degrade_saadc()) }
+    });
+    let select_pins = matrix
+        .select_pins
+        .clone()
+        .unwrap()
+        .into_iter()
+        .map(|pin| convert_gpio_str_to_output_pin(chip, pin, false));
+    let settle_time_us = matrix.analog.as_ref().and_then(|a| a.settle_time_us).unwrap_or(5) as u64;
+    quote! {
+        let analog_source = {
+            use embassy_nrf::saadc::{self, Input as _};
+            ::embassy_nrf::bind_interrupts!(struct AnalogSaadcIrqs {
+                SAADC => ::embassy_nrf::saadc::InterruptHandler;
+            });
+            embassy_nrf::interrupt::SAADC.set_priority(embassy_nrf::interrupt::Priority::P3);
+            let adc = saadc::Saadc::new(p.SAADC, AnalogSaadcIrqs, saadc::Config::default(), [#(#channel_cfg),*]);
+            adc.calibrate().await;
+            ::rmk::input_device::adc::analog_matrix::MuxAnalogSource::new(
+                adc,
+                [#(#select_pins),*],
+                ::embassy_time::Duration::from_micros(#settle_time_us),
+            )
+        };
+    }
+}
+
 pub(crate) fn expand_matrix_direct_pins(
     chip: &ChipModel,
     direct_pins: Vec<Vec<String>>,
@@ -22,7 +22,7 @@ fn expand_split_communication_config(chip: &ChipModel, split_config: &SplitConfi
                 let peripheral_addrs = ::rmk::split::ble::central::read_peripheral_addresses::<#num_peripheral, _, ROW, COL, NUM_LAYER, NUM_ENCODER>(&mut storage).await;
             }
         }
-        "serial" => {
+        "serial" | "serial_bus" => {
             // We need to initialize serial instance for serial
             let serial_config: Vec<SerialConfig> =
                 split_config.central.serial.clone().expect("central.serial is required");
@@ -72,7 +72,7 @@ pub(crate) fn expand_serial_init(chip: &ChipModel, serial: Vec<SerialConfig>) ->
                     }
                     i if i.starts_with("PIO") => {
                         let uart_irq = format_ident!("{}_IRQ_0", s.instance);
-                        let instance_init = if s.rx_pin.eq(&s.tx_pin) {
+                        let instance_init = if s.is_half_duplex() {
                             quote! {
                                 let #uart_name = ::rmk::split::rp::uart::BufferedUart::new_half_duplex(
                                     p.#uart_instance,
@@ -103,6 +103,121 @@ pub(crate) fn expand_serial_init(chip: &ChipModel, serial: Vec<SerialConfig>) ->
                     _ => panic!("Serial instance {:?} is not recognised", s.instance),
                 }
             }
+            ChipSeries::Stm32 => {
+                let uart_instance = format_ident!("{}", s.instance);
+                let uart_name = format_ident!("{}", s.instance.to_lowercase());
+                let tx_pin = format_ident!("{}", s.tx_pin);
+                let rx_pin = format_ident!("{}", s.rx_pin);
+                let irq_name = format_ident!("IrqsUart{}", idx);
+                let instance_init = if s.is_half_duplex() {
+                    // The USART switches the direction in half-duplex mode, the echo is read back to detect collisions
+                    quote! {
+                        let #uart_name = ::rmk::split::serial::HalfDuplexSerial::new(
+                            ::embassy_stm32::usart::BufferedUart::new_half_duplex(
+                                p.#uart_instance,
+                                p.#tx_pin,
+                                #tx_buf_name,
+                                #rx_buf_name,
+                                #irq_name,
+                                ::embassy_stm32::usart::Config::default(),
+                                ::embassy_stm32::usart::HalfDuplexReadback::Readback,
+                                ::embassy_stm32::usart::HalfDuplexConfig::OpenDrainInternal,
+                            )
+                            .expect("Failed to initialize half-duplex serial"),
+                            ::rmk::split::serial::AutoDirection,
+                            ::rmk::split::serial::HalfDuplexConfig::default(),
+                        );
+                    }
+                } else {
+                    quote! {
+                        let #uart_name = ::embassy_stm32::usart::BufferedUart::new(
+                            p.#uart_instance,
+                            p.#rx_pin,
+                            p.#tx_pin,
+                            #tx_buf_name,
+                            #rx_buf_name,
+                            #irq_name,
+                            ::embassy_stm32::usart::Config::default(),
+                        )
+                        .expect("Failed to initialize serial");
+                    }
+                };
+                quote! {
+                    ::embassy_stm32::bind_interrupts!(struct #irq_name {
+                        #uart_instance => ::embassy_stm32::usart::BufferedInterruptHandler<::embassy_stm32::peripherals::#uart_instance>;
+                    });
+                    #instance_init
+                }
+            }
+            ChipSeries::Nrf52 => {
+                let uart_instance = format_ident!("{}", s.instance);
+                let uart_name = format_ident!("{}", s.instance.to_lowercase());
+                let tx_pin = format_ident!("{}", s.tx_pin);
+                let rx_pin = format_ident!("{}", s.rx_pin);
+                let irq_name = format_ident!("IrqsUart{}", idx);
+                // Each buffered UARTE takes a timer, two PPI channels and a PPI group to count the received bytes
+                let timer = format_ident!("TIMER{}", idx + 1);
+                let ppi_ch1 = format_ident!("PPI_CH{}", idx * 2);
+                let ppi_ch2 = format_ident!("PPI_CH{}", idx * 2 + 1);
+                let ppi_group = format_ident!("PPI_GROUP{}", idx);
+                let instance_init = if s.is_half_duplex() {
+                    // TXD and RXD of the UARTE are connected to the same pin. The pin is switched to output only when
+                    // transmitting, and its input is kept connected, so that the echo is read back to detect collisions
+                    let direction = format_ident!("UarteDirection{}", idx);
+                    quote! {
+                        struct #direction(::embassy_nrf::gpio::Flex<'static>);
+                        impl ::rmk::split::serial::HalfDuplexDirection for #direction {
+                            fn set_transmit(&mut self) {
+                                self.0.set_as_input_output(::embassy_nrf::gpio::Pull::Up, ::embassy_nrf::gpio::OutputDrive::Standard);
+                            }
+                            fn set_receive(&mut self) {
+                                self.0.set_as_input(::embassy_nrf::gpio::Pull::Up);
+                            }
+                        }
+                        let direction_pin = ::embassy_nrf::gpio::Flex::new(unsafe { p.#tx_pin.clone_unchecked() });
+                        let txd_pin = unsafe { p.#tx_pin.clone_unchecked() };
+                        let #uart_name = ::rmk::split::serial::HalfDuplexSerial::new(
+                            ::embassy_nrf::buffered_uarte::BufferedUarte::new(
+                                p.#uart_instance,
+                                p.#timer,
+                                p.#ppi_ch1,
+                                p.#ppi_ch2,
+                                p.#ppi_group,
+                                p.#tx_pin,
+                                txd_pin,
+                                #irq_name,
+                                ::embassy_nrf::uarte::Config::default(),
+                                #rx_buf_name,
+                                #tx_buf_name,
+                            ),
+                            #direction(direction_pin),
+                            ::rmk::split::serial::HalfDuplexConfig::default(),
+                        );
+                    }
+                } else {
+                    quote! {
+                        let #uart_name = ::embassy_nrf::buffered_uarte::BufferedUarte::new(
+                            p.#uart_instance,
+                            p.#timer,
+                            p.#ppi_ch1,
+                            p.#ppi_ch2,
+                            p.#ppi_group,
+                            p.#rx_pin,
+                            p.#tx_pin,
+                            #irq_name,
+                            ::embassy_nrf::uarte::Config::default(),
+                            #rx_buf_name,
+                            #tx_buf_name,
+                        );
+                    }
+                };
+                quote! {
+                    ::embassy_nrf::bind_interrupts!(struct #irq_name {
+                        #uart_instance => ::embassy_nrf::buffered_uarte::InterruptHandler<::embassy_nrf::peripherals::#uart_instance>;
+                    });
+                    #instance_init
+                }
+            }
             _ => panic!("Serial for chip {:?} isn't implemented yet", chip.series),
         };
         uart_initializers.extend(quote! {
@@ -6,9 +6,11 @@ use rmk_config::{
 };
 use syn::ItemMod;
 
+use crate::bootloader::{check_bootloader_features, expand_bootloader_init, expand_bootmagic_config, expand_dfu_task};
 use crate::chip_init::expand_chip_init;
 use crate::entry::join_all_tasks;
 use crate::feature::{get_rmk_features, is_feature_enabled};
+use crate::firmware::expand_firmware_metadata;
 use crate::flash::expand_flash_init;
 use crate::import::expand_custom_imports;
 use crate::input_device::adc::expand_adc_device;
@@ -24,7 +26,12 @@ pub(crate) fn parse_split_peripheral_mod(id: usize, _attr: proc_macro::TokenStre
         panic!("\"split\" feature of RMK should be enabled");
     }
 
-    let toml_config = read_keyboard_toml_config();
+    let toml_config = match read_keyboard_toml_config() {
+        Ok(config) => config,
+        Err(errors) => return errors,
+    };
+
+    check_bootloader_features(&toml_config, &rmk_features);
 
     let main_function = expand_split_peripheral(id, &toml_config, item_mod, &rmk_features);
     let chip = toml_config.get_chip_model().unwrap();
@@ -149,7 +156,10 @@ fn expand_split_peripheral(
     let peripheral_config = split_config.peripheral.get(id).expect("Missing peripheral config");
 
     let imports = expand_custom_imports(&item_mod);
-    let mut chip_init = expand_chip_init(keyboard_config, Some(id), &item_mod);
+    let (bootloader_check, bootloader_init) = expand_bootloader_init(keyboard_config, split_config.connection == "ble");
+    let mut chip_init = bootloader_check;
+    chip_init.extend(expand_firmware_metadata(keyboard_config, id as u8 + 1));
+    chip_init.extend(expand_chip_init(keyboard_config, Some(id), &item_mod));
     if split_config.connection == "ble" {
         // Add storage when using BLE split
         let flash_init = expand_flash_init(keyboard_config);
@@ -158,6 +168,16 @@ fn expand_split_peripheral(
             let mut storage = ::rmk::storage::new_storage_for_split_peripheral(flash, storage_config).await;
         });
     }
+    chip_init.extend(bootloader_init);
+    chip_init.extend(expand_bootmagic_config(
+        keyboard_config,
+        Some((
+            peripheral_config.row_offset,
+            peripheral_config.col_offset,
+            peripheral_config.rows,
+            peripheral_config.cols,
+        )),
+    ));
 
     // Debouncer config
     let rapid_debouncer_enabled = is_feature_enabled(rmk_features, "rapid_debouncer");
@@ -223,11 +243,17 @@ fn expand_split_peripheral(
                 let mut matrix = ::rmk::direct_pin::DirectPinMatrix::<_, _, #row, #col, #size>::new(direct_pins, debouncer, #low_active);
             });
         }
+        MatrixType::analog => {
+            matrix_config.extend(quote! {
+                compile_error!("The analog matrix isn't supported on split keyboards in keyboard.toml");
+            });
+        }
     }
 
     // Peripherals don't need to run processors
     let (device_initialization, devices, _processors) = expand_peripheral_input_device_config(id, keyboard_config);
-    let run_rmk_peripheral = expand_split_peripheral_entry(id, &chip, split_config, peripheral_config, devices);
+    let run_rmk_peripheral =
+        expand_split_peripheral_entry(id, keyboard_config, &chip, split_config, peripheral_config, devices);
 
     quote! {
         #imports
@@ -240,6 +266,7 @@ fn expand_split_peripheral(
 
 fn expand_split_peripheral_entry(
     id: usize,
+    keyboard_config: &KeyboardTomlConfig,
     chip: &ChipModel,
     split_config: &SplitConfig,
     peripheral_config: &SplitBoardConfig,
@@ -253,6 +280,8 @@ fn expand_split_peripheral_entry(
             (#(#devs),*) => ::rmk::channel::EVENT_CHANNEL,
         )
     };
+    // The update of a peripheral is forwarded by the central
+    let dfu_task = expand_dfu_task(keyboard_config);
 
     if split_config.connection == "ble" {
         let peripheral_run = quote! {
@@ -262,11 +291,11 @@ fn expand_split_peripheral_entry(
                 &mut storage,
             )
         };
-        let run_rmk_peripheral = join_all_tasks(vec![device_task, peripheral_run]);
+        let run_rmk_peripheral = join_all_tasks([device_task, peripheral_run].into_iter().chain(dfu_task).collect());
         quote! {
             #run_rmk_peripheral
         }
-    } else if split_config.connection == "serial" {
+    } else if split_config.connection == "serial" || split_config.connection == "serial_bus" {
         let peripheral_serial = peripheral_config
             .serial
             .clone()
@@ -287,10 +316,17 @@ fn expand_split_peripheral_entry(
                 .instance
                 .to_lowercase()
         );
-        let peripheral_run = quote! {
-            ::rmk::split::peripheral::run_rmk_split_peripheral(#uart_instance)
+        let peripheral_run = if split_config.connection == "serial_bus" {
+            let addr = peripheral_config.bus_addr.unwrap_or(id as u8 + 1);
+            quote! {
+                ::rmk::split::bus::run_bus_split_peripheral(::rmk::split::bus::SerialBusTarget::new(#uart_instance, #addr))
+            }
+        } else {
+            quote! {
+                ::rmk::split::peripheral::run_rmk_split_peripheral(#uart_instance)
+            }
         };
-        let run_rmk_peripheral = join_all_tasks(vec![device_task, peripheral_run]);
+        let run_rmk_peripheral = join_all_tasks([device_task, peripheral_run].into_iter().chain(dfu_task).collect());
         quote! {
             #serial_init
             #run_rmk_peripheral
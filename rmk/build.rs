@@ -6,8 +6,13 @@ use std::process::Command;
 use std::{env, fs};
 
 use const_gen::*;
+use rmk_config::keycode_names::KEYCODE_NAMES;
+use rmk_config::vial::RMK_CUSTOM_MENUS;
 use rmk_config::{KeyboardTomlConfig, RmkConstantsConfig};
 
+/// Offset of the firmware metadata block in the image, see `rmk::firmware::FIRMWARE_METADATA_OFFSET`
+const FIRMWARE_METADATA_OFFSET: usize = 0x400;
+
 fn main() {
     // Set the compilation target configuration
     let mut cfgs = common::CfgSet::new();
@@ -18,6 +23,10 @@ fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=KEYBOARD_TOML_PATH");
     println!("cargo:rerun-if-env-changed=VIAL_JSON_PATH");
+    println!("cargo:rerun-if-changed=src/keycode.rs");
+
+    // The keycode names of `keyboard.toml` are validated against the list in rmk-config
+    check_keycode_names();
 
     // Read keyboard.toml if it's present
     let user_config_str = if let Ok(toml_path) = std::env::var("KEYBOARD_TOML_PATH") {
@@ -40,6 +49,106 @@ fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("constants.rs");
     fs::write(&dest_path, constants).expect("Failed to write constants.rs file");
+
+    // Write RMK settings of the VIA custom menus
+    let dest_path = Path::new(&out_dir).join("rmk_settings.rs");
+    fs::write(&dest_path, get_rmk_settings_str()).expect("Failed to write rmk_settings.rs file");
+
+    // Write the linker script which places the firmware metadata, the firmware links it by `-Trmk.x`.
+    // It depends on the sections of `cortex-m-rt`, so it's only generated for Cortex-M targets
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if target_arch == "arm" && target_os == "none" {
+        let dest_path = Path::new(&out_dir).join("rmk.x");
+        fs::write(&dest_path, get_linker_script_str()).expect("Failed to write rmk.x file");
+        println!("cargo:rustc-link-search={out_dir}");
+    }
+}
+
+/// Generate `rmk.x`, which places the `.rmk_metadata` section at a fixed offset of the image, after the vector table
+/// of `cortex-m-rt`. The code is moved after the section, the linker reports an error if the vector table overlaps it.
+fn get_linker_script_str() -> String {
+    format!(
+        "/* Generated by RMK, place the firmware metadata at a fixed offset of the image */
+SECTIONS
+{{
+  .rmk_metadata ORIGIN(FLASH) + {FIRMWARE_METADATA_OFFSET:#x} :
+  {{
+    KEEP(*(.rmk_metadata));
+  }} > FLASH
+}}
+INSERT AFTER .vector_table;
+
+_stext = ADDR(.rmk_metadata) + SIZEOF(.rmk_metadata);
+"
+    )
+}
+
+/// Generate `RmkSetting` and the sizes and ranges of its values from the VIA custom menus in `rmk-config`
+fn get_rmk_settings_str() -> String {
+    let values: Vec<_> = RMK_CUSTOM_MENUS.iter().flat_map(|menu| menu.values).collect();
+    let variants: String = values
+        .iter()
+        .map(|v| format!("    {} = {:#04x},\n", v.name, v.id))
+        .collect();
+    let sizes: String = values
+        .iter()
+        .map(|v| format!("            RmkSetting::{} => {},\n", v.name, v.ty.size()))
+        .collect();
+    let ranges: String = values
+        .iter()
+        .map(|v| {
+            let (min, max) = v.ty.range();
+            format!("            RmkSetting::{} => ({min}, {max}),\n", v.name)
+        })
+        .collect();
+    format!(
+        "/// RMK settings in the custom channel 0, generated from `RMK_CUSTOM_MENUS` in `rmk-config`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(feature = \"defmt\", derive(defmt::Format))]
+#[repr(u8)]
+pub(crate) enum RmkSetting {{
+{variants}}}
+
+impl RmkSetting {{
+    /// Size of the value in VIA packets
+    fn size(self) -> usize {{
+        match self {{
+{sizes}        }}
+    }}
+
+    /// Min and max of the value, which are the range of the control in VIA
+    fn range(self) -> (u16, u16) {{
+        match self {{
+{ranges}        }}
+    }}
+}}
+"
+    )
+}
+
+/// Check that `rmk_config::keycode_names::KEYCODE_NAMES` matches the variants of `KeyCode` in `src/keycode.rs`
+fn check_keycode_names() {
+    let source = fs::read_to_string("src/keycode.rs").expect("Failed to read src/keycode.rs");
+    let body = source
+        .split_once("pub enum KeyCode {")
+        .and_then(|(_, rest)| rest.split_once("\n}"))
+        .map(|(body, _)| body)
+        .expect("`pub enum KeyCode` is not found in src/keycode.rs");
+    // Variants are declared as `Name = 0x0000,`, one per line
+    let names: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, _)| name.trim())
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()))
+        .collect();
+    if names != KEYCODE_NAMES {
+        let missing: Vec<&&str> = names.iter().filter(|name| !KEYCODE_NAMES.contains(name)).collect();
+        let removed: Vec<&&str> = KEYCODE_NAMES.iter().filter(|name| !names.contains(name)).collect();
+        panic!(
+            "rmk-config/src/keycode_names.rs is out of sync with `KeyCode`, missing: {missing:?}, removed: {removed:?}"
+        );
+    }
 }
 
 fn get_constants_str(constants: RmkConstantsConfig) -> String {
@@ -67,6 +176,7 @@ fn get_constants_str(constants: RmkConstantsConfig) -> String {
         const_declaration!(pub(crate) SPLIT_CENTRAL_SLEEP_TIMEOUT_MINUTES = constants.split_central_sleep_timeout_minutes),
         const_declaration!(pub(crate) MORSE_MAX_NUM = constants.morse_max_num),
         const_declaration!(pub(crate) MAX_PATTERNS_PER_KEY = constants.max_patterns_per_key),
+        format!("pub(crate) const FIRMWARE_METADATA_OFFSET: usize = {FIRMWARE_METADATA_OFFSET:#x};\n"),
         format!("pub(crate) const BUILD_HASH: u32 = {build_hash:#010x};\n"),
     ]
     .map(|s| "#[allow(clippy::redundant_static_lifetimes)]\n".to_owned() + s.as_str())
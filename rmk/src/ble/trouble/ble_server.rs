@@ -1,3 +1,5 @@
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
 use ssmarshal::serialize;
 use trouble_host::prelude::*;
 use usbd_hid::descriptor::SerializedDescriptor;
@@ -6,12 +8,36 @@ use super::battery_service::BatteryService;
 use super::device_info::DeviceInformationService;
 use crate::channel::{KEYBOARD_REPORT_CHANNEL, VIAL_READ_CHANNEL};
 use crate::descriptor::{CompositeReport, CompositeReportType, KeyboardReport, ViaReport};
-use crate::hid::{HidError, HidReaderTrait, HidWriterTrait, Report, RunnableHidWriter};
+use crate::hid::{HidError, HidReaderTrait, HidWriterTrait, Report, RunnableHidWriter, ScrollAccumulator};
+#[cfg(feature = "dfu")]
+use {
+    crate::dfu::{
+        DFU_CONTROL_POINT_MAX_SIZE, DFU_PACKET_MAX_SIZE, DFU_RESPONSE_CHANNEL, DfuOpcode, DfuRequest, DfuResponse,
+        DfuStatus, send_dfu_request, send_dfu_response,
+    },
+    heapless::Vec,
+};
 
 // Used for saving the CCCD table
 pub(crate) const CCCD_TABLE_SIZE: usize = _CCCD_TABLE_SIZE;
 
+/// Whether the host selected the boot protocol via the Protocol Mode characteristic of the keyboard service.
+/// It's reset to the report protocol when a new connection is established.
+pub(crate) static BOOT_PROTOCOL: AtomicBool = AtomicBool::new(false);
+
 // GATT Server definition
+#[cfg(not(feature = "dfu"))]
+#[gatt_server]
+pub(crate) struct Server {
+    pub(crate) battery_service: BatteryService,
+    pub(crate) hid_service: HidService,
+    pub(crate) via_service: ViaService,
+    pub(crate) composite_service: CompositeService,
+    pub(crate) device_info_service: DeviceInformationService,
+}
+
+// GATT Server definition with the DFU service
+#[cfg(feature = "dfu")]
 #[gatt_server]
 pub(crate) struct Server {
     pub(crate) battery_service: BatteryService,
@@ -19,6 +45,7 @@ pub(crate) struct Server {
     pub(crate) via_service: ViaService,
     pub(crate) composite_service: CompositeService,
     pub(crate) device_info_service: DeviceInformationService,
+    pub(crate) dfu_service: DfuService,
 }
 
 #[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
@@ -37,27 +64,34 @@ pub(crate) struct HidService {
     #[descriptor(uuid = "2908", read, value = [0u8, 2u8])]
     #[characteristic(uuid = "2a4d", read, write, write_without_response)]
     pub(crate) output_keyboard: [u8; 1],
+    #[characteristic(uuid = "2a22", read, notify)]
+    pub(crate) boot_keyboard_input: [u8; 8],
+    #[characteristic(uuid = "2a32", read, write, write_without_response)]
+    pub(crate) boot_keyboard_output: [u8; 1],
 }
 
 #[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
 pub(crate) struct CompositeService {
     #[characteristic(uuid = "2a4a", read, value = [0x01, 0x01, 0x00, 0x03])]
     pub(crate) hid_info: [u8; 4],
-    #[characteristic(uuid = "2a4b", read, value = CompositeReport::desc().try_into().expect("Failed to convert CompositeReport to [u8; 111]"))]
-    pub(crate) report_map: [u8; 111],
+    #[characteristic(uuid = "2a4b", read, value = CompositeReport::desc().try_into().expect("Failed to convert CompositeReport to [u8; 185]"))]
+    pub(crate) report_map: [u8; 185],
     #[characteristic(uuid = "2a4c", write_without_response)]
     pub(crate) hid_control_point: u8,
     #[characteristic(uuid = "2a4e", read, write_without_response, value = 1)]
     pub(crate) protocol_mode: u8,
     #[descriptor(uuid = "2908", read, value = [CompositeReportType::Mouse as u8, 1u8])]
     #[characteristic(uuid = "2a4d", read, notify)]
-    pub(crate) mouse_report: [u8; 5],
+    pub(crate) mouse_report: [u8; 9],
     #[descriptor(uuid = "2908", read, value = [CompositeReportType::Media as u8, 1u8])]
     #[characteristic(uuid = "2a4d", read, notify)]
     pub(crate) media_report: [u8; 2],
     #[descriptor(uuid = "2908", read, value = [CompositeReportType::System as u8, 1u8])]
     #[characteristic(uuid = "2a4d", read, notify)]
     pub(crate) system_report: [u8; 1],
+    #[descriptor(uuid = "2908", read, value = [CompositeReportType::ResolutionMultiplier as u8, 3u8])]
+    #[characteristic(uuid = "2a4d", read, write, write_without_response)]
+    pub(crate) resolution_multiplier: u8,
 }
 
 #[gatt_service(uuid = service::HUMAN_INTERFACE_DEVICE)]
@@ -78,22 +112,39 @@ pub(crate) struct ViaService {
     pub(crate) output_via: [u8; 32],
 }
 
+/// Firmware update service, see [`crate::dfu`] for the protocol
+#[cfg(feature = "dfu")]
+#[gatt_service(uuid = "138b8c5c-60a1-4038-b323-5e931bbc64b0")]
+pub(crate) struct DfuService {
+    #[characteristic(uuid = "cc2ca17b-fa84-4992-baa0-4043ebdd272a", write, notify)]
+    pub(crate) control_point: Vec<u8, DFU_CONTROL_POINT_MAX_SIZE>,
+    #[characteristic(uuid = "957e356f-a5bf-4ac4-90b2-af156479dc21", write_without_response)]
+    pub(crate) packet: Vec<u8, DFU_PACKET_MAX_SIZE>,
+}
+
+/// The resolution multiplier feature report set by the BLE host, see [`ScrollAccumulator`]
+pub(crate) static BLE_RESOLUTION_MULTIPLIER: AtomicU8 = AtomicU8::new(0);
+
 pub(crate) struct BleHidServer<'stack, 'server, 'conn, P: PacketPool> {
     pub(crate) input_keyboard: Characteristic<[u8; 8]>,
-    pub(crate) mouse_report: Characteristic<[u8; 5]>,
+    pub(crate) boot_keyboard_input: Characteristic<[u8; 8]>,
+    pub(crate) mouse_report: Characteristic<[u8; 9]>,
     pub(crate) media_report: Characteristic<[u8; 2]>,
     pub(crate) system_report: Characteristic<[u8; 1]>,
     pub(crate) conn: &'conn GattConnection<'stack, 'server, P>,
+    scroll: ScrollAccumulator,
 }
 
 impl<'stack, 'server, 'conn, P: PacketPool> BleHidServer<'stack, 'server, 'conn, P> {
     pub(crate) fn new(server: &Server, conn: &'conn GattConnection<'stack, 'server, P>) -> Self {
         Self {
             input_keyboard: server.hid_service.input_keyboard,
+            boot_keyboard_input: server.hid_service.boot_keyboard_input,
             mouse_report: server.composite_service.mouse_report,
             media_report: server.composite_service.media_report,
             system_report: server.composite_service.system_report,
             conn,
+            scroll: ScrollAccumulator::new(&BLE_RESOLUTION_MULTIPLIER),
         }
     }
 }
@@ -102,18 +153,28 @@ impl<P: PacketPool> HidWriterTrait for BleHidServer<'_, '_, '_, P> {
     type ReportType = Report;
 
     async fn write_report(&mut self, report: Self::ReportType) -> Result<usize, HidError> {
+        let Some(target) = report_characteristic(&report, BOOT_PROTOCOL.load(Ordering::Acquire)) else {
+            debug!("Report is not supported via BLE in current protocol mode");
+            return Ok(0);
+        };
         match report {
             Report::KeyboardReport(keyboard_report) => {
                 let mut buf = [0u8; 8];
                 let n = serialize(&mut buf, &keyboard_report).map_err(|_| HidError::ReportSerializeError)?;
-                self.input_keyboard.notify(self.conn, &buf).await.map_err(|e| {
+                let characteristic = if target == ReportCharacteristic::BootKeyboard {
+                    &self.boot_keyboard_input
+                } else {
+                    &self.input_keyboard
+                };
+                characteristic.notify(self.conn, &buf).await.map_err(|e| {
                     error!("Failed to notify keyboard report: {:?}", e);
                     HidError::BleError
                 })?;
                 Ok(n)
             }
             Report::MouseReport(mouse_report) => {
-                let mut buf = [0u8; 5];
+                let mouse_report = self.scroll.convert(mouse_report);
+                let mut buf = [0u8; 9];
                 let n = serialize(&mut buf, &mouse_report).map_err(|_| HidError::ReportSerializeError)?;
                 self.mouse_report.notify(self.conn, &buf).await.map_err(|e| {
                     error!("Failed to notify mouse report: {:?}", e);
@@ -139,10 +200,42 @@ impl<P: PacketPool> HidWriterTrait for BleHidServer<'_, '_, '_, P> {
                 })?;
                 Ok(n)
             }
+            // Not available via BLE, which is filtered out by `report_characteristic`
+            #[cfg(any(feature = "digitizer", feature = "gamepad"))]
+            _ => Ok(0),
         }
     }
 }
 
+/// Characteristic of the BLE HID services which sends a report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportCharacteristic {
+    Keyboard,
+    BootKeyboard,
+    Mouse,
+    Media,
+    System,
+}
+
+/// Select the characteristic which sends the report, `None` if the report can't be sent in current protocol mode
+fn report_characteristic(report: &Report, boot_protocol: bool) -> Option<ReportCharacteristic> {
+    match report {
+        // The keyboard report has the same layout as the boot keyboard report
+        Report::KeyboardReport(_) if boot_protocol => Some(ReportCharacteristic::BootKeyboard),
+        Report::KeyboardReport(_) => Some(ReportCharacteristic::Keyboard),
+        // Only the keyboard is available in boot protocol
+        _ if boot_protocol => None,
+        Report::MouseReport(_) => Some(ReportCharacteristic::Mouse),
+        Report::MediaKeyboardReport(_) => Some(ReportCharacteristic::Media),
+        Report::SystemControlReport(_) => Some(ReportCharacteristic::System),
+        // The digitizer and the gamepad are only available via USB
+        #[cfg(feature = "digitizer")]
+        Report::DigitizerReport(_) => None,
+        #[cfg(feature = "gamepad")]
+        Report::GamepadReport(_) => None,
+    }
+}
+
 impl<P: PacketPool> RunnableHidWriter for BleHidServer<'_, '_, '_, P> {
     async fn get_report(&mut self) -> Self::ReportType {
         KEYBOARD_REPORT_CHANNEL.receive().await
@@ -191,3 +284,106 @@ impl<P: PacketPool> HidReaderTrait for BleViaServer<'_, '_, '_, P> {
         })
     }
 }
+
+#[cfg(feature = "dfu")]
+pub(crate) struct BleDfuServer<'stack, 'server, 'conn, P: PacketPool> {
+    pub(crate) control_point: Characteristic<Vec<u8, DFU_CONTROL_POINT_MAX_SIZE>>,
+    pub(crate) packet: Characteristic<Vec<u8, DFU_PACKET_MAX_SIZE>>,
+    pub(crate) conn: &'conn GattConnection<'stack, 'server, P>,
+}
+
+#[cfg(feature = "dfu")]
+impl<'stack, 'server, 'conn, P: PacketPool> BleDfuServer<'stack, 'server, 'conn, P> {
+    pub(crate) fn new(server: &Server, conn: &'conn GattConnection<'stack, 'server, P>) -> Self {
+        Self {
+            control_point: server.dfu_service.control_point.clone(),
+            packet: server.dfu_service.packet.clone(),
+            conn,
+        }
+    }
+
+    /// Process the write to the DFU service, returns false if the handle doesn't belong to the DFU service.
+    ///
+    /// The firmware can only be updated through an encrypted connection.
+    pub(crate) async fn process_write(&self, handle: u16, data: &[u8]) -> bool {
+        if handle != self.control_point.handle && handle != self.packet.handle {
+            return false;
+        }
+        if !self.conn.raw().encrypted() {
+            warn!("DFU is rejected because the connection is not encrypted");
+            return true;
+        }
+        let request = if handle == self.control_point.handle {
+            DfuRequest::from_control_point(data)
+        } else {
+            DfuRequest::from_packet(data)
+        };
+        match request {
+            Some(request) => send_dfu_request(request).await,
+            None => {
+                warn!("Invalid DFU request: {:?}", data);
+                let opcode = if handle == self.control_point.handle {
+                    data.first().copied().unwrap_or(0)
+                } else {
+                    DfuOpcode::Data as u8
+                };
+                send_dfu_response(DfuResponse {
+                    opcode,
+                    status: DfuStatus::InvalidCommand,
+                    offset: 0,
+                });
+            }
+        }
+        true
+    }
+
+    /// Notify the responses of the DFU task
+    pub(crate) async fn run(&self) {
+        // Drop the responses of previous connections
+        DFU_RESPONSE_CHANNEL.clear();
+        loop {
+            let response = DFU_RESPONSE_CHANNEL.receive().await;
+            let value = Vec::from_slice(&response.to_bytes()).unwrap_or_default();
+            if let Err(e) = self.control_point.notify(self.conn, &value).await {
+                error!("Failed to notify DFU response: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::{MediaKeyboardReport, SystemControlReport};
+
+    use super::*;
+    use crate::descriptor::MouseReport;
+
+    #[test]
+    fn test_report_characteristic() {
+        let keyboard = Report::KeyboardReport(KeyboardReport::default());
+        let mouse = Report::MouseReport(MouseReport::default());
+        let media = Report::MediaKeyboardReport(MediaKeyboardReport { usage_id: 0 });
+        let system = Report::SystemControlReport(SystemControlReport { usage_id: 0 });
+
+        // Report protocol
+        assert_eq!(
+            report_characteristic(&keyboard, false),
+            Some(ReportCharacteristic::Keyboard)
+        );
+        assert_eq!(report_characteristic(&mouse, false), Some(ReportCharacteristic::Mouse));
+        assert_eq!(report_characteristic(&media, false), Some(ReportCharacteristic::Media));
+        assert_eq!(
+            report_characteristic(&system, false),
+            Some(ReportCharacteristic::System)
+        );
+
+        // Boot protocol, only the keyboard report is sent via the boot keyboard input
+        assert_eq!(
+            report_characteristic(&keyboard, true),
+            Some(ReportCharacteristic::BootKeyboard)
+        );
+        assert_eq!(report_characteristic(&mouse, true), None);
+        assert_eq!(report_characteristic(&media, true), None);
+        assert_eq!(report_characteristic(&system, true), None);
+    }
+}
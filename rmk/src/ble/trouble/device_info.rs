@@ -61,4 +61,10 @@ pub(crate) struct DeviceInformationService {
     pub(crate) serial_number: heapless::String<20>,
     #[characteristic(uuid = "2a29", read)]
     pub(crate) manufacturer_name: heapless::String<20>,
+    /// Version in the firmware metadata, `major.minor.patch`
+    #[characteristic(uuid = "2a26", read)]
+    pub(crate) firmware_revision: heapless::String<20>,
+    /// Build hash in the firmware metadata, as 8 hex digits
+    #[characteristic(uuid = "2a28", read)]
+    pub(crate) software_revision: heapless::String<20>,
 }
@@ -2,15 +2,21 @@ use core::cell::RefCell;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 use battery_service::BleBatteryServer;
-use ble_server::{BleHidServer, BleViaServer, Server};
+#[cfg(feature = "dfu")]
+use ble_server::BleDfuServer;
+use ble_server::{BLE_RESOLUTION_MULTIPLIER, BOOT_PROTOCOL, BleHidServer, BleViaServer, Server};
 use bt_hci::cmd::le::{LeReadLocalSupportedFeatures, LeSetPhy};
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
 use device_info::{PnPID, VidSource};
 use embassy_futures::join::join;
-use embassy_futures::select::{Either3, select, select3};
-use embassy_time::{Duration, Timer, with_timeout};
-use profile::{ProfileInfo, ProfileManager, UPDATED_CCCD_TABLE, UPDATED_PROFILE};
+use embassy_futures::select::{Either, Either3, Either4, select, select3, select4};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use profile::{
+    HOST_NAME_MAX_LEN, LATENCY_MODE_CHANGED, ProfileInfo, ProfileManager, UPDATED_CCCD_TABLE, UPDATED_HOST_NAME,
+    UPDATED_PROFILE,
+};
 use rand_core::{CryptoRng, RngCore};
+use trouble_host::IdentityResolvingKey;
 use trouble_host::prelude::appearance::human_interface_device::KEYBOARD;
 use trouble_host::prelude::service::{BATTERY, HUMAN_INTERFACE_DEVICE};
 use trouble_host::prelude::*;
@@ -18,18 +24,17 @@ use trouble_host::prelude::*;
 use {
     crate::channel::{CONTROLLER_CHANNEL, send_controller_event},
     crate::event::ControllerEvent,
-    embassy_time::Instant,
 };
 #[cfg(not(feature = "_no_usb"))]
 use {
     crate::descriptor::{CompositeReport, KeyboardReport, ViaReport},
+    crate::hid::{HidWriterTrait, MirrorHidWriter, Report},
     crate::light::UsbLedReader,
     crate::state::get_connection_type,
     crate::usb::UsbKeyboardWriter,
-    crate::usb::{USB_ENABLED, USB_REMOTE_WAKEUP, USB_SUSPENDED},
+    crate::usb::{USB_ENABLED, USB_SUSPENDED},
     crate::usb::{add_usb_reader_writer, add_usb_writer, new_usb_builder},
     crate::via::UsbVialReaderWriter,
-    embassy_futures::select::{Either, Either4, select4},
     embassy_usb::driver::Driver,
 };
 #[cfg(feature = "storage")]
@@ -41,7 +46,11 @@ use {
 
 use crate::ble::led::BleLedReader;
 use crate::channel::{KEYBOARD_REPORT_CHANNEL, LED_SIGNAL, VIAL_READ_CHANNEL};
-use crate::config::RmkConfig;
+use crate::config::{BleConfig, RmkConfig};
+#[cfg(all(feature = "digitizer", not(feature = "_no_usb")))]
+use crate::descriptor::DigitizerReport;
+#[cfg(all(feature = "gamepad", not(feature = "_no_usb")))]
+use crate::descriptor::GamepadReport;
 use crate::hid::{DummyWriter, RunnableHidWriter};
 use crate::keymap::KeyMap;
 use crate::light::LedIndicator;
@@ -57,6 +66,8 @@ pub(crate) mod ble_server;
 pub(crate) mod device_info;
 pub(crate) mod profile;
 
+pub use profile::{BleLatencyMode, HostName, HostOs, get_host_os, get_latency_mode, set_host_os, set_latency_mode};
+
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum BleState {
@@ -82,6 +93,9 @@ pub(crate) const CONNECTIONS_MAX: usize = 4; // Should be number of the peripher
 /// Max number of L2CAP channels
 pub(crate) const L2CAP_CHANNELS_MAX: usize = CONNECTIONS_MAX * 4; // Signal + att + smp + hid
 
+/// Timeout of high duty cycle directed advertising, which is limited to 1.28s by the BLE spec
+const DIRECTED_ADVERTISING_TIMEOUT: Duration = Duration::from_millis(1280);
+
 /// Build the BLE stack.
 pub async fn build_ble_stack<
     'a,
@@ -126,9 +140,14 @@ pub(crate) async fn run_ble<
     // Initialize usb device and usb hid reader/writer
     #[cfg(not(feature = "_no_usb"))]
     let (mut _usb_builder, mut keyboard_reader, mut keyboard_writer, mut other_writer, mut vial_reader_writer) = {
-        let mut usb_builder: embassy_usb::Builder<'_, D> = new_usb_builder(usb_driver, rmk_config.usb_config);
+        let mut usb_builder: embassy_usb::Builder<'_, D> = new_usb_builder(
+            usb_driver,
+            rmk_config.usb_config,
+            #[cfg(feature = "webusb")]
+            rmk_config.webusb_config,
+        );
         let keyboard_reader_writer = add_usb_reader_writer!(&mut usb_builder, KeyboardReport, 1, 8);
-        let other_writer = add_usb_writer!(&mut usb_builder, CompositeReport, 9);
+        let other_writer = add_usb_writer!(&mut usb_builder, CompositeReport, 10);
         let vial_reader_writer = add_usb_reader_writer!(&mut usb_builder, ViaReport, 32, 32);
         let (keyboard_reader, keyboard_writer) = keyboard_reader_writer.split();
         (
@@ -140,10 +159,21 @@ pub(crate) async fn run_ble<
         )
     };
 
+    // Optional digitizer hid interface, which is only available via USB
+    #[cfg(all(feature = "digitizer", not(feature = "_no_usb")))]
+    let mut digitizer_writer = add_usb_writer!(&mut _usb_builder, DigitizerReport, 35);
+    // Optional gamepad hid interface, which is only available via USB
+    #[cfg(all(feature = "gamepad", not(feature = "_no_usb")))]
+    let mut gamepad_writer = add_usb_writer!(&mut _usb_builder, GamepadReport, 17);
+
     // Optional usb logger initialization
     #[cfg(all(feature = "usb_log", not(feature = "_no_usb")))]
     let usb_logger = add_usb_logger!(&mut _usb_builder);
 
+    // Optional usb console initialization
+    #[cfg(all(feature = "usb_console", not(feature = "_no_usb")))]
+    let usb_console = crate::usb::add_usb_console!(&mut _usb_builder);
+
     #[cfg(not(feature = "_no_usb"))]
     let mut usb_device = _usb_builder.build();
 
@@ -156,10 +186,11 @@ pub(crate) async fn run_ble<
         let mut buf: [u8; 16] = [0; 16];
         if let Ok(Some(StorageData::ConnectionType(conn_type))) =
             read_storage!(storage, &(StorageKeys::ConnectionType as u32), buf)
+            && conn_type <= u8::from(ConnectionType::Auto)
         {
             CONNECTION_TYPE.store(conn_type, Ordering::SeqCst);
         } else {
-            // If no saved connection type, return default value
+            // If no saved or an unknown connection type, for example saved by a newer firmware, use the default value
             #[cfg(feature = "_no_usb")]
             CONNECTION_TYPE.store(ConnectionType::Ble.into(), Ordering::SeqCst);
             #[cfg(not(feature = "_no_usb"))]
@@ -224,21 +255,28 @@ pub(crate) async fn run_ble<
         )
         .unwrap();
 
+    if let Some(metadata) = crate::firmware::firmware_metadata() {
+        use core::fmt::Write as _;
+        let mut firmware_revision = heapless::String::new();
+        let mut software_revision = heapless::String::new();
+        let v = metadata.version;
+        write!(firmware_revision, "{}.{}.{}", v.major, v.minor, v.patch).ok();
+        write!(software_revision, "{:08x}", metadata.build_hash).ok();
+        server
+            .set(&server.device_info_service.firmware_revision, &firmware_revision)
+            .unwrap();
+        server
+            .set(&server.device_info_service.software_revision, &software_revision)
+            .unwrap();
+    }
+
     #[cfg(not(feature = "_no_usb"))]
-    let usb_task = async {
-        loop {
-            usb_device.run_until_suspend().await;
-            match select(usb_device.wait_resume(), USB_REMOTE_WAKEUP.wait()).await {
-                Either::First(_) => continue,
-                Either::Second(_) => {
-                    info!("USB wakeup remote");
-                    if let Err(e) = usb_device.remote_wakeup().await {
-                        info!("USB wakeup remote error: {:?}", e)
-                    }
-                }
-            }
-        }
-    };
+    let usb_device_task = crate::usb::power::run_usb_device(&mut usb_device);
+
+    #[cfg(all(feature = "usb_console", not(feature = "_no_usb")))]
+    let usb_task = join(usb_device_task, crate::usb::console::run_console(usb_console, keymap));
+    #[cfg(all(not(feature = "usb_console"), not(feature = "_no_usb")))]
+    let usb_task = usb_device_task;
 
     #[cfg(all(not(feature = "usb_log"), not(feature = "_no_usb")))]
     let background_task = join(ble_task(runner), usb_task);
@@ -256,12 +294,21 @@ pub(crate) async fn run_ble<
     // Main loop
     join(background_task, async {
         loop {
-            let adv_fut = advertise(rmk_config.usb_config.product_name, &mut peripheral, &server);
+            let adv_fut = advertise(
+                rmk_config.usb_config.product_name,
+                &mut peripheral,
+                &server,
+                rmk_config.ble_config,
+                profile_manager
+                    .active_peer_identity()
+                    .map(|identity| directed_advertising_peer(&identity)),
+            );
             // USB + BLE dual mode
             #[cfg(not(feature = "_no_usb"))]
             {
-                match get_connection_type() {
-                    ConnectionType::Usb => {
+                let connection_type = get_connection_type();
+                match connection_type {
+                    ConnectionType::Usb | ConnectionType::Auto => {
                         info!("USB priority mode, waiting for USB enabled or BLE connection");
                         match select4(
                             USB_ENABLED.wait(),
@@ -282,16 +329,23 @@ pub(crate) async fn run_ble<
                                 }
                                 // Re-send the consumed flag
                                 USB_ENABLED.signal(());
-                                let usb_fut = run_keyboard(
+                                let usb_fut = crate::usb::power::run_usb_keyboard(run_keyboard(
                                     keymap,
                                     #[cfg(feature = "storage")]
                                     storage,
                                     USB_SUSPENDED.wait(),
                                     UsbLedReader::new(&mut keyboard_reader),
                                     UsbVialReaderWriter::new(&mut vial_reader_writer),
-                                    UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                                    UsbKeyboardWriter::new(
+                                        &mut keyboard_writer,
+                                        &mut other_writer,
+                                        #[cfg(feature = "digitizer")]
+                                        &mut digitizer_writer,
+                                        #[cfg(feature = "gamepad")]
+                                        &mut gamepad_writer,
+                                    ),
                                     rmk_config.vial_config,
-                                );
+                                ));
                                 select(usb_fut, profile_manager.update_profile()).await;
                             }
                             Either4::Second(Ok(conn)) => {
@@ -299,6 +353,18 @@ pub(crate) async fn run_ble<
                                 if USB_SUSPENDED.signaled() {
                                     USB_SUSPENDED.reset();
                                 }
+                                // Mirror the reports to USB if the output mirror is enabled, they're mirrored once the
+                                // USB is enabled and resumed
+                                let usb_writer = rmk_config.ble_config.output_mirror.then(|| {
+                                    UsbKeyboardWriter::new(
+                                        &mut keyboard_writer,
+                                        &mut other_writer,
+                                        #[cfg(feature = "digitizer")]
+                                        &mut digitizer_writer,
+                                        #[cfg(feature = "gamepad")]
+                                        &mut gamepad_writer,
+                                    )
+                                });
                                 let ble_fut = run_ble_keyboard(
                                     &server,
                                     &conn,
@@ -307,8 +373,19 @@ pub(crate) async fn run_ble<
                                     &mut rmk_config,
                                     #[cfg(feature = "storage")]
                                     storage,
+                                    usb_writer,
                                 );
-                                select3(ble_fut, USB_SUSPENDED.wait(), profile_manager.update_profile()).await;
+                                // In auto mode, switch to USB when USB is connected
+                                let usb_fut = async {
+                                    if matches!(connection_type, ConnectionType::Auto) {
+                                        USB_ENABLED.wait().await;
+                                        // Re-send the consumed flag
+                                        USB_ENABLED.signal(());
+                                    } else {
+                                        USB_SUSPENDED.wait().await;
+                                    }
+                                };
+                                select3(ble_fut, usb_fut, profile_manager.update_profile()).await;
                                 continue;
                             }
                             Either4::Second(Err(BleHostError::BleHost(Error::Timeout))) => {
@@ -337,19 +414,38 @@ pub(crate) async fn run_ble<
                     }
                     ConnectionType::Ble => {
                         info!("BLE priority mode, running USB keyboard while advertising");
-                        let usb_fut = run_keyboard(
+                        let usb_fut = crate::usb::power::run_usb_keyboard(run_keyboard(
                             keymap,
                             #[cfg(feature = "storage")]
                             storage,
                             core::future::pending::<()>(), // Run forever until BLE connected
                             UsbLedReader::new(&mut keyboard_reader),
                             UsbVialReaderWriter::new(&mut vial_reader_writer),
-                            UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                            UsbKeyboardWriter::new(
+                                &mut keyboard_writer,
+                                &mut other_writer,
+                                #[cfg(feature = "digitizer")]
+                                &mut digitizer_writer,
+                                #[cfg(feature = "gamepad")]
+                                &mut gamepad_writer,
+                            ),
                             rmk_config.vial_config,
-                        );
-                        match select3(adv_fut, usb_fut, profile_manager.update_profile()).await {
+                        ));
+                        let result = select3(adv_fut, usb_fut, profile_manager.update_profile()).await;
+                        match result {
                             Either3::First(Ok(conn)) => {
                                 info!("BLE connected, running BLE keyboard");
+                                // Mirror the reports to USB if the output mirror is enabled
+                                let usb_writer = rmk_config.ble_config.output_mirror.then(|| {
+                                    UsbKeyboardWriter::new(
+                                        &mut keyboard_writer,
+                                        &mut other_writer,
+                                        #[cfg(feature = "digitizer")]
+                                        &mut digitizer_writer,
+                                        #[cfg(feature = "gamepad")]
+                                        &mut gamepad_writer,
+                                    )
+                                });
                                 select(
                                     run_ble_keyboard(
                                         &server,
@@ -359,6 +455,7 @@ pub(crate) async fn run_ble<
                                         &mut rmk_config,
                                         #[cfg(feature = "storage")]
                                         storage,
+                                        usb_writer,
                                     ),
                                     profile_manager.update_profile(),
                                 )
@@ -474,6 +571,9 @@ pub(crate) async fn ble_task<C: Controller + ControllerCmdAsync<LeSetPhy>, P: Pa
 async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, DefaultPacketPool>) -> Result<(), Error> {
     let level = server.battery_service.level;
     let output_keyboard = server.hid_service.output_keyboard;
+    let boot_keyboard_output = server.hid_service.boot_keyboard_output;
+    let boot_keyboard_input = server.hid_service.boot_keyboard_input;
+    let protocol_mode = server.hid_service.protocol_mode;
     let hid_control_point = server.hid_service.hid_control_point;
     let input_keyboard = server.hid_service.input_keyboard;
     let output_via = server.via_service.output_via;
@@ -484,7 +584,14 @@ async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, Def
     let media = server.composite_service.media_report;
     let media_control_point = server.composite_service.hid_control_point;
     let system_control = server.composite_service.system_report;
-
+    let resolution_multiplier = server.composite_service.resolution_multiplier;
+    #[cfg(feature = "dfu")]
+    let dfu_server = BleDfuServer::new(server, conn);
+
+    // The host sets the resolution multiplier again after connected
+    BLE_RESOLUTION_MULTIPLIER.store(0, Ordering::Relaxed);
+    // The report protocol is used by default
+    BOOT_PROTOCOL.store(false, Ordering::Release);
     CONNECTION_STATE.store(ConnectionState::Connected.into(), Ordering::Release);
     #[cfg(feature = "controller")]
     let check_connected_time = Instant::now() + Duration::from_secs(2);
@@ -534,7 +641,7 @@ async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, Def
                         }
                     }
                     GattEvent::Write(event) => {
-                        if event.handle() == output_keyboard.handle {
+                        if event.handle() == output_keyboard.handle || event.handle() == boot_keyboard_output.handle {
                             if event.data().len() == 1 {
                                 let led_indicator = LedIndicator::from_bits(event.data()[0]);
                                 debug!("Got keyboard state: {:?}", led_indicator);
@@ -550,7 +657,22 @@ async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, Def
                             } else {
                                 warn!("Wrong via packet data: {:?}", event.data());
                             }
+                        } else if event.handle() == protocol_mode.handle {
+                            // 0: boot protocol, 1: report protocol
+                            if let Some(mode) = event.data().first() {
+                                info!("Protocol mode: {}", mode);
+                                BOOT_PROTOCOL.store(*mode == 0, Ordering::Release);
+                            }
+                        } else if event.handle() == resolution_multiplier.handle {
+                            debug!("Got resolution multiplier: {:?}", event.data());
+                            if let Some(multiplier) = event.data().last() {
+                                BLE_RESOLUTION_MULTIPLIER.store(*multiplier, Ordering::Relaxed);
+                            }
                         } else if event.handle() == input_keyboard.cccd_handle.expect("No CCCD for input keyboard")
+                            || event.handle()
+                                == boot_keyboard_input
+                                    .cccd_handle
+                                    .expect("No CCCD for boot keyboard input")
                             || event.handle() == input_via.cccd_handle.expect("No CCCD for input via")
                             || event.handle() == mouse.cccd_handle.expect("No CCCD for mouse report")
                             || event.handle() == media.cccd_handle.expect("No CCCD for media report")
@@ -576,7 +698,13 @@ async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, Def
                                 }
                             }
                         } else {
-                            debug!("Write GATT Event to Unknown: {:?}", event.handle());
+                            #[cfg(feature = "dfu")]
+                            let handled = dfu_server.process_write(event.handle(), event.data()).await;
+                            #[cfg(not(feature = "dfu"))]
+                            let handled = false;
+                            if !handled {
+                                debug!("Write GATT Event to Unknown: {:?}", event.handle());
+                            }
                         }
 
                         if conn.raw().encrypted() {
@@ -633,14 +761,88 @@ async fn gatt_events_task(server: &Server<'_>, conn: &GattConnection<'_, '_, Def
     Ok(())
 }
 
+/// Get the target address of directed advertising to the bonded host.
+///
+/// If the host distributed its IRK, it uses resolvable private addresses, so the target is a resolvable private address
+/// generated from the IRK, which is resolved by the host. Otherwise the target is the identity address of the host.
+fn directed_advertising_peer(identity: &Identity) -> Address {
+    match identity.irk {
+        Some(irk) => {
+            // The random part only has to change between advertisements, it's not a secret
+            let ticks = Instant::now().as_ticks().to_le_bytes();
+            Address {
+                kind: AddrKind::RANDOM,
+                addr: resolvable_private_address(&irk, [ticks[0], ticks[1], ticks[2]]),
+            }
+        }
+        None => Address {
+            kind: identity_address_kind(&identity.bd_addr),
+            addr: identity.bd_addr,
+        },
+    }
+}
+
+/// Get the kind of an identity address.
+///
+/// The address kind isn't saved in the bonding information, but a random static address always has
+/// the two most significant bits set, see Bluetooth Core Specification Vol 6, Part B, Section 1.3.2.1.
+fn identity_address_kind(addr: &BdAddr) -> AddrKind {
+    if addr.raw()[5] & 0b1100_0000 == 0b1100_0000 {
+        AddrKind::RANDOM
+    } else {
+        AddrKind::PUBLIC
+    }
+}
+
+/// Generate a resolvable private address from the IRK and the random part `prand` in little endian,
+/// see Bluetooth Core Specification Vol 3, Part C, Section 10.8.2.2.
+fn resolvable_private_address(irk: &IdentityResolvingKey, mut prand: [u8; 3]) -> BdAddr {
+    use aes::Aes128;
+    use aes::cipher::{BlockEncrypt, KeyInit};
+
+    // The two most significant bits of a resolvable private address are `0b01`
+    prand[2] = (prand[2] & 0b0011_1111) | 0b0100_0000;
+
+    // Random address hash function `ah`, see Bluetooth Core Specification Vol 3, Part H, Section 2.2.2
+    let mut block = [0u8; 16];
+    block[13..].copy_from_slice(&[prand[2], prand[1], prand[0]]);
+    let cipher = Aes128::new(&irk.0.to_be_bytes().into());
+    cipher.encrypt_block((&mut block).into());
+
+    BdAddr::new([block[15], block[14], block[13], prand[0], prand[1], prand[2]])
+}
+
 /// Create an advertiser to use to connect to a BLE Central, and wait for it to connect.
 async fn advertise<'a, 'b, C: Controller>(
     name: &'a str,
     peripheral: &mut Peripheral<'a, C, DefaultPacketPool>,
     server: &'b Server<'_>,
+    ble_config: BleConfig,
+    peer: Option<Address>,
 ) -> Result<GattConnection<'a, 'b, DefaultPacketPool>, BleHostError<C::Error>> {
     // Wait for 10ms to ensure the USB is checked
     embassy_time::Timer::after_millis(10).await;
+
+    // Reconnect the bonded host quickly with high duty cycle directed advertising
+    if let Some(peer) = peer
+        && ble_config.directed_advertising
+    {
+        info!("[adv] directed advertising");
+        let advertiser = peripheral
+            .advertise(
+                &AdvertisementParameters {
+                    tx_power: TxPower::Plus8dBm,
+                    ..Default::default()
+                },
+                Advertisement::ConnectableNonscannableDirectedHighDuty { peer },
+            )
+            .await?;
+        if let Ok(Ok(conn)) = with_timeout(DIRECTED_ADVERTISING_TIMEOUT, advertiser.accept()).await {
+            info!("[adv] connection established by directed advertising");
+            return Ok(conn.with_attribute_server(server)?);
+        }
+        info!("[adv] directed advertising timeout, fallback to undirected advertising");
+    }
     let mut advertiser_data = [0; 31];
     AdStructure::encode_slice(
         &[
@@ -659,8 +861,8 @@ async fn advertise<'a, 'b, C: Controller>(
         primary_phy: PhyKind::Le2M,
         secondary_phy: PhyKind::Le2M,
         tx_power: TxPower::Plus8dBm,
-        interval_min: Duration::from_millis(200),
-        interval_max: Duration::from_millis(200),
+        interval_min: ble_config.advertising_interval,
+        interval_max: ble_config.advertising_interval,
         ..Default::default()
     };
 
@@ -685,8 +887,7 @@ async fn advertise<'a, 'b, C: Controller>(
         );
     }
 
-    // Timeout for advertising is 300s
-    match with_timeout(Duration::from_secs(300), advertiser.accept()).await {
+    match with_timeout(ble_config.advertising_timeout, advertiser.accept()).await {
         Ok(conn_res) => {
             let conn = conn_res?.with_attribute_server(server)?;
             info!("[adv] connection established");
@@ -748,22 +949,18 @@ pub(crate) async fn set_conn_params<
     embassy_time::Timer::after_secs(5).await;
 
     // Setting the conn param the second time ensures that we have best performance on all platforms
-    update_conn_params(
-        stack,
-        conn.raw(),
-        &ConnectParams {
-            min_connection_interval: Duration::from_micros(7500),
-            max_connection_interval: Duration::from_micros(7500),
-            max_latency: 99,
-            event_length: Duration::from_secs(0),
-            supervision_timeout: Duration::from_secs(5),
-        },
-    )
-    .await;
+    LATENCY_MODE_CHANGED.reset();
+    let latency_mode = get_latency_mode();
+    info!("[set_conn_params] latency mode: {:?}", latency_mode);
+    update_conn_params(stack, conn.raw(), &latency_mode.conn_params()).await;
 
-    // Wait forever. This is because we want the conn params setting can be interrupted when the connection is lost.
-    // So this task shouldn't quit after setting the conn params.
-    core::future::pending::<()>().await;
+    // Update the conn params when the latency mode is changed.
+    // This task never quits, so that it can be interrupted when the connection is lost.
+    loop {
+        let latency_mode = LATENCY_MODE_CHANGED.wait().await;
+        info!("[set_conn_params] latency mode changed: {:?}", latency_mode);
+        update_conn_params(stack, conn.raw(), &latency_mode.conn_params()).await;
+    }
 }
 
 /// Run BLE keyboard with connected device
@@ -781,10 +978,11 @@ async fn run_ble_keyboard<
 >(
     server: &'b Server<'_>,
     conn: &GattConnection<'a, 'b, DefaultPacketPool>,
-    stack: &Stack<'_, C, DefaultPacketPool>,
+    stack: &'a Stack<'a, C, DefaultPacketPool>,
     keymap: &'c RefCell<KeyMap<'c, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     rmk_config: &'d mut RmkConfig<'static>,
     #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    #[cfg(not(feature = "_no_usb"))] usb_writer: Option<impl HidWriterTrait<ReportType = Report>>,
 ) {
     let ble_hid_server = BleHidServer::new(&server, &conn);
     let ble_via_server = BleViaServer::new(&server, &conn);
@@ -807,18 +1005,44 @@ async fn run_ble_keyboard<
     update_ble_phy(stack, conn.raw()).await;
 
     let communication_task = async {
-        match select3(
+        match select4(
             gatt_events_task(server, conn),
             set_conn_params(stack, conn),
             ble_battery_server.run(),
+            async {
+                if let Some(host_name) = read_host_name(stack, conn.raw()).await {
+                    UPDATED_HOST_NAME.signal(host_name);
+                }
+                // Notify the responses of the firmware update
+                #[cfg(feature = "dfu")]
+                BleDfuServer::new(server, conn).run().await;
+                core::future::pending::<()>().await
+            },
         )
         .await
         {
-            Either3::First(e) => error!("[gatt_events_task] end: {:?}", e),
+            Either4::First(e) => error!("[gatt_events_task] end: {:?}", e),
             _ => {}
         }
     };
 
+    // Mirror the reports to USB
+    #[cfg(not(feature = "_no_usb"))]
+    if let Some(usb_writer) = usb_writer {
+        run_keyboard(
+            keymap,
+            #[cfg(feature = "storage")]
+            storage,
+            communication_task,
+            ble_led_reader,
+            ble_via_server,
+            MirrorHidWriter::new(ble_hid_server, usb_writer),
+            rmk_config.vial_config,
+        )
+        .await;
+        return;
+    }
+
     run_keyboard(
         keymap,
         #[cfg(feature = "storage")]
@@ -832,6 +1056,48 @@ async fn run_ble_keyboard<
     .await;
 }
 
+/// Read the device name of the connected host from its GAP service
+async fn read_host_name<'a, C: Controller, P: PacketPool>(
+    stack: &'a Stack<'a, C, P>,
+    conn: &Connection<'a, P>,
+) -> Option<HostName> {
+    const GAP_SERVICE: u16 = 0x1800;
+    const DEVICE_NAME: u16 = 0x2A00;
+
+    let client = match GattClient::<C, P, 1>::new(stack, conn).await {
+        Ok(client) => client,
+        Err(e) => {
+            #[cfg(feature = "defmt")]
+            let e = defmt::Debug2Format(&e);
+            debug!("[host_name] failed to create GATT client: {:?}", e);
+            return None;
+        }
+    };
+    let read_name = async {
+        let services = client.services_by_uuid(&Uuid::new_short(GAP_SERVICE)).await.ok()?;
+        let service = services.first()?;
+        let characteristic: Characteristic<[u8; HOST_NAME_MAX_LEN]> = client
+            .characteristic_by_uuid(service, &Uuid::new_short(DEVICE_NAME))
+            .await
+            .ok()?;
+        let mut buf = [0u8; HOST_NAME_MAX_LEN];
+        let len = client.read_characteristic(&characteristic, &mut buf).await.ok()?;
+        // The name may be truncated in the middle of a character
+        let name = match core::str::from_utf8(&buf[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).ok()?,
+        };
+        HostName::try_from(name).ok()
+    };
+    match select(client.task(), with_timeout(Duration::from_secs(5), read_name)).await {
+        Either::Second(Ok(name)) => {
+            info!("[host_name] host name: {:?}", name);
+            name
+        }
+        _ => None,
+    }
+}
+
 // Update the PHY to 2M
 pub(crate) async fn update_ble_phy<P: PacketPool>(
     stack: &Stack<'_, impl Controller + ControllerCmdAsync<LeSetPhy>, P>,
@@ -894,3 +1160,39 @@ pub(crate) async fn update_conn_params<
         break;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directed_advertising_peer() {
+        // Sample data of `ah` in Bluetooth Core Specification Vol 3, Part H, Appendix D.7
+        let irk = IdentityResolvingKey::new(0xec0234a357c8ad05341010a60a397d9b);
+        let addr = resolvable_private_address(&irk, [0x94, 0x81, 0x70]);
+        assert_eq!(addr, BdAddr::new([0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70]));
+        assert!(irk.resolve_address(&addr));
+
+        // The host with an IRK is reached by a resolvable private address
+        let identity = Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+            irk: Some(irk),
+        };
+        let peer = directed_advertising_peer(&identity);
+        assert_eq!(peer.kind, AddrKind::RANDOM);
+        assert!(irk.resolve_address(&peer.addr));
+
+        // Without an IRK, the identity address is used
+        let identity = Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 0xc6]),
+            irk: None,
+        };
+        assert_eq!(directed_advertising_peer(&identity).kind, AddrKind::RANDOM);
+        let identity = Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+            irk: None,
+        };
+        let peer = directed_advertising_peer(&identity);
+        assert_eq!((peer.kind, peer.addr), (AddrKind::PUBLIC, identity.bd_addr));
+    }
+}
@@ -1,11 +1,12 @@
 //! Manage BLE profiles and bonding information
 
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
 #[cfg(feature = "_ble")]
 use bt_hci::{cmd::le::LeSetPhy, controller::ControllerCmdAsync};
-use embassy_futures::select::{Either3, select3};
+use embassy_futures::select::{Either4, select4};
 use embassy_sync::signal::Signal;
+use embassy_time::Duration;
 use trouble_host::prelude::*;
 use trouble_host::{BondInformation, LongTermKey};
 #[cfg(feature = "storage")]
@@ -23,10 +24,138 @@ use super::ble_server::CCCD_TABLE_SIZE;
 use crate::NUM_BLE_PROFILE;
 use crate::ble::trouble::ACTIVE_PROFILE;
 use crate::channel::BLE_PROFILE_CHANNEL;
-use crate::state::CONNECTION_TYPE;
+use crate::state::{CONNECTION_TYPE, ConnectionType, get_connection_type};
 
 pub(crate) static UPDATED_PROFILE: Signal<crate::RawMutex, ProfileInfo> = Signal::new();
 pub(crate) static UPDATED_CCCD_TABLE: Signal<crate::RawMutex, CccdTable<CCCD_TABLE_SIZE>> = Signal::new();
+pub(crate) static UPDATED_HOST_NAME: Signal<crate::RawMutex, HostName> = Signal::new();
+
+/// Signal the latency mode of the active profile is changed, the connection parameters should be updated
+pub(crate) static LATENCY_MODE_CHANGED: Signal<crate::RawMutex, BleLatencyMode> = Signal::new();
+
+/// Host OS of the active profile
+static ACTIVE_HOST_OS: AtomicU8 = AtomicU8::new(0);
+
+/// Latency mode of the active profile
+static ACTIVE_LATENCY_MODE: AtomicU8 = AtomicU8::new(0);
+
+/// Bitmask of the profiles which have a bonded host
+pub(crate) static BONDED_PROFILES: AtomicU32 = AtomicU32::new(0);
+
+/// Max length of the host name in bytes
+pub(crate) const HOST_NAME_MAX_LEN: usize = 24;
+
+/// Name of the host, which is read from the GAP device name of the host
+pub type HostName = heapless::String<HOST_NAME_MAX_LEN>;
+
+/// OS of the host connected to a profile
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HostOs {
+    #[default]
+    Unknown = 0,
+    Windows = 1,
+    MacOs = 2,
+    Linux = 3,
+    Ios = 4,
+    Android = 5,
+}
+
+impl From<u8> for HostOs {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => HostOs::Windows,
+            2 => HostOs::MacOs,
+            3 => HostOs::Linux,
+            4 => HostOs::Ios,
+            5 => HostOs::Android,
+            _ => HostOs::Unknown,
+        }
+    }
+}
+
+/// Get the host OS of the active profile
+pub fn get_host_os() -> HostOs {
+    ACTIVE_HOST_OS.load(Ordering::Acquire).into()
+}
+
+/// Set the host OS of the active profile, it's saved to the storage
+pub async fn set_host_os(host_os: HostOs) {
+    BLE_PROFILE_CHANNEL.send(BleProfileAction::SetHostOs(host_os)).await;
+}
+
+/// Trade-off between the latency and the power consumption of a BLE connection
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BleLatencyMode {
+    /// 7.5ms connection interval, for gaming and fast typing
+    #[default]
+    LowLatency = 0,
+    /// 15ms connection interval
+    Balanced = 1,
+    /// 30ms connection interval, the keyboard skips more connection events when idle
+    PowerSaving = 2,
+}
+
+impl From<u8> for BleLatencyMode {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => BleLatencyMode::Balanced,
+            2 => BleLatencyMode::PowerSaving,
+            _ => BleLatencyMode::LowLatency,
+        }
+    }
+}
+
+impl BleLatencyMode {
+    /// The next latency mode, used by the latency mode switch key
+    pub fn next(self) -> Self {
+        ((self as u8 + 1) % 3).into()
+    }
+
+    /// Connection parameters of the latency mode.
+    ///
+    /// The max peripheral latency keeps the effective interval when idle at about 750ms,
+    /// the keyboard reports immediately when a key is pressed anyway.
+    pub(crate) fn conn_params(self) -> ConnectParams {
+        let (interval, max_latency) = match self {
+            BleLatencyMode::LowLatency => (Duration::from_micros(7500), 99),
+            BleLatencyMode::Balanced => (Duration::from_millis(15), 49),
+            BleLatencyMode::PowerSaving => (Duration::from_millis(30), 24),
+        };
+        ConnectParams {
+            min_connection_interval: interval,
+            max_connection_interval: interval,
+            max_latency,
+            event_length: Duration::from_secs(0),
+            supervision_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Get the latency mode of the active profile
+pub fn get_latency_mode() -> BleLatencyMode {
+    ACTIVE_LATENCY_MODE.load(Ordering::Acquire).into()
+}
+
+/// Set the latency mode of the active profile, it's saved to the storage
+pub async fn set_latency_mode(latency_mode: BleLatencyMode) {
+    BLE_PROFILE_CHANNEL
+        .send(BleProfileAction::SetLatencyMode(Some(latency_mode)))
+        .await;
+}
+
+/// Host name, host OS and latency mode of a profile, which are stored separately from the bonding information
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProfileMeta {
+    pub(crate) slot_num: u8,
+    pub(crate) host_os: HostOs,
+    pub(crate) latency_mode: BleLatencyMode,
+    pub(crate) host_name: HostName,
+}
 
 /// BLE profile info
 #[derive(Clone, Debug)]
@@ -62,6 +191,12 @@ pub(crate) enum BleProfileAction {
     NextProfile,
     ClearProfile,
     ToggleConnection,
+    /// Switch to the connection type, see [`crate::state::ConnectionType`]
+    SwitchConnection(u8),
+    /// Set the host OS of the active profile
+    SetHostOs(HostOs),
+    /// Set the latency mode of the active profile, `None` switches to the next mode
+    SetLatencyMode(Option<BleLatencyMode>),
 }
 
 /// Manage BLE profiles and bonding information
@@ -71,10 +206,13 @@ pub(crate) enum BleProfileAction {
 /// 2. Storing and loading bonding information for each profile
 /// 3. Updating the bonding information of the active profile to the BLE stack
 /// 4. Handling profile switch, clear, and save operations
+/// 5. Storing the host name, host OS and latency mode of each profile
 #[cfg(feature = "_ble")]
 pub struct ProfileManager<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> {
     /// List of bonded devices
     bonded_devices: heapless::Vec<ProfileInfo, NUM_BLE_PROFILE>,
+    /// Host name, host OS and latency mode of each profile
+    profile_metas: [ProfileMeta; NUM_BLE_PROFILE],
     /// BLE stack
     stack: &'a Stack<'a, C, P>,
     /// Publisher for controller channel
@@ -88,6 +226,10 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
     pub fn new(stack: &'a Stack<'a, C, P>, #[cfg(feature = "controller")] controller_pub: ControllerPub) -> Self {
         Self {
             bonded_devices: heapless::Vec::new(),
+            profile_metas: core::array::from_fn(|i| ProfileMeta {
+                slot_num: i as u8,
+                ..Default::default()
+            }),
             stack,
             #[cfg(feature = "controller")]
             controller_pub,
@@ -121,6 +263,12 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
         }
         debug!("Loaded {} bond info", self.bonded_devices.len());
 
+        for slot_num in 0..NUM_BLE_PROFILE {
+            if let Ok(Some(meta)) = storage.read_profile_meta(slot_num as u8).await {
+                self.profile_metas[slot_num] = meta;
+            }
+        }
+
         let mut buf: [u8; 128] = [0; 128];
 
         // Load current active profile, save to `ACTIVE_PROFILE`
@@ -140,12 +288,70 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
             #[cfg(feature = "controller")]
             send_controller_event(&mut self.controller_pub, ControllerEvent::BleProfile(0));
         };
+        self.update_active_meta();
+    }
+
+    /// Update the host OS and latency mode according to the current active profile
+    fn update_active_meta(&self) {
+        let active_profile = ACTIVE_PROFILE.load(Ordering::SeqCst);
+        let (host_os, latency_mode) = self
+            .profile_metas
+            .get(active_profile as usize)
+            .map(|meta| (meta.host_os, meta.latency_mode))
+            .unwrap_or_default();
+        ACTIVE_HOST_OS.store(host_os as u8, Ordering::Release);
+        ACTIVE_LATENCY_MODE.store(latency_mode as u8, Ordering::Release);
+    }
+
+    /// Get the identity of the host bonded to the active profile, which is used for directed advertising.
+    pub fn active_peer_identity(&self) -> Option<Identity> {
+        let active_profile = ACTIVE_PROFILE.load(Ordering::SeqCst);
+        self.bonded_devices
+            .iter()
+            .find(|bond_info| !bond_info.removed && bond_info.slot_num == active_profile)
+            .map(|bond_info| bond_info.info.identity)
+    }
+
+    /// Get the host name of the profile
+    pub fn host_name(&self, slot_num: u8) -> Option<&str> {
+        self.profile_metas
+            .get(slot_num as usize)
+            .map(|meta| meta.host_name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Update the meta of the active profile, and save it to the storage
+    async fn update_profile_meta(&mut self, f: impl FnOnce(&mut ProfileMeta)) {
+        let active_profile = ACTIVE_PROFILE.load(Ordering::SeqCst);
+        let Some(meta) = self.profile_metas.get_mut(active_profile as usize) else {
+            return;
+        };
+        let mut updated = meta.clone();
+        f(&mut updated);
+        if updated == *meta {
+            return;
+        }
+        info!("Update profile {} meta: {:?}", active_profile, updated);
+        *meta = updated.clone();
+        self.update_active_meta();
+
+        #[cfg(feature = "storage")]
+        FLASH_CHANNEL
+            .send(crate::storage::FlashOperationMessage::ProfileMeta(updated))
+            .await;
     }
 
     /// Update bonding information in the stack according to the current active profile
     pub fn update_stack_bonds(&self) {
         let active_profile = ACTIVE_PROFILE.load(core::sync::atomic::Ordering::SeqCst);
 
+        let bonded_profiles = self
+            .bonded_devices
+            .iter()
+            .filter(|bond_info| !bond_info.removed)
+            .fold(0, |mask, bond_info| mask | (1 << bond_info.slot_num));
+        BONDED_PROFILES.store(bonded_profiles, Ordering::Release);
+
         // Remove current bonding information in the stack
         let current_bond_info = self.stack.get_bond_information();
         for bond in current_bond_info {
@@ -242,6 +448,21 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
         // Update the active bonding information in the stack
         self.update_stack_bonds();
 
+        // The host name and host OS belong to the cleared host
+        if let Some(meta) = self.profile_metas.get_mut(slot_num as usize) {
+            *meta = ProfileMeta {
+                slot_num,
+                ..Default::default()
+            };
+            #[cfg(feature = "storage")]
+            let meta = meta.clone();
+            self.update_active_meta();
+            #[cfg(feature = "storage")]
+            FLASH_CHANNEL
+                .send(crate::storage::FlashOperationMessage::ProfileMeta(meta))
+                .await;
+        }
+
         #[cfg(feature = "storage")]
         // Send the clear slot message to the flash task
         FLASH_CHANNEL
@@ -260,6 +481,7 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
 
         // Update the active bonding information in the stack
         self.update_stack_bonds();
+        self.update_active_meta();
 
         #[cfg(feature = "storage")]
         FLASH_CHANNEL
@@ -274,6 +496,30 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
         true
     }
 
+    /// Switch the connection type and save it to the storage.
+    ///
+    /// Returns `false` if the connection type is not changed.
+    async fn switch_connection(&mut self, connection_type: u8) -> bool {
+        if CONNECTION_TYPE.swap(connection_type, Ordering::SeqCst) == connection_type {
+            return false;
+        }
+
+        info!("Switching connection type to: {}", connection_type);
+
+        #[cfg(feature = "controller")]
+        send_controller_event(
+            &mut self.controller_pub,
+            ControllerEvent::ConnectionType(connection_type),
+        );
+
+        #[cfg(feature = "storage")]
+        FLASH_CHANNEL
+            .send(FlashOperationMessage::ConnectionType(connection_type))
+            .await;
+
+        true
+    }
+
     /// Wait for profile switch event and update active profile
     ///
     /// This function will wait for profile switch operation, then update the active profile
@@ -282,14 +528,15 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
     pub async fn update_profile(&mut self) {
         // Wait for profile switch or updated profile event
         loop {
-            match select3(
+            match select4(
                 BLE_PROFILE_CHANNEL.receive(),
                 UPDATED_PROFILE.wait(),
                 UPDATED_CCCD_TABLE.wait(),
+                UPDATED_HOST_NAME.wait(),
             )
             .await
             {
-                Either3::First(action) => {
+                Either4::First(action) => {
                     #[cfg(feature = "storage")]
                     if FLASH_OPERATION_FINISHED.signaled() {
                         FLASH_OPERATION_FINISHED.reset();
@@ -318,20 +565,29 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
                             self.clear_bond(profile).await;
                         }
                         BleProfileAction::ToggleConnection => {
-                            let current = CONNECTION_TYPE.load(Ordering::SeqCst);
-                            let updated = 1 - current;
-                            CONNECTION_TYPE.store(updated, Ordering::SeqCst);
-
-                            info!("Switching connection type to: {}", updated);
-
-                            #[cfg(feature = "controller")]
-                            send_controller_event(
-                                &mut self.controller_pub,
-                                ControllerEvent::ConnectionType(updated.into()),
-                            );
-
-                            #[cfg(feature = "storage")]
-                            FLASH_CHANNEL.send(FlashOperationMessage::ConnectionType(updated)).await;
+                            let connection_type = match get_connection_type() {
+                                ConnectionType::Ble => ConnectionType::Usb,
+                                _ => ConnectionType::Ble,
+                            };
+                            self.switch_connection(connection_type.into()).await;
+                        }
+                        BleProfileAction::SwitchConnection(connection_type) => {
+                            if !self.switch_connection(connection_type).await {
+                                // If the connection type is not changed, do nothing
+                                continue;
+                            }
+                        }
+                        BleProfileAction::SetHostOs(host_os) => {
+                            // The connection is kept when the host OS is changed
+                            self.update_profile_meta(|meta| meta.host_os = host_os).await;
+                            continue;
+                        }
+                        BleProfileAction::SetLatencyMode(latency_mode) => {
+                            // The connection parameters are updated without reconnecting
+                            let latency_mode = latency_mode.unwrap_or_else(|| get_latency_mode().next());
+                            self.update_profile_meta(|meta| meta.latency_mode = latency_mode).await;
+                            LATENCY_MODE_CHANGED.signal(latency_mode);
+                            continue;
                         }
                     }
                     #[cfg(feature = "storage")]
@@ -339,12 +595,15 @@ impl<'a, C: Controller + ControllerCmdAsync<LeSetPhy>, P: PacketPool> ProfileMan
                     info!("Update profile done");
                     break;
                 }
-                Either3::Second(profile_info) => {
+                Either4::Second(profile_info) => {
                     self.add_profile_info(profile_info).await;
                 }
-                Either3::Third(table) => {
+                Either4::Third(table) => {
                     self.update_profile_cccd_table(table).await;
                 }
+                Either4::Fourth(host_name) => {
+                    self.update_profile_meta(|meta| meta.host_name = host_name).await;
+                }
             }
         }
     }
@@ -1,16 +1,51 @@
+//! Bootloader support
+//!
+//! A [`Bootloader`] prepares the entry of a bootloader, the keyboard is rebooted right after it.
+//! The bootloader is set by [`set_bootloader`], and used by `KeyCode::Bootloader`, VIA's `BootloaderJump` and bootmagic.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// A bootloader that the keyboard can jump to
+pub trait Bootloader: Send {
+    /// Prepare the bootloader entry, the keyboard is rebooted after this returns.
+    ///
+    /// Some bootloaders are entered immediately, so this function might never return.
+    fn enter(&mut self);
+}
+
+static BOOTLOADER: Mutex<CriticalSectionRawMutex, RefCell<Option<&'static mut dyn Bootloader>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Set the bootloader used by [`jump_to_bootloader`]
+pub fn set_bootloader(bootloader: &'static mut dyn Bootloader) {
+    BOOTLOADER.lock(|b| *b.borrow_mut() = Some(bootloader));
+}
+
+/// Jump to the bootloader.
+///
+/// If no bootloader is set, the bootloader enabled by the `adafruit_bl` or `rp2040_bl` feature is used.
 pub fn jump_to_bootloader() {
-    #[cfg(feature = "adafruit_bl")]
-    // Reference: https://github.com/adafruit/Adafruit_nRF52_Bootloader/blob/d6b28e66053eea467166f44875e3c7ec741cb471/src/main.c#L107
-    embassy_nrf::pac::POWER
-        .gpregret()
-        .write_value(embassy_nrf::pac::power::regs::Gpregret(0x57));
+    let entered = BOOTLOADER.lock(|b| match b.borrow_mut().as_mut() {
+        Some(bootloader) => {
+            bootloader.enter();
+            true
+        }
+        None => false,
+    });
+
+    if !entered {
+        #[cfg(feature = "adafruit_bl")]
+        AdafruitUf2Bootloader.enter();
 
-    #[cfg(feature = "rp2040_bl")]
-    // Jump to RP2040 bootloader
-    embassy_rp::rom_data::reset_to_usb_boot(0, 0);
+        #[cfg(feature = "rp2040_bl")]
+        RpRomBootloader.enter();
 
-    #[cfg(not(any(feature = "adafruit_bl", feature = "rp2040_bl")))]
-    warn!("Please specified a bootloader to jump to!");
+        #[cfg(not(any(feature = "adafruit_bl", feature = "rp2040_bl")))]
+        warn!("Please specified a bootloader to jump to!");
+    }
 
     reboot_keyboard();
 }
@@ -28,3 +63,258 @@ pub(crate) fn reboot_keyboard() {
     #[cfg(feature = "_esp_ble")]
     esp_hal::system::software_reset();
 }
+
+/// The UF2 bootloader of Adafruit, which is used by most nRF52 boards, such as nice!nano
+#[cfg(feature = "_nrf_ble")]
+pub struct AdafruitUf2Bootloader;
+
+#[cfg(feature = "_nrf_ble")]
+impl Bootloader for AdafruitUf2Bootloader {
+    fn enter(&mut self) {
+        // Reference: https://github.com/adafruit/Adafruit_nRF52_Bootloader/blob/d6b28e66053eea467166f44875e3c7ec741cb471/src/main.c#L107
+        embassy_nrf::pac::POWER
+            .gpregret()
+            .write_value(embassy_nrf::pac::power::regs::Gpregret(0x57));
+    }
+}
+
+/// The USB boot mode in the ROM of RP2040 and RP2350, which exposes a UF2 drive.
+///
+/// Enable `rp2040_bl` or `rp2350_bl` feature according to your chip.
+#[cfg(any(feature = "rp2040_bl", feature = "rp2350_bl"))]
+pub struct RpRomBootloader;
+
+#[cfg(any(feature = "rp2040_bl", feature = "rp2350_bl"))]
+impl Bootloader for RpRomBootloader {
+    fn enter(&mut self) {
+        #[cfg(feature = "rp2040_bl")]
+        embassy_rp::rom_data::reset_to_usb_boot(0, 0);
+
+        // `REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL`, see section 5.4.8.24 of the RP2350 datasheet
+        #[cfg(feature = "rp2350_bl")]
+        embassy_rp::rom_data::reboot(0x0002, 10, 0, 0);
+    }
+}
+
+/// The system memory DFU bootloader of STM32.
+///
+/// The system memory bootloader expects the peripherals in their reset state, so [`Bootloader::enter`] only
+/// saves a magic value in the RAM. After the reboot, [`Stm32DfuBootloader::check`] jumps to the system memory.
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    any(target_abi = "eabi", target_abi = "eabihf")
+))]
+pub struct Stm32DfuBootloader {
+    /// Address of the system memory, see AN2606 for the address of your chip, such as `0x1FFF0000` for STM32F4
+    system_memory: u32,
+}
+
+/// The magic value which requests the STM32 system memory bootloader.
+///
+/// It's saved in `.uninit` section, which is not initialized at startup, so that it survives the reboot.
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    any(target_abi = "eabi", target_abi = "eabihf")
+))]
+#[unsafe(link_section = ".uninit.RMK_STM32_DFU_MAGIC")]
+static mut STM32_DFU_MAGIC: core::mem::MaybeUninit<u32> = core::mem::MaybeUninit::uninit();
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    any(target_abi = "eabi", target_abi = "eabihf")
+))]
+const STM32_DFU_MAGIC_VALUE: u32 = 0xB007_DF00;
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    any(target_abi = "eabi", target_abi = "eabihf")
+))]
+impl Stm32DfuBootloader {
+    pub const fn new(system_memory: u32) -> Self {
+        Self { system_memory }
+    }
+
+    /// Jump to the system memory bootloader, if it's requested before the reboot.
+    ///
+    /// It MUST be called at the very beginning of `main`, before any peripheral is initialized.
+    pub fn check(&self) {
+        let magic = &raw mut STM32_DFU_MAGIC as *mut u32;
+        // SAFETY: the magic is only accessed by a single core, before and after the reboot
+        unsafe {
+            if magic.read_volatile() == STM32_DFU_MAGIC_VALUE {
+                magic.write_volatile(0);
+                cortex_m::asm::bootload(self.system_memory as *const u32);
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    any(target_abi = "eabi", target_abi = "eabihf")
+))]
+impl Bootloader for Stm32DfuBootloader {
+    fn enter(&mut self) {
+        // SAFETY: the magic is only accessed by a single core
+        unsafe { (&raw mut STM32_DFU_MAGIC as *mut u32).write_volatile(STM32_DFU_MAGIC_VALUE) };
+    }
+}
+
+/// The download mode of ESP32 ROM bootloader, which is used by `espflash` and `esptool`
+#[cfg(feature = "_esp_ble")]
+pub struct Esp32DownloadBootloader;
+
+#[cfg(feature = "_esp_ble")]
+impl Bootloader for Esp32DownloadBootloader {
+    fn enter(&mut self) {
+        // Set `FORCE_DOWNLOAD_BOOT`, the ROM bootloader enters download mode after the software reset.
+        // `RTC_CNTL_OPTION1_REG` on ESP32-C3/S3 and `LP_AON_SYS_CFG_REG` on ESP32-C6, see the technical reference manuals
+        #[cfg(feature = "esp32c3_ble")]
+        let (reg, bit) = (0x6000_8128 as *mut u32, 1 << 0);
+        #[cfg(feature = "esp32s3_ble")]
+        let (reg, bit) = (0x6000_812C as *mut u32, 1 << 0);
+        #[cfg(feature = "esp32c6_ble")]
+        let (reg, bit) = (0x600B_1008 as *mut u32, 1 << 30);
+        // SAFETY: the register is only written right before the reset
+        unsafe { reg.write_volatile(reg.read_volatile() | bit) };
+    }
+}
+
+/// Max write size of the state partition that [`EmbassyBootBootloader`] and [`mark_booted`] support
+#[cfg(feature = "embassy_boot")]
+const EMBASSY_BOOT_MAX_WRITE_SIZE: usize = 32;
+
+/// The aligned buffer which is required by the `FirmwareState` of embassy-boot
+#[cfg(feature = "embassy_boot")]
+#[repr(align(32))]
+pub(crate) struct EmbassyBootBuffer([u8; EMBASSY_BOOT_MAX_WRITE_SIZE]);
+
+#[cfg(feature = "embassy_boot")]
+impl EmbassyBootBuffer {
+    pub(crate) const fn new() -> Self {
+        Self([0; EMBASSY_BOOT_MAX_WRITE_SIZE])
+    }
+
+    /// Get the buffer for the state partition whose flash has `write_size` and `read_size`
+    pub(crate) fn get(&mut self, write_size: usize, read_size: usize) -> &mut [u8] {
+        let len = write_size.max(read_size);
+        assert!(len <= EMBASSY_BOOT_MAX_WRITE_SIZE);
+        &mut self.0[..len]
+    }
+}
+
+/// Confirm that the current firmware boots successfully, otherwise embassy-boot reverts it at next boot.
+///
+/// `state` is the state partition of embassy-boot. It's used when the flash is async, such as `nrf_mpsl::Flash`,
+/// the error is logged as well.
+#[cfg(feature = "embassy_boot")]
+pub async fn mark_booted<F: embedded_storage_async::nor_flash::NorFlash>(
+    state: F,
+) -> Result<(), embassy_boot::FirmwareUpdaterError> {
+    let mut buffer = EmbassyBootBuffer::new();
+    embassy_boot::FirmwareState::new(state, buffer.get(F::WRITE_SIZE, F::READ_SIZE))
+        .mark_booted()
+        .await
+        .inspect_err(|_| error!("Failed to mark the firmware as booted"))
+}
+
+/// A flash shared by the storage and [`EmbassyBootBootloader`]
+#[cfg(feature = "embassy_boot")]
+pub type SharedFlash<F> = Mutex<CriticalSectionRawMutex, RefCell<F>>;
+
+/// A partition of [`SharedFlash`]
+#[cfg(feature = "embassy_boot")]
+pub type SharedFlashPartition<F> =
+    embassy_embedded_hal::flash::partition::BlockingPartition<'static, CriticalSectionRawMutex, F>;
+
+/// An async flash shared by the storage, [`mark_booted`] and [`crate::dfu::DfuUpdater`], such as `nrf_mpsl::Flash`
+#[cfg(feature = "embassy_boot")]
+pub type SharedAsyncFlash<F> = embassy_sync::mutex::Mutex<CriticalSectionRawMutex, F>;
+
+/// A partition of [`SharedAsyncFlash`]
+#[cfg(feature = "embassy_boot")]
+pub type SharedAsyncFlashPartition<F> =
+    embassy_embedded_hal::flash::partition::Partition<'static, CriticalSectionRawMutex, F>;
+
+/// The bootloader built with embassy-boot, which has an ACTIVE and a DFU partition for double-bank updates.
+///
+/// The state partition is written by the `BlockingFirmwareState` of embassy-boot, so the flash must be blocking:
+/// - [`Bootloader::enter`] requests the DFU mode by `mark_dfu`, which is used by `embassy-usb-dfu`
+/// - [`EmbassyBootBootloader::mark_updated`] requests a swap to the firmware in the DFU partition
+/// - [`EmbassyBootBootloader::mark_booted`] confirms the current firmware, otherwise it's reverted at next boot
+#[cfg(feature = "embassy_boot")]
+pub struct EmbassyBootBootloader<F: embedded_storage::nor_flash::NorFlash> {
+    /// The state partition
+    state: F,
+    buffer: EmbassyBootBuffer,
+}
+
+#[cfg(feature = "embassy_boot")]
+impl<F: embedded_storage::nor_flash::NorFlash> EmbassyBootBootloader<F> {
+    /// Create the bootloader with the state partition of embassy-boot
+    pub fn new(state: F) -> Self {
+        Self {
+            state,
+            buffer: EmbassyBootBuffer::new(),
+        }
+    }
+
+    /// Confirm that the current firmware boots successfully, the error is logged as well
+    pub fn mark_booted(&mut self) -> Result<(), embassy_boot::FirmwareUpdaterError> {
+        self.firmware_state()
+            .mark_booted()
+            .inspect_err(|_| error!("Failed to mark the firmware as booted"))
+    }
+
+    /// Swap to the firmware in the DFU partition at next boot
+    pub fn mark_updated(&mut self) -> Result<(), embassy_boot::FirmwareUpdaterError> {
+        self.firmware_state().mark_updated()
+    }
+
+    fn firmware_state(&mut self) -> embassy_boot::BlockingFirmwareState<'_, &mut F> {
+        embassy_boot::BlockingFirmwareState::new(&mut self.state, self.buffer.get(F::WRITE_SIZE, F::READ_SIZE))
+    }
+}
+
+#[cfg(feature = "embassy_boot")]
+impl<F: embedded_storage::nor_flash::NorFlash + Send> Bootloader for EmbassyBootBootloader<F> {
+    fn enter(&mut self) {
+        if self.firmware_state().mark_dfu().is_err() {
+            error!("Failed to write the state partition of embassy-boot");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "embassy_boot"))]
+mod tests {
+    use super::*;
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    #[test]
+    fn test_embassy_boot_state() {
+        let mut flash = MemoryFlash::<512, 256>::new();
+        // Swap progress left by a previous update
+        flash.data[4..8].fill(0x00);
+        let mut bootloader = EmbassyBootBootloader::new(flash);
+
+        bootloader.enter();
+        assert_eq!(&bootloader.state.data[..4], &[0xE0; 4]);
+        assert!(bootloader.state.data[4..].iter().all(|&b| b == 0xFF));
+
+        bootloader.mark_updated().unwrap();
+        assert_eq!(&bootloader.state.data[..4], &[0xF0; 4]);
+
+        bootloader.mark_booted().unwrap();
+        assert_eq!(&bootloader.state.data[..4], &[0xD0; 4]);
+
+        // The async version for a shared async flash
+        let mut flash = MemoryFlash::<512, 256>::new();
+        embassy_futures::block_on(mark_booted(&mut flash)).unwrap();
+        assert_eq!(&flash.data[..4], &[0xD0; 4]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Bootmagic, hold keys at power-up to enter the bootloader, clear the storage or clear BLE bonds
+//!
+//! Key events of local input devices are recorded by `run_devices!` right after power-up,
+//! then the held keys are checked against [`BootmagicConfig`].
+
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Timer};
+#[cfg(feature = "storage")]
+use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
+use heapless::Vec;
+
+use crate::config::BootmagicConfig;
+use crate::event::{KeyboardEvent, KeyboardEventPos};
+#[cfg(feature = "storage")]
+use crate::storage::Storage;
+
+/// Time to wait for the held keys after power-up, which should be longer than the debounce time
+pub(crate) const BOOTMAGIC_WINDOW: Duration = Duration::from_millis(200);
+
+/// Max number of held keys that are recorded
+const BOOTMAGIC_MAX_KEYS: usize = 8;
+
+static CONFIG: Mutex<CriticalSectionRawMutex, Cell<BootmagicConfig>> = Mutex::new(Cell::new(BootmagicConfig {
+    bootloader_keys: &[],
+    clear_storage_keys: &[],
+    clear_bonds_keys: &[],
+}));
+
+/// Keys held after power-up
+static HELD_KEYS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(u8, u8), BOOTMAGIC_MAX_KEYS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Key events are recorded until bootmagic is checked
+static RECORDING: AtomicBool = AtomicBool::new(true);
+
+/// Actions of bootmagic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum BootmagicAction {
+    Bootloader,
+    ClearStorage,
+    ClearBonds,
+}
+
+/// Set the bootmagic config, it should be set before running RMK
+pub fn set_bootmagic_config(config: BootmagicConfig) {
+    CONFIG.lock(|c| c.set(config));
+}
+
+/// Record a key event of local input devices, used by `run_devices!`
+#[doc(hidden)]
+pub fn record_key_event(event: &KeyboardEvent) {
+    if !RECORDING.load(Ordering::Acquire) {
+        return;
+    }
+    if let KeyboardEventPos::Key(pos) = event.pos {
+        let key = (pos.row, pos.col);
+        HELD_KEYS.lock(|keys| {
+            let mut keys = keys.borrow_mut();
+            if !event.pressed {
+                keys.retain(|k| *k != key);
+            } else if !keys.contains(&key) && keys.push(key).is_err() {
+                warn!("Too many keys are held at power-up");
+            }
+        });
+    }
+}
+
+/// Wait for the keys held after power-up, and find the triggered action.
+///
+/// If several combinations are held, the one with the most keys wins.
+pub(crate) async fn check_bootmagic() -> Option<BootmagicAction> {
+    let config = CONFIG.lock(|c| c.get());
+    let combinations = [
+        (config.bootloader_keys, BootmagicAction::Bootloader),
+        (config.clear_storage_keys, BootmagicAction::ClearStorage),
+        (config.clear_bonds_keys, BootmagicAction::ClearBonds),
+    ];
+    if combinations.iter().all(|(keys, _)| keys.is_empty()) {
+        RECORDING.store(false, Ordering::Release);
+        return None;
+    }
+
+    Timer::after(BOOTMAGIC_WINDOW).await;
+    RECORDING.store(false, Ordering::Release);
+
+    let held_keys = HELD_KEYS.lock(|keys| keys.borrow().clone());
+    let mut triggered: Option<(usize, BootmagicAction)> = None;
+    for (keys, action) in combinations {
+        if keys.is_empty() || !keys.iter().all(|k| held_keys.contains(k)) {
+            continue;
+        }
+        if triggered.is_none_or(|(len, _)| keys.len() > len) {
+            triggered = Some((keys.len(), action));
+        }
+    }
+    triggered.map(|(_, action)| action)
+}
+
+/// Run bootmagic without storage, only the bootloader action is supported
+pub(crate) async fn run_bootmagic() {
+    if let Some(action) = check_bootmagic().await {
+        if action != BootmagicAction::Bootloader {
+            warn!("Bootmagic: {:?} is not supported without storage", action);
+            return;
+        }
+        finish_bootmagic(action);
+    }
+}
+
+/// Run bootmagic, the keyboard is rebooted if an action is triggered.
+///
+/// `peripheral` is whether it runs on a split peripheral, which saves the central address instead of host bonds.
+#[cfg(feature = "storage")]
+#[cfg_attr(not(feature = "_ble"), allow(unused_variables))]
+pub(crate) async fn run_bootmagic_with_storage<
+    F: AsyncNorFlash,
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+>(
+    storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
+    peripheral: bool,
+) {
+    let Some(action) = check_bootmagic().await else {
+        return;
+    };
+    match action {
+        BootmagicAction::Bootloader => (),
+        BootmagicAction::ClearStorage => {
+            if storage.erase_all().await.is_err() {
+                error!("Bootmagic: failed to clear the storage");
+            }
+        }
+        #[cfg(feature = "_ble")]
+        BootmagicAction::ClearBonds => {
+            if peripheral {
+                // The split peripheral only saves the address of the central
+                #[cfg(feature = "split")]
+                if storage
+                    .write_peer_address(crate::split::ble::PeerAddress::new(0, false, [0; 6]))
+                    .await
+                    .is_err()
+                {
+                    error!("Bootmagic: failed to clear the central address");
+                }
+            } else {
+                for slot_num in 0..crate::NUM_BLE_PROFILE as u8 {
+                    if storage.remove_bond_info(slot_num).await.is_err() {
+                        error!("Bootmagic: failed to clear the bond info of profile {}", slot_num);
+                    }
+                }
+            }
+        }
+        #[cfg(not(feature = "_ble"))]
+        BootmagicAction::ClearBonds => {
+            warn!("Bootmagic: there's no BLE bond to clear");
+            return;
+        }
+    }
+    finish_bootmagic(action);
+}
+
+/// Enter the bootloader or reboot after the action is done
+fn finish_bootmagic(action: BootmagicAction) {
+    info!("Bootmagic: {:?} is done", action);
+    if action == BootmagicAction::Bootloader {
+        crate::boot::jump_to_bootloader();
+    }
+    crate::boot::reboot_keyboard();
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+
+    rusty_fork_test! {
+        #[test]
+        fn test_check_bootmagic() {
+            set_bootmagic_config(BootmagicConfig {
+                bootloader_keys: &[(0, 0)],
+                clear_storage_keys: &[(0, 0), (0, 1)],
+                clear_bonds_keys: &[(1, 1)],
+            });
+            record_key_event(&KeyboardEvent::key(0, 0, true));
+            record_key_event(&KeyboardEvent::key(0, 1, true));
+            record_key_event(&KeyboardEvent::key(1, 1, true));
+            record_key_event(&KeyboardEvent::key(1, 1, false));
+            // The longest combination wins
+            assert_eq!(block_on(check_bootmagic()), Some(BootmagicAction::ClearStorage));
+            // Key events after the check are ignored
+            record_key_event(&KeyboardEvent::key(1, 1, true));
+            assert_eq!(HELD_KEYS.lock(|keys| keys.borrow().len()), 2);
+        }
+
+        #[test]
+        fn test_bootmagic_not_triggered() {
+            set_bootmagic_config(BootmagicConfig {
+                bootloader_keys: &[(0, 0), (0, 1)],
+                ..Default::default()
+            });
+            record_key_event(&KeyboardEvent::key(0, 0, true));
+            record_key_event(&KeyboardEvent::key(2, 3, true));
+            assert_eq!(block_on(check_bootmagic()), None);
+        }
+
+        #[test]
+        fn test_bootmagic_disabled() {
+            record_key_event(&KeyboardEvent::key(0, 0, true));
+            assert_eq!(block_on(check_bootmagic()), None);
+            assert!(!RECORDING.load(Ordering::Acquire));
+        }
+    }
+}
@@ -68,15 +68,24 @@ pub(crate) static VIAL_READ_CHANNEL: Channel<RawMutex, [u8; 32], VIAL_CHANNEL_SI
 pub(crate) static FLASH_CHANNEL: Channel<RawMutex, FlashOperationMessage, FLASH_CHANNEL_SIZE> = Channel::new();
 #[cfg(feature = "_ble")]
 pub(crate) static BLE_PROFILE_CHANNEL: Channel<RawMutex, BleProfileAction, 1> = Channel::new();
-// Channel for publish split messages to all peripherals
+// Channel for publish split messages to all peripherals, the publishers are the keyboard and the DFU task
 #[cfg(feature = "split")]
 pub(crate) static SPLIT_MESSAGE_PUBLISHER: PubSubChannel<
     RawMutex,
     SplitMessage,
     SPLIT_MESSAGE_CHANNEL_SIZE,
     SPLIT_PERIPHERALS_NUM,
-    1,
+    2,
 > = PubSubChannel::new();
+#[cfg(all(feature = "split", feature = "dfu"))]
+pub(crate) type SplitMessagePub = embassy_sync::pubsub::Publisher<
+    'static,
+    RawMutex,
+    SplitMessage,
+    SPLIT_MESSAGE_CHANNEL_SIZE,
+    SPLIT_PERIPHERALS_NUM,
+    2,
+>;
 
 #[cfg(feature = "controller")]
 pub fn send_controller_event(publisher: &mut ControllerPub, event: ControllerEvent) {
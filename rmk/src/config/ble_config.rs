@@ -1,5 +1,6 @@
 #[cfg(feature = "_nrf_ble")]
 use embassy_nrf::gpio::{Input, Output};
+use embassy_time::Duration;
 
 pub struct BleBatteryConfig<'a> {
     #[cfg(feature = "_nrf_ble")]
@@ -51,3 +52,27 @@ impl<'a> BleBatteryConfig<'a> {
         }
     }
 }
+
+/// Config for BLE connections
+#[derive(Clone, Copy, Debug)]
+pub struct BleConfig {
+    /// Write the reports to both USB and the connected BLE host, when a BLE host is in use
+    pub output_mirror: bool,
+    /// Interval of undirected advertising
+    pub advertising_interval: Duration,
+    /// Timeout of undirected advertising, the keyboard sleeps after the timeout until a key is pressed
+    pub advertising_timeout: Duration,
+    /// Reconnect the bonded host of the active profile with directed advertising first
+    pub directed_advertising: bool,
+}
+
+impl Default for BleConfig {
+    fn default() -> Self {
+        Self {
+            output_mirror: false,
+            advertising_interval: Duration::from_millis(200),
+            advertising_timeout: Duration::from_secs(300),
+            directed_advertising: true,
+        }
+    }
+}
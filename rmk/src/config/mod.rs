@@ -3,13 +3,15 @@ mod ble_config;
 pub mod macro_config;
 
 #[cfg(feature = "_ble")]
-pub use ble_config::BleBatteryConfig;
+pub use ble_config::{BleBatteryConfig, BleConfig};
 use embassy_time::Duration;
 use heapless::Vec;
 use macro_config::KeyboardMacrosConfig;
 
+use crate::action::KeyAction;
 use crate::combo::Combo;
 use crate::fork::Fork;
+use crate::input_device::rotary_encoder::{EncoderBehavior, EncoderMode};
 use crate::morse::{Morse, MorseMode};
 use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MORSE_MAX_NUM};
 
@@ -17,11 +19,17 @@ use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, MORSE_MAX_NUM};
 #[derive(Default)]
 pub struct RmkConfig<'a> {
     pub usb_config: KeyboardUsbConfig<'a>,
+    #[cfg(feature = "webusb")]
+    pub webusb_config: WebUsbConfig,
+    #[cfg(not(feature = "_no_usb"))]
+    pub usb_power_config: UsbPowerConfig,
     pub vial_config: VialConfig<'a>,
     #[cfg(feature = "storage")]
     pub storage_config: StorageConfig,
     #[cfg(feature = "_ble")]
     pub ble_battery_config: BleBatteryConfig<'a>,
+    #[cfg(feature = "_ble")]
+    pub ble_config: BleConfig,
 }
 
 /// Config for configurable action behavior
@@ -36,6 +44,8 @@ pub struct BehaviorConfig {
     pub morse: MorsesConfig,
     pub keyboard_macros: KeyboardMacrosConfig,
     pub mouse_key: MouseKeyConfig,
+    pub layout_options: LayoutOptionsConfig,
+    pub encoder: EncodersConfig,
 }
 
 /// Configurations for morse behavior
@@ -134,6 +144,74 @@ impl Default for ForksConfig {
     }
 }
 
+/// A remapped matrix position `((row, col), (to_row, to_col))`
+pub type KeyRemap = ((u8, u8), (u8, u8));
+
+/// Keymap changes which are enabled by a choice of a layout option
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutOption {
+    /// Index of the layout option, in the same order as the layout options in the VIA definition
+    pub option: u8,
+    /// The choice of the layout option which enables the changes, for toggle options `1` means enabled
+    pub choice: u8,
+    /// Remapped matrix positions `((row, col), (to_row, to_col))`, pressing `(row, col)` triggers the key at `(to_row, to_col)`.
+    ///
+    /// A target position must not be remapped again by any layout option.
+    pub remaps: &'static [KeyRemap],
+    /// Default keys `(layer, row, col, action)`, which are written to the keymap when the choice is selected
+    pub keys: &'static [(u8, u8, u8, KeyAction)],
+}
+
+/// Config for layout options, which are selected by the VIA layout options value
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LayoutOptionsConfig {
+    /// Number of bits of each layout option in the VIA layout options value
+    pub option_bits: &'static [u8],
+    /// Keymap changes of layout options
+    pub options: &'static [LayoutOption],
+}
+
+impl LayoutOptionsConfig {
+    /// Get the selected choice of a layout option from the VIA layout options value.
+    ///
+    /// The first layout option takes the most significant bits of the value.
+    pub fn choice(&self, value: u32, option: u8) -> u8 {
+        let Some(&bits) = self.option_bits.get(option as usize) else {
+            return 0;
+        };
+        let shift = self.option_bits[option as usize + 1..]
+            .iter()
+            .map(|b| *b as u32)
+            .sum::<u32>();
+        let mask = 1u32.checked_shl(bits as u32).map_or(u32::MAX, |m| m - 1);
+        (value.checked_shr(shift).unwrap_or(0) & mask) as u8
+    }
+
+    /// Get the keymap changes which are enabled by the VIA layout options value
+    pub fn selected(&self, value: u32) -> impl Iterator<Item = &'static LayoutOption> {
+        let config = *self;
+        self.options
+            .iter()
+            .filter(move |o| config.choice(value, o.option) == o.choice)
+    }
+
+    /// Get the keymap changes which are enabled by either `previous` or `value`, but not by both
+    pub fn changed(&self, previous: u32, value: u32) -> impl Iterator<Item = &'static LayoutOption> {
+        let config = *self;
+        self.options.iter().filter(move |o| {
+            (config.choice(previous, o.option) == o.choice) != (config.choice(value, o.option) == o.choice)
+        })
+    }
+
+    /// Map the matrix position according to the remapped positions of the selected layout options
+    pub fn remap(&self, value: u32, row: u8, col: u8) -> (u8, u8) {
+        self.selected(value)
+            .flat_map(|o| o.remaps.iter())
+            .find(|(from, _)| *from == (row, col))
+            .map_or((row, col), |(_, to)| *to)
+    }
+}
+
 /// Config for storage
 #[derive(Clone, Copy, Debug)]
 pub struct StorageConfig {
@@ -163,6 +241,8 @@ pub struct VialConfig<'a> {
     pub vial_keyboard_id: &'a [u8],
     pub vial_keyboard_def: &'a [u8],
     pub unlock_keys: &'a [(u8, u8)],
+    /// Relock Vial if no VIA/Vial command is received within the timeout, Vial is never relocked if it's `None`
+    pub lock_timeout: Option<Duration>,
 }
 
 impl<'a> VialConfig<'a> {
@@ -171,6 +251,7 @@ impl<'a> VialConfig<'a> {
             vial_keyboard_id,
             vial_keyboard_def,
             unlock_keys,
+            lock_timeout: None,
         }
     }
 }
@@ -202,8 +283,86 @@ impl Default for KeyboardUsbConfig<'_> {
     }
 }
 
+/// Config for the USB suspend
+#[cfg(not(feature = "_no_usb"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UsbPowerConfig {
+    /// Matrix positions `(row, col)` of the keys which wake up the suspended host, any key wakes up the host if it's empty
+    pub wake_keys: &'static [(u8, u8)],
+}
+
+/// Config for bootmagic, which checks the keys held at power-up.
+///
+/// Each field is a combination of matrix positions `(row, col)`, all keys in it must be held to trigger the action.
+/// An empty combination disables the action.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BootmagicConfig {
+    /// Keys to enter the bootloader
+    pub bootloader_keys: &'static [(u8, u8)],
+    /// Keys to clear the storage, including the keymap and BLE bonds
+    pub clear_storage_keys: &'static [(u8, u8)],
+    /// Keys to clear BLE bonds
+    pub clear_bonds_keys: &'static [(u8, u8)],
+}
+
+/// Config for WebUSB and MS OS 2.0 descriptors
+#[cfg(feature = "webusb")]
+#[derive(Clone, Copy, Debug)]
+pub struct WebUsbConfig {
+    /// URL of the landing page, which the browser shows when the keyboard is connected, the scheme is optional
+    pub landing_url: Option<&'static str>,
+    /// GUID of the vendor interface, which is used by WinUSB on Windows
+    pub device_interface_guid: &'static str,
+}
+
+#[cfg(feature = "webusb")]
+impl Default for WebUsbConfig {
+    fn default() -> Self {
+        Self {
+            landing_url: None,
+            device_interface_guid: "{A6C1B1D3-6C7E-4F5B-9A3E-52524D4B5742}",
+        }
+    }
+}
+
+/// Config for rotary encoders
+///
+/// It's derived from the behaviors of all encoders by [`EncodersConfig::from_behaviors`],
+/// so it's always in sync with the [`EncoderBehavior`](crate::input_device::rotary_encoder::EncoderBehavior) of the devices.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncodersConfig {
+    /// Bitmask of the ids of the encoders in [`EncoderMode::HoldUntilIdle`](crate::input_device::rotary_encoder::EncoderMode::HoldUntilIdle) mode.
+    ///
+    /// A tap-hold action on these encoders holds the hold action while rotating and taps the tap action at every detent,
+    /// a tap-hold action on other encoders works like on a normal key.
+    hold_until_idle: u32,
+}
+
+impl EncodersConfig {
+    /// Create the config from the behaviors of all encoders, indexed by the encoder id.
+    ///
+    /// Only the first 32 encoders can work in [`EncoderMode::HoldUntilIdle`](crate::input_device::rotary_encoder::EncoderMode::HoldUntilIdle) mode.
+    pub const fn from_behaviors(behaviors: &[EncoderBehavior]) -> Self {
+        let mut hold_until_idle = 0;
+        let mut id = 0;
+        while id < behaviors.len() && id < 32 {
+            if matches!(behaviors[id].mode, EncoderMode::HoldUntilIdle { .. }) {
+                hold_until_idle |= 1 << id;
+            }
+            id += 1;
+        }
+        Self { hold_until_idle }
+    }
+
+    /// Whether the encoder works in [`EncoderMode::HoldUntilIdle`](crate::input_device::rotary_encoder::EncoderMode::HoldUntilIdle) mode
+    pub fn is_hold_until_idle(&self, id: u8) -> bool {
+        id < 32 && self.hold_until_idle & (1 << id) != 0
+    }
+}
+
 /// Config for mouse key behavior
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MouseKeyConfig {
     // Accelerated mode parameters
     /// Initial delay between pressing a movement key and first cursor movement (in milliseconds)
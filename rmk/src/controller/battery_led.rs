@@ -4,6 +4,7 @@ use crate::channel::{CONTROLLER_CHANNEL, ControllerSub};
 use crate::controller::{Controller, PollingController};
 use crate::driver::gpio::OutputController;
 use crate::event::ControllerEvent;
+use crate::light::lighting_config;
 
 /// Battery state
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -36,7 +37,7 @@ impl<P: StatefulOutputPin> Controller for BatteryLedController<P> {
         match event {
             ControllerEvent::Battery(level) => {
                 if self.state != BatteryState::Charging {
-                    if level < 10 {
+                    if level < lighting_config().low_battery {
                         self.state = BatteryState::Low;
                     } else {
                         self.state = BatteryState::Normal;
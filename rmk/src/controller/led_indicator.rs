@@ -5,6 +5,7 @@ use crate::channel::{CONTROLLER_CHANNEL, ControllerSub};
 use crate::controller::Controller;
 use crate::driver::gpio::OutputController;
 use crate::event::ControllerEvent;
+use crate::light::lighting_config;
 
 /// Indicators defined in the HID spec 11.1
 #[derive(Debug)]
@@ -21,6 +22,10 @@ pub struct KeyboardIndicatorController<P: StatefulOutputPin> {
     pin: OutputController<P>,
     sub: ControllerSub,
     indicator: KeyboardIndicator,
+    /// Whether the indicator is activated by the host
+    activated: bool,
+    /// The LED is turned off while the USB host is suspended
+    suspended: bool,
 }
 
 impl<P: StatefulOutputPin> KeyboardIndicatorController<P> {
@@ -29,6 +34,18 @@ impl<P: StatefulOutputPin> KeyboardIndicatorController<P> {
             pin: OutputController::new(pin, low_active),
             sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
             indicator: lock_name,
+            activated: false,
+            suspended: false,
+        }
+    }
+}
+
+impl<P: StatefulOutputPin> KeyboardIndicatorController<P> {
+    fn update_pin(&mut self) {
+        if self.activated && !self.suspended && lighting_config().indicators {
+            self.pin.activate();
+        } else {
+            self.pin.deactivate();
         }
     }
 }
@@ -47,11 +64,14 @@ impl<P: StatefulOutputPin> Controller for KeyboardIndicatorController<P> {
                     KeyboardIndicator::Kana => state.kana(),
                 };
                 info!("Activating {} {}", self.indicator, activated);
-                if activated {
-                    self.pin.activate();
-                } else {
-                    self.pin.deactivate();
-                }
+                self.activated = activated;
+                self.update_pin();
+            }
+            ControllerEvent::Lighting(_) => self.update_pin(),
+            #[cfg(not(feature = "_no_usb"))]
+            ControllerEvent::UsbSuspended(suspended) => {
+                self.suspended = suspended;
+                self.update_pin();
             }
             _ => (),
         }
@@ -1,4 +1,5 @@
 use serde::Serialize;
+use usbd_hid::descriptor::SerializedDescriptor;
 use usbd_hid::descriptor::generator_prelude::*;
 
 /// KeyboardReport describes a report and its companion descriptor that can be
@@ -47,7 +48,7 @@ pub struct ViaReport {
 }
 
 /// Predefined report ids for composite hid report.
-/// Should be same with `#[gen_hid_descriptor]`
+/// Should be same with [`COMPOSITE_REPORT_DESCRIPTOR`]
 /// DO NOT EDIT
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
@@ -58,6 +59,8 @@ pub enum CompositeReportType {
     Mouse = 0x01,
     Media = 0x02,
     System = 0x03,
+    /// Feature report of the wheel resolution multiplier
+    ResolutionMultiplier = 0x04,
 }
 
 impl CompositeReportType {
@@ -66,61 +69,447 @@ impl CompositeReportType {
             0x01 => Self::Mouse,
             0x02 => Self::Media,
             0x03 => Self::System,
+            0x04 => Self::ResolutionMultiplier,
             _ => Self::None,
         }
     }
 }
 
+/// Number of high-resolution wheel units per wheel notch.
+///
+/// It's the physical maximum of the resolution multiplier in [`COMPOSITE_REPORT_DESCRIPTOR`].
+pub const WHEEL_RESOLUTION: i16 = 120;
+
+/// Mouse report with 16-bit X/Y, high-resolution wheel and AC Pan.
+///
+/// The wheel and pan are in high-resolution units, [`WHEEL_RESOLUTION`] units is one wheel notch.
+/// If the host doesn't enable the resolution multiplier, the hid writers convert them to notches before sending.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MouseReport {
+    pub buttons: u8, // HidMouseButtons
+    pub x: i16,
+    pub y: i16,
+    pub wheel: i16, // Scroll down (negative) or up (positive) this many units
+    pub pan: i16,   // Scroll left (negative) or right (positive) this many units
+}
+
+/// Report descriptor of the composite hid report, which contains mouse, consumer, system reports.
+/// Report id is used to distinguish from them.
+///
+/// The wheel and AC Pan are in separated logical collections with the resolution multiplier feature report,
+/// which is required by Windows and Linux to enable high-resolution scrolling.
+#[rustfmt::skip]
+pub const COMPOSITE_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x02,             // Usage (Mouse)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, 0x01,             //   Report ID (1)
+    0x09, 0x01,             //   Usage (Pointer)
+    0xA1, 0x00,             //   Collection (Physical)
+    0x05, 0x09,             //     Usage Page (Button)
+    0x19, 0x01,             //     Usage Minimum (1)
+    0x29, 0x08,             //     Usage Maximum (8)
+    0x15, 0x00,             //     Logical Minimum (0)
+    0x25, 0x01,             //     Logical Maximum (1)
+    0x75, 0x01,             //     Report Size (1)
+    0x95, 0x08,             //     Report Count (8)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0x05, 0x01,             //     Usage Page (Generic Desktop)
+    0x09, 0x30,             //     Usage (X)
+    0x09, 0x31,             //     Usage (Y)
+    0x16, 0x01, 0x80,       //     Logical Minimum (-32767)
+    0x26, 0xFF, 0x7F,       //     Logical Maximum (32767)
+    0x75, 0x10,             //     Report Size (16)
+    0x95, 0x02,             //     Report Count (2)
+    0x81, 0x06,             //     Input (Data, Variable, Relative)
+    0xA1, 0x02,             //     Collection (Logical)
+    0x85, 0x04,             //       Report ID (4)
+    0x09, 0x48,             //       Usage (Resolution Multiplier)
+    0x15, 0x00,             //       Logical Minimum (0)
+    0x25, 0x01,             //       Logical Maximum (1)
+    0x35, 0x01,             //       Physical Minimum (1)
+    0x45, 0x78,             //       Physical Maximum (120)
+    0x75, 0x02,             //       Report Size (2)
+    0x95, 0x01,             //       Report Count (1)
+    0xB1, 0x02,             //       Feature (Data, Variable, Absolute)
+    0x85, 0x01,             //       Report ID (1)
+    0x09, 0x38,             //       Usage (Wheel)
+    0x35, 0x00,             //       Physical Minimum (0)
+    0x45, 0x00,             //       Physical Maximum (0)
+    0x16, 0x01, 0x80,       //       Logical Minimum (-32767)
+    0x26, 0xFF, 0x7F,       //       Logical Maximum (32767)
+    0x75, 0x10,             //       Report Size (16)
+    0x95, 0x01,             //       Report Count (1)
+    0x81, 0x06,             //       Input (Data, Variable, Relative)
+    0xC0,                   //     End Collection
+    0xA1, 0x02,             //     Collection (Logical)
+    0x85, 0x04,             //       Report ID (4)
+    0x09, 0x48,             //       Usage (Resolution Multiplier)
+    0x15, 0x00,             //       Logical Minimum (0)
+    0x25, 0x01,             //       Logical Maximum (1)
+    0x35, 0x01,             //       Physical Minimum (1)
+    0x45, 0x78,             //       Physical Maximum (120)
+    0x75, 0x02,             //       Report Size (2)
+    0x95, 0x01,             //       Report Count (1)
+    0xB1, 0x02,             //       Feature (Data, Variable, Absolute)
+    0x75, 0x04,             //       Report Size (4)
+    0xB1, 0x03,             //       Feature (Constant, Variable, Absolute), padding
+    0x85, 0x01,             //       Report ID (1)
+    0x05, 0x0C,             //       Usage Page (Consumer)
+    0x0A, 0x38, 0x02,       //       Usage (AC Pan)
+    0x35, 0x00,             //       Physical Minimum (0)
+    0x45, 0x00,             //       Physical Maximum (0)
+    0x16, 0x01, 0x80,       //       Logical Minimum (-32767)
+    0x26, 0xFF, 0x7F,       //       Logical Maximum (32767)
+    0x75, 0x10,             //       Report Size (16)
+    0x95, 0x01,             //       Report Count (1)
+    0x81, 0x06,             //       Input (Data, Variable, Relative)
+    0xC0,                   //     End Collection
+    0xC0,                   //   End Collection
+    0xC0,                   // End Collection
+    0x05, 0x0C,             // Usage Page (Consumer)
+    0x09, 0x01,             // Usage (Consumer Control)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, 0x02,             //   Report ID (2)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x2A, 0x14, 0x05,       //   Usage Maximum (0x514)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x26, 0x14, 0x05,       //   Logical Maximum (0x514)
+    0x75, 0x10,             //   Report Size (16)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x00,             //   Input (Data, Array, Absolute)
+    0xC0,                   // End Collection
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x80,             // Usage (System Control)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, 0x03,             //   Report ID (3)
+    0x19, 0x81,             //   Usage Minimum (0x81)
+    0x29, 0xB7,             //   Usage Maximum (0xB7)
+    0x15, 0x01,             //   Logical Minimum (1)
+    0x25, 0xB7,             //   Logical Maximum (0xB7)
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x00,             //   Input (Data, Array, Absolute)
+    0xC0,                   // End Collection
+];
+
 /// A composite hid report which contains mouse, consumer, system reports.
 /// Report id is used to distinguish from them.
-#[gen_hid_descriptor(
-    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = MOUSE) = {
-        (collection = PHYSICAL, usage = POINTER) = {
-            (report_id = 0x01,) = {
-                (usage_page = BUTTON, usage_min = BUTTON_1, usage_max = BUTTON_8) = {
-                    #[packed_bits 8] #[item_settings data,variable,absolute] buttons=input;
-                };
-                (usage_page = GENERIC_DESKTOP,) = {
-                    (usage = X,) = {
-                        #[item_settings data,variable,relative] x=input;
-                    };
-                    (usage = Y,) = {
-                        #[item_settings data,variable,relative] y=input;
-                    };
-                    (usage = WHEEL,) = {
-                        #[item_settings data,variable,relative] wheel=input;
-                    };
-                };
-                (usage_page = CONSUMER,) = {
-                    (usage = AC_PAN,) = {
-                        #[item_settings data,variable,relative] pan=input;
-                    };
-                };
-            };
-        };
-    },
-    (collection = APPLICATION, usage_page = CONSUMER, usage = CONSUMER_CONTROL) = {
-        (report_id = 0x02,) = {
-            (usage_page = CONSUMER, usage_min = 0x00, usage_max = 0x514) = {
-            #[item_settings data,array,absolute,not_null] media_usage_id=input;
-            }
-        };
-    },
-    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = SYSTEM_CONTROL) = {
-        (report_id = 0x03,) = {
-            (usage_min = 0x81, usage_max = 0xB7, logical_min = 1) = {
-                #[item_settings data,array,absolute,not_null] system_usage_id=input;
-            };
-        };
-    }
-)]
+///
+/// The report descriptor is [`COMPOSITE_REPORT_DESCRIPTOR`].
 #[derive(Default, Serialize)]
 pub struct CompositeReport {
     pub(crate) buttons: u8, // HidMouseButtons
-    pub(crate) x: i8,
-    pub(crate) y: i8,
-    pub(crate) wheel: i8, // Scroll down (negative) or up (positive) this many units
-    pub(crate) pan: i8,   // Scroll left (negative) or right (positive) this many units
+    pub(crate) x: i16,
+    pub(crate) y: i16,
+    pub(crate) wheel: i16, // Scroll down (negative) or up (positive) this many units
+    pub(crate) pan: i16,   // Scroll left (negative) or right (positive) this many units
     pub(crate) media_usage_id: u16,
     pub(crate) system_usage_id: u8,
 }
+
+impl SerializedDescriptor for CompositeReport {
+    fn desc() -> &'static [u8] {
+        COMPOSITE_REPORT_DESCRIPTOR
+    }
+}
+
+/// Maximum number of contacts reported by the digitizer
+#[cfg(feature = "digitizer")]
+pub const DIGITIZER_MAX_CONTACTS: usize = 5;
+
+/// Logical maximum of X/Y of digitizer contacts
+#[cfg(feature = "digitizer")]
+pub const DIGITIZER_LOGICAL_MAX: u16 = 4095;
+
+/// Report ids of the digitizer hid interface.
+/// Should be same with [`DIGITIZER_REPORT_DESCRIPTOR`]
+/// DO NOT EDIT
+#[cfg(feature = "digitizer")]
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DigitizerReportType {
+    /// Input report of touch contacts
+    Touch = 0x05,
+    /// Feature report of the maximum contact count and the pad type
+    Capabilities = 0x06,
+    /// Feature report of the input mode, set by the host
+    InputMode = 0x07,
+    /// Feature report of the surface switch and the button switch, set by the host
+    FunctionSwitch = 0x08,
+}
+
+/// A contact in [`DigitizerReport`]
+#[cfg(feature = "digitizer")]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DigitizerContact {
+    /// Bit 0 is the confidence, bit 1 is the tip switch
+    pub flags: u8,
+    /// Contact identifier, which should be unique while the finger is on the surface
+    pub id: u8,
+    pub x: u16,
+    pub y: u16,
+}
+
+#[cfg(feature = "digitizer")]
+impl DigitizerContact {
+    pub const CONFIDENCE: u8 = 1 << 0;
+    pub const TIP_SWITCH: u8 = 1 << 1;
+}
+
+/// Touchpad report of the digitizer, which follows the input report layout of Windows Precision Touchpad.
+///
+/// The report descriptor is [`DIGITIZER_REPORT_DESCRIPTOR`].
+#[cfg(feature = "digitizer")]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DigitizerReport {
+    pub contacts: [DigitizerContact; DIGITIZER_MAX_CONTACTS],
+    /// Relative scan time in 100us units
+    pub scan_time: u16,
+    /// Number of valid contacts in `contacts`
+    pub contact_count: u8,
+    /// Bit 0 is the button of the touchpad
+    pub buttons: u8,
+}
+
+/// Descriptor of a single finger in the touchpad collection
+#[cfg(feature = "digitizer")]
+#[rustfmt::skip]
+const DIGITIZER_FINGER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,             // Usage Page (Digitizers)
+    0x09, 0x22,             // Usage (Finger)
+    0xA1, 0x02,             // Collection (Logical)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x09, 0x47,             //   Usage (Confidence)
+    0x09, 0x42,             //   Usage (Tip Switch)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x02,             //   Report Count (2)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x95, 0x06,             //   Report Count (6)
+    0x81, 0x03,             //   Input (Constant, Variable, Absolute), padding
+    0x25, 0x7F,             //   Logical Maximum (127)
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x01,             //   Report Count (1)
+    0x09, 0x51,             //   Usage (Contact Identifier)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x05, 0x01,             //   Usage Page (Generic Desktop)
+    0x26, 0xFF, 0x0F,       //   Logical Maximum (4095)
+    0x75, 0x10,             //   Report Size (16)
+    0x55, 0x0E,             //   Unit Exponent (-2)
+    0x65, 0x11,             //   Unit (Centimeter)
+    0x35, 0x00,             //   Physical Minimum (0)
+    0x46, 0xE8, 0x03,       //   Physical Maximum (1000)
+    0x09, 0x30,             //   Usage (X)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x46, 0x58, 0x02,       //   Physical Maximum (600)
+    0x09, 0x31,             //   Usage (Y)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x55, 0x00,             //   Unit Exponent (0)
+    0x65, 0x00,             //   Unit (None)
+    0x45, 0x00,             //   Physical Maximum (0)
+    0xC0,                   // End Collection
+];
+
+#[cfg(feature = "digitizer")]
+#[rustfmt::skip]
+const DIGITIZER_HEADER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,             // Usage Page (Digitizers)
+    0x09, 0x05,             // Usage (Touch Pad)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, 0x05,             //   Report ID (5)
+];
+
+#[cfg(feature = "digitizer")]
+#[rustfmt::skip]
+const DIGITIZER_FOOTER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,                     //   Usage Page (Digitizers)
+    0x15, 0x00,                     //   Logical Minimum (0)
+    0x27, 0xFF, 0xFF, 0x00, 0x00,   //   Logical Maximum (65535)
+    0x55, 0x0C,                     //   Unit Exponent (-4)
+    0x66, 0x01, 0x10,               //   Unit (Seconds)
+    0x47, 0xFF, 0xFF, 0x00, 0x00,   //   Physical Maximum (65535)
+    0x75, 0x10,                     //   Report Size (16)
+    0x95, 0x01,                     //   Report Count (1)
+    0x09, 0x56,                     //   Usage (Scan Time)
+    0x81, 0x02,                     //   Input (Data, Variable, Absolute)
+    0x55, 0x00,                     //   Unit Exponent (0)
+    0x65, 0x00,                     //   Unit (None)
+    0x45, 0x00,                     //   Physical Maximum (0)
+    0x25, 0x7F,                     //   Logical Maximum (127)
+    0x75, 0x08,                     //   Report Size (8)
+    0x09, 0x54,                     //   Usage (Contact Count)
+    0x81, 0x02,                     //   Input (Data, Variable, Absolute)
+    0x05, 0x09,                     //   Usage Page (Button)
+    0x09, 0x01,                     //   Usage (Button 1)
+    0x25, 0x01,                     //   Logical Maximum (1)
+    0x75, 0x01,                     //   Report Size (1)
+    0x81, 0x02,                     //   Input (Data, Variable, Absolute)
+    0x95, 0x07,                     //   Report Count (7)
+    0x81, 0x03,                     //   Input (Constant, Variable, Absolute), padding
+    0x05, 0x0D,                     //   Usage Page (Digitizers)
+    0x85, 0x06,                     //   Report ID (6)
+    0x09, 0x55,                     //   Usage (Contact Count Maximum)
+    0x09, 0x59,                     //   Usage (Pad Type)
+    0x25, 0x0F,                     //   Logical Maximum (15)
+    0x75, 0x04,                     //   Report Size (4)
+    0x95, 0x02,                     //   Report Count (2)
+    0xB1, 0x02,                     //   Feature (Data, Variable, Absolute)
+    0xC0,                           // End Collection
+    0x05, 0x0D,                     // Usage Page (Digitizers)
+    0x09, 0x0E,                     // Usage (Device Configuration)
+    0xA1, 0x01,                     // Collection (Application)
+    0x85, 0x07,                     //   Report ID (7)
+    0x09, 0x22,                     //   Usage (Finger)
+    0xA1, 0x02,                     //   Collection (Logical)
+    0x09, 0x52,                     //     Usage (Input Mode)
+    0x15, 0x00,                     //     Logical Minimum (0)
+    0x25, 0x0A,                     //     Logical Maximum (10)
+    0x75, 0x08,                     //     Report Size (8)
+    0x95, 0x01,                     //     Report Count (1)
+    0xB1, 0x02,                     //     Feature (Data, Variable, Absolute)
+    0xC0,                           //   End Collection
+    0x09, 0x22,                     //   Usage (Finger)
+    0xA1, 0x00,                     //   Collection (Physical)
+    0x85, 0x08,                     //     Report ID (8)
+    0x09, 0x57,                     //     Usage (Surface Switch)
+    0x09, 0x58,                     //     Usage (Button Switch)
+    0x25, 0x01,                     //     Logical Maximum (1)
+    0x75, 0x01,                     //     Report Size (1)
+    0x95, 0x02,                     //     Report Count (2)
+    0xB1, 0x02,                     //     Feature (Data, Variable, Absolute)
+    0x95, 0x06,                     //     Report Count (6)
+    0xB1, 0x03,                     //     Feature (Constant, Variable, Absolute), padding
+    0xC0,                           //   End Collection
+    0xC0,                           // End Collection
+];
+
+#[cfg(feature = "digitizer")]
+const DIGITIZER_REPORT_DESCRIPTOR_LEN: usize = DIGITIZER_HEADER_DESCRIPTOR.len()
+    + DIGITIZER_FINGER_DESCRIPTOR.len() * DIGITIZER_MAX_CONTACTS
+    + DIGITIZER_FOOTER_DESCRIPTOR.len();
+
+/// Report descriptor of the digitizer, which contains a touchpad collection with [`DIGITIZER_MAX_CONTACTS`] fingers
+/// and a device configuration collection.
+///
+/// The certification status feature report (usage page 0xFF00, usage 0xC5) is not included, so Windows doesn't
+/// enable the precision touchpad mode, other hosts use the touchpad as a generic multi-touch digitizer.
+#[cfg(feature = "digitizer")]
+pub const DIGITIZER_REPORT_DESCRIPTOR: [u8; DIGITIZER_REPORT_DESCRIPTOR_LEN] = {
+    const fn copy(
+        mut desc: [u8; DIGITIZER_REPORT_DESCRIPTOR_LEN],
+        offset: usize,
+        part: &[u8],
+    ) -> [u8; DIGITIZER_REPORT_DESCRIPTOR_LEN] {
+        let mut i = 0;
+        while i < part.len() {
+            desc[offset + i] = part[i];
+            i += 1;
+        }
+        desc
+    }
+
+    let mut desc = copy([0; DIGITIZER_REPORT_DESCRIPTOR_LEN], 0, DIGITIZER_HEADER_DESCRIPTOR);
+    let mut offset = DIGITIZER_HEADER_DESCRIPTOR.len();
+    let mut finger = 0;
+    while finger < DIGITIZER_MAX_CONTACTS {
+        desc = copy(desc, offset, DIGITIZER_FINGER_DESCRIPTOR);
+        offset += DIGITIZER_FINGER_DESCRIPTOR.len();
+        finger += 1;
+    }
+    copy(desc, offset, DIGITIZER_FOOTER_DESCRIPTOR)
+};
+
+#[cfg(feature = "digitizer")]
+impl SerializedDescriptor for DigitizerReport {
+    fn desc() -> &'static [u8] {
+        &DIGITIZER_REPORT_DESCRIPTOR
+    }
+}
+
+/// Gamepad report with 32 buttons, a hat switch and 6 axes.
+///
+/// The report descriptor is [`GAMEPAD_REPORT_DESCRIPTOR`].
+#[cfg(feature = "gamepad")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GamepadReport {
+    /// Bit n is the button n+1
+    pub buttons: u32,
+    /// Direction of the hat switch, 0 is up, increased by 45 degrees clockwise. [`GamepadReport::HAT_CENTERED`] means the hat is released
+    pub hat: u8,
+    /// X, Y, Z, Rx, Ry, Rz axes
+    pub axes: [i16; 6],
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadReport {
+    /// Null state of the hat switch
+    pub const HAT_CENTERED: u8 = 8;
+}
+
+#[cfg(feature = "gamepad")]
+impl Default for GamepadReport {
+    fn default() -> Self {
+        Self {
+            buttons: 0,
+            hat: Self::HAT_CENTERED,
+            axes: [0; 6],
+        }
+    }
+}
+
+/// Report descriptor of the gamepad
+#[cfg(feature = "gamepad")]
+#[rustfmt::skip]
+pub const GAMEPAD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x05,             // Usage (Game Pad)
+    0xA1, 0x01,             // Collection (Application)
+    0x05, 0x09,             //   Usage Page (Button)
+    0x19, 0x01,             //   Usage Minimum (1)
+    0x29, 0x20,             //   Usage Maximum (32)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x20,             //   Report Count (32)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x05, 0x01,             //   Usage Page (Generic Desktop)
+    0x09, 0x39,             //   Usage (Hat Switch)
+    0x25, 0x07,             //   Logical Maximum (7)
+    0x35, 0x00,             //   Physical Minimum (0)
+    0x46, 0x3B, 0x01,       //   Physical Maximum (315)
+    0x65, 0x14,             //   Unit (Degrees)
+    0x75, 0x04,             //   Report Size (4)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x42,             //   Input (Data, Variable, Absolute, Null State)
+    0x65, 0x00,             //   Unit (None)
+    0x45, 0x00,             //   Physical Maximum (0)
+    0x81, 0x03,             //   Input (Constant, Variable, Absolute), padding
+    0x09, 0x30,             //   Usage (X)
+    0x09, 0x31,             //   Usage (Y)
+    0x09, 0x32,             //   Usage (Z)
+    0x09, 0x33,             //   Usage (Rx)
+    0x09, 0x34,             //   Usage (Ry)
+    0x09, 0x35,             //   Usage (Rz)
+    0x16, 0x01, 0x80,       //   Logical Minimum (-32767)
+    0x26, 0xFF, 0x7F,       //   Logical Maximum (32767)
+    0x75, 0x10,             //   Report Size (16)
+    0x95, 0x06,             //   Report Count (6)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0xC0,                   // End Collection
+];
+
+#[cfg(feature = "gamepad")]
+impl AsInputReport for GamepadReport {}
+
+#[cfg(feature = "gamepad")]
+impl SerializedDescriptor for GamepadReport {
+    fn desc() -> &'static [u8] {
+        GAMEPAD_REPORT_DESCRIPTOR
+    }
+}
@@ -0,0 +1,867 @@
+//! Firmware update over BLE, using the DFU partition of embassy-boot
+//!
+//! The firmware image is streamed through the DFU GATT service, written to the DFU partition and verified by its
+//! Ed25519 signature. Then the state partition is marked, so that embassy-boot swaps to the new firmware at next boot.
+//! A split central forwards the requests whose target is a peripheral over the split link.
+//!
+//! Writes to the control point:
+//! - `[0x01, size: u32 LE, target: u8]` starts an update, `target` is 0 for the keyboard itself, or `id + 1` for a split peripheral
+//! - `[0x02, signature: [u8; 64]]` finishes the update
+//! - `[0x03]` aborts the update
+//! - `[0x04]` queries the number of received bytes
+//!
+//! Writes to the data characteristic are `[offset: u32 LE, data...]`, the data must be sent in order.
+//!
+//! The responses are notified by the control point: `[opcode, status, offset: u32 LE]`,
+//! where `offset` is the number of received bytes. Data packets are only responded when there's an error.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ed25519_compact::{PublicKey, Signature};
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+use embedded_storage_async::nor_flash::NorFlash;
+use heapless::Vec;
+use num_enum::TryFromPrimitive;
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::RawMutex;
+use crate::boot::EmbassyBootBuffer;
+use crate::firmware::{FirmwareMetadataReader, verify_firmware_metadata};
+
+/// Size of the Ed25519 public key which verifies the firmware image
+pub const DFU_PUBLIC_KEY_SIZE: usize = PublicKey::BYTES;
+
+/// Size of the Ed25519 signature of the firmware image
+pub const DFU_SIGNATURE_SIZE: usize = Signature::BYTES;
+
+/// Max size of a packet written to the data characteristic, including the 4-byte offset
+pub const DFU_PACKET_MAX_SIZE: usize = 244;
+
+/// Max size of a write to the control point
+pub(crate) const DFU_CONTROL_POINT_MAX_SIZE: usize = 1 + DFU_SIGNATURE_SIZE;
+
+/// Size of the response notified by the control point
+pub(crate) const DFU_RESPONSE_SIZE: usize = 6;
+
+/// Max size of image data in a split message
+pub(crate) const DFU_SPLIT_CHUNK_SIZE: usize = 16;
+
+/// Size of the buffer which aligns the image data to the write size of the flash
+const DFU_WRITE_BUFFER_SIZE: usize = 256;
+
+/// Requests from the DFU GATT service or the split central
+pub(crate) static DFU_REQUEST_CHANNEL: Channel<RawMutex, DfuRequest, 4> = Channel::new();
+
+/// Responses to the DFU GATT service or the split central
+pub(crate) static DFU_RESPONSE_CHANNEL: Channel<RawMutex, DfuResponse, 4> = Channel::new();
+
+/// Whether [`run_dfu`] is running
+static DFU_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Opcodes of the DFU control point
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum DfuOpcode {
+    Start = 0x01,
+    Finish = 0x02,
+    Abort = 0x03,
+    Status = 0x04,
+    /// Used in the responses to data packets
+    Data = 0x05,
+}
+
+/// Status in the responses of DFU requests
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DfuStatus {
+    Success = 0,
+    /// The request is malformed
+    InvalidCommand = 1,
+    /// The request isn't expected in current state, such as data before the update is started
+    InvalidState = 2,
+    /// The offset of the data isn't the number of received bytes
+    InvalidOffset = 3,
+    /// The image doesn't fit in the DFU partition, or is larger than the size in the start request
+    ImageTooLarge = 4,
+    /// Failed to write the flash
+    FlashError = 5,
+    /// The signature doesn't match the image
+    VerifyFailed = 6,
+    /// The target of the update is not available, such as a split peripheral on a non-split keyboard
+    TargetUnavailable = 7,
+    /// The image is built for another keyboard or split part, or is older than the running firmware.
+    /// See [`crate::firmware::FirmwareMetadata::verify_update`]
+    IncompatibleImage = 8,
+}
+
+/// Response of a DFU request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DfuResponse {
+    pub(crate) opcode: u8,
+    pub(crate) status: DfuStatus,
+    /// Number of received bytes
+    pub(crate) offset: u32,
+}
+
+impl DfuResponse {
+    pub(crate) fn new(opcode: DfuOpcode, status: DfuStatus, offset: u32) -> Self {
+        Self {
+            opcode: opcode as u8,
+            status,
+            offset,
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; DFU_RESPONSE_SIZE] {
+        let offset = self.offset.to_le_bytes();
+        [
+            self.opcode,
+            self.status as u8,
+            offset[0],
+            offset[1],
+            offset[2],
+            offset[3],
+        ]
+    }
+}
+
+/// Request of the firmware update
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum DfuRequest {
+    /// Start an update, `target` is 0 for the keyboard itself, or `id + 1` for a split peripheral
+    Start {
+        target: u8,
+        size: u32,
+    },
+    Data {
+        offset: u32,
+        data: Vec<u8, DFU_PACKET_MAX_SIZE>,
+    },
+    /// Part of the signature, which is sent before [`DfuRequest::Finish`] by the split central,
+    /// because the signature doesn't fit in a split message
+    Signature {
+        offset: u8,
+        data: [u8; DFU_SPLIT_CHUNK_SIZE],
+    },
+    /// Finish the update, the signature is `None` if it's sent by [`DfuRequest::Signature`]
+    Finish {
+        signature: Option<[u8; DFU_SIGNATURE_SIZE]>,
+    },
+    Abort,
+    Status,
+}
+
+impl DfuRequest {
+    /// Parse a write to the control point
+    pub(crate) fn from_control_point(data: &[u8]) -> Option<Self> {
+        let (&opcode, params) = data.split_first()?;
+        match DfuOpcode::try_from(opcode).ok()? {
+            DfuOpcode::Start => {
+                let size = u32::from_le_bytes(params.get(0..4)?.try_into().ok()?);
+                let target = params.get(4).copied().unwrap_or(0);
+                Some(DfuRequest::Start { target, size })
+            }
+            DfuOpcode::Finish => Some(DfuRequest::Finish {
+                signature: Some(params.try_into().ok()?),
+            }),
+            DfuOpcode::Abort => Some(DfuRequest::Abort),
+            DfuOpcode::Status => Some(DfuRequest::Status),
+            DfuOpcode::Data => None,
+        }
+    }
+
+    /// Parse a write to the data characteristic, which is the offset followed by the image data
+    pub(crate) fn from_packet(data: &[u8]) -> Option<Self> {
+        if data.len() <= 4 {
+            return None;
+        }
+        let (offset, data) = data.split_at(4);
+        Some(DfuRequest::Data {
+            offset: u32::from_le_bytes(offset.try_into().ok()?),
+            data: Vec::from_slice(data).ok()?,
+        })
+    }
+
+    pub(crate) fn opcode(&self) -> DfuOpcode {
+        match self {
+            DfuRequest::Start { .. } => DfuOpcode::Start,
+            DfuRequest::Data { .. } => DfuOpcode::Data,
+            DfuRequest::Signature { .. } | DfuRequest::Finish { .. } => DfuOpcode::Finish,
+            DfuRequest::Abort => DfuOpcode::Abort,
+            DfuRequest::Status => DfuOpcode::Status,
+        }
+    }
+}
+
+/// DFU request forwarded from the central to a split peripheral.
+///
+/// The image data and the signature are split into small chunks, to keep the split message small.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, MaxSize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum SplitDfuRequest {
+    Start(u32),
+    Data {
+        offset: u32,
+        len: u8,
+        data: [u8; DFU_SPLIT_CHUNK_SIZE],
+    },
+    Signature {
+        offset: u8,
+        data: [u8; DFU_SPLIT_CHUNK_SIZE],
+    },
+    Finish,
+    Abort,
+    Status,
+}
+
+impl From<SplitDfuRequest> for DfuRequest {
+    fn from(request: SplitDfuRequest) -> Self {
+        match request {
+            // The peripheral updates itself
+            SplitDfuRequest::Start(size) => DfuRequest::Start { target: 0, size },
+            SplitDfuRequest::Data { offset, len, data } => DfuRequest::Data {
+                offset,
+                data: Vec::from_slice(&data[..(len as usize).min(DFU_SPLIT_CHUNK_SIZE)]).unwrap_or_default(),
+            },
+            SplitDfuRequest::Signature { offset, data } => DfuRequest::Signature { offset, data },
+            SplitDfuRequest::Finish => DfuRequest::Finish { signature: None },
+            SplitDfuRequest::Abort => DfuRequest::Abort,
+            SplitDfuRequest::Status => DfuRequest::Status,
+        }
+    }
+}
+
+/// Send a request to [`run_dfu`], the request is rejected if it's not running
+pub(crate) async fn send_dfu_request(request: DfuRequest) {
+    if DFU_RUNNING.load(Ordering::Acquire) {
+        DFU_REQUEST_CHANNEL.send(request).await;
+    } else {
+        warn!("DFU is not running");
+        send_dfu_response(DfuResponse::new(request.opcode(), DfuStatus::TargetUnavailable, 0));
+    }
+}
+
+/// Send a response, it's dropped if nobody receives the responses
+pub(crate) fn send_dfu_response(response: DfuResponse) {
+    if DFU_RESPONSE_CHANNEL.try_send(response).is_err() {
+        warn!("DFU response channel is full, dropping {:?}", response);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DfuState {
+    Idle,
+    /// Receiving an image of `size` bytes
+    Receiving {
+        size: u32,
+        received: u32,
+    },
+    /// The image is verified, and will be swapped at next boot
+    Done {
+        size: u32,
+    },
+}
+
+#[repr(align(4))]
+struct AlignedBuffer([u8; DFU_WRITE_BUFFER_SIZE]);
+
+/// The transfer state machine of the firmware update.
+///
+/// The image is written to the DFU partition of embassy-boot. After it's verified, the state partition is marked by
+/// the `FirmwareState` of embassy-boot, then the bootloader swaps to the image at next boot.
+/// The new firmware should mark itself as booted, see [`DfuUpdater::mark_booted`].
+pub struct DfuUpdater<D: NorFlash, S: NorFlash> {
+    /// The DFU partition
+    dfu: D,
+    /// The state partition
+    state: S,
+    state_buffer: EmbassyBootBuffer,
+    /// Public key which verifies the signature of the image
+    public_key: [u8; DFU_PUBLIC_KEY_SIZE],
+    /// Signature received by [`DfuRequest::Signature`]
+    signature: [u8; DFU_SIGNATURE_SIZE],
+    dfu_state: DfuState,
+    /// Image data which isn't written yet
+    buffer: AlignedBuffer,
+    buffer_len: usize,
+    /// Number of bytes written to the DFU partition
+    written: u32,
+}
+
+impl<D: NorFlash, S: NorFlash> DfuUpdater<D, S> {
+    /// Create the updater with the DFU and state partitions of embassy-boot, and the Ed25519 public key which
+    /// verifies the image. Only the public key is built into the firmware, the private key is kept by the developer
+    pub fn new(dfu: D, state: S, public_key: [u8; DFU_PUBLIC_KEY_SIZE]) -> Self {
+        assert!(
+            DFU_WRITE_BUFFER_SIZE.is_multiple_of(D::WRITE_SIZE) && DFU_WRITE_BUFFER_SIZE.is_multiple_of(D::READ_SIZE)
+        );
+        Self {
+            dfu,
+            state,
+            state_buffer: EmbassyBootBuffer::new(),
+            public_key,
+            signature: [0; DFU_SIGNATURE_SIZE],
+            dfu_state: DfuState::Idle,
+            buffer: AlignedBuffer([0xFF; DFU_WRITE_BUFFER_SIZE]),
+            buffer_len: 0,
+            written: 0,
+        }
+    }
+
+    /// Process a request, data packets are only responded when there's an error
+    pub(crate) async fn process(&mut self, request: DfuRequest) -> Option<DfuResponse> {
+        let opcode = request.opcode();
+        // Data packets and signature parts are only responded when there's an error
+        let silent = matches!(request, DfuRequest::Data { .. } | DfuRequest::Signature { .. });
+        let result = match request {
+            DfuRequest::Start { size, .. } => self.start(size).await,
+            DfuRequest::Data { offset, data } => self.write(offset, &data).await,
+            DfuRequest::Signature { offset, data } => self.receive_signature(offset as usize, &data),
+            DfuRequest::Finish { signature } => {
+                if let Some(signature) = signature {
+                    self.signature = signature;
+                }
+                self.finish().await
+            }
+            DfuRequest::Abort => {
+                info!("DFU aborted");
+                self.dfu_state = DfuState::Idle;
+                Ok(())
+            }
+            DfuRequest::Status => Ok(()),
+        };
+        match result {
+            Ok(()) if silent => None,
+            Ok(()) => Some(DfuResponse::new(opcode, DfuStatus::Success, self.received())),
+            Err(status) => {
+                warn!("DFU {:?} failed: {:?}", opcode, status);
+                Some(DfuResponse::new(opcode, status, self.received()))
+            }
+        }
+    }
+
+    /// Number of received bytes of the image
+    fn received(&self) -> u32 {
+        match self.dfu_state {
+            DfuState::Idle => 0,
+            DfuState::Receiving { received, .. } => received,
+            DfuState::Done { size } => size,
+        }
+    }
+
+    async fn start(&mut self, size: u32) -> Result<(), DfuStatus> {
+        self.dfu_state = DfuState::Idle;
+        if size == 0 {
+            return Err(DfuStatus::InvalidCommand);
+        }
+        if size as usize > self.dfu.capacity() {
+            return Err(DfuStatus::ImageTooLarge);
+        }
+        info!("DFU started, image size: {}", size);
+        let erase_end = (size as usize).next_multiple_of(D::ERASE_SIZE).min(self.dfu.capacity());
+        self.dfu
+            .erase(0, erase_end as u32)
+            .await
+            .map_err(|_| DfuStatus::FlashError)?;
+        self.buffer_len = 0;
+        self.written = 0;
+        self.dfu_state = DfuState::Receiving { size, received: 0 };
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, mut data: &[u8]) -> Result<(), DfuStatus> {
+        let DfuState::Receiving { size, received } = self.dfu_state else {
+            return Err(DfuStatus::InvalidState);
+        };
+        if offset != received {
+            return Err(DfuStatus::InvalidOffset);
+        }
+        if received as usize + data.len() > size as usize {
+            return Err(DfuStatus::ImageTooLarge);
+        }
+        let received = received + data.len() as u32;
+        while !data.is_empty() {
+            let len = data.len().min(DFU_WRITE_BUFFER_SIZE - self.buffer_len);
+            self.buffer.0[self.buffer_len..self.buffer_len + len].copy_from_slice(&data[..len]);
+            self.buffer_len += len;
+            data = &data[len..];
+            if self.buffer_len == DFU_WRITE_BUFFER_SIZE
+                && let Err(e) = self.flush().await
+            {
+                // The update must be restarted
+                self.dfu_state = DfuState::Idle;
+                return Err(e);
+            }
+        }
+        self.dfu_state = DfuState::Receiving { size, received };
+        Ok(())
+    }
+
+    /// Write the buffered data, the last write is padded to the write size
+    async fn flush(&mut self) -> Result<(), DfuStatus> {
+        if self.buffer_len == 0 {
+            return Ok(());
+        }
+        let len = self.buffer_len.next_multiple_of(D::WRITE_SIZE);
+        self.buffer.0[self.buffer_len..len].fill(0xFF);
+        let result = self.dfu.write(self.written, &self.buffer.0[..len]).await;
+        self.written += len as u32;
+        self.buffer_len = 0;
+        result.map_err(|_| DfuStatus::FlashError)
+    }
+
+    fn receive_signature(&mut self, offset: usize, data: &[u8]) -> Result<(), DfuStatus> {
+        if !matches!(self.dfu_state, DfuState::Receiving { .. }) {
+            return Err(DfuStatus::InvalidState);
+        }
+        let len = data.len().min(DFU_SIGNATURE_SIZE.saturating_sub(offset));
+        if len == 0 {
+            return Err(DfuStatus::InvalidCommand);
+        }
+        self.signature[offset..offset + len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), DfuStatus> {
+        let DfuState::Receiving { size, received } = self.dfu_state else {
+            return Err(DfuStatus::InvalidState);
+        };
+        if received != size {
+            return Err(DfuStatus::InvalidState);
+        }
+        self.flush().await?;
+        // Verify the image in the flash
+        if let Err(e) = self.verify(size).await {
+            self.dfu_state = DfuState::Idle;
+            return Err(e);
+        }
+        self.mark_updated().await?;
+        info!("DFU finished, the firmware will be updated at next boot");
+        self.dfu_state = DfuState::Done { size };
+        Ok(())
+    }
+
+    async fn verify(&mut self, size: u32) -> Result<(), DfuStatus> {
+        let mut verifier = PublicKey::new(self.public_key)
+            .verify_incremental(&Signature::new(self.signature))
+            .map_err(|_| DfuStatus::VerifyFailed)?;
+        let mut metadata = FirmwareMetadataReader::default();
+        let mut offset = 0;
+        while offset < size {
+            let len = ((size - offset) as usize).min(DFU_WRITE_BUFFER_SIZE);
+            let read_len = len.next_multiple_of(D::READ_SIZE);
+            self.dfu
+                .read(offset, &mut self.buffer.0[..read_len])
+                .await
+                .map_err(|_| DfuStatus::FlashError)?;
+            verifier.absorb(&self.buffer.0[..len]);
+            metadata.update(offset as usize, &self.buffer.0[..len]);
+            offset += len as u32;
+        }
+        verifier.verify().map_err(|_| DfuStatus::VerifyFailed)?;
+        verify_firmware_metadata(metadata.finish().as_ref()).map_err(|e| {
+            error!("Incompatible firmware image: {:?}", e);
+            DfuStatus::IncompatibleImage
+        })
+    }
+
+    /// Confirm that the current firmware boots successfully, otherwise embassy-boot reverts it at next boot.
+    ///
+    /// It's the same as [`crate::boot::mark_booted`].
+    pub async fn mark_booted(&mut self) -> Result<(), embassy_boot::FirmwareUpdaterError> {
+        self.firmware_state().mark_booted().await
+    }
+
+    /// Request a swap to the image in the DFU partition at next boot
+    async fn mark_updated(&mut self) -> Result<(), DfuStatus> {
+        self.firmware_state()
+            .mark_updated()
+            .await
+            .map_err(|_| DfuStatus::FlashError)
+    }
+
+    fn firmware_state(&mut self) -> embassy_boot::FirmwareState<'_, &mut S> {
+        embassy_boot::FirmwareState::new(&mut self.state, self.state_buffer.get(S::WRITE_SIZE, S::READ_SIZE))
+    }
+}
+
+/// Run the firmware update, which processes the requests from the DFU GATT service or the split central.
+///
+/// On a split central, requests whose target is a peripheral are forwarded to it.
+/// The keyboard reboots after the image is verified, then embassy-boot swaps to the new firmware.
+pub async fn run_dfu<D: NorFlash, S: NorFlash>(updater: &mut DfuUpdater<D, S>) {
+    DFU_RUNNING.store(true, Ordering::Release);
+    #[cfg(feature = "split")]
+    let mut target = 0;
+    #[cfg(feature = "split")]
+    let publisher = crate::channel::SPLIT_MESSAGE_PUBLISHER
+        .publisher()
+        .expect("Failed to create split message publisher: MaximumPublishersReached");
+
+    loop {
+        let request = DFU_REQUEST_CHANNEL.receive().await;
+        #[cfg(feature = "split")]
+        {
+            if let DfuRequest::Start { target: t, .. } = request {
+                target = t;
+            }
+            if target != 0 {
+                // Forward to the split peripheral, which sends the response back
+                forward_to_peripheral(&publisher, target - 1, request).await;
+                continue;
+            }
+        }
+        let response = match request {
+            DfuRequest::Start { target, .. } if target != 0 => {
+                Some(DfuResponse::new(DfuOpcode::Start, DfuStatus::TargetUnavailable, 0))
+            }
+            request => updater.process(request).await,
+        };
+        if let Some(response) = response {
+            send_dfu_response(response);
+            if response.opcode == DfuOpcode::Finish as u8 && response.status == DfuStatus::Success {
+                // Wait for the response to be sent
+                Timer::after_secs(1).await;
+                crate::boot::reboot_keyboard();
+            }
+        }
+    }
+}
+
+/// Forward a request to the split peripheral with the `id`
+#[cfg(feature = "split")]
+async fn forward_to_peripheral(publisher: &crate::channel::SplitMessagePub, id: u8, request: DfuRequest) {
+    use crate::split::SplitMessage;
+
+    let request = match request {
+        DfuRequest::Start { size, .. } => SplitDfuRequest::Start(size),
+        DfuRequest::Data { offset, data } => {
+            for (i, chunk) in data.chunks(DFU_SPLIT_CHUNK_SIZE).enumerate() {
+                let mut buf = [0; DFU_SPLIT_CHUNK_SIZE];
+                buf[..chunk.len()].copy_from_slice(chunk);
+                let request = SplitDfuRequest::Data {
+                    offset: offset + (i * DFU_SPLIT_CHUNK_SIZE) as u32,
+                    len: chunk.len() as u8,
+                    data: buf,
+                };
+                publisher.publish(SplitMessage::Dfu(id, request)).await;
+            }
+            return;
+        }
+        DfuRequest::Signature { offset, data } => SplitDfuRequest::Signature { offset, data },
+        DfuRequest::Finish { signature } => {
+            // Send the signature in parts before finishing
+            for (i, chunk) in signature
+                .iter()
+                .flat_map(|s| s.chunks(DFU_SPLIT_CHUNK_SIZE))
+                .enumerate()
+            {
+                let mut data = [0; DFU_SPLIT_CHUNK_SIZE];
+                data[..chunk.len()].copy_from_slice(chunk);
+                let request = SplitDfuRequest::Signature {
+                    offset: (i * DFU_SPLIT_CHUNK_SIZE) as u8,
+                    data,
+                };
+                publisher.publish(SplitMessage::Dfu(id, request)).await;
+            }
+            SplitDfuRequest::Finish
+        }
+        DfuRequest::Abort => SplitDfuRequest::Abort,
+        DfuRequest::Status => SplitDfuRequest::Status,
+    };
+    publisher.publish(SplitMessage::Dfu(id, request)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_compact::{KeyPair, Seed};
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::firmware::{
+        FIRMWARE_METADATA_OFFSET, FIRMWARE_METADATA_SIZE, FirmwareMetadata, FirmwareVersion, set_firmware_metadata,
+    };
+    use crate::storage::dummy_flash::MemoryFlash;
+
+    /// Magic values in the state partition of embassy-boot
+    const SWAP_MAGIC: u8 = 0xF0;
+    const BOOT_MAGIC: u8 = 0xD0;
+
+    type DfuFlash = MemoryFlash<2048, 256>;
+    type StateFlash = MemoryFlash<256, 256>;
+
+    fn key_pair() -> KeyPair {
+        KeyPair::from_seed(Seed::new([0x2B; Seed::BYTES]))
+    }
+
+    fn updater() -> DfuUpdater<DfuFlash, StateFlash> {
+        DfuUpdater::new(DfuFlash::new(), StateFlash::new(), *key_pair().pk)
+    }
+
+    fn image(size: usize) -> std::vec::Vec<u8> {
+        (0..size).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn sign(image: &[u8]) -> [u8; DFU_SIGNATURE_SIZE] {
+        *key_pair().sk.sign(image, None)
+    }
+
+    fn packet(offset: usize, data: &[u8]) -> std::vec::Vec<u8> {
+        let mut packet = (offset as u32).to_le_bytes().to_vec();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn start(size: u32) -> DfuRequest {
+        let mut cmd = std::vec![DfuOpcode::Start as u8];
+        cmd.extend_from_slice(&size.to_le_bytes());
+        DfuRequest::from_control_point(&cmd).unwrap()
+    }
+
+    fn finish(signature: &[u8; DFU_SIGNATURE_SIZE]) -> DfuRequest {
+        let mut cmd = std::vec![DfuOpcode::Finish as u8];
+        cmd.extend_from_slice(signature);
+        DfuRequest::from_control_point(&cmd).unwrap()
+    }
+
+    /// Send the image in packets of `chunk_size` bytes
+    fn send_image(updater: &mut DfuUpdater<DfuFlash, StateFlash>, image: &[u8], chunk_size: usize) {
+        for (i, chunk) in image.chunks(chunk_size).enumerate() {
+            let request = DfuRequest::from_packet(&packet(i * chunk_size, chunk)).unwrap();
+            assert_eq!(block_on(updater.process(request)), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            DfuRequest::from_control_point(&[0x01, 0x00, 0x10, 0x00, 0x00, 0x02]),
+            Some(DfuRequest::Start {
+                target: 2,
+                size: 0x1000
+            })
+        );
+        assert_eq!(
+            DfuRequest::from_control_point(&[0x01, 0x00, 0x10, 0x00, 0x00]),
+            Some(DfuRequest::Start {
+                target: 0,
+                size: 0x1000
+            })
+        );
+        assert_eq!(DfuRequest::from_control_point(&[0x01, 0x00]), None);
+        assert_eq!(DfuRequest::from_control_point(&[0x02, 0x00]), None);
+        assert_eq!(DfuRequest::from_control_point(&[0x03]), Some(DfuRequest::Abort));
+        assert_eq!(DfuRequest::from_control_point(&[0x05]), None);
+        assert_eq!(DfuRequest::from_control_point(&[]), None);
+        assert_eq!(
+            DfuRequest::from_packet(&[0x10, 0x00, 0x00, 0x00, 0xAA, 0xBB]),
+            Some(DfuRequest::Data {
+                offset: 0x10,
+                data: Vec::from_slice(&[0xAA, 0xBB]).unwrap()
+            })
+        );
+        assert_eq!(DfuRequest::from_packet(&[0x10, 0x00, 0x00, 0x00]), None);
+        assert_eq!(
+            DfuResponse::new(DfuOpcode::Finish, DfuStatus::VerifyFailed, 0x0102).to_bytes(),
+            [0x02, 0x06, 0x02, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_dfu_update() {
+        let mut updater = updater();
+        updater.dfu.data.fill(0x00);
+        // Swap progress left by a previous update
+        updater.state.data[4..8].fill(0x00);
+        let image = image(1001);
+
+        let response = block_on(updater.process(start(image.len() as u32))).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Start, DfuStatus::Success, 0));
+        // Only the pages of the image are erased
+        assert!(updater.dfu.data[..1024].iter().all(|&b| b == 0xFF));
+        assert!(updater.dfu.data[1024..].iter().all(|&b| b == 0));
+
+        send_image(&mut updater, &image, 100);
+        let response = block_on(updater.process(DfuRequest::Status)).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Status, DfuStatus::Success, 1001));
+
+        let response = block_on(updater.process(finish(&sign(&image)))).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Finish, DfuStatus::Success, 1001));
+        assert_eq!(&updater.dfu.data[..1001], &image[..]);
+        // The state partition requests a swap
+        assert_eq!(&updater.state.data[..4], &[SWAP_MAGIC; 4]);
+        assert!(updater.state.data[4..].iter().all(|&b| b == 0xFF));
+
+        // The new firmware confirms itself after the swap
+        block_on(updater.mark_booted()).unwrap();
+        assert_eq!(&updater.state.data[..4], &[BOOT_MAGIC; 4]);
+    }
+
+    #[test]
+    fn test_dfu_invalid_signature() {
+        let mut updater = updater();
+        let image = image(512);
+        block_on(updater.process(start(image.len() as u32))).unwrap();
+        send_image(&mut updater, &image, 244 - 4);
+
+        let mut signature = sign(&image);
+        signature[0] ^= 0x01;
+        let response = block_on(updater.process(finish(&signature))).unwrap();
+        assert_eq!(response.status, DfuStatus::VerifyFailed);
+        // The state partition is untouched, and the update must be restarted
+        assert!(updater.state.data.iter().all(|&b| b == 0xFF));
+        let response = block_on(updater.process(finish(&sign(&image)))).unwrap();
+        assert_eq!(response.status, DfuStatus::InvalidState);
+    }
+
+    #[test]
+    fn test_dfu_transfer_errors() {
+        let mut updater = updater();
+        let image = image(300);
+
+        // Data before start
+        let request = DfuRequest::from_packet(&packet(0, &image[..10])).unwrap();
+        let response = block_on(updater.process(request)).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Data, DfuStatus::InvalidState, 0));
+
+        // Image larger than the DFU partition
+        let response = block_on(updater.process(start(4096))).unwrap();
+        assert_eq!(response.status, DfuStatus::ImageTooLarge);
+
+        block_on(updater.process(start(image.len() as u32))).unwrap();
+        send_image(&mut updater, &image[..100], 50);
+        // A lost packet, the response tells the number of received bytes to resume from
+        let request = DfuRequest::from_packet(&packet(150, &image[150..200])).unwrap();
+        let response = block_on(updater.process(request)).unwrap();
+        assert_eq!(
+            response,
+            DfuResponse::new(DfuOpcode::Data, DfuStatus::InvalidOffset, 100)
+        );
+        // Finish before all data is received
+        let response = block_on(updater.process(finish(&sign(&image)))).unwrap();
+        assert_eq!(
+            response,
+            DfuResponse::new(DfuOpcode::Finish, DfuStatus::InvalidState, 100)
+        );
+
+        // Resume from the received bytes
+        let request = DfuRequest::from_packet(&packet(100, &image[100..])).unwrap();
+        assert_eq!(block_on(updater.process(request)), None);
+        // Data beyond the image size
+        let request = DfuRequest::from_packet(&packet(300, &[0; 4])).unwrap();
+        let response = block_on(updater.process(request)).unwrap();
+        assert_eq!(response.status, DfuStatus::ImageTooLarge);
+
+        let response = block_on(updater.process(finish(&sign(&image)))).unwrap();
+        assert_eq!(response.status, DfuStatus::Success);
+
+        // Abort resets the state
+        let response = block_on(updater.process(DfuRequest::Abort)).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Abort, DfuStatus::Success, 0));
+    }
+
+    #[test]
+    fn test_dfu_flash_error() {
+        let mut updater = updater();
+        // The erase and the first write succeed
+        updater.dfu.power_loss_after = Some(2);
+        let image = image(1000);
+        block_on(updater.process(start(image.len() as u32))).unwrap();
+        // The first 256 bytes are written, then the flash fails
+        send_image(&mut updater, &image[..384], 128);
+        let request = DfuRequest::from_packet(&packet(384, &image[384..512])).unwrap();
+        let response = block_on(updater.process(request)).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Data, DfuStatus::FlashError, 0));
+        // The update must be restarted
+        let request = DfuRequest::from_packet(&packet(512, &image[512..640])).unwrap();
+        let response = block_on(updater.process(request)).unwrap();
+        assert_eq!(response.status, DfuStatus::InvalidState);
+    }
+
+    #[test]
+    fn test_split_dfu_request() {
+        let mut data = [0; DFU_SPLIT_CHUNK_SIZE];
+        data[..3].copy_from_slice(&[1, 2, 3]);
+        let request: DfuRequest = SplitDfuRequest::Data {
+            offset: 32,
+            len: 3,
+            data,
+        }
+        .into();
+        assert_eq!(
+            request,
+            DfuRequest::Data {
+                offset: 32,
+                data: Vec::from_slice(&[1, 2, 3]).unwrap()
+            }
+        );
+        let request: DfuRequest = SplitDfuRequest::Start(1024).into();
+        assert_eq!(request, DfuRequest::Start { target: 0, size: 1024 });
+    }
+
+    #[test]
+    fn test_dfu_split_signature() {
+        let mut updater = updater();
+        let image = image(300);
+        block_on(updater.process(start(image.len() as u32))).unwrap();
+        send_image(&mut updater, &image, 100);
+
+        // The split central sends the signature in parts, then finishes without the signature
+        let signature = sign(&image);
+        for (i, chunk) in signature.chunks(DFU_SPLIT_CHUNK_SIZE).enumerate() {
+            let request = SplitDfuRequest::Signature {
+                offset: (i * DFU_SPLIT_CHUNK_SIZE) as u8,
+                data: chunk.try_into().unwrap(),
+            };
+            assert_eq!(block_on(updater.process(request.into())), None);
+        }
+        let request = SplitDfuRequest::Signature {
+            offset: DFU_SIGNATURE_SIZE as u8,
+            data: [0; DFU_SPLIT_CHUNK_SIZE],
+        };
+        let response = block_on(updater.process(request.into())).unwrap();
+        assert_eq!(response.status, DfuStatus::InvalidCommand);
+        let response = block_on(updater.process(SplitDfuRequest::Finish.into())).unwrap();
+        assert_eq!(response, DfuResponse::new(DfuOpcode::Finish, DfuStatus::Success, 300));
+        assert_eq!(&updater.state.data[..4], &[SWAP_MAGIC; 4]);
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_dfu_incompatible_image() {
+            static METADATA: [u8; FIRMWARE_METADATA_SIZE] =
+                FirmwareMetadata::new(FirmwareVersion::new(1, 2, 0), [1; 8], 0).to_bytes();
+            set_firmware_metadata(&METADATA);
+
+            let update = |metadata: FirmwareMetadata| {
+                let mut image = image(1200);
+                image[FIRMWARE_METADATA_OFFSET..FIRMWARE_METADATA_OFFSET + FIRMWARE_METADATA_SIZE]
+                    .copy_from_slice(&metadata.to_bytes());
+                let mut updater = updater();
+                block_on(updater.process(start(image.len() as u32))).unwrap();
+                send_image(&mut updater, &image, 200);
+                let status = block_on(updater.process(finish(&sign(&image)))).unwrap().status;
+                // The state partition is marked only if the image is compatible
+                assert_eq!(status == DfuStatus::Success, updater.state.data[0] == SWAP_MAGIC);
+                status
+            };
+            assert_eq!(update(FirmwareMetadata::new(FirmwareVersion::new(1, 3, 0), [1; 8], 0)), DfuStatus::Success);
+            assert_eq!(
+                update(FirmwareMetadata::new(FirmwareVersion::new(1, 1, 0), [1; 8], 0)),
+                DfuStatus::IncompatibleImage
+            );
+            assert_eq!(
+                update(FirmwareMetadata::new(FirmwareVersion::new(1, 3, 0), [2; 8], 0)),
+                DfuStatus::IncompatibleImage
+            );
+        }
+    }
+}
@@ -70,6 +70,15 @@ impl<
         loop {
             let (row_idx_start, col_idx_start) = self.scan_pos;
 
+            // Scan slowly when the USB host is suspended, the async matrix waits for a key press
+            #[cfg(not(feature = "_no_usb"))]
+            if self.scan_pos == (0, 0) && crate::usb::power::is_host_suspended() {
+                #[cfg(feature = "async_matrix")]
+                {
+                    self.scan_start = None;
+                }
+                Timer::after(crate::usb::power::SUSPENDED_SCAN_INTERVAL).await;
+            }
             #[cfg(feature = "async_matrix")]
             self.wait_for_key().await;
 
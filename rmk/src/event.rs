@@ -204,6 +204,16 @@ pub enum ControllerEvent {
     SplitCentral(bool),
     /// Lock state led indicator
     KeyboardIndicator(LedIndicator),
+    /// Device indication requested by VIA, which blinks to identify the keyboard. `true` means on, `false` means off
+    DeviceIndication(bool),
+    /// Lighting settings changed in VIA
+    Lighting(crate::light::LightingConfig),
+    /// Health of the flash storage, it's sent after the storage is changed
+    #[cfg(feature = "storage")]
+    StorageHealth(crate::storage::StorageHealth),
+    /// USB host suspended or resumed, lighting should be turned off while the host is suspended
+    #[cfg(not(feature = "_no_usb"))]
+    UsbSuspended(bool),
     /// Ble state changed
     #[cfg(feature = "_ble")]
     BleState(u8, crate::ble::trouble::BleState),
@@ -0,0 +1,356 @@
+//! Firmware metadata, which identifies the firmware installed on the keyboard
+//!
+//! The metadata block is embedded in the firmware image by `rmk-macro`, in the `.rmk_metadata` section. The section is
+//! placed at [`FIRMWARE_METADATA_OFFSET`] of the image by the `rmk.x` linker script, so that the block can be read from an
+//! image and verified before the image is installed. It's also reported through VIA/Vial and the BLE Device Information
+//! Service.
+//!
+//! Layout of the block, all numbers are little endian:
+//!
+//! | Offset | Size | Field                                   |
+//! | ------ | ---- | --------------------------------------- |
+//! | 0      | 8    | Magic, `RMKMETA\0`                      |
+//! | 8      | 1    | Format of the block, 1                  |
+//! | 9      | 1    | Role, 0 for the central or `id + 1` for a split peripheral |
+//! | 10     | 6    | Version, major, minor and patch as u16  |
+//! | 16     | 8    | Keyboard id                             |
+//! | 24     | 4    | Build hash                              |
+//! | 28     | 4    | Feature set, see [`FirmwareFeatures`]   |
+
+use core::cell::Cell;
+
+use bitfield_struct::bitfield;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+use crate::BUILD_HASH;
+
+/// Magic at the beginning of the metadata block
+pub const FIRMWARE_METADATA_MAGIC: [u8; 8] = *b"RMKMETA\0";
+
+/// Size of the metadata block
+pub const FIRMWARE_METADATA_SIZE: usize = 32;
+
+/// Offset of the metadata block in the firmware image, which is the offset of the `.rmk_metadata` section from the
+/// start of the flash in `rmk.x`. The block is placed after the vector table, and the code starts after the block.
+pub const FIRMWARE_METADATA_OFFSET: usize = crate::FIRMWARE_METADATA_OFFSET;
+
+/// Format of the metadata block
+const FIRMWARE_METADATA_FORMAT: u8 = 1;
+
+static FIRMWARE_METADATA: Mutex<CriticalSectionRawMutex, Cell<Option<FirmwareMetadata>>> = Mutex::new(Cell::new(None));
+
+/// Features of RMK which are enabled in the firmware
+#[bitfield(u32, order = Lsb, defmt = cfg(feature = "defmt"))]
+#[derive(Eq, PartialEq)]
+pub struct FirmwareFeatures {
+    pub storage: bool,
+    pub vial_lock: bool,
+    pub split: bool,
+    pub ble: bool,
+    pub usb: bool,
+    pub controller: bool,
+    pub dfu: bool,
+    pub usb_console: bool,
+    pub webusb: bool,
+    pub digitizer: bool,
+    pub gamepad: bool,
+    #[bits(21)]
+    _reserved: u32,
+}
+
+impl FirmwareFeatures {
+    /// Features of the current build
+    pub const fn current() -> Self {
+        Self::new()
+            .with_storage(cfg!(feature = "storage"))
+            .with_vial_lock(cfg!(feature = "vial_lock"))
+            .with_split(cfg!(feature = "split"))
+            .with_ble(cfg!(feature = "_ble"))
+            .with_usb(cfg!(not(feature = "_no_usb")))
+            .with_controller(cfg!(feature = "controller"))
+            .with_dfu(cfg!(feature = "dfu"))
+            .with_usb_console(cfg!(feature = "usb_console"))
+            .with_webusb(cfg!(feature = "webusb"))
+            .with_digitizer(cfg!(feature = "digitizer"))
+            .with_gamepad(cfg!(feature = "gamepad"))
+    }
+}
+
+/// Semantic version of the firmware
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// The version reported by VIA's `id_firmware_version`, which is `major(8) | minor(8) | patch(16)`
+    pub const fn to_via(&self) -> u32 {
+        ((self.major as u32 & 0xFF) << 24) | ((self.minor as u32 & 0xFF) << 16) | self.patch as u32
+    }
+}
+
+/// Error of verifying a firmware image against the installed firmware
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FirmwareVerifyError {
+    /// There's no metadata block in the image
+    NoMetadata,
+    /// The image is built for another keyboard
+    KeyboardMismatch,
+    /// The image is built for another part of a split keyboard
+    RoleMismatch,
+    /// The version of the image is older than the installed one
+    Downgrade,
+}
+
+/// Metadata of a firmware
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareMetadata {
+    pub version: FirmwareVersion,
+    /// Id of the keyboard, which is derived from the name, vendor id and product id in `keyboard.toml`
+    pub keyboard_id: [u8; 8],
+    /// 0 for the central or a non-split keyboard, `id + 1` for a split peripheral
+    pub role: u8,
+    /// Hash of the git commit and the build time of RMK, which changes on every build
+    pub build_hash: u32,
+    pub features: FirmwareFeatures,
+}
+
+impl FirmwareMetadata {
+    /// Create the metadata of the current build, the build hash and the feature set are filled by RMK
+    pub const fn new(version: FirmwareVersion, keyboard_id: [u8; 8], role: u8) -> Self {
+        Self {
+            version,
+            keyboard_id,
+            role,
+            build_hash: BUILD_HASH,
+            features: FirmwareFeatures::current(),
+        }
+    }
+
+    /// Serialize to the metadata block
+    pub const fn to_bytes(&self) -> [u8; FIRMWARE_METADATA_SIZE] {
+        let mut bytes = [0; FIRMWARE_METADATA_SIZE];
+        let mut i = 0;
+        while i < 8 {
+            bytes[i] = FIRMWARE_METADATA_MAGIC[i];
+            bytes[16 + i] = self.keyboard_id[i];
+            i += 1;
+        }
+        bytes[8] = FIRMWARE_METADATA_FORMAT;
+        bytes[9] = self.role;
+        let version = [self.version.major, self.version.minor, self.version.patch];
+        let mut i = 0;
+        while i < 3 {
+            let v = version[i].to_le_bytes();
+            bytes[10 + i * 2] = v[0];
+            bytes[11 + i * 2] = v[1];
+            i += 1;
+        }
+        let build_hash = self.build_hash.to_le_bytes();
+        let features = self.features.into_bits().to_le_bytes();
+        let mut i = 0;
+        while i < 4 {
+            bytes[24 + i] = build_hash[i];
+            bytes[28 + i] = features[i];
+            i += 1;
+        }
+        bytes
+    }
+
+    /// Parse the metadata block, returns `None` if it's not a valid block
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..FIRMWARE_METADATA_SIZE)?;
+        if bytes[..8] != FIRMWARE_METADATA_MAGIC || bytes[8] != FIRMWARE_METADATA_FORMAT {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Some(Self {
+            version: FirmwareVersion::new(u16_at(10), u16_at(12), u16_at(14)),
+            keyboard_id: bytes[16..24].try_into().ok()?,
+            role: bytes[9],
+            build_hash: u32_at(24),
+            features: FirmwareFeatures::from_bits(u32_at(28)),
+        })
+    }
+
+    /// Read the metadata block at [`FIRMWARE_METADATA_OFFSET`] of a firmware image
+    pub fn find(image: &[u8]) -> Option<Self> {
+        Self::from_bytes(image.get(FIRMWARE_METADATA_OFFSET..)?)
+    }
+
+    /// Check whether the firmware with the `new` metadata can replace the firmware with this metadata.
+    ///
+    /// The image must be built for the same keyboard and the same part of a split keyboard, and must not be older.
+    pub fn verify_update(&self, new: &FirmwareMetadata) -> Result<(), FirmwareVerifyError> {
+        if new.keyboard_id != self.keyboard_id {
+            return Err(FirmwareVerifyError::KeyboardMismatch);
+        }
+        if new.role != self.role {
+            return Err(FirmwareVerifyError::RoleMismatch);
+        }
+        if new.version < self.version {
+            return Err(FirmwareVerifyError::Downgrade);
+        }
+        Ok(())
+    }
+}
+
+/// Read the metadata block of a firmware image which is read in chunks
+pub struct FirmwareMetadataReader {
+    block: [u8; FIRMWARE_METADATA_SIZE],
+}
+
+impl Default for FirmwareMetadataReader {
+    fn default() -> Self {
+        Self {
+            block: [0; FIRMWARE_METADATA_SIZE],
+        }
+    }
+}
+
+impl FirmwareMetadataReader {
+    /// Read the next chunk of the image, which starts at `offset` of the image
+    pub fn update(&mut self, offset: usize, chunk: &[u8]) {
+        // Overlap of the chunk and the block
+        let start = offset.max(FIRMWARE_METADATA_OFFSET);
+        let end = (offset + chunk.len()).min(FIRMWARE_METADATA_OFFSET + FIRMWARE_METADATA_SIZE);
+        if start < end {
+            self.block[start - FIRMWARE_METADATA_OFFSET..end - FIRMWARE_METADATA_OFFSET]
+                .copy_from_slice(&chunk[start - offset..end - offset]);
+        }
+    }
+
+    /// Get the metadata block, returns `None` if the image doesn't have a valid block
+    pub fn finish(self) -> Option<FirmwareMetadata> {
+        FirmwareMetadata::from_bytes(&self.block)
+    }
+}
+
+/// Set the metadata of the running firmware, it's called by `rmk-macro` with the embedded metadata block
+pub fn set_firmware_metadata(block: &'static [u8; FIRMWARE_METADATA_SIZE]) {
+    // Read the block by volatile, so that it's kept in the image
+    let block = unsafe { core::ptr::read_volatile(block) };
+    match FirmwareMetadata::from_bytes(&block) {
+        Some(metadata) => FIRMWARE_METADATA.lock(|m| m.set(Some(metadata))),
+        None => error!("Invalid firmware metadata block"),
+    }
+}
+
+/// Get the metadata of the running firmware, returns `None` if it's not set
+pub fn firmware_metadata() -> Option<FirmwareMetadata> {
+    FIRMWARE_METADATA.lock(|m| m.get())
+}
+
+/// Check whether a firmware image can be installed, it's always allowed if the metadata of the running firmware is not set
+pub fn verify_firmware_image(image: &[u8]) -> Result<(), FirmwareVerifyError> {
+    verify_firmware_metadata(FirmwareMetadata::find(image).as_ref())
+}
+
+/// Check the metadata of a firmware image read by [`FirmwareMetadataReader`]
+pub fn verify_firmware_metadata(new: Option<&FirmwareMetadata>) -> Result<(), FirmwareVerifyError> {
+    match (firmware_metadata(), new) {
+        (None, _) => Ok(()),
+        (Some(_), None) => Err(FirmwareVerifyError::NoMetadata),
+        (Some(current), Some(new)) => current.verify_update(new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+
+    const KEYBOARD_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn metadata(major: u16, minor: u16, patch: u16) -> FirmwareMetadata {
+        FirmwareMetadata::new(FirmwareVersion::new(major, minor, patch), KEYBOARD_ID, 0)
+    }
+
+    #[test]
+    fn test_metadata_bytes() {
+        let metadata = metadata(1, 2, 300);
+        let bytes = metadata.to_bytes();
+        assert_eq!(&bytes[..10], b"RMKMETA\0\x01\x00");
+        assert_eq!(&bytes[10..16], &[1, 0, 2, 0, 0x2C, 0x01]);
+        assert_eq!(&bytes[16..24], &KEYBOARD_ID);
+        assert_eq!(FirmwareMetadata::from_bytes(&bytes), Some(metadata));
+        assert_eq!(metadata.version.to_via(), 0x0102_012C);
+        assert_eq!(metadata.features.storage(), cfg!(feature = "storage"));
+
+        let mut invalid = bytes;
+        invalid[8] = 2;
+        assert_eq!(FirmwareMetadata::from_bytes(&invalid), None);
+        assert_eq!(FirmwareMetadata::from_bytes(&bytes[..31]), None);
+    }
+
+    #[test]
+    fn test_find_metadata() {
+        let block = metadata(1, 0, 0).to_bytes();
+        let mut image = [0xAAu8; 2000];
+        // A block at another offset is ignored
+        image[500..532].copy_from_slice(&block);
+        assert_eq!(FirmwareMetadata::find(&image), None);
+        image[FIRMWARE_METADATA_OFFSET..FIRMWARE_METADATA_OFFSET + 32].copy_from_slice(&block);
+        assert_eq!(FirmwareMetadata::find(&image), Some(metadata(1, 0, 0)));
+        assert_eq!(FirmwareMetadata::find(&image[..FIRMWARE_METADATA_OFFSET + 20]), None);
+
+        // The block is across chunks of different sizes
+        for chunk_size in [1, 7, 31, 32, 100, 256] {
+            let mut reader = FirmwareMetadataReader::default();
+            for (i, chunk) in image.chunks(chunk_size).enumerate() {
+                reader.update(i * chunk_size, chunk);
+            }
+            assert_eq!(reader.finish(), Some(metadata(1, 0, 0)), "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_verify_update() {
+        let current = metadata(1, 2, 3);
+        assert_eq!(current.verify_update(&metadata(1, 2, 3)), Ok(()));
+        assert_eq!(current.verify_update(&metadata(1, 3, 0)), Ok(()));
+        assert_eq!(
+            current.verify_update(&metadata(1, 1, 9)),
+            Err(FirmwareVerifyError::Downgrade)
+        );
+        let mut other = metadata(2, 0, 0);
+        other.keyboard_id[0] = 0;
+        assert_eq!(
+            current.verify_update(&other),
+            Err(FirmwareVerifyError::KeyboardMismatch)
+        );
+        let peripheral = FirmwareMetadata::new(FirmwareVersion::new(2, 0, 0), KEYBOARD_ID, 1);
+        assert_eq!(
+            current.verify_update(&peripheral),
+            Err(FirmwareVerifyError::RoleMismatch)
+        );
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_verify_firmware_image() {
+            static BLOCK: [u8; FIRMWARE_METADATA_SIZE] = FirmwareMetadata::new(FirmwareVersion::new(1, 2, 3), KEYBOARD_ID, 0).to_bytes();
+            let mut image = [0u8; 2048];
+            // Any image is allowed if the metadata is not set
+            assert_eq!(verify_firmware_image(&image), Ok(()));
+
+            set_firmware_metadata(&BLOCK);
+            assert_eq!(firmware_metadata().map(|m| m.version), Some(FirmwareVersion::new(1, 2, 3)));
+            assert_eq!(verify_firmware_image(&image), Err(FirmwareVerifyError::NoMetadata));
+            image[FIRMWARE_METADATA_OFFSET..FIRMWARE_METADATA_OFFSET + 32].copy_from_slice(&metadata(1, 2, 4).to_bytes());
+            assert_eq!(verify_firmware_image(&image), Ok(()));
+        }
+    }
+}
@@ -1,17 +1,26 @@
 /// Traits and types for HID message reporting and listening.
-use core::{future::Future, sync::atomic::Ordering};
+#[cfg(feature = "gamepad")]
+use core::cell::RefCell;
+use core::future::Future;
+use core::sync::atomic::{AtomicU8, Ordering};
 
+#[cfg(feature = "gamepad")]
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_usb::class::hid::ReadError;
 use embassy_usb::driver::EndpointError;
 use serde::Serialize;
-use usbd_hid::descriptor::{AsInputReport, MediaKeyboardReport, MouseReport, SystemControlReport};
+use usbd_hid::descriptor::{AsInputReport, MediaKeyboardReport, SystemControlReport};
 
 use crate::CONNECTION_STATE;
+#[cfg(feature = "gamepad")]
+use crate::RawMutex;
 use crate::channel::KEYBOARD_REPORT_CHANNEL;
-use crate::descriptor::KeyboardReport;
+#[cfg(feature = "digitizer")]
+use crate::descriptor::DigitizerReport;
+#[cfg(feature = "gamepad")]
+use crate::descriptor::GamepadReport;
+use crate::descriptor::{KeyboardReport, MouseReport, WHEEL_RESOLUTION};
 use crate::state::ConnectionState;
-#[cfg(not(feature = "_no_usb"))]
-use crate::usb::USB_REMOTE_WAKEUP;
 
 #[derive(Serialize, Debug, Clone)]
 pub enum Report {
@@ -23,10 +32,106 @@ pub enum Report {
     MediaKeyboardReport(MediaKeyboardReport),
     /// System control report
     SystemControlReport(SystemControlReport),
+    /// Digitizer report of touchpads
+    #[cfg(feature = "digitizer")]
+    DigitizerReport(DigitizerReport),
+    /// Gamepad report of joysticks and joystick keycodes
+    #[cfg(feature = "gamepad")]
+    GamepadReport(GamepadReport),
 }
 
 impl AsInputReport for Report {}
 
+/// The input mode feature report of the digitizer set by the host, 3 means touchpad mode.
+#[cfg(feature = "digitizer")]
+pub(crate) static DIGITIZER_INPUT_MODE: AtomicU8 = AtomicU8::new(0);
+
+/// The function switch feature report of the digitizer set by the host.
+///
+/// Bit 0 is the surface switch, bit 1 is the button switch. Both are enabled by default.
+#[cfg(feature = "digitizer")]
+pub(crate) static DIGITIZER_FUNCTION_SWITCH: AtomicU8 = AtomicU8::new(0b11);
+
+/// Gamepad state shared by the keyboard, which updates buttons and the hat, and joystick processors, which update axes.
+#[cfg(feature = "gamepad")]
+pub(crate) static GAMEPAD_STATE: Mutex<RawMutex, RefCell<GamepadReport>> = Mutex::new(RefCell::new(GamepadReport {
+    buttons: 0,
+    hat: GamepadReport::HAT_CENTERED,
+    axes: [0; 6],
+}));
+
+/// Update the shared gamepad state, returns the report to send
+#[cfg(feature = "gamepad")]
+pub(crate) fn update_gamepad_state(f: impl FnOnce(&mut GamepadReport)) -> GamepadReport {
+    GAMEPAD_STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        f(&mut state);
+        *state
+    })
+}
+
+/// Get the hat switch value from pressed directions, opposite directions cancel each other
+#[cfg(feature = "gamepad")]
+pub(crate) fn hat_from_directions(up: bool, right: bool, down: bool, left: bool) -> u8 {
+    let vertical = up as i8 - down as i8;
+    let horizontal = right as i8 - left as i8;
+    match (vertical, horizontal) {
+        (1, 0) => 0,
+        (1, 1) => 1,
+        (0, 1) => 2,
+        (-1, 1) => 3,
+        (-1, 0) => 4,
+        (-1, -1) => 5,
+        (0, -1) => 6,
+        (1, -1) => 7,
+        _ => GamepadReport::HAT_CENTERED,
+    }
+}
+
+/// Converts the high-resolution wheel and pan of mouse reports to what the host expects.
+///
+/// If the host doesn't enable the resolution multiplier, the movement is accumulated until a whole notch is reached.
+pub(crate) struct ScrollAccumulator {
+    wheel: i16,
+    pan: i16,
+    /// The resolution multiplier feature report set by the host of the transport.
+    ///
+    /// Bits 0-1 are the multiplier of the wheel, bits 2-3 are the multiplier of the AC Pan.
+    /// When a multiplier is 1, the host accepts high-resolution values.
+    multiplier: &'static AtomicU8,
+}
+
+impl ScrollAccumulator {
+    pub(crate) fn new(multiplier: &'static AtomicU8) -> Self {
+        Self {
+            wheel: 0,
+            pan: 0,
+            multiplier,
+        }
+    }
+
+    pub(crate) fn convert(&mut self, report: MouseReport) -> MouseReport {
+        self.convert_with_multiplier(report, self.multiplier.load(Ordering::Relaxed))
+    }
+
+    /// Convert the report with the resolution multiplier feature report set by the host
+    fn convert_with_multiplier(&mut self, mut report: MouseReport, multiplier: u8) -> MouseReport {
+        if multiplier & 0b11 == 0 {
+            report.wheel = Self::to_notches(&mut self.wheel, report.wheel);
+        }
+        if (multiplier >> 2) & 0b11 == 0 {
+            report.pan = Self::to_notches(&mut self.pan, report.pan);
+        }
+        report
+    }
+
+    fn to_notches(remainder: &mut i16, value: i16) -> i16 {
+        let total = *remainder as i32 + value as i32;
+        *remainder = (total % WHEEL_RESOLUTION as i32) as i16;
+        (total / WHEEL_RESOLUTION as i32) as i16
+    }
+}
+
 #[derive(PartialEq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum HidError {
@@ -64,19 +169,8 @@ pub trait RunnableHidWriter: HidWriterTrait {
                 if CONNECTION_STATE.load(Ordering::Acquire)
                     == <ConnectionState as Into<bool>>::into(ConnectionState::Connected)
                 {
-                    if let Err(e) = self.write_report(report.clone()).await {
+                    if let Err(e) = self.write_report(report).await {
                         error!("Failed to send report: {:?}", e);
-                        #[cfg(not(feature = "_no_usb"))]
-                        // If the USB endpoint is disabled, try wakeup
-                        if let HidError::UsbEndpointError(EndpointError::Disabled) = e {
-                            USB_REMOTE_WAKEUP.signal(());
-                            // Wait 200ms for the wakeup, then send the report again
-                            // Ignore the error for the second send
-                            embassy_time::Timer::after_millis(200).await;
-                            if let Err(e) = self.write_report(report).await {
-                                error!("Failed to send report after wakeup: {:?}", e);
-                            }
-                        }
                     };
                 }
             }
@@ -120,6 +214,50 @@ impl RunnableHidWriter for DummyWriter {
     }
 }
 
+/// Writer which writes each report to the BLE host, and mirrors it to the USB host.
+///
+/// The report is mirrored only when the USB is enabled and not suspended,
+/// errors of the USB writer are ignored.
+#[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+pub(crate) struct MirrorHidWriter<B, U> {
+    ble_writer: B,
+    usb_writer: U,
+}
+
+#[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+impl<B, U> MirrorHidWriter<B, U> {
+    pub(crate) fn new(ble_writer: B, usb_writer: U) -> Self {
+        Self { ble_writer, usb_writer }
+    }
+}
+
+#[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+impl<B: HidWriterTrait<ReportType = Report>, U: HidWriterTrait<ReportType = Report>> HidWriterTrait
+    for MirrorHidWriter<B, U>
+{
+    type ReportType = Report;
+
+    async fn write_report(&mut self, report: Self::ReportType) -> Result<usize, HidError> {
+        use crate::usb::{USB_ENABLED, USB_SUSPENDED};
+
+        if USB_ENABLED.signaled() && !USB_SUSPENDED.signaled() {
+            if let Err(e) = self.usb_writer.write_report(report.clone()).await {
+                debug!("Failed to mirror report to USB: {:?}", e);
+            }
+        }
+        self.ble_writer.write_report(report).await
+    }
+}
+
+#[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+impl<B: HidWriterTrait<ReportType = Report>, U: HidWriterTrait<ReportType = Report>> RunnableHidWriter
+    for MirrorHidWriter<B, U>
+{
+    async fn get_report(&mut self) -> Self::ReportType {
+        KEYBOARD_REPORT_CHANNEL.receive().await
+    }
+}
+
 #[cfg(feature = "_nrf_ble")]
 pub(crate) fn get_serial_number() -> &'static str {
     use heapless::String;
@@ -149,3 +287,119 @@ pub(crate) fn get_serial_number() -> &'static str {
 
     serial.as_str()
 }
+
+#[cfg(test)]
+mod test {
+    #[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+
+    #[test]
+    fn test_scroll_accumulator() {
+        static MULTIPLIER: AtomicU8 = AtomicU8::new(0);
+        let mut accumulator = ScrollAccumulator::new(&MULTIPLIER);
+        let report = |wheel, pan| MouseReport {
+            wheel,
+            pan,
+            ..Default::default()
+        };
+
+        // Multiplier disabled, high-resolution movement is accumulated to notches
+        assert_eq!(accumulator.convert_with_multiplier(report(60, -30), 0), report(0, 0));
+        assert_eq!(accumulator.convert_with_multiplier(report(60, -90), 0), report(1, -1));
+        assert_eq!(
+            accumulator.convert_with_multiplier(report(2 * WHEEL_RESOLUTION, 0), 0),
+            report(2, 0)
+        );
+
+        // Wheel multiplier enabled, the wheel is sent as is
+        assert_eq!(accumulator.convert_with_multiplier(report(30, 60), 0b01), report(30, 0));
+        assert_eq!(accumulator.convert_with_multiplier(report(30, 60), 0b01), report(30, 1));
+
+        // Each transport has its own multiplier
+        static OTHER_MULTIPLIER: AtomicU8 = AtomicU8::new(0);
+        let mut other = ScrollAccumulator::new(&OTHER_MULTIPLIER);
+        MULTIPLIER.store(0b01, Ordering::Relaxed);
+        assert_eq!(accumulator.convert(report(30, 0)), report(30, 0));
+        assert_eq!(other.convert(report(30, 0)), report(0, 0));
+    }
+
+    #[cfg(feature = "gamepad")]
+    #[test]
+    fn test_hat_from_directions() {
+        assert_eq!(
+            hat_from_directions(false, false, false, false),
+            GamepadReport::HAT_CENTERED
+        );
+        assert_eq!(hat_from_directions(true, false, false, false), 0);
+        assert_eq!(hat_from_directions(true, true, false, false), 1);
+        assert_eq!(hat_from_directions(false, true, true, false), 3);
+        assert_eq!(hat_from_directions(false, false, true, true), 5);
+        assert_eq!(hat_from_directions(true, false, false, true), 7);
+        // Opposite directions cancel each other
+        assert_eq!(
+            hat_from_directions(true, false, true, false),
+            GamepadReport::HAT_CENTERED
+        );
+        assert_eq!(hat_from_directions(true, true, true, false), 2);
+    }
+
+    /// Writer which counts the written reports
+    #[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+    #[derive(Default)]
+    struct CountingWriter {
+        reports: usize,
+        fail: bool,
+    }
+
+    #[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+    impl HidWriterTrait for CountingWriter {
+        type ReportType = Report;
+
+        async fn write_report(&mut self, _report: Self::ReportType) -> Result<usize, HidError> {
+            if self.fail {
+                return Err(HidError::UsbDisabled);
+            }
+            self.reports += 1;
+            Ok(1)
+        }
+    }
+
+    #[cfg(all(feature = "_ble", not(feature = "_no_usb")))]
+    rusty_fork_test! {
+        #[test]
+        fn test_mirror_hid_writer() {
+            use embassy_futures::block_on;
+
+            use crate::usb::{USB_ENABLED, USB_SUSPENDED};
+
+            let report = || Report::KeyboardReport(KeyboardReport::default());
+            let mut writer = MirrorHidWriter::new(CountingWriter::default(), CountingWriter::default());
+
+            // USB is not enabled, the report is only sent to BLE
+            assert!(block_on(writer.write_report(report())).is_ok());
+            assert_eq!((writer.ble_writer.reports, writer.usb_writer.reports), (1, 0));
+
+            // USB is enabled, the report is mirrored
+            USB_ENABLED.signal(());
+            assert!(block_on(writer.write_report(report())).is_ok());
+            assert_eq!((writer.ble_writer.reports, writer.usb_writer.reports), (2, 1));
+
+            // USB is suspended, the report isn't mirrored
+            USB_SUSPENDED.signal(());
+            assert!(block_on(writer.write_report(report())).is_ok());
+            assert_eq!((writer.ble_writer.reports, writer.usb_writer.reports), (3, 1));
+            USB_SUSPENDED.reset();
+
+            // Errors of the USB writer are ignored
+            writer.usb_writer.fail = true;
+            assert!(block_on(writer.write_report(report())).is_ok());
+            assert_eq!(writer.ble_writer.reports, 4);
+
+            // Errors of the BLE writer are returned
+            writer.ble_writer.fail = true;
+            assert!(block_on(writer.write_report(report())).is_err());
+        }
+    }
+}
@@ -0,0 +1,656 @@
+//! Analog key matrix
+//!
+//! [`AnalogMatrix`] reads the travel of every key from an analog source, e.g. Hall-effect sensors connected to ADC via multiplexers,
+//! and converts it to [`KeyboardEvent`]s.
+//! Per-key calibration, adjustable actuation/release points, rapid trigger and dual-action keys are supported.
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
+
+use crate::event::{Event, KeyboardEvent};
+use crate::input_device::InputDevice;
+use crate::matrix::MatrixTrait;
+#[cfg(feature = "storage")]
+use crate::{channel::FLASH_CHANNEL, storage::FlashOperationMessage};
+
+/// Default total travel of the switch, in 0.01mm
+pub const DEFAULT_TOTAL_TRAVEL: u16 = 400;
+
+/// Hysteresis of the second actuation of dual-action keys, in 0.01mm
+const DUAL_ACTION_HYSTERESIS: u16 = 10;
+
+/// ADC which samples a single channel
+pub trait AdcChannelReader {
+    /// Read the raw value of the given ADC channel
+    async fn read_channel(&mut self, channel: u8) -> u16;
+}
+
+/// Source of the raw analog values of keys
+pub trait AnalogSource {
+    /// Read the raw value of the key at (row, col)
+    async fn read_key(&mut self, row: usize, col: usize) -> u16;
+}
+
+/// Analog source which reads keys through analog multiplexers.
+///
+/// Each row is connected to the ADC channel with the same index via a multiplexer,
+/// all multiplexers share the select pins, which select the column.
+pub struct MuxAnalogSource<A: AdcChannelReader, P: OutputPin, const SELECT_PIN_NUM: usize> {
+    adc: A,
+    select_pins: [P; SELECT_PIN_NUM],
+    /// Time to wait for the output of multiplexers being stable after switching the column
+    settle_time: Duration,
+    /// Currently selected column
+    selected: Option<usize>,
+}
+
+impl<A: AdcChannelReader, P: OutputPin, const SELECT_PIN_NUM: usize> MuxAnalogSource<A, P, SELECT_PIN_NUM> {
+    /// Create an analog source from ADC and select pins of multiplexers, the first select pin is the least significant bit
+    pub fn new(adc: A, select_pins: [P; SELECT_PIN_NUM], settle_time: Duration) -> Self {
+        Self {
+            adc,
+            select_pins,
+            settle_time,
+            selected: None,
+        }
+    }
+}
+
+impl<A: AdcChannelReader, P: OutputPin, const SELECT_PIN_NUM: usize> AnalogSource
+    for MuxAnalogSource<A, P, SELECT_PIN_NUM>
+{
+    async fn read_key(&mut self, row: usize, col: usize) -> u16 {
+        if self.selected != Some(col) {
+            for (i, pin) in self.select_pins.iter_mut().enumerate() {
+                if (col >> i) & 1 == 1 {
+                    pin.set_high().ok();
+                } else {
+                    pin.set_low().ok();
+                }
+            }
+            self.selected = Some(col);
+            Timer::after(self.settle_time).await;
+        }
+        self.adc.read_channel(row as u8).await
+    }
+}
+
+/// Calibration data of an analog key
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyCalibration {
+    /// Raw value when the key is released
+    pub rest: u16,
+    /// Raw value when the key is bottomed out
+    pub bottom: u16,
+}
+
+impl KeyCalibration {
+    /// Whether the key is calibrated, uncalibrated key has the same rest and bottom value
+    pub fn is_calibrated(&self) -> bool {
+        self.rest != self.bottom
+    }
+
+    /// Create the calibration from the rest value, the bottom is estimated by `default_range`
+    fn from_rest(rest: u16, default_range: i16) -> Self {
+        Self {
+            rest,
+            bottom: (rest as i32 + default_range as i32).clamp(0, u16::MAX as i32) as u16,
+        }
+    }
+
+    /// Extend the bottom value if the key is pressed deeper than the current bottom, returns true if the bottom is updated
+    fn update_bottom(&mut self, raw: u16) -> bool {
+        let deeper = if self.bottom > self.rest {
+            raw > self.bottom
+        } else {
+            raw < self.bottom
+        };
+        if deeper {
+            self.bottom = raw;
+        }
+        deeper
+    }
+
+    /// Convert the raw value to the key travel, in the same unit as `total_travel`
+    pub fn travel(&self, raw: u16, total_travel: u16) -> u16 {
+        let range = self.bottom as i32 - self.rest as i32;
+        if range == 0 {
+            return 0;
+        }
+        let travel = (raw as i32 - self.rest as i32) * total_travel as i32 / range;
+        travel.clamp(0, total_travel as i32) as u16
+    }
+}
+
+/// Config of rapid trigger
+///
+/// After the first actuation, the key is released once it moves up by `release_sensitivity`,
+/// and pressed again once it moves down by `press_sensitivity`, until it goes back above the release point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RapidTrigger {
+    /// Downward travel which presses the key again, in 0.01mm
+    pub press_sensitivity: u16,
+    /// Upward travel which releases the key, in 0.01mm
+    pub release_sensitivity: u16,
+}
+
+/// Config of the second action of a dual-action key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DualAction {
+    /// Actuation point of the second action, in 0.01mm
+    pub actuation_point: u16,
+    /// Row of the second action in the keymap, it must be in the matrix
+    pub row: u8,
+    /// Column of the second action in the keymap, it must be in the matrix
+    pub col: u8,
+}
+
+/// Config of an analog key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogKeyConfig {
+    /// Travel at which the key is pressed, in 0.01mm
+    pub actuation_point: u16,
+    /// Travel at which the key is released, in 0.01mm. It should be less than `actuation_point`
+    pub release_point: u16,
+    /// Rapid trigger config, `None` means rapid trigger is disabled
+    pub rapid_trigger: Option<RapidTrigger>,
+    /// Second action triggered by a deeper press, `None` means the key has only one action
+    pub dual_action: Option<DualAction>,
+}
+
+impl Default for AnalogKeyConfig {
+    fn default() -> Self {
+        Self {
+            actuation_point: 200,
+            release_point: 150,
+            rapid_trigger: None,
+            dual_action: None,
+        }
+    }
+}
+
+/// Config of the analog matrix
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogMatrixConfig {
+    /// Total travel of the switch, in 0.01mm
+    pub total_travel: u16,
+    /// Estimated difference of raw values between the rest and bottom-out positions, used before the key is fully pressed.
+    /// Negative value means the raw value decreases when the key is pressed. It must not be 0
+    pub default_range: i16,
+    /// Minimal change of the bottom-out raw value which triggers saving the calibration to the storage
+    pub save_threshold: u16,
+    /// Interval between two scans of the whole matrix
+    pub scan_interval: Duration,
+    /// Default config of all keys
+    pub key: AnalogKeyConfig,
+}
+
+impl Default for AnalogMatrixConfig {
+    fn default() -> Self {
+        Self {
+            total_travel: DEFAULT_TOTAL_TRAVEL,
+            default_range: 500,
+            save_threshold: 50,
+            scan_interval: Duration::from_millis(1),
+            key: AnalogKeyConfig::default(),
+        }
+    }
+}
+
+/// State change of an analog key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyChange {
+    Primary(bool),
+    Secondary(bool),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct AnalogKeyState {
+    pressed: bool,
+    secondary_pressed: bool,
+    /// Whether the key is between the first actuation and the full release, where rapid trigger works
+    rapid_trigger_active: bool,
+    /// Deepest travel since pressed, or highest travel since released
+    extreme: u16,
+}
+
+impl AnalogKeyState {
+    /// Update the key state with current travel, returns the changes in the order they should be reported
+    fn update(&mut self, travel: u16, config: &AnalogKeyConfig) -> [Option<KeyChange>; 2] {
+        let (was_pressed, was_secondary_pressed) = (self.pressed, self.secondary_pressed);
+        if self.pressed {
+            self.extreme = self.extreme.max(travel);
+            let rapid_release = self.rapid_trigger_active
+                && config
+                    .rapid_trigger
+                    .is_some_and(|rt| self.extreme.saturating_sub(travel) >= rt.release_sensitivity);
+            if travel <= config.release_point || rapid_release {
+                self.pressed = false;
+                self.extreme = travel;
+            }
+        } else {
+            self.extreme = self.extreme.min(travel);
+            let press = if self.rapid_trigger_active {
+                config
+                    .rapid_trigger
+                    .is_some_and(|rt| travel.saturating_sub(self.extreme) >= rt.press_sensitivity)
+            } else {
+                travel >= config.actuation_point
+            };
+            if press {
+                self.pressed = true;
+                self.extreme = travel;
+                self.rapid_trigger_active = config.rapid_trigger.is_some();
+            }
+        }
+        if travel <= config.release_point {
+            self.rapid_trigger_active = false;
+        }
+
+        self.secondary_pressed = match config.dual_action {
+            Some(dual) if self.pressed => {
+                if self.secondary_pressed {
+                    travel + DUAL_ACTION_HYSTERESIS > dual.actuation_point
+                } else {
+                    travel >= dual.actuation_point
+                }
+            }
+            _ => false,
+        };
+
+        let primary = (self.pressed != was_pressed).then_some(KeyChange::Primary(self.pressed));
+        let secondary =
+            (self.secondary_pressed != was_secondary_pressed).then_some(KeyChange::Secondary(self.secondary_pressed));
+        // The primary action wraps the second action
+        if self.pressed {
+            [primary, secondary]
+        } else {
+            [secondary, primary]
+        }
+    }
+}
+
+/// Analog key matrix, which reads the key travel from [`AnalogSource`]
+///
+/// Keys are scanned column by column, so that the multiplexers switch only once per column.
+/// Uncalibrated keys take the first reading as the rest value.
+/// The bottom-out value is extended when a key is pressed deeper, and saved to the storage after the key is released.
+pub struct AnalogMatrix<S: AnalogSource, const ROW: usize, const COL: usize> {
+    source: S,
+    config: AnalogMatrixConfig,
+    key_configs: [[AnalogKeyConfig; COL]; ROW],
+    calibration: [[KeyCalibration; COL]; ROW],
+    /// Calibration which is already saved in the storage
+    #[cfg(feature = "storage")]
+    saved_calibration: [[KeyCalibration; COL]; ROW],
+    states: [[AnalogKeyState; COL]; ROW],
+    /// Index of the next key to be scanned, in column-major order
+    scan_idx: usize,
+    /// Event which is generated in the same scan of the previous event
+    pending: Option<KeyboardEvent>,
+}
+
+impl<S: AnalogSource, const ROW: usize, const COL: usize> AnalogMatrix<S, ROW, COL> {
+    /// Create an analog matrix, all keys use `config.key` as the key config
+    pub fn new(source: S, config: AnalogMatrixConfig) -> Self {
+        Self {
+            source,
+            config,
+            key_configs: [[Self::check_key_config(config.key); COL]; ROW],
+            calibration: [[KeyCalibration::default(); COL]; ROW],
+            #[cfg(feature = "storage")]
+            saved_calibration: [[KeyCalibration::default(); COL]; ROW],
+            states: [[AnalogKeyState::default(); COL]; ROW],
+            scan_idx: 0,
+            pending: None,
+        }
+    }
+
+    /// Use the calibration data, which is usually read by [`crate::storage::Storage::read_analog_calibration`]
+    pub fn with_calibration(mut self, calibration: [[KeyCalibration; COL]; ROW]) -> Self {
+        self.calibration = calibration;
+        #[cfg(feature = "storage")]
+        {
+            self.saved_calibration = calibration;
+        }
+        self
+    }
+
+    /// Get the calibration data of all keys
+    pub fn calibration(&self) -> &[[KeyCalibration; COL]; ROW] {
+        &self.calibration
+    }
+
+    /// Set the config of the key at (row, col), e.g. actuation point, rapid trigger or dual action
+    pub fn set_key_config(&mut self, row: usize, col: usize, config: AnalogKeyConfig) {
+        if let Some(c) = self.key_configs.get_mut(row).and_then(|r| r.get_mut(col)) {
+            *c = Self::check_key_config(config);
+        } else {
+            warn!("Analog key ({}, {}) is out of range", row, col);
+        }
+    }
+
+    /// Disable the dual action whose position is out of the matrix, which has no action in the keymap
+    fn check_key_config(mut config: AnalogKeyConfig) -> AnalogKeyConfig {
+        if config
+            .dual_action
+            .is_some_and(|dual| dual.row as usize >= ROW || dual.col as usize >= COL)
+        {
+            error!(
+                "Dual action of analog key is out of the {}x{} matrix, it's disabled",
+                ROW, COL
+            );
+            config.dual_action = None;
+        }
+        config
+    }
+
+    /// Read and process a key, returns the generated events
+    async fn scan_key(&mut self, row: usize, col: usize) -> [Option<KeyboardEvent>; 2] {
+        let raw = self.source.read_key(row, col).await;
+        let calibration = &mut self.calibration[row][col];
+        if !calibration.is_calibrated() {
+            *calibration = KeyCalibration::from_rest(raw, self.config.default_range);
+            debug!("Analog key ({}, {}) rest value: {}", row, col, raw);
+            return [None, None];
+        }
+        calibration.update_bottom(raw);
+        let travel = calibration.travel(raw, self.config.total_travel);
+        let key_config = self.key_configs[row][col];
+        let changes = self.states[row][col].update(travel, &key_config);
+
+        #[cfg(feature = "storage")]
+        if !self.states[row][col].pressed {
+            self.save_calibration(row, col).await;
+        }
+
+        changes.map(|change| {
+            change.and_then(|change| match change {
+                KeyChange::Primary(pressed) => Some(KeyboardEvent::key(row as u8, col as u8, pressed)),
+                KeyChange::Secondary(pressed) => key_config
+                    .dual_action
+                    .map(|dual| KeyboardEvent::key(dual.row, dual.col, pressed)),
+            })
+        })
+    }
+
+    /// Save the calibration of the released key, if it changes enough
+    #[cfg(feature = "storage")]
+    async fn save_calibration(&mut self, row: usize, col: usize) {
+        let calibration = self.calibration[row][col];
+        let saved = &mut self.saved_calibration[row][col];
+        if saved.rest != calibration.rest || saved.bottom.abs_diff(calibration.bottom) >= self.config.save_threshold {
+            *saved = calibration;
+            FLASH_CHANNEL
+                .send(FlashOperationMessage::AnalogCalibration {
+                    row: row as u8,
+                    col: col as u8,
+                    calibration,
+                })
+                .await;
+        }
+    }
+}
+
+impl<S: AnalogSource, const ROW: usize, const COL: usize> InputDevice for AnalogMatrix<S, ROW, COL> {
+    async fn read_event(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.pending.take() {
+                return Event::Key(event);
+            }
+            while self.scan_idx < ROW * COL {
+                let (row, col) = (self.scan_idx % ROW, self.scan_idx / ROW);
+                self.scan_idx += 1;
+                let mut events = self.scan_key(row, col).await.into_iter().flatten();
+                if let Some(event) = events.next() {
+                    self.pending = events.next();
+                    return Event::Key(event);
+                }
+            }
+            self.scan_idx = 0;
+            Timer::after(self.config.scan_interval).await;
+        }
+    }
+}
+
+impl<S: AnalogSource, const ROW: usize, const COL: usize> MatrixTrait for AnalogMatrix<S, ROW, COL> {
+    const ROW: usize = ROW;
+    const COL: usize = COL;
+
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Simulated ADC, each full scan of the matrix reads the next frame of raw values
+    struct SimulatedAdc<const ROW: usize, const COL: usize> {
+        frames: std::vec::Vec<[[u16; COL]; ROW]>,
+        reads: usize,
+    }
+
+    impl<const ROW: usize, const COL: usize> AnalogSource for SimulatedAdc<ROW, COL> {
+        async fn read_key(&mut self, row: usize, col: usize) -> u16 {
+            let frame = (self.reads / (ROW * COL)).min(self.frames.len() - 1);
+            self.reads += 1;
+            self.frames[frame][row][col]
+        }
+    }
+
+    async fn read_key<S: AnalogSource, const ROW: usize, const COL: usize>(
+        matrix: &mut AnalogMatrix<S, ROW, COL>,
+    ) -> KeyboardEvent {
+        match matrix.read_event().await {
+            Event::Key(event) => event,
+            _ => panic!("Expected key event"),
+        }
+    }
+
+    #[test]
+    fn test_calibration_travel() {
+        // Raw value increases when pressed
+        let mut calibration = KeyCalibration::from_rest(1000, 400);
+        assert!(calibration.is_calibrated());
+        assert_eq!(calibration.travel(1000, 400), 0);
+        assert_eq!(calibration.travel(1200, 400), 200);
+        assert_eq!(calibration.travel(900, 400), 0);
+        assert_eq!(calibration.travel(1500, 400), 400);
+        assert!(!calibration.update_bottom(1300));
+        assert!(calibration.update_bottom(1800));
+        assert_eq!(calibration.travel(1400, 400), 200);
+
+        // Raw value decreases when pressed
+        let mut calibration = KeyCalibration::from_rest(2000, -500);
+        assert_eq!(calibration.bottom, 1500);
+        assert_eq!(calibration.travel(1750, 400), 200);
+        assert_eq!(calibration.travel(2100, 400), 0);
+        assert!(calibration.update_bottom(1000));
+        assert_eq!(calibration.travel(1500, 400), 200);
+
+        assert!(!KeyCalibration::default().is_calibrated());
+        assert_eq!(KeyCalibration::default().travel(100, 400), 0);
+    }
+
+    #[test]
+    fn test_actuation_and_release() {
+        let config = AnalogKeyConfig::default();
+        let mut state = AnalogKeyState::default();
+        assert_eq!(state.update(199, &config), [None, None]);
+        assert_eq!(state.update(200, &config), [Some(KeyChange::Primary(true)), None]);
+        // Hysteresis between the actuation point and the release point
+        assert_eq!(state.update(151, &config), [None, None]);
+        assert_eq!(state.update(400, &config), [None, None]);
+        assert_eq!(state.update(150, &config), [None, Some(KeyChange::Primary(false))]);
+        assert_eq!(state.update(180, &config), [None, None]);
+    }
+
+    #[test]
+    fn test_rapid_trigger() {
+        let config = AnalogKeyConfig {
+            rapid_trigger: Some(RapidTrigger {
+                press_sensitivity: 20,
+                release_sensitivity: 30,
+            }),
+            ..Default::default()
+        };
+        let mut state = AnalogKeyState::default();
+        // The first press needs the actuation point
+        assert_eq!(state.update(100, &config), [None, None]);
+        assert_eq!(state.update(250, &config), [Some(KeyChange::Primary(true)), None]);
+        assert_eq!(state.update(350, &config), [None, None]);
+        // Move up by the release sensitivity
+        assert_eq!(state.update(321, &config), [None, None]);
+        assert_eq!(state.update(320, &config), [None, Some(KeyChange::Primary(false))]);
+        // Still above the actuation point, but the key is not pressed until it moves down by the press sensitivity
+        assert_eq!(state.update(300, &config), [None, None]);
+        assert_eq!(state.update(319, &config), [None, None]);
+        assert_eq!(state.update(320, &config), [Some(KeyChange::Primary(true)), None]);
+        // Released below the actuation point, and pressed again above the release point
+        assert_eq!(state.update(180, &config), [None, Some(KeyChange::Primary(false))]);
+        assert_eq!(state.update(160, &config), [None, None]);
+        assert_eq!(state.update(180, &config), [Some(KeyChange::Primary(true)), None]);
+        // Fully released, rapid trigger is reset
+        assert_eq!(state.update(50, &config), [None, Some(KeyChange::Primary(false))]);
+        assert_eq!(state.update(190, &config), [None, None]);
+        assert_eq!(state.update(200, &config), [Some(KeyChange::Primary(true)), None]);
+    }
+
+    #[test]
+    fn test_dual_action() {
+        let config = AnalogKeyConfig {
+            dual_action: Some(DualAction {
+                actuation_point: 350,
+                row: 1,
+                col: 0,
+            }),
+            ..Default::default()
+        };
+        let mut state = AnalogKeyState::default();
+        assert_eq!(state.update(250, &config), [Some(KeyChange::Primary(true)), None]);
+        assert_eq!(state.update(350, &config), [None, Some(KeyChange::Secondary(true))]);
+        assert_eq!(state.update(341, &config), [None, None]);
+        assert_eq!(state.update(340, &config), [None, Some(KeyChange::Secondary(false))]);
+        // Both actions are pressed in a single scan, the primary action goes first
+        assert_eq!(state.update(100, &config), [None, Some(KeyChange::Primary(false))]);
+        assert_eq!(
+            state.update(400, &config),
+            [Some(KeyChange::Primary(true)), Some(KeyChange::Secondary(true))]
+        );
+        // And the secondary action is released first
+        assert_eq!(
+            state.update(0, &config),
+            [Some(KeyChange::Secondary(false)), Some(KeyChange::Primary(false))]
+        );
+    }
+
+    #[test]
+    fn test_dual_action_out_of_matrix() {
+        let source = SimulatedAdc {
+            frames: std::vec![[[1000; 2]; 2]],
+            reads: 0,
+        };
+        let mut matrix: AnalogMatrix<_, 2, 2> = AnalogMatrix::new(source, AnalogMatrixConfig::default());
+        let dual_action = |row, col| AnalogKeyConfig {
+            dual_action: Some(DualAction {
+                actuation_point: 350,
+                row,
+                col,
+            }),
+            ..Default::default()
+        };
+        matrix.set_key_config(0, 0, dual_action(1, 1));
+        matrix.set_key_config(0, 1, dual_action(1, 2));
+        matrix.set_key_config(1, 0, dual_action(2, 0));
+        assert_eq!(matrix.key_configs[0][0], dual_action(1, 1));
+        assert_eq!(matrix.key_configs[0][1].dual_action, None);
+        assert_eq!(matrix.key_configs[1][0].dual_action, None);
+    }
+
+    #[test]
+    fn test_analog_matrix_with_simulated_adc() {
+        // Column 2 is not connected, (1, 2) is reserved for the dual action
+        let rest = [[1000; 3]; 2];
+        let source = SimulatedAdc {
+            frames: std::vec![
+                // The first scan calibrates the rest values
+                rest,
+                // Key (1, 0) is bottomed out, key (0, 1) is pressed to 2.0mm
+                [[1000, 1250, 1000], [1500, 1000, 1000]],
+                // Key (1, 1) is pressed deeper than the default range
+                [[1000, 1250, 1000], [1500, 1800, 1000]],
+                rest,
+            ],
+            reads: 0,
+        };
+        let config = AnalogMatrixConfig {
+            default_range: 500,
+            scan_interval: Duration::from_micros(10),
+            ..Default::default()
+        };
+        let mut matrix: AnalogMatrix<_, 2, 3> = AnalogMatrix::new(source, config);
+        matrix.set_key_config(
+            1,
+            0,
+            AnalogKeyConfig {
+                dual_action: Some(DualAction {
+                    actuation_point: 350,
+                    row: 1,
+                    col: 2,
+                }),
+                ..Default::default()
+            },
+        );
+
+        let scan = async {
+            // Keys are scanned column by column
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 0, true));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 2, true));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(0, 1, true));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 1, true));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 2, false));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 0, false));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(0, 1, false));
+            assert_eq!(read_key(&mut matrix).await, KeyboardEvent::key(1, 1, false));
+        };
+        // Consume the saved calibrations, otherwise the flash channel gets full
+        #[cfg(feature = "storage")]
+        let scan = embassy_futures::select::select(scan, async {
+            loop {
+                FLASH_CHANNEL.receive().await;
+            }
+        });
+        block_on(scan);
+
+        let calibration = matrix.calibration();
+        assert_eq!(
+            calibration[0][0],
+            KeyCalibration {
+                rest: 1000,
+                bottom: 1500
+            }
+        );
+        assert_eq!(
+            calibration[1][0],
+            KeyCalibration {
+                rest: 1000,
+                bottom: 1500
+            }
+        );
+        // The bottom of key (1, 1) is extended
+        assert_eq!(
+            calibration[1][1],
+            KeyCalibration {
+                rest: 1000,
+                bottom: 1800
+            }
+        );
+    }
+}
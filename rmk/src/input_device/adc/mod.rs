@@ -1,3 +1,4 @@
+pub mod analog_matrix;
 #[cfg(feature = "_nrf_ble")]
 pub mod nrf;
 
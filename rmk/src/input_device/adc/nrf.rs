@@ -1,6 +1,7 @@
 use embassy_nrf::saadc::Saadc;
 use embassy_time::{Duration, Instant};
 
+use super::analog_matrix::AdcChannelReader;
 use super::{AdcState, AnalogEventType};
 use crate::event::{Axis, AxisEvent, AxisValType, Event};
 use crate::input_device::InputDevice;
@@ -128,3 +129,12 @@ impl<'a, const PIN_NUM: usize, const EVENT_NUM: usize> InputDevice for NrfAdc<'a
         ret_e
     }
 }
+
+/// Sample all channels and read one of them, used for reading analog keys via multiplexers
+impl<'a, const PIN_NUM: usize> AdcChannelReader for Saadc<'a, PIN_NUM> {
+    async fn read_channel(&mut self, channel: u8) -> u16 {
+        let mut buf = [0; PIN_NUM];
+        self.sample(&mut buf).await;
+        buf.get(channel as usize).copied().unwrap_or(0).max(0) as u16
+    }
+}
@@ -1,5 +1,6 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embedded_hal::digital::InputPin;
 #[cfg(all(feature = "_ble", feature = "controller"))]
@@ -14,6 +15,52 @@ use crate::input_device::ProcessResult;
 
 pub(crate) static BATTERY_UPDATE: Signal<crate::RawMutex, BatteryState> = Signal::new();
 
+static BATTERY_CALIBRATION: Mutex<crate::RawMutex, Cell<BatteryCalibration>> =
+    Mutex::new(Cell::new(BatteryCalibration::new()));
+
+/// Calibration of the battery level, which can be changed in VIA.
+///
+/// The values are the ADC values of the battery voltage before the voltage divider, the defaults are 3.6v and 4.2v
+/// of nRF52840's SAADC with the default settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryCalibration {
+    /// ADC value of an empty battery
+    pub empty: u16,
+    /// ADC value of a full battery
+    pub full: u16,
+}
+
+impl BatteryCalibration {
+    pub const fn new() -> Self {
+        // 4055 ~= 3.6v * 1137.8, rounded down to simplify the calculation, 4755 ~= 4.2v * 1137.8
+        Self {
+            empty: 4055,
+            full: 4755,
+        }
+    }
+}
+
+impl Default for BatteryCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the current battery calibration
+pub fn battery_calibration() -> BatteryCalibration {
+    BATTERY_CALIBRATION.lock(|c| c.get())
+}
+
+/// Update the battery calibration, returns false if the empty value isn't lower than the full value
+pub(crate) fn set_battery_calibration(calibration: BatteryCalibration) -> bool {
+    if calibration.empty >= calibration.full {
+        return false;
+    }
+    BATTERY_CALIBRATION.lock(|c| c.set(calibration));
+    true
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum BatteryState {
@@ -120,7 +167,8 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         // For example, rmk-ble-keyboard uses two resistors 820K and 2M adjusting the v_adc, then,
         // v_adc = v_bat * measured / total => val = v_bat * 1137.8 * measured / total
         //
-        // If the battery voltage range is 3.6v ~ 4.2v, the adc val range should be (4096 ~ 4755) * measured / total
+        // The battery voltage range is calibrated by `BatteryCalibration`, the default range is 3.6v ~ 4.2v,
+        // so the adc val range should be (4055 ~ 4755) * measured / total
         let mut measured = self.adc_divider_measured as i32;
         let mut total = self.adc_divider_total as i32;
         if 500 < val && val < 1000 {
@@ -130,15 +178,14 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             measured = 1;
             total = 5;
         }
-        if val > 4755_i32 * measured / total {
-            // 4755 ~= 4.2v * 1137.8
+        let calibration = battery_calibration();
+        let (empty, full) = (calibration.empty as i32, calibration.full as i32);
+        if val > full * measured / total {
             100_u8
-        } else if val < 4055_i32 * measured / total {
-            // 4096 ~= 3.6v * 1137.8
-            // To simplify the calculation, we use 4055 here
+        } else if val < empty * measured / total {
             0_u8
         } else {
-            ((val * total / measured - 4055) / 7) as u8
+            ((val * total / measured - empty) * 100 / (full - empty)).min(100) as u8
         }
     }
 }
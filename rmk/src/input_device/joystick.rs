@@ -1,13 +1,70 @@
 use core::cell::RefCell;
 
-use usbd_hid::descriptor::MouseReport;
-
-use crate::channel::KEYBOARD_REPORT_CHANNEL;
-use crate::event::Event;
+use crate::channel::{KEY_EVENT_CHANNEL, KEYBOARD_REPORT_CHANNEL};
+use crate::descriptor::MouseReport;
+use crate::event::{Event, KeyPos, KeyboardEvent};
 use crate::hid::Report;
+#[cfg(feature = "gamepad")]
+use crate::hid::update_gamepad_state;
 use crate::input_device::{InputProcessor, ProcessResult};
 use crate::keymap::KeyMap;
 
+/// Output mode of the joystick
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoystickMode {
+    /// Move the mouse cursor with X and Y axes
+    #[default]
+    Mouse,
+    /// Report X, Y and Z axes as the axes of the gamepad
+    #[cfg(feature = "gamepad")]
+    Gamepad,
+    /// Scroll with X and Y axes, X is the horizontal scroll, the values are in high-resolution wheel units
+    Scroll,
+    /// Press keys when X or Y axis exceeds the threshold.
+    ///
+    /// `keys` are the positions of the up, down, left and right keys in the keymap,
+    /// the key events are processed by the keyboard like the events from the matrix.
+    ArrowKeys { threshold: i16, keys: [KeyPos; 4] },
+}
+
+/// Keys pressed by the joystick in [`JoystickMode::ArrowKeys`] mode
+#[derive(Default)]
+struct ArrowKeyState {
+    /// Index of the pressed key of X and Y axes in `keys`
+    pressed: [Option<usize>; 2],
+}
+
+impl ArrowKeyState {
+    /// Update the pressed keys, returns the key events of changed keys
+    fn update(&mut self, x: i16, y: i16, threshold: i16, keys: &[KeyPos; 4]) -> heapless::Vec<KeyboardEvent, 4> {
+        let direction = |value: i16, negative: usize, positive: usize| {
+            if value > threshold {
+                Some(positive)
+            } else if value < -threshold {
+                Some(negative)
+            } else {
+                None
+            }
+        };
+        let mut events = heapless::Vec::new();
+        for (last, current) in self.pressed.iter_mut().zip([direction(x, 2, 3), direction(y, 0, 1)]) {
+            if *last == current {
+                continue;
+            }
+            let mut push = |index: Option<usize>, pressed: bool| {
+                if let Some(pos) = index.map(|i| keys[i]) {
+                    events.push(KeyboardEvent::key(pos.row, pos.col, pressed)).ok();
+                }
+            };
+            push(*last, false);
+            push(current, true);
+            *last = current;
+        }
+        events
+    }
+}
+
 pub struct JoystickProcessor<
     'a,
     const ROW: usize,
@@ -21,6 +78,8 @@ pub struct JoystickProcessor<
     keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     record: [i16; N],
     resolution: u16,
+    mode: JoystickMode,
+    arrow_keys: ArrowKeyState,
 }
 
 impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize, const N: usize>
@@ -38,8 +97,17 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             resolution,
             keymap,
             record: [0; N],
+            mode: JoystickMode::Mouse,
+            arrow_keys: ArrowKeyState::default(),
         }
     }
+
+    /// Set the output mode of the joystick
+    pub fn with_mode(mut self, mode: JoystickMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     async fn generate_report(&mut self) {
         let mut report = [0i16; N];
 
@@ -60,15 +128,44 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
 
         debug!("JoystickProcessor::generate_report: report = {:?}", report);
-        // map to mouse
-        let mouse_report = MouseReport {
-            buttons: 0,
-            x: (report[0].clamp(i8::MIN as i16, i8::MAX as i16)) as i8,
-            y: (report[1].clamp(i8::MIN as i16, i8::MAX as i16)) as i8,
-            wheel: 0,
-            pan: 0,
-        };
-        self.send_report(Report::MouseReport(mouse_report)).await;
+        let axis = |i: usize| report.get(i).copied().unwrap_or(0);
+        match self.mode {
+            JoystickMode::Mouse => {
+                let mouse_report = MouseReport {
+                    buttons: 0,
+                    x: axis(0),
+                    y: axis(1),
+                    wheel: 0,
+                    pan: 0,
+                };
+                self.send_report(Report::MouseReport(mouse_report)).await;
+            }
+            #[cfg(feature = "gamepad")]
+            JoystickMode::Gamepad => {
+                let gamepad_report = update_gamepad_state(|state| {
+                    for (i, a) in state.axes.iter_mut().take(3).enumerate() {
+                        *a = axis(i);
+                    }
+                });
+                self.send_report(Report::GamepadReport(gamepad_report)).await;
+            }
+            JoystickMode::Scroll => {
+                // Pushing the joystick up scrolls up
+                let mouse_report = MouseReport {
+                    buttons: 0,
+                    x: 0,
+                    y: 0,
+                    wheel: axis(1).saturating_neg(),
+                    pan: axis(0),
+                };
+                self.send_report(Report::MouseReport(mouse_report)).await;
+            }
+            JoystickMode::ArrowKeys { threshold, keys } => {
+                for event in self.arrow_keys.update(axis(0), axis(1), threshold, &keys) {
+                    KEY_EVENT_CHANNEL.send(event).await;
+                }
+            }
+        }
     }
 }
 
@@ -101,3 +198,42 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         self.keymap
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: [KeyPos; 4] = [
+        KeyPos { row: 0, col: 1 },
+        KeyPos { row: 1, col: 1 },
+        KeyPos { row: 1, col: 0 },
+        KeyPos { row: 1, col: 2 },
+    ];
+
+    #[test]
+    fn test_arrow_keys() {
+        let mut state = ArrowKeyState::default();
+        assert!(state.update(50, -100, 100, &KEYS).is_empty());
+
+        // Up
+        let events = state.update(50, -101, 100, &KEYS);
+        assert_eq!(events.as_slice(), &[KeyboardEvent::key(0, 1, true)]);
+        assert!(state.update(0, -200, 100, &KEYS).is_empty());
+
+        // Up to down, then right
+        let events = state.update(0, 200, 100, &KEYS);
+        assert_eq!(
+            events.as_slice(),
+            &[KeyboardEvent::key(0, 1, false), KeyboardEvent::key(1, 1, true)]
+        );
+        let events = state.update(200, 200, 100, &KEYS);
+        assert_eq!(events.as_slice(), &[KeyboardEvent::key(1, 2, true)]);
+
+        // Release both axes
+        let events = state.update(-100, 0, 100, &KEYS);
+        assert_eq!(
+            events.as_slice(),
+            &[KeyboardEvent::key(1, 2, false), KeyboardEvent::key(1, 1, false)]
+        );
+    }
+}
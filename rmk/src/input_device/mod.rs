@@ -13,6 +13,8 @@ pub mod adc;
 pub mod battery;
 pub mod joystick;
 pub mod rotary_encoder;
+#[cfg(feature = "digitizer")]
+pub mod touchpad;
 
 /// The trait for runnable input devices and processors.
 ///
@@ -132,6 +134,7 @@ macro_rules! run_devices {
                                 // For KeyboardEvent, send it to KEY_EVENT_CHANNEL
                                 match e {
                                     $crate::event::Event::Key(key_event) => {
+                                        $crate::bootmagic::record_key_event(&key_event);
                                         $crate::channel::KEY_EVENT_CHANNEL.send(key_event).await;
                                     }
                                     _ => {
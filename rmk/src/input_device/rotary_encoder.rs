@@ -1,14 +1,20 @@
 //! General rotary encoder
 //!
 //! The rotary encoder implementation is adapted from: <https://github.com/leshow/rotary-encoder-hal/blob/master/src/lib.rs>
+use core::cell::RefCell;
+
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal::digital::InputPin;
 #[cfg(feature = "async_matrix")]
 use embedded_hal_async::digital::Wait;
 use postcard::experimental::max_size::MaxSize;
 use serde::{Deserialize, Serialize};
 
-use super::InputDevice;
-use crate::event::{Event, KeyboardEvent};
+use super::{InputDevice, InputProcessor, ProcessResult};
+use crate::descriptor::MouseReport;
+use crate::event::{Axis, AxisEvent, AxisValType, Event, KeyboardEvent};
+use crate::hid::Report;
+use crate::keymap::KeyMap;
 
 /// Holds current/old state and both [`InputPin`](https://docs.rs/embedded-hal/latest/embedded_hal/digital/trait.InputPin.html)
 #[derive(Clone, Debug)]
@@ -23,6 +29,77 @@ pub struct RotaryEncoder<A, B, P> {
     /// The last action of the rotary encoder.
     /// When it's not `None`, the rotary encoder needs to emit a release event.
     last_action: Option<Direction>,
+    /// Per-encoder behavior: acceleration and output mode
+    behavior: EncoderBehavior,
+    /// Timestamp of the last detent, used for acceleration
+    last_detent: Option<Instant>,
+    /// Direction of the last detent
+    last_direction: Direction,
+    /// Remaining repeated presses of the last detent, produced by acceleration
+    repeats: u8,
+    /// The direction which is currently held in [`EncoderMode::HoldUntilIdle`] mode
+    held: Option<Direction>,
+    /// The previously held direction which should be released after the direction is changed
+    released: Option<Direction>,
+    /// Whether an [`Event::Eos`] should be emitted after a scroll event
+    eos_pending: bool,
+}
+
+/// Acceleration curve of a rotary encoder.
+///
+/// When two detents are closer than `threshold_ms`, the detent is repeated.
+/// The number of repeats grows linearly as the interval shrinks, up to `max_multiplier`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncoderAcceleration {
+    /// Detent interval below which the acceleration starts, in milliseconds
+    pub threshold_ms: u16,
+    /// Maximum number of events emitted for a single detent
+    pub max_multiplier: u8,
+}
+
+impl EncoderAcceleration {
+    /// Get the number of events for a detent which comes `interval` after the previous one
+    pub fn multiplier(&self, interval: Duration) -> u8 {
+        let threshold = self.threshold_ms as u64;
+        let interval = interval.as_millis();
+        if threshold == 0 || interval >= threshold || self.max_multiplier <= 1 {
+            return 1;
+        }
+        let extra = (self.max_multiplier as u64 - 1) * (threshold - interval) / threshold;
+        1 + extra as u8
+    }
+}
+
+/// Output mode of a rotary encoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncoderMode {
+    /// Each detent is a press followed by a release of the encoder position.
+    #[default]
+    Key,
+    /// The encoder position is pressed on every detent, and released only after the encoder is idle for `idle_timeout_ms`.
+    ///
+    /// Combined with a tap-hold action in the encoder map, the hold action stays active while rotating,
+    /// and the tap action is tapped on every detent, for example `TH(Tab, LAlt)` for a window switcher.
+    HoldUntilIdle { idle_timeout_ms: u16 },
+    /// Each detent is sent as a relative wheel movement, bypassing the encoder map.
+    Scroll {
+        /// Scroll vertically(wheel) or horizontally(pan)
+        horizontal: bool,
+        /// Scroll units for each detent, in high-resolution units. [`WHEEL_RESOLUTION`](crate::descriptor::WHEEL_RESOLUTION) units is a full notch
+        units_per_detent: i16,
+    },
+}
+
+/// Behavior of a rotary encoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncoderBehavior {
+    /// Optional acceleration curve, `None` means one event per detent
+    pub acceleration: Option<EncoderAcceleration>,
+    /// Output mode of the encoder
+    pub mode: EncoderMode,
 }
 
 /// The encoder direction is either `Clockwise`, `CounterClockwise`, or `None`
@@ -128,6 +205,13 @@ where
             phase: DefaultPhase,
             id,
             last_action: None,
+            behavior: EncoderBehavior::default(),
+            last_detent: None,
+            last_direction: Direction::None,
+            repeats: 0,
+            held: None,
+            released: None,
+            eos_pending: false,
         }
     }
 }
@@ -147,6 +231,13 @@ where
             phase: ResolutionPhase::new(resolution, reverse),
             id,
             last_action: None,
+            behavior: EncoderBehavior::default(),
+            last_detent: None,
+            last_direction: Direction::None,
+            repeats: 0,
+            held: None,
+            released: None,
+            eos_pending: false,
         }
     }
 }
@@ -161,9 +252,54 @@ impl<A: InputPin, B: InputPin, P: Phase> RotaryEncoder<A, B, P> {
             phase,
             id,
             last_action: None,
+            behavior: EncoderBehavior::default(),
+            last_detent: None,
+            last_direction: Direction::None,
+            repeats: 0,
+            held: None,
+            released: None,
+            eos_pending: false,
         }
     }
 
+    /// Set the acceleration and output mode of the encoder
+    pub fn with_behavior(mut self, behavior: EncoderBehavior) -> Self {
+        self.behavior = behavior;
+        self
+    }
+
+    /// Record a detent, returns the number of events that the detent should produce
+    fn detent_multiplier(&mut self) -> u8 {
+        let now = Instant::now();
+        let multiplier = match (self.behavior.acceleration, self.last_detent) {
+            (Some(acceleration), Some(last)) => acceleration.multiplier(now.saturating_duration_since(last)),
+            _ => 1,
+        };
+        self.last_detent = Some(now);
+        multiplier
+    }
+
+    /// Release the held direction after the encoder is idle
+    fn release_held(&mut self) -> Event {
+        let direction = self.held.take().unwrap_or(self.last_direction);
+        Event::Key(KeyboardEvent::rotary_encoder(self.id, direction, false))
+    }
+
+    /// Convert a detent to a scroll event
+    fn scroll_event(&mut self, direction: Direction, multiplier: u8, horizontal: bool, units_per_detent: i16) -> Event {
+        let value = units_per_detent.saturating_mul(multiplier as i16);
+        let value = match direction {
+            Direction::Clockwise => value,
+            _ => -value,
+        };
+        self.eos_pending = true;
+        Event::AxisEventStream(AxisEvent {
+            typ: AxisValType::Rel,
+            axis: if horizontal { Axis::H } else { Axis::V },
+            value,
+        })
+    }
+
     /// Call `update` to evaluate the next state of the encoder, propagates errors from `InputPin` read
     pub fn update(&mut self) -> Direction {
         // use mask to get previous state value
@@ -220,40 +356,199 @@ impl<
 > InputDevice for RotaryEncoder<A, B, P>
 {
     async fn read_event(&mut self) -> Event {
-        // Read until a valid rotary encoder event is detected
+        // Finish the scroll event stream
+        if self.eos_pending {
+            self.eos_pending = false;
+            return Event::Eos;
+        }
+
+        // Release the last pressed direction
         if let Some(last_action) = self.last_action {
-            embassy_time::Timer::after_millis(5).await;
+            Timer::after_millis(5).await;
             let e = Event::Key(KeyboardEvent::rotary_encoder(self.id, last_action, false));
             self.last_action = None;
             return e;
         }
 
+        // The direction is changed while holding, release the previous direction after the new one is pressed
+        if let Some(released) = self.released.take() {
+            return Event::Key(KeyboardEvent::rotary_encoder(self.id, released, false));
+        }
+
+        // Accelerated detent, press the encoder position again
+        if self.repeats > 0 {
+            Timer::after_millis(5).await;
+            self.repeats -= 1;
+            if self.behavior.mode == EncoderMode::Key {
+                self.last_action = Some(self.last_direction);
+            }
+            return Event::Key(KeyboardEvent::rotary_encoder(self.id, self.last_direction, true));
+        }
+
+        // Deadline of releasing the held direction
+        let idle_deadline = match (self.behavior.mode, self.held, self.last_detent) {
+            (EncoderMode::HoldUntilIdle { idle_timeout_ms }, Some(_), Some(last)) => {
+                Some(last + Duration::from_millis(idle_timeout_ms as u64))
+            }
+            _ => None,
+        };
+
         loop {
             #[cfg(feature = "async_matrix")]
             {
                 let (pin_a, pin_b) = self.pins();
-                embassy_futures::select::select(pin_a.wait_for_any_edge(), pin_b.wait_for_any_edge()).await;
+                let edge = embassy_futures::select::select(pin_a.wait_for_any_edge(), pin_b.wait_for_any_edge());
+                match idle_deadline {
+                    Some(deadline) => {
+                        if let embassy_futures::select::Either::Second(_) =
+                            embassy_futures::select::select(edge, Timer::at(deadline)).await
+                        {
+                            return self.release_held();
+                        }
+                    }
+                    None => {
+                        edge.await;
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "async_matrix"))]
+            if let Some(deadline) = idle_deadline
+                && Instant::now() >= deadline
+            {
+                return self.release_held();
             }
 
             let direction = self.update();
 
             if direction != Direction::None {
-                self.last_action = Some(direction);
+                let multiplier = self.detent_multiplier();
+                self.last_direction = direction;
+                self.repeats = multiplier - 1;
+                match self.behavior.mode {
+                    EncoderMode::Key => self.last_action = Some(direction),
+                    EncoderMode::HoldUntilIdle { .. } => {
+                        if let Some(held) = self.held
+                            && held != direction
+                        {
+                            self.released = Some(held);
+                        }
+                        self.held = Some(direction);
+                    }
+                    EncoderMode::Scroll {
+                        horizontal,
+                        units_per_detent,
+                    } => {
+                        self.repeats = 0;
+                        return self.scroll_event(direction, multiplier, horizontal, units_per_detent);
+                    }
+                }
                 return Event::Key(KeyboardEvent::rotary_encoder(self.id, direction, true));
             }
 
             #[cfg(not(feature = "async_matrix"))]
             {
                 // Wait for 20ms to avoid busy loop
-                embassy_time::Timer::after_millis(20).await;
+                Timer::after_millis(20).await;
             }
         }
     }
 }
 
+/// Processor which converts scroll events from rotary encoders in [`EncoderMode::Scroll`] mode to mouse reports.
+pub struct EncoderScrollProcessor<
+    'a,
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    /// Accumulated wheel movement of current event stream
+    wheel: i16,
+    /// Accumulated pan movement of current event stream
+    pan: i16,
+    /// Whether an event stream is being processed
+    streaming: bool,
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    EncoderScrollProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            keymap,
+            wheel: 0,
+            pan: 0,
+            streaming: false,
+        }
+    }
+
+    /// Build the mouse report from the accumulated movement, and reset the accumulator
+    fn take_report(&mut self) -> MouseReport {
+        let report = MouseReport {
+            buttons: 0,
+            x: 0,
+            y: 0,
+            wheel: self.wheel,
+            pan: self.pan,
+        };
+        self.wheel = 0;
+        self.pan = 0;
+        self.streaming = false;
+        report
+    }
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    InputProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+    for EncoderScrollProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    async fn process(&mut self, event: Event) -> ProcessResult {
+        match event {
+            Event::AxisEventStream(AxisEvent {
+                typ: AxisValType::Rel,
+                axis: Axis::V,
+                value,
+            }) => {
+                self.wheel = self.wheel.saturating_add(value);
+                self.streaming = true;
+                ProcessResult::Stop
+            }
+            Event::AxisEventStream(AxisEvent {
+                typ: AxisValType::Rel,
+                axis: Axis::H,
+                value,
+            }) => {
+                self.pan = self.pan.saturating_add(value);
+                self.streaming = true;
+                ProcessResult::Stop
+            }
+            Event::Eos if self.streaming => {
+                let report = self.take_report();
+                self.send_report(Report::MouseReport(report)).await;
+                ProcessResult::Stop
+            }
+            _ => ProcessResult::Continue(event),
+        }
+    }
+
+    fn get_keymap(&self) -> &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>> {
+        self.keymap
+    }
+}
+
 #[cfg(test)]
 mod test {
+    extern crate std;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use embassy_futures::block_on;
+
     use super::*;
+    use crate::event::KeyboardEventPos;
     // Init logger for tests
 
     #[ctor::ctor]
@@ -294,4 +589,218 @@ mod test {
             assert_eq!(d, d2);
         }
     }
+
+    #[test]
+    fn test_encoder_acceleration() {
+        let acceleration = EncoderAcceleration {
+            threshold_ms: 100,
+            max_multiplier: 5,
+        };
+        // Slow rotation is not accelerated
+        assert_eq!(acceleration.multiplier(Duration::from_millis(100)), 1);
+        assert_eq!(acceleration.multiplier(Duration::from_millis(500)), 1);
+        // The multiplier grows as the interval shrinks
+        assert_eq!(acceleration.multiplier(Duration::from_millis(75)), 2);
+        assert_eq!(acceleration.multiplier(Duration::from_millis(50)), 3);
+        assert_eq!(acceleration.multiplier(Duration::from_millis(0)), 5);
+
+        // Acceleration is disabled when the max multiplier is 1
+        let acceleration = EncoderAcceleration {
+            threshold_ms: 100,
+            max_multiplier: 1,
+        };
+        assert_eq!(acceleration.multiplier(Duration::from_millis(0)), 1);
+    }
+
+    /// Detents which are not read by the encoder yet
+    type Detents = Rc<RefCell<VecDeque<Direction>>>;
+
+    /// Phase which returns the scripted detents
+    struct ScriptedPhase(Detents);
+
+    impl Phase for ScriptedPhase {
+        fn direction(&mut self, _s: u8) -> Direction {
+            self.0.borrow_mut().pop_front().unwrap_or(Direction::None)
+        }
+    }
+
+    /// Pin of the scripted encoder, an edge is detected when there are detents to be read
+    struct ScriptedPin(Detents);
+
+    impl embedded_hal::digital::ErrorType for ScriptedPin {
+        type Error = core::convert::Infallible;
+    }
+
+    impl InputPin for ScriptedPin {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+    }
+
+    #[cfg(feature = "async_matrix")]
+    impl Wait for ScriptedPin {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for_any_edge().await
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for_any_edge().await
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            while self.0.borrow().is_empty() {
+                Timer::after_millis(1).await;
+            }
+            Ok(())
+        }
+    }
+
+    fn scripted_encoder(
+        behavior: EncoderBehavior,
+    ) -> (RotaryEncoder<ScriptedPin, ScriptedPin, ScriptedPhase>, Detents) {
+        let detents = Detents::default();
+        let encoder = RotaryEncoder::with_phase(
+            ScriptedPin(detents.clone()),
+            ScriptedPin(detents.clone()),
+            ScriptedPhase(detents.clone()),
+            0,
+        )
+        .with_behavior(behavior);
+        (encoder, detents)
+    }
+
+    /// Read the next event, which should be a key event of the encoder
+    fn read_key(encoder: &mut RotaryEncoder<ScriptedPin, ScriptedPin, ScriptedPhase>) -> (Direction, bool) {
+        match block_on(encoder.read_event()) {
+            Event::Key(event) => {
+                let KeyboardEventPos::RotaryEncoder(pos) = event.pos else {
+                    panic!("Unexpected key event {:?}", event);
+                };
+                (pos.direction, event.pressed)
+            }
+            e => panic!("Unexpected event {:?}", e),
+        }
+    }
+
+    /// Read the next event, which should be a scroll event followed by the end of stream.
+    ///
+    /// Returns whether the scroll is horizontal and the scroll value
+    fn read_scroll(encoder: &mut RotaryEncoder<ScriptedPin, ScriptedPin, ScriptedPhase>) -> (bool, i16) {
+        let scroll = match block_on(encoder.read_event()) {
+            Event::AxisEventStream(AxisEvent {
+                typ: AxisValType::Rel,
+                axis,
+                value,
+            }) => (matches!(axis, Axis::H), value),
+            e => panic!("Unexpected event {:?}", e),
+        };
+        assert!(matches!(block_on(encoder.read_event()), Event::Eos));
+        scroll
+    }
+
+    const CW: Direction = Direction::Clockwise;
+    const CCW: Direction = Direction::CounterClockwise;
+
+    #[test]
+    fn test_encoder_key_mode_acceleration() {
+        let (mut encoder, detents) = scripted_encoder(EncoderBehavior {
+            acceleration: Some(EncoderAcceleration {
+                threshold_ms: 10000,
+                max_multiplier: 11,
+            }),
+            mode: EncoderMode::Key,
+        });
+
+        // The first detent is not accelerated
+        detents.borrow_mut().push_back(CW);
+        assert_eq!(read_key(&mut encoder), (CW, true));
+        assert_eq!(read_key(&mut encoder), (CW, false));
+
+        // A fast detent is repeated, each repeat is a press followed by a release.
+        // The detent comes less than 1s after the previous one, so it's repeated 10 times.
+        detents.borrow_mut().push_back(CCW);
+        for _ in 0..10 {
+            assert_eq!(read_key(&mut encoder), (CCW, true));
+            assert_eq!(read_key(&mut encoder), (CCW, false));
+        }
+        assert!(detents.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_encoder_hold_until_idle() {
+        let (mut encoder, detents) = scripted_encoder(EncoderBehavior {
+            acceleration: None,
+            mode: EncoderMode::HoldUntilIdle { idle_timeout_ms: 50 },
+        });
+
+        // Every detent presses the encoder position without releasing it
+        detents.borrow_mut().extend([CW, CW]);
+        assert_eq!(read_key(&mut encoder), (CW, true));
+        assert_eq!(read_key(&mut encoder), (CW, true));
+
+        // Changing the direction presses the new direction, then releases the previous one
+        detents.borrow_mut().push_back(CCW);
+        assert_eq!(read_key(&mut encoder), (CCW, true));
+        assert_eq!(read_key(&mut encoder), (CW, false));
+
+        // The held direction is released after the encoder is idle
+        let start = Instant::now();
+        assert_eq!(read_key(&mut encoder), (CCW, false));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_encoders_config_from_behaviors() {
+        let hold = EncoderBehavior {
+            acceleration: None,
+            mode: EncoderMode::HoldUntilIdle { idle_timeout_ms: 500 },
+        };
+        let config = crate::config::EncodersConfig::from_behaviors(&[EncoderBehavior::default(), hold, hold]);
+        assert!(!config.is_hold_until_idle(0));
+        assert!(config.is_hold_until_idle(1));
+        assert!(config.is_hold_until_idle(2));
+        assert!(!config.is_hold_until_idle(3));
+    }
+
+    #[test]
+    fn test_encoder_scroll_mode() {
+        let (mut encoder, detents) = scripted_encoder(EncoderBehavior {
+            acceleration: Some(EncoderAcceleration {
+                threshold_ms: 1000,
+                max_multiplier: 2,
+            }),
+            mode: EncoderMode::Scroll {
+                horizontal: false,
+                units_per_detent: 120,
+            },
+        });
+
+        detents.borrow_mut().push_back(CW);
+        assert_eq!(read_scroll(&mut encoder), (false, 120));
+        // The accelerated detent is a single larger movement instead of repeated events
+        detents.borrow_mut().push_back(CCW);
+        assert_eq!(read_scroll(&mut encoder), (false, -240));
+
+        let (mut encoder, detents) = scripted_encoder(EncoderBehavior {
+            acceleration: None,
+            mode: EncoderMode::Scroll {
+                horizontal: true,
+                units_per_detent: 40,
+            },
+        });
+        detents.borrow_mut().push_back(CCW);
+        assert_eq!(read_scroll(&mut encoder), (true, -40));
+    }
 }
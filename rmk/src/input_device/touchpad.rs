@@ -0,0 +1,383 @@
+//! Touchpad processor
+//!
+//! [`TouchpadProcessor`] converts absolute multi-finger [`TouchpadEvent`]s to digitizer reports,
+//! which gives native touchpad gestures on the host.
+//! Until the host switches the digitizer to touchpad mode, the primary finger moves the mouse cursor instead.
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+
+use embassy_time::Instant;
+
+use super::{InputProcessor, ProcessResult};
+use crate::descriptor::{
+    DIGITIZER_LOGICAL_MAX, DIGITIZER_MAX_CONTACTS, DigitizerContact, DigitizerReport, MouseReport,
+};
+use crate::event::{Axis, AxisValType, Event, TouchpadEvent};
+use crate::hid::{DIGITIZER_FUNCTION_SWITCH, DIGITIZER_INPUT_MODE, Report};
+use crate::keymap::KeyMap;
+
+/// Input mode set by the host when it uses the digitizer as a touchpad
+const TOUCHPAD_INPUT_MODE: u8 = 3;
+
+/// Digitizer units per mouse count in the mouse mode
+const MOUSE_DIVISOR: i32 = 4;
+
+/// Config of the touchpad sensor
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TouchpadConfig {
+    /// Maximum raw X value of the sensor
+    pub x_max: u16,
+    /// Maximum raw Y value of the sensor
+    pub y_max: u16,
+}
+
+impl Default for TouchpadConfig {
+    fn default() -> Self {
+        Self {
+            x_max: DIGITIZER_LOGICAL_MAX,
+            y_max: DIGITIZER_LOGICAL_MAX,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Contact {
+    x: u16,
+    y: u16,
+    /// Whether the finger is on the surface
+    touching: bool,
+    /// Whether the contact is updated in current frame
+    updated: bool,
+}
+
+/// Tracks contacts of all finger slots, and generates a digitizer report for each frame
+struct ContactTracker {
+    config: TouchpadConfig,
+    contacts: [Option<Contact>; DIGITIZER_MAX_CONTACTS],
+}
+
+impl ContactTracker {
+    fn new(config: TouchpadConfig) -> Self {
+        Self {
+            config,
+            contacts: [None; DIGITIZER_MAX_CONTACTS],
+        }
+    }
+
+    /// Whether the finger is already updated in current frame, which means a new frame starts
+    fn is_updated(&self, finger: u8) -> bool {
+        self.contacts
+            .get(finger as usize)
+            .is_some_and(|c| c.is_some_and(|c| c.updated))
+    }
+
+    /// Whether there're contacts updated in current frame
+    fn has_pending(&self) -> bool {
+        self.contacts.iter().flatten().any(|c| c.updated)
+    }
+
+    fn update(&mut self, event: &TouchpadEvent) {
+        let Some(slot) = self.contacts.get_mut(event.finger as usize) else {
+            debug!("Touchpad finger {} is out of range", event.finger);
+            return;
+        };
+        let mut contact = slot.unwrap_or_default();
+        let mut pressure = None;
+        for axis in event.axis.iter() {
+            if let AxisValType::Rel = axis.typ {
+                continue;
+            }
+            match axis.axis {
+                Axis::X => contact.x = Self::scale(axis.value, self.config.x_max),
+                Axis::Y => contact.y = Self::scale(axis.value, self.config.y_max),
+                Axis::Z => pressure = Some(axis.value),
+                _ => {}
+            }
+        }
+        // Zero pressure means the finger is lifted
+        contact.touching = pressure.is_none_or(|p| p > 0);
+        if !contact.touching && slot.is_none() {
+            // The finger is never reported, ignore it
+            return;
+        }
+        contact.updated = true;
+        *slot = Some(contact);
+    }
+
+    /// Generate the report of current frame, and remove lifted contacts
+    fn take_report(&mut self, scan_time: u16) -> Option<DigitizerReport> {
+        if !self.has_pending() {
+            return None;
+        }
+        let mut report = DigitizerReport {
+            scan_time,
+            ..Default::default()
+        };
+        for (id, slot) in self.contacts.iter_mut().enumerate() {
+            if let Some(contact) = slot {
+                let mut flags = DigitizerContact::CONFIDENCE;
+                if contact.touching {
+                    flags |= DigitizerContact::TIP_SWITCH;
+                }
+                report.contacts[report.contact_count as usize] = DigitizerContact {
+                    flags,
+                    id: id as u8,
+                    x: contact.x,
+                    y: contact.y,
+                };
+                report.contact_count += 1;
+                if contact.touching {
+                    contact.updated = false;
+                } else {
+                    // Lifted contact is reported once
+                    *slot = None;
+                }
+            }
+        }
+        Some(report)
+    }
+
+    /// Scale the raw value to the logical range of the digitizer
+    fn scale(value: i16, max: u16) -> u16 {
+        let value = (value.max(0) as u32).min(max as u32);
+        (value * DIGITIZER_LOGICAL_MAX as u32 / max.max(1) as u32) as u16
+    }
+}
+
+/// Converts the movement of the primary finger to relative mouse reports, used when the host doesn't enable the touchpad mode
+#[derive(Default)]
+struct MouseMotion {
+    /// Id and last consumed position of the primary finger
+    last: Option<(u8, u16, u16)>,
+}
+
+impl MouseMotion {
+    fn update(&mut self, report: &DigitizerReport) -> Option<MouseReport> {
+        let Some(primary) = report.contacts[..report.contact_count as usize]
+            .iter()
+            .find(|c| c.flags & DigitizerContact::TIP_SWITCH != 0)
+        else {
+            self.last = None;
+            return None;
+        };
+        let (dx, dy) = match self.last {
+            Some((id, x, y)) if id == primary.id => (
+                (primary.x as i32 - x as i32) / MOUSE_DIVISOR,
+                (primary.y as i32 - y as i32) / MOUSE_DIVISOR,
+            ),
+            // A new primary finger doesn't move the cursor
+            _ => {
+                self.last = Some((primary.id, primary.x, primary.y));
+                return None;
+            }
+        };
+        if dx == 0 && dy == 0 {
+            return None;
+        }
+        // Keep the remainder for the next frame
+        if let Some((_, x, y)) = self.last.as_mut() {
+            *x = (*x as i32 + dx * MOUSE_DIVISOR) as u16;
+            *y = (*y as i32 + dy * MOUSE_DIVISOR) as u16;
+        }
+        Some(MouseReport {
+            x: dx as i16,
+            y: dy as i16,
+            ..Default::default()
+        })
+    }
+}
+
+/// Processor which converts [`TouchpadEvent`]s to digitizer reports.
+///
+/// All fingers reported between two [`Event::Eos`] form a frame, a finger which is reported twice also starts a new frame.
+/// Mouse reports are sent instead if the host doesn't set the input mode of the digitizer to touchpad.
+pub struct TouchpadProcessor<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    tracker: ContactTracker,
+    mouse: MouseMotion,
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    TouchpadProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>, config: TouchpadConfig) -> Self {
+        Self {
+            keymap,
+            tracker: ContactTracker::new(config),
+            mouse: MouseMotion::default(),
+        }
+    }
+
+    async fn send_frame(&mut self) {
+        // Scan time is in 100us units
+        let scan_time = (Instant::now().as_micros() / 100) as u16;
+        if let Some(report) = self.tracker.take_report(scan_time) {
+            // The surface is disabled by the host
+            if DIGITIZER_FUNCTION_SWITCH.load(Ordering::Relaxed) & 0b01 == 0 {
+                return;
+            }
+            if DIGITIZER_INPUT_MODE.load(Ordering::Relaxed) == TOUCHPAD_INPUT_MODE {
+                self.mouse.last = None;
+                self.send_report(Report::DigitizerReport(report)).await;
+            } else if let Some(report) = self.mouse.update(&report) {
+                self.send_report(Report::MouseReport(report)).await;
+            }
+        }
+    }
+}
+
+impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
+    InputProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER> for TouchpadProcessor<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
+{
+    async fn process(&mut self, event: Event) -> ProcessResult {
+        match event {
+            Event::Touchpad(touchpad_event) => {
+                if self.tracker.is_updated(touchpad_event.finger) {
+                    self.send_frame().await;
+                }
+                self.tracker.update(&touchpad_event);
+                ProcessResult::Stop
+            }
+            Event::Eos => {
+                // Other processors may also wait for the end of stream
+                self.send_frame().await;
+                ProcessResult::Continue(event)
+            }
+            _ => ProcessResult::Continue(event),
+        }
+    }
+
+    fn get_keymap(&self) -> &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>> {
+        self.keymap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::AxisEvent;
+
+    fn touch(finger: u8, x: i16, y: i16, z: i16) -> TouchpadEvent {
+        let axis = |axis, value| AxisEvent {
+            typ: AxisValType::Abs,
+            axis,
+            value,
+        };
+        TouchpadEvent {
+            finger,
+            axis: [axis(Axis::X, x), axis(Axis::Y, y), axis(Axis::Z, z)],
+        }
+    }
+
+    fn contact(id: u8, x: u16, y: u16, touching: bool) -> DigitizerContact {
+        DigitizerContact {
+            flags: if touching {
+                DigitizerContact::CONFIDENCE | DigitizerContact::TIP_SWITCH
+            } else {
+                DigitizerContact::CONFIDENCE
+            },
+            id,
+            x,
+            y,
+        }
+    }
+
+    #[test]
+    fn test_single_finger() {
+        let mut tracker = ContactTracker::new(TouchpadConfig::default());
+        assert_eq!(tracker.take_report(0), None);
+
+        tracker.update(&touch(0, 100, 200, 30));
+        let report = tracker.take_report(10).unwrap();
+        assert_eq!(report.contact_count, 1);
+        assert_eq!(report.scan_time, 10);
+        assert_eq!(report.contacts[0], contact(0, 100, 200, true));
+
+        // No update, no report
+        assert_eq!(tracker.take_report(20), None);
+
+        // Lift the finger, reported once with tip switch released
+        tracker.update(&touch(0, 110, 210, 0));
+        let report = tracker.take_report(30).unwrap();
+        assert_eq!(report.contact_count, 1);
+        assert_eq!(report.contacts[0], contact(0, 110, 210, false));
+        assert_eq!(tracker.take_report(40), None);
+    }
+
+    #[test]
+    fn test_multi_finger_frames() {
+        let mut tracker = ContactTracker::new(TouchpadConfig::default());
+        tracker.update(&touch(0, 100, 100, 10));
+        assert!(!tracker.is_updated(1));
+        tracker.update(&touch(1, 300, 300, 10));
+        assert!(tracker.is_updated(0));
+        let report = tracker.take_report(0).unwrap();
+        assert_eq!(report.contact_count, 2);
+        assert_eq!(report.contacts[0], contact(0, 100, 100, true));
+        assert_eq!(report.contacts[1], contact(1, 300, 300, true));
+
+        // Only finger 1 moves, the resting finger 0 is still reported
+        tracker.update(&touch(1, 310, 320, 10));
+        let report = tracker.take_report(1).unwrap();
+        assert_eq!(report.contact_count, 2);
+        assert_eq!(report.contacts[0], contact(0, 100, 100, true));
+        assert_eq!(report.contacts[1], contact(1, 310, 320, true));
+
+        // Finger 0 is lifted, finger 1 becomes the first contact in the next frame
+        tracker.update(&touch(0, 100, 100, 0));
+        let report = tracker.take_report(2).unwrap();
+        assert_eq!(report.contact_count, 2);
+        assert_eq!(report.contacts[0], contact(0, 100, 100, false));
+        tracker.update(&touch(1, 320, 330, 10));
+        let report = tracker.take_report(3).unwrap();
+        assert_eq!(report.contact_count, 1);
+        assert_eq!(report.contacts[0], contact(1, 320, 330, true));
+        assert_eq!(report.contacts[1], DigitizerContact::default());
+    }
+
+    #[test]
+    fn test_scale_and_invalid_finger() {
+        let mut tracker = ContactTracker::new(TouchpadConfig {
+            x_max: 1000,
+            y_max: 500,
+        });
+        tracker.update(&touch(DIGITIZER_MAX_CONTACTS as u8, 10, 10, 10));
+        assert_eq!(tracker.take_report(0), None);
+
+        // Lifting a finger which is never reported is ignored
+        tracker.update(&touch(2, 10, 10, 0));
+        assert_eq!(tracker.take_report(0), None);
+
+        tracker.update(&touch(2, 500, 600, 10));
+        let report = tracker.take_report(0).unwrap();
+        assert_eq!(report.contacts[0], contact(2, 2047, DIGITIZER_LOGICAL_MAX, true));
+    }
+
+    #[test]
+    fn test_mouse_motion() {
+        let mut tracker = ContactTracker::new(TouchpadConfig::default());
+        let mut mouse = MouseMotion::default();
+        let mut motion = |tracker: &mut ContactTracker, event| {
+            tracker.update(&event);
+            let report = tracker.take_report(0).unwrap();
+            mouse.update(&report).map(|r| (r.x, r.y))
+        };
+
+        // Touching down doesn't move the cursor
+        assert_eq!(motion(&mut tracker, touch(0, 100, 100, 10)), None);
+        assert_eq!(motion(&mut tracker, touch(0, 140, 80, 10)), Some((10, -5)));
+        // The remainder is kept for the next frame
+        assert_eq!(motion(&mut tracker, touch(0, 142, 80, 10)), None);
+        assert_eq!(motion(&mut tracker, touch(0, 146, 80, 10)), Some((1, 0)));
+
+        // The second finger doesn't move the cursor
+        tracker.update(&touch(1, 1000, 1000, 10));
+        assert_eq!(motion(&mut tracker, touch(0, 146, 84, 10)), Some((0, 1)));
+
+        // Finger 1 becomes the primary finger after finger 0 is lifted
+        assert_eq!(motion(&mut tracker, touch(0, 146, 84, 0)), None);
+        assert_eq!(motion(&mut tracker, touch(1, 1000, 1000, 10)), None);
+        assert_eq!(motion(&mut tracker, touch(1, 960, 1000, 10)), Some((-10, 0)));
+    }
+}
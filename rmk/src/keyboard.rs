@@ -7,7 +7,7 @@ use embassy_futures::yield_now;
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Timer, with_deadline};
 use heapless::Vec;
-use usbd_hid::descriptor::{MediaKeyboardReport, MouseReport, SystemControlReport};
+use usbd_hid::descriptor::{MediaKeyboardReport, SystemControlReport};
 #[cfg(feature = "controller")]
 use {
     crate::channel::{CONTROLLER_CHANNEL, ControllerPub, send_controller_event},
@@ -17,10 +17,12 @@ use {
 use crate::action::{Action, KeyAction};
 use crate::channel::{KEY_EVENT_CHANNEL, KEYBOARD_REPORT_CHANNEL};
 use crate::combo::Combo;
-use crate::descriptor::KeyboardReport;
-use crate::event::{KeyboardEvent, KeyboardEventPos};
+use crate::descriptor::{KeyboardReport, MouseReport, WHEEL_RESOLUTION};
+use crate::event::{KeyboardEvent, KeyboardEventPos, RotaryEncoderPos};
 use crate::fork::{ActiveFork, StateBits};
 use crate::hid::Report;
+#[cfg(feature = "gamepad")]
+use crate::hid::{hat_from_directions, update_gamepad_state};
 use crate::hid_state::{HidModifiers, HidMouseButtons};
 use crate::input_device::Runnable;
 use crate::input_device::rotary_encoder::Direction;
@@ -98,6 +100,19 @@ impl<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCOD
                 None => {
                     // No buffered tap-hold event, wait for new key
                     let event = KEY_EVENT_CHANNEL.receive().await;
+                    // Key presses only wake up the host when the USB host is suspended
+                    #[cfg(not(feature = "_no_usb"))]
+                    match crate::usb::power::on_key_event(&event) {
+                        crate::usb::power::KeyEventAction::Process => {}
+                        crate::usb::power::KeyEventAction::Drop => continue,
+                        crate::usb::power::KeyEventAction::ProcessAfterResume => {
+                            // Replay the wake key press after the host is resumed, so that it isn't lost
+                            if !crate::usb::power::wait_for_resume().await {
+                                warn!("Host is not resumed, the wake key press is dropped");
+                                continue;
+                            }
+                        }
+                    }
                     // Process the key event
                     self.process_inner(event).await
                 }
@@ -148,6 +163,9 @@ pub struct Keyboard<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usi
     /// Timer which records the timestamp of rotary encoder changes
     pub(crate) rotary_encoder_timer: [[Option<Instant>; 2]; NUM_ENCODER],
 
+    /// The active hold action of each rotary encoder and the direction which activated it
+    encoder_hold: [Option<(Direction, Action)>; NUM_ENCODER],
+
     /// Record the timestamp of last **simple key** press.
     /// It's used in tap-hold prior-idle-time check.
     last_press_time: Instant,
@@ -202,6 +220,10 @@ pub struct Keyboard<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usi
     mouse_repeat: u8,
     mouse_wheel_repeat: u8,
 
+    /// Pressed joystick hat directions, bit 0~3 are up, right, down and left
+    #[cfg(feature = "gamepad")]
+    joystick_hat: u8,
+
     /// Used for temporarily disabling combos
     combo_on: bool,
 
@@ -218,6 +240,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             keymap,
             timer: [[None; ROW]; COL],
             rotary_encoder_timer: [[None; 2]; NUM_ENCODER],
+            encoder_hold: [None; NUM_ENCODER],
             last_press_time: Instant::now(),
             osl_state: OneShotState::default(),
             osm_state: OneShotState::default(),
@@ -233,19 +256,15 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             registered_keys: [None; 6],
             held_modifiers: HidModifiers::default(),
             held_keycodes: [KeyCode::No; 6],
-            mouse_report: MouseReport {
-                buttons: 0,
-                x: 0,
-                y: 0,
-                wheel: 0,
-                pan: 0,
-            },
+            mouse_report: MouseReport::default(),
             media_report: MediaKeyboardReport { usage_id: 0 },
             system_control_report: SystemControlReport { usage_id: 0 },
             last_key_code: KeyCode::No,
             mouse_accel: 0,
             mouse_repeat: 0,
             mouse_wheel_repeat: 0,
+            #[cfg(feature = "gamepad")]
+            joystick_hat: 0,
             combo_on: true,
             #[cfg(feature = "controller")]
             controller_pub: unwrap!(CONTROLLER_CHANNEL.publisher()),
@@ -337,6 +356,9 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         #[cfg(feature = "matrix_tester")]
         self.keymap.borrow_mut().matrix_state.update(&event);
 
+        // Events are processed at the positions remapped by the selected layout options
+        let event = self.keymap.borrow().remap_event(event);
+
         // Matrix should process key pressed event first, record the timestamp of key changes
         if event.pressed {
             self.set_timer_value(event, Some(Instant::now()));
@@ -348,6 +370,13 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         // Process key
         let key_action = &self.keymap.borrow_mut().get_action_with_layer_cache(event);
 
+        // Tap-hold on rotary encoders in hold-until-idle mode doesn't need tap/hold decision
+        if let (KeyboardEventPos::RotaryEncoder(encoder_pos), KeyAction::TapHold(tap, hold)) = (event.pos, key_action)
+            && self.keymap.borrow().behavior.encoder.is_hold_until_idle(encoder_pos.id)
+        {
+            return self.process_encoder_tap_hold(encoder_pos, *tap, *hold, event).await;
+        }
+
         if self.combo_on {
             if let (Some(key_action), is_combo) = self.process_combo(key_action, event).await {
                 self.process_key_action(&key_action, event, is_combo).await
@@ -1054,6 +1083,49 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Tap-hold action on a rotary encoder.
+    ///
+    /// The hold action is activated at the first detent and is kept active until the encoder position is released,
+    /// the tap action is tapped at every detent.
+    /// Changing the direction with the same hold action keeps the hold action active.
+    async fn process_encoder_tap_hold(
+        &mut self,
+        encoder_pos: RotaryEncoderPos,
+        tap: Action,
+        hold: Action,
+        event: KeyboardEvent,
+    ) -> LoopState {
+        let Some(held) = self.encoder_hold.get(encoder_pos.id as usize).copied() else {
+            return LoopState::OK;
+        };
+        if event.pressed {
+            match held {
+                Some((_, held_action)) if held_action == hold => {}
+                _ => {
+                    if let Some((_, held_action)) = held {
+                        self.process_key_action_normal(
+                            held_action,
+                            KeyboardEvent {
+                                pressed: false,
+                                ..event
+                            },
+                        )
+                        .await;
+                    }
+                    self.process_key_action_normal(hold, event).await;
+                }
+            }
+            self.encoder_hold[encoder_pos.id as usize] = Some((encoder_pos.direction, hold));
+            self.process_key_action_tap(tap, event).await;
+        } else if let Some((direction, held_action)) = held
+            && direction == encoder_pos.direction
+        {
+            self.encoder_hold[encoder_pos.id as usize] = None;
+            self.process_key_action_normal(held_action, event).await;
+        }
+        LoopState::OK
+    }
+
     /// Tap action, send a key when the key is pressed, then release the key.
     async fn process_key_action_tap(&mut self, action: Action, mut event: KeyboardEvent) {
         debug!("TAP action: {:?}, {:?}", action, event);
@@ -1325,10 +1397,17 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             self.process_action_system_control(key, event).await;
         } else if key.is_mouse_key() {
             self.process_action_mouse(key, event).await;
+        } else if key.is_joystick() || key.is_joystick_hat() {
+            #[cfg(feature = "gamepad")]
+            self.process_action_joystick(key, event).await;
+            #[cfg(not(feature = "gamepad"))]
+            warn!("Joystick key {:?} requires the `gamepad` feature", key);
         } else if key.is_basic() {
             self.process_basic(key, event).await;
         } else if key.is_user() {
             self.process_user(key, event).await;
+        } else if key.is_output() {
+            self.process_output(key, event).await;
         } else if key.is_macro() {
             // Process macro
             self.process_action_macro(key, event).await;
@@ -1393,6 +1472,38 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Process joystick button and hat action.
+    ///
+    /// Axes of the gamepad report are updated by joystick processors, so the report is kept in the shared gamepad state.
+    #[cfg(feature = "gamepad")]
+    async fn process_action_joystick(&mut self, key: KeyCode, event: KeyboardEvent) {
+        let report = if key.is_joystick() {
+            let bit = 1u32 << (key as u16 - KeyCode::JoystickButton0 as u16);
+            update_gamepad_state(|state| {
+                if event.pressed {
+                    state.buttons |= bit;
+                } else {
+                    state.buttons &= !bit;
+                }
+            })
+        } else {
+            let bit = 1u8 << (key as u16 - KeyCode::JoystickHatUp as u16);
+            if event.pressed {
+                self.joystick_hat |= bit;
+            } else {
+                self.joystick_hat &= !bit;
+            }
+            let hat = hat_from_directions(
+                self.joystick_hat & 0b0001 != 0,
+                self.joystick_hat & 0b0010 != 0,
+                self.joystick_hat & 0b0100 != 0,
+                self.joystick_hat & 0b1000 != 0,
+            );
+            update_gamepad_state(|state| state.hat = hat)
+        };
+        self.send_report(Report::GamepadReport(report)).await;
+    }
+
     /// Process mouse key action with acceleration support.
     async fn process_action_mouse(&mut self, key: KeyCode, event: KeyboardEvent) {
         if key.is_mouse_key() {
@@ -1605,11 +1716,13 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     let mut released = false;
                     for _ in 0..len {
                         let queued_event = KEY_EVENT_CHANNEL.receive().await;
-                        if queued_event.pos != event.pos || !queued_event.pressed {
+                        // Queued events aren't remapped yet
+                        let queued_pos = self.keymap.borrow().remap_event(queued_event).pos;
+                        if queued_pos != event.pos || !queued_event.pressed {
                             KEY_EVENT_CHANNEL.send(queued_event).await;
                         }
                         // If there's a release event in the channel
-                        if queued_event.pos == event.pos && !queued_event.pressed {
+                        if queued_pos == event.pos && !queued_event.pressed {
                             released = true;
                         }
                     }
@@ -1672,11 +1785,39 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                 } else if id == NUM_BLE_PROFILE as u8 + 3 {
                     // User11:
                     BLE_PROFILE_CHANNEL.send(BleProfileAction::ToggleConnection).await;
+                } else if id == NUM_BLE_PROFILE as u8 + 5 {
+                    // User13: Switch to the next latency mode
+                    BLE_PROFILE_CHANNEL.send(BleProfileAction::SetLatencyMode(None)).await;
                 }
             }
         }
     }
 
+    async fn process_output(&mut self, key: KeyCode, event: KeyboardEvent) {
+        debug!("Processing output key: {:?}, event: {:?}", key, event);
+        // Output keys are processed when released
+        if event.pressed {
+            return;
+        }
+        #[cfg(feature = "_ble")]
+        {
+            use crate::ble::trouble::profile::BleProfileAction;
+            use crate::channel::BLE_PROFILE_CHANNEL;
+            use crate::state::ConnectionType;
+
+            let connection_type = match key {
+                KeyCode::OutputUsb => ConnectionType::Usb,
+                KeyCode::OutputBluetooth => ConnectionType::Ble,
+                _ => ConnectionType::Auto,
+            };
+            BLE_PROFILE_CHANNEL
+                .send(BleProfileAction::SwitchConnection(connection_type.into()))
+                .await;
+        }
+        #[cfg(not(feature = "_ble"))]
+        warn!("Output key {:?} requires BLE", key);
+    }
+
     fn process_boot(&mut self, key: KeyCode, event: KeyboardEvent) {
         // When releasing the key, process the boot action
         if !event.pressed {
@@ -1989,7 +2130,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
     }
 
     /// Calculate mouse movement distance based on current repeat count and acceleration settings
-    fn calculate_mouse_move_unit(&self) -> i8 {
+    fn calculate_mouse_move_unit(&self) -> i16 {
         let config = &self.keymap.borrow().behavior.mouse_key;
 
         let unit = if self.mouse_accel & (1 << 2) != 0 {
@@ -2028,11 +2169,13 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             unit
         };
 
-        final_unit.min(i8::MAX as u16) as i8
+        final_unit.min(i16::MAX as u16) as i16
     }
 
     /// Calculate mouse wheel movement distance based on current repeat count and acceleration settings
-    fn calculate_mouse_wheel_unit(&self) -> i8 {
+    ///
+    /// The returned distance is in high-resolution units, [`WHEEL_RESOLUTION`] units per notch
+    fn calculate_mouse_wheel_unit(&self) -> i16 {
         let config = &self.keymap.borrow().behavior.mouse_key;
 
         let unit = if self.mouse_accel & (1 << 2) != 0 {
@@ -2071,26 +2214,26 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
             unit
         };
 
-        final_unit.min(i8::MAX as u16) as i8
+        final_unit.min(i8::MAX as u16) as i16 * WHEEL_RESOLUTION
     }
 
     /// Apply diagonal movement compensation (approximation of 1/sqrt(2))
-    fn apply_diagonal_compensation(&self, mut x: i8, mut y: i8) -> (i8, i8) {
+    fn apply_diagonal_compensation(&self, mut x: i16, mut y: i16) -> (i16, i16) {
         if x != 0 && y != 0 {
             // Apply 1/sqrt(2) approximation using 181/256 (0.70703125)
-            let x_compensated = (x as i16 * 181 + 128) / 256;
-            let y_compensated = (y as i16 * 181 + 128) / 256;
+            let x_compensated = (x as i32 * 181 + 128) / 256;
+            let y_compensated = (y as i32 * 181 + 128) / 256;
 
             x = if x_compensated == 0 && x != 0 {
                 if x > 0 { 1 } else { -1 }
             } else {
-                x_compensated as i8
+                x_compensated as i16
             };
 
             y = if y_compensated == 0 && y != 0 {
                 if y > 0 { 1 } else { -1 }
             } else {
-                y_compensated as i8
+                y_compensated as i16
             };
         }
         (x, y)
@@ -2241,6 +2384,35 @@ mod test {
     }
 
     rusty_fork_test! {
+        #[cfg(feature = "_ble")]
+        #[test]
+        fn test_output_keys() {
+            use crate::ble::trouble::profile::BleProfileAction;
+            use crate::channel::BLE_PROFILE_CHANNEL;
+            use crate::state::ConnectionType;
+
+            let main = async {
+                let mut keyboard = create_test_keyboard();
+                for (key, connection_type) in [
+                    (KeyCode::OutputUsb, ConnectionType::Usb),
+                    (KeyCode::OutputBluetooth, ConnectionType::Ble),
+                    (KeyCode::OutputAuto, ConnectionType::Auto),
+                ] {
+                    assert!(key.is_output());
+                    // Output keys are processed when released
+                    keyboard.process_output(key, event(0, 0, true)).await;
+                    assert!(BLE_PROFILE_CHANNEL.try_receive().is_err());
+                    keyboard.process_output(key, event(0, 0, false)).await;
+                    let expected: u8 = connection_type.into();
+                    assert!(matches!(
+                        BLE_PROFILE_CHANNEL.try_receive(),
+                        Ok(BleProfileAction::SwitchConnection(t)) if t == expected
+                    ));
+                }
+            };
+            block_on(main);
+        }
+
         #[test]
         fn test_register_key() {
             let main = async {
@@ -5,6 +5,9 @@ use crate::keycode::KeyCode;
 use crate::keymap::fill_vec;
 use crate::via::keycode_convert::{from_ascii, to_ascii};
 
+/// Number of macros, which can be triggered by `KeyCode::Macro0` ~ `KeyCode::Macro31`
+pub(crate) const NUM_MACRO: u8 = (KeyCode::Macro31 as u16 - KeyCode::Macro0 as u16 + 1) as u8;
+
 /// encoded with the two bytes, content at the third byte
 /// 0b 0000 0001 1000-1010 (VIAL_MACRO_EXT) are not supported
 ///
@@ -795,6 +795,11 @@ pub enum KeyCode {
     ProgrammableButton30 = 0x43D,
     ProgrammableButton31 = 0x43E,
     ProgrammableButton32 = 0x43F,
+    // Joystick hat keycodes, use 0x440 ~ 0x443
+    JoystickHatUp = 0x440,
+    JoystickHatRight = 0x441,
+    JoystickHatDown = 0x442,
+    JoystickHatLeft = 0x443,
     // Audio keycodes, use 0x460 ~ 0x47F
     AudioOn = 0x460,
     AudioOff = 0x461,
@@ -1114,6 +1119,11 @@ impl KeyCode {
         KeyCode::JoystickButton0 <= self && self <= KeyCode::JoystickButton31
     }
 
+    /// Returns `true` if the keycode is a joystick hat keycode
+    pub(crate) fn is_joystick_hat(self) -> bool {
+        KeyCode::JoystickHatUp <= self && self <= KeyCode::JoystickHatLeft
+    }
+
     /// Returns `true` if the keycode is a programmable button keycode
     pub(crate) fn is_programmable_button(self) -> bool {
         KeyCode::ProgrammableButton1 <= self && self <= KeyCode::ProgrammableButton32
@@ -1160,6 +1170,11 @@ impl KeyCode {
         KeyCode::Bootloader <= self && self <= KeyCode::Reboot
     }
 
+    /// Returns `true` if the keycode is an output keycode, which selects the connection type
+    pub(crate) fn is_output(self) -> bool {
+        KeyCode::OutputAuto <= self && self <= KeyCode::OutputBluetooth
+    }
+
     /// Returns `true` if the keycode is a kb keycode
     pub(crate) fn is_kb(self) -> bool {
         KeyCode::Kb0 <= self && self <= KeyCode::Kb31
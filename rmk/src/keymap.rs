@@ -27,10 +27,16 @@ pub struct KeyMap<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize
     pub(crate) layers: &'a mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
     /// Rotary encoders, each rotary encoder is represented as (Clockwise, CounterClockwise)
     pub(crate) encoders: Option<&'a mut [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+    /// Default layers, which are restored by the VIA keymap reset and layout options
+    default_layers: Option<&'static [[[KeyAction; COL]; ROW]; NUM_LAYER]>,
+    /// Default rotary encoders
+    default_encoders: Option<&'static [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
     /// Current state of each layer
     layer_state: [bool; NUM_LAYER],
     /// Default layer number, max: 32
     default_layer: u8,
+    /// VIA layout options value, which selects the choices of layout options
+    layout_option: u32,
     /// Layer cache
     layer_cache: [[u8; COL]; ROW],
     /// Rotary encoder cache
@@ -78,8 +84,11 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         KeyMap {
             layers: action_map,
             encoders: encoder_map,
+            default_layers: None,
+            default_encoders: None,
             layer_state: [false; NUM_LAYER],
             default_layer: 0,
+            layout_option: 0,
             layer_cache: [[0; COL]; ROW],
             encoder_layer_cache: [[0; 2]; NUM_ENCODER],
             behavior,
@@ -101,6 +110,7 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         fill_vec(&mut behavior.fork.forks); // Is this needed? (has no Vial support)
         fill_vec(&mut behavior.morse.morses);
 
+        let mut layout_option = 0;
         if let Some(storage) = storage {
             if {
                 Ok(())
@@ -120,6 +130,12 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
                     .and(storage.read_forks(&mut behavior.fork.forks).await)
                     // Read morse cache
                     .and(storage.read_morses(&mut behavior.morse.morses).await)
+                    // Read layout options
+                    .and(storage.read_layout_option(&mut layout_option).await)
+                    // Read lighting settings and battery calibration
+                    .and(storage.read_lighting_and_battery().await)
+                    // Read vial lock state
+                    .and(storage.read_vial_lock_state().await)
             }
             .is_err()
             {
@@ -135,8 +151,11 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         KeyMap {
             layers: action_map,
             encoders: encoder_map,
+            default_layers: None,
+            default_encoders: None,
             layer_state: [false; NUM_LAYER],
             default_layer: 0,
+            layout_option,
             layer_cache: [[0; COL]; ROW],
             encoder_layer_cache: [[0; 2]; NUM_ENCODER],
             behavior,
@@ -147,10 +166,27 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Set the default keymap, which is restored by the VIA keymap reset and layout options.
+    ///
+    /// Use `static` items, so that the default keymap stays in flash instead of taking a second copy of the keymap in RAM.
+    pub fn set_default_keymap(
+        &mut self,
+        layers: &'static [[[KeyAction; COL]; ROW]; NUM_LAYER],
+        encoders: Option<&'static [[EncoderAction; NUM_ENCODER]; NUM_LAYER]>,
+    ) {
+        self.default_layers = Some(layers);
+        self.default_encoders = encoders;
+    }
+
     pub(crate) fn get_keymap_config(&self) -> (usize, usize, usize) {
         (ROW, COL, NUM_LAYER)
     }
 
+    /// Whether the layer is activated, the default layer is not included
+    pub(crate) fn is_layer_activated(&self, layer_num: usize) -> bool {
+        self.layer_state[layer_num]
+    }
+
     /// Get the default layer number
     pub(crate) fn get_default_layer(&self) -> u8 {
         self.default_layer
@@ -161,6 +197,46 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         self.default_layer = layer_num;
     }
 
+    /// Get the VIA layout options value
+    pub(crate) fn get_layout_option(&self) -> u32 {
+        self.layout_option
+    }
+
+    /// Set the VIA layout options value.
+    ///
+    /// The keys of the newly selected or deselected layout option choices are reset to the default keys,
+    /// so deselecting a choice restores the keys which were replaced by it.
+    pub(crate) fn set_layout_option(&mut self, layout_option: u32) {
+        let layout_options = self.behavior.layout_options;
+        let previous = self.layout_option;
+        self.layout_option = layout_option;
+        for option in layout_options.changed(previous, layout_option) {
+            for &(layer, row, col, _) in option.keys {
+                if (layer as usize) < NUM_LAYER && (row as usize) < ROW && (col as usize) < COL {
+                    let pos = KeyboardEventPos::key_pos(col, row);
+                    match self.get_default_action_at(pos, layer as usize) {
+                        Some(action) => self.layers[layer as usize][row as usize][col as usize] = action,
+                        None => warn!(
+                            "Default keymap isn't set, key ({}, {}) of layer {} isn't restored",
+                            row, col, layer
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Map the matrix position of the event according to the selected layout options
+    pub(crate) fn remap_event(&self, mut event: KeyboardEvent) -> KeyboardEvent {
+        if let KeyboardEventPos::Key(key_pos) = &mut event.pos {
+            (key_pos.row, key_pos.col) =
+                self.behavior
+                    .layout_options
+                    .remap(self.layout_option, key_pos.row, key_pos.col);
+        }
+        event
+    }
+
     pub(crate) fn get_next_macro_operation(&self, macro_start_idx: usize, offset: usize) -> (MacroOperation, usize) {
         MacroOperation::get_next_macro_operation(
             &self.behavior.keyboard_macros.macro_sequences,
@@ -223,6 +299,41 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
 
+    /// Whether the default keymap is set by [`KeyMap::set_default_keymap`]
+    pub(crate) fn has_default_keymap(&self) -> bool {
+        self.default_layers.is_some()
+    }
+
+    /// Fetch the default action, with the default keys of the selected layout options.
+    ///
+    /// Returns `None` if the default keymap isn't set.
+    pub(crate) fn get_default_action_at(&self, pos: KeyboardEventPos, layer_num: usize) -> Option<KeyAction> {
+        match pos {
+            KeyboardEventPos::Key(key_pos) => self
+                .behavior
+                .layout_options
+                .selected(self.layout_option)
+                .flat_map(|o| o.keys.iter())
+                .find(|(layer, row, col, _)| *layer as usize == layer_num && *row == key_pos.row && *col == key_pos.col)
+                .map(|(_, _, _, action)| *action)
+                .or_else(|| {
+                    self.default_layers
+                        .map(|layers| layers[layer_num][key_pos.row as usize][key_pos.col as usize])
+                }),
+            KeyboardEventPos::RotaryEncoder(encoder_pos) => {
+                self.default_encoders
+                    .map(|encoders| match encoders[layer_num].get(encoder_pos.id as usize) {
+                        Some(encoder_action) => match encoder_pos.direction {
+                            Direction::Clockwise => encoder_action.clockwise(),
+                            Direction::CounterClockwise => encoder_action.counter_clockwise(),
+                            Direction::None => KeyAction::No,
+                        },
+                        None => KeyAction::No,
+                    })
+            }
+        }
+    }
+
     /// Fetch the action in keymap, with layer cache
     pub(crate) fn get_action_with_layer_cache(&mut self, event: KeyboardEvent) -> KeyAction {
         if !event.pressed {
@@ -408,14 +519,101 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
 
 #[cfg(test)]
 mod test {
-    use super::{_reorder_combos, Combo};
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
+
+    use super::{_reorder_combos, Combo, KeyMap};
     use crate::action::{Action, KeyAction};
+    use crate::config::{BehaviorConfig, LayoutOption, LayoutOptionsConfig};
+    use crate::event::KeyboardEvent;
     use crate::fork::{Fork, StateBits};
     use crate::hid_state::HidModifiers;
     use crate::keycode::KeyCode;
     use crate::keymap::fill_vec;
     use crate::{COMBO_MAX_NUM, FORK_MAX_NUM, k};
 
+    // A toggle option remapping (0,1) to (0,0), and a select option with 3 choices changing the default key at (0,1)
+    static LAYOUT_OPTIONS: [LayoutOption; 2] = [
+        LayoutOption {
+            option: 0,
+            choice: 1,
+            remaps: &[((0, 1), (0, 0))],
+            keys: &[],
+        },
+        LayoutOption {
+            option: 1,
+            choice: 2,
+            remaps: &[],
+            keys: &[(0, 0, 1, k!(C))],
+        },
+    ];
+
+    fn layout_options_config() -> LayoutOptionsConfig {
+        LayoutOptionsConfig {
+            option_bits: &[1, 2],
+            options: &LAYOUT_OPTIONS,
+        }
+    }
+
+    #[test]
+    fn test_layout_option_choice() {
+        let config = layout_options_config();
+        assert_eq!(config.choice(0b110, 0), 1);
+        assert_eq!(config.choice(0b110, 1), 2);
+        assert_eq!(config.choice(0b001, 0), 0);
+        assert_eq!(config.choice(0b001, 1), 1);
+        // Undefined option
+        assert_eq!(config.choice(0b111, 2), 0);
+
+        assert_eq!(config.selected(0b100).count(), 1);
+        assert_eq!(config.changed(0b100, 0b110).count(), 1);
+        assert_eq!(config.changed(0b110, 0b010).count(), 1);
+        assert_eq!(config.changed(0b010, 0b010).count(), 0);
+        assert_eq!(config.remap(0b100, 0, 1), (0, 0));
+        assert_eq!(config.remap(0b000, 0, 1), (0, 1));
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_set_layout_option() {
+            let behavior_config = Box::leak(Box::new(BehaviorConfig {
+                layout_options: layout_options_config(),
+                ..Default::default()
+            }));
+            let layers = Box::leak(Box::new([[[k!(A), k!(B)]]]));
+            let mut keymap: KeyMap<'_, 1, 2, 1> = block_on(KeyMap::new(layers, None, behavior_config));
+            keymap.set_default_keymap(Box::leak(Box::new(*keymap.layers)), None);
+            fn press(keymap: &mut KeyMap<'_, 1, 2, 1>) -> KeyAction {
+                let action = keymap.get_action_with_layer_cache(keymap.remap_event(KeyboardEvent::key(0, 1, true)));
+                keymap.get_action_with_layer_cache(keymap.remap_event(KeyboardEvent::key(0, 1, false)));
+                action
+            }
+
+            assert_eq!(press(&mut keymap), k!(B));
+
+            // The toggle option remaps (0,1) to (0,0)
+            keymap.set_layout_option(0b100);
+            assert_eq!(keymap.get_layout_option(), 0b100);
+            assert_eq!(press(&mut keymap), k!(A));
+
+            // Selecting the choice 2 of the second option writes its default key
+            keymap.set_layout_option(0b010);
+            assert_eq!(press(&mut keymap), k!(C));
+            let pos = KeyboardEvent::key(0, 1, true).pos;
+            assert_eq!(keymap.get_action_at(pos, 0), k!(C));
+
+            // Setting the same choice again doesn't overwrite the keymap
+            keymap.set_action_at(pos, 0, k!(D));
+            keymap.set_layout_option(0b010);
+            assert_eq!(press(&mut keymap), k!(D));
+
+            // Deselecting the choice restores the default key
+            keymap.set_layout_option(0b001);
+            assert_eq!(press(&mut keymap), k!(B));
+            assert_eq!(keymap.get_action_at(pos, 0), k!(B));
+        }
+    }
+
     #[test]
     fn test_fill_vec() {
         let mut combos: heapless::Vec<_, COMBO_MAX_NUM> = heapless::Vec::from_slice(&[
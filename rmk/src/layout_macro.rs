@@ -49,7 +49,8 @@ macro_rules! lm {
     };
 }
 
-/// Create a layer activate action or tap key(tap/hold)
+/// Create a layer activate action or tap key(tap/hold).
+/// The tapped key can be combined with modifiers, for example `lt!(1, A, modifier_combination)`
 #[macro_export]
 macro_rules! lt {
     ($x: literal, $k: ident) => {
@@ -58,6 +59,12 @@ macro_rules! lt {
             $crate::action::Action::LayerOn($x),
         )
     };
+    ($x: literal, $k: ident, $m: expr) => {
+        $crate::action::KeyAction::TapHold(
+            $crate::action::Action::KeyWithModifier($crate::keycode::KeyCode::$k, $m),
+            $crate::action::Action::LayerOn($x),
+        )
+    };
 }
 
 /// Create a modifier-tap-hold action
@@ -109,6 +116,30 @@ macro_rules! osm {
     };
 }
 
+/// Create an oneshot key in keymap
+#[macro_export]
+macro_rules! osk {
+    ($k: ident) => {
+        $crate::action::KeyAction::Single($crate::action::Action::OneShotKey($crate::keycode::KeyCode::$k))
+    };
+}
+
+/// Create a modifier key in keymap, which holds the modifier combination while pressed
+#[macro_export]
+macro_rules! modifier {
+    ($m: expr) => {
+        $crate::action::KeyAction::Single($crate::action::Action::Modifier($m))
+    };
+}
+
+/// Create a tap key, which sends press and release of the key immediately when it's pressed
+#[macro_export]
+macro_rules! tap {
+    ($k: ident) => {
+        $crate::action::KeyAction::Tap($crate::action::Action::Key($crate::keycode::KeyCode::$k))
+    };
+}
+
 /// Create a layer toggle action
 #[macro_export]
 macro_rules! tg {
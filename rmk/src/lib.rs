@@ -30,7 +30,13 @@ use bt_hci::{
 use config::{RmkConfig, VialConfig};
 #[cfg(feature = "controller")]
 use controller::{PollingController, wpm::WpmController};
+#[cfg(all(feature = "digitizer", not(feature = "_ble")))]
+use descriptor::DigitizerReport;
+#[cfg(all(feature = "gamepad", not(feature = "_ble")))]
+use descriptor::GamepadReport;
 use descriptor::ViaReport;
+#[cfg(feature = "controller")]
+use embassy_futures::select::select3;
 use embassy_futures::select::{Either4, select4};
 #[cfg(not(any(cortex_m)))]
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as RawMutex;
@@ -73,7 +79,8 @@ use crate::state::ConnectionState;
 pub mod action;
 #[cfg(feature = "_ble")]
 pub mod ble;
-mod boot;
+pub mod boot;
+pub mod bootmagic;
 pub mod channel;
 pub mod combo;
 pub mod config;
@@ -81,9 +88,12 @@ pub mod config;
 pub mod controller;
 pub mod debounce;
 pub mod descriptor;
+#[cfg(feature = "dfu")]
+pub mod dfu;
 pub mod direct_pin;
 pub mod driver;
 pub mod event;
+pub mod firmware;
 pub mod fork;
 pub mod hid;
 pub mod hid_state;
@@ -206,6 +216,15 @@ pub async fn run_rmk<
     #[cfg(feature = "storage")] storage: &mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     rmk_config: RmkConfig<'static>,
 ) -> ! {
+    #[cfg(not(feature = "_no_usb"))]
+    crate::usb::power::set_wake_keys(rmk_config.usb_power_config.wake_keys);
+
+    // Check the keys held at power-up before the storage is used
+    #[cfg(feature = "storage")]
+    crate::bootmagic::run_bootmagic_with_storage(storage, false).await;
+    #[cfg(not(feature = "storage"))]
+    crate::bootmagic::run_bootmagic().await;
+
     // Dispatch the keyboard runner
     #[cfg(feature = "_ble")]
     crate::ble::trouble::run_ble(
@@ -223,10 +242,19 @@ pub async fn run_rmk<
     // USB keyboard
     #[cfg(all(not(feature = "_no_usb"), not(feature = "_ble")))]
     {
-        let mut usb_builder: embassy_usb::Builder<'_, D> = new_usb_builder(usb_driver, rmk_config.usb_config);
+        let mut usb_builder: embassy_usb::Builder<'_, D> = new_usb_builder(
+            usb_driver,
+            rmk_config.usb_config,
+            #[cfg(feature = "webusb")]
+            rmk_config.webusb_config,
+        );
         let keyboard_reader_writer = add_usb_reader_writer!(&mut usb_builder, KeyboardReport, 1, 8);
-        let mut other_writer = add_usb_writer!(&mut usb_builder, CompositeReport, 9);
+        let mut other_writer = add_usb_writer!(&mut usb_builder, CompositeReport, 10);
         let mut vial_reader_writer = add_usb_reader_writer!(&mut usb_builder, ViaReport, 32, 32);
+        #[cfg(feature = "digitizer")]
+        let mut digitizer_writer = add_usb_writer!(&mut usb_builder, DigitizerReport, 35);
+        #[cfg(feature = "gamepad")]
+        let mut gamepad_writer = add_usb_writer!(&mut usb_builder, GamepadReport, 17);
         let (mut keyboard_reader, mut keyboard_writer) = keyboard_reader_writer.split();
 
         #[cfg(feature = "usb_log")]
@@ -236,39 +264,36 @@ pub async fn run_rmk<
         };
         #[cfg(not(feature = "usb_log"))]
         let logger_fut = async {};
+        #[cfg(feature = "usb_console")]
+        let console_fut = crate::usb::console::run_console(crate::usb::add_usb_console!(&mut usb_builder), keymap);
+        #[cfg(not(feature = "usb_console"))]
+        let console_fut = async {};
         let mut usb_device = usb_builder.build();
 
         // Run all tasks, if one of them fails, wait 1 second and then restart
-        embassy_futures::join::join(logger_fut, async {
+        embassy_futures::join::join3(logger_fut, console_fut, async {
             loop {
                 let usb_task = async {
-                    loop {
-                        use embassy_futures::select::{Either, select};
-
-                        use crate::usb::USB_REMOTE_WAKEUP;
-
-                        // Run
-                        usb_device.run_until_suspend().await;
-                        // Suspended, wait resume or remote wakeup
-                        match select(usb_device.wait_resume(), USB_REMOTE_WAKEUP.wait()).await {
-                            Either::First(_) => continue,
-                            Either::Second(_) => {
-                                info!("USB wakeup remote");
-                            }
-                        }
-                    }
+                    crate::usb::power::run_usb_device(&mut usb_device).await;
                 };
 
-                run_keyboard(
+                crate::usb::power::run_usb_keyboard(run_keyboard(
                     keymap,
                     #[cfg(feature = "storage")]
                     storage,
                     usb_task,
                     UsbLedReader::new(&mut keyboard_reader),
                     UsbVialReaderWriter::new(&mut vial_reader_writer),
-                    UsbKeyboardWriter::new(&mut keyboard_writer, &mut other_writer),
+                    UsbKeyboardWriter::new(
+                        &mut keyboard_writer,
+                        &mut other_writer,
+                        #[cfg(feature = "digitizer")]
+                        &mut digitizer_writer,
+                        #[cfg(feature = "gamepad")]
+                        &mut gamepad_writer,
+                    ),
                     rmk_config.vial_config,
-                )
+                ))
                 .await;
             }
         })
@@ -345,7 +370,7 @@ pub(crate) async fn run_keyboard<
         #[cfg(not(feature = "storage"))]
         via_fut,
         #[cfg(feature = "controller")]
-        select(wpm_controller.polling_loop(), led_fut),
+        select3(wpm_controller.polling_loop(), led_fut, via::run_device_indication()),
         #[cfg(not(feature = "controller"))]
         led_fut,
         writer_fut,
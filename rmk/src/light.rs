@@ -1,12 +1,52 @@
+use core::cell::Cell;
 use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
 
 use bitfield_struct::bitfield;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_usb::class::hid::HidReader;
 use embassy_usb::driver::Driver;
 use serde::{Deserialize, Serialize};
 
+use crate::RawMutex;
 use crate::hid::{HidError, HidReaderTrait};
 
+static LIGHTING_CONFIG: Mutex<RawMutex, Cell<LightingConfig>> = Mutex::new(Cell::new(LightingConfig::new()));
+
+/// Lighting settings, which can be changed in VIA
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LightingConfig {
+    /// Whether the lock indicator LEDs are enabled
+    pub indicators: bool,
+    /// The battery LED blinks when the battery level is below this percent
+    pub low_battery: u8,
+}
+
+impl LightingConfig {
+    pub const fn new() -> Self {
+        Self {
+            indicators: true,
+            low_battery: 10,
+        }
+    }
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the current lighting settings
+pub fn lighting_config() -> LightingConfig {
+    LIGHTING_CONFIG.lock(|c| c.get())
+}
+
+/// Update the lighting settings, they're applied by the LED controllers
+pub(crate) fn set_lighting_config(config: LightingConfig) {
+    LIGHTING_CONFIG.lock(|c| c.set(config));
+}
+
 #[bitfield(u8, defmt = cfg(feature = "defmt"))]
 #[derive(Eq, PartialEq, Serialize, Deserialize)]
 
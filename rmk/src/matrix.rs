@@ -185,6 +185,15 @@ impl<
     async fn read_event(&mut self) -> crate::event::Event {
         loop {
             let (out_idx_start, in_idx_start) = self.scan_pos;
+            // Scan slowly when the USB host is suspended, the async matrix waits for a key press
+            #[cfg(not(feature = "_no_usb"))]
+            if self.scan_pos == (0, 0) && crate::usb::power::is_host_suspended() {
+                #[cfg(feature = "async_matrix")]
+                {
+                    self.scan_start = None;
+                }
+                Timer::after(crate::usb::power::SUSPENDED_SCAN_INTERVAL).await;
+            }
             #[cfg(feature = "async_matrix")]
             self.wait_for_key().await;
 
@@ -0,0 +1,410 @@
+//! Split keyboards whose peripherals share a bus, such as a multi-drop UART(RS-485)
+//!
+//! Only [`SerialBus`] is provided, other buses can be used by implementing [`SplitBusController`] and
+//! [`SplitBusTarget`].
+//!
+//! The central polls the addressed peripherals one by one. The request carries a split message from the central,
+//! such as the connection state, or [`SplitMessage::BusPoll`] if there's nothing to send. The peripheral responds
+//! with one buffered event, or [`SplitMessage::BusPoll`] if there's no event.
+//!
+//! A peripheral which doesn't respond for several polls is treated as unplugged, it's polled less frequently
+//! until it responds again. The presence is published as [`ControllerEvent::SplitPeripheral`].
+use core::sync::atomic::Ordering;
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "controller")]
+use {
+    crate::channel::{CONTROLLER_CHANNEL, send_controller_event},
+    crate::event::ControllerEvent,
+};
+
+use super::{SPLIT_MESSAGE_MAX_SIZE, SplitMessage};
+use crate::CONNECTION_STATE;
+use crate::channel::{EVENT_CHANNEL, KEY_EVENT_CHANNEL, SPLIT_MESSAGE_PUBLISHER};
+use crate::event::{KeyboardEvent, KeyboardEventPos};
+use crate::state::ConnectionState;
+
+mod serial;
+
+pub use serial::{SerialBus, SerialBusTarget};
+
+/// Max size of a frame on the split bus.
+///
+/// The response is prefixed by the length of the message.
+pub const SPLIT_BUS_FRAME_SIZE: usize = SPLIT_MESSAGE_MAX_SIZE + 1;
+
+/// Poll interval of a present peripheral
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// Poll interval of an absent peripheral, which is used to detect the hot-plugged peripheral
+const ABSENT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A peripheral is treated as absent after missing this number of polls
+const MAX_MISSED_POLLS: u8 = 3;
+/// Interval of syncing the connection state to peripherals
+const SYNC_INTERVAL: Duration = Duration::from_millis(3000);
+
+/// Error of the split bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SplitBusError {
+    /// The addressed peripheral doesn't respond
+    NoResponse,
+    /// Error of the bus
+    Bus,
+}
+
+/// Central side of the split bus
+pub trait SplitBusController {
+    /// Send the request to the peripheral at `addr` and receive its response, returns the length of the response
+    async fn transfer(&mut self, addr: u8, request: &[u8], response: &mut [u8]) -> Result<usize, SplitBusError>;
+}
+
+/// Peripheral side of the split bus
+pub trait SplitBusTarget {
+    /// Wait for a request addressed to this peripheral, returns the length of the request
+    async fn receive(&mut self, request: &mut [u8]) -> Result<usize, SplitBusError>;
+
+    /// Respond to the last received request
+    async fn respond(&mut self, response: &[u8]) -> Result<(), SplitBusError>;
+}
+
+/// A peripheral on the split bus
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BusPeripheral {
+    /// Address of the peripheral on the bus
+    pub addr: u8,
+    /// Row number of the peripheral's matrix
+    pub rows: u8,
+    /// Col number of the peripheral's matrix
+    pub cols: u8,
+    /// Row offset of the peripheral's matrix in the whole matrix
+    pub row_offset: u8,
+    /// Col offset of the peripheral's matrix in the whole matrix
+    pub col_offset: u8,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PeripheralState {
+    present: bool,
+    missed_polls: u8,
+    next_poll: Option<Instant>,
+}
+
+/// Run the central's manager of all peripherals on the split bus.
+///
+/// The index of the peripheral in `peripherals` is used as the peripheral id.
+pub async fn run_bus_peripheral_manager<B: SplitBusController, const N: usize>(
+    mut bus: B,
+    peripherals: [BusPeripheral; N],
+) {
+    let mut states = [PeripheralState::default(); N];
+    let mut subscriber = SPLIT_MESSAGE_PUBLISHER
+        .subscriber()
+        .expect("Failed to create split message subscriber: MaximumSubscribersReached");
+    #[cfg(feature = "controller")]
+    let mut controller_pub = unwrap!(CONTROLLER_CHANNEL.publisher());
+    // Sync the connection state on start
+    let mut next_sync = Instant::now();
+
+    info!("Running split bus manager with {} peripherals", N);
+    loop {
+        let next_poll = states
+            .iter()
+            .filter_map(|s| s.next_poll)
+            .min()
+            .unwrap_or(Instant::now());
+        // Messages from central are sent to all peripherals
+        let broadcast = match select(subscriber.next_message_pure(), Timer::at(next_poll.min(next_sync))).await {
+            Either::First(message) => Some(message),
+            Either::Second(_) if Instant::now() >= next_sync => {
+                next_sync = Instant::now() + SYNC_INTERVAL;
+                Some(SplitMessage::ConnectionState(CONNECTION_STATE.load(Ordering::Acquire)))
+            }
+            Either::Second(_) => None,
+        };
+
+        for (id, (peripheral, state)) in peripherals.iter().zip(states.iter_mut()).enumerate() {
+            if broadcast.is_none() && state.next_poll.is_some_and(|t| t > Instant::now()) {
+                continue;
+            }
+            let request = match broadcast {
+                // DFU requests are sent to the target peripheral only
+                #[cfg(feature = "dfu")]
+                Some(SplitMessage::Dfu(target, _)) if target as usize != id => SplitMessage::BusPoll,
+                Some(message) => message,
+                None => SplitMessage::BusPoll,
+            };
+            let response = poll_peripheral(&mut bus, peripheral.addr, &request).await;
+
+            let present = match response {
+                Ok(message) => {
+                    state.missed_polls = 0;
+                    process_peripheral_message(peripheral, message).await;
+                    true
+                }
+                Err(SplitBusError::NoResponse) => {
+                    state.missed_polls = state.missed_polls.saturating_add(1);
+                    state.present && state.missed_polls < MAX_MISSED_POLLS
+                }
+                Err(e) => {
+                    // The peripheral is still there when the bus has an error
+                    error!("Split bus error of peripheral {}: {:?}", id, e);
+                    state.present
+                }
+            };
+            if present != state.present {
+                info!("Split peripheral {} present: {}", id, present);
+                state.present = present;
+                #[cfg(feature = "controller")]
+                send_controller_event(&mut controller_pub, ControllerEvent::SplitPeripheral(id, present));
+            }
+            let interval = if present { POLL_INTERVAL } else { ABSENT_POLL_INTERVAL };
+            state.next_poll = Some(Instant::now() + interval);
+        }
+    }
+}
+
+/// Send a split message to the peripheral, and decode the response
+async fn poll_peripheral<B: SplitBusController>(
+    bus: &mut B,
+    addr: u8,
+    request: &SplitMessage,
+) -> Result<SplitMessage, SplitBusError> {
+    let mut request_buf = [0u8; SPLIT_BUS_FRAME_SIZE];
+    let request = postcard::to_slice(request, &mut request_buf).map_err(|e| {
+        error!("Postcard serialize split message error: {}", e);
+        SplitBusError::Bus
+    })?;
+    let mut response = [0u8; SPLIT_BUS_FRAME_SIZE];
+    let n = bus.transfer(addr, request, &mut response).await?;
+    let len = response[0] as usize;
+    if n == 0 || len + 1 > n {
+        return Err(SplitBusError::Bus);
+    }
+    postcard::from_bytes(&response[1..1 + len]).map_err(|e| {
+        error!("Postcard deserialize split message error: {}", e);
+        SplitBusError::Bus
+    })
+}
+
+/// Forward the message from a peripheral to the keyboard
+async fn process_peripheral_message(peripheral: &BusPeripheral, message: SplitMessage) {
+    // Only when the connection is established, send the events
+    let connected = CONNECTION_STATE.load(Ordering::Acquire);
+    match message {
+        SplitMessage::Key(e) => {
+            if !connected {
+                warn!("Key event from peripheral is ignored because the connection is not established.");
+                return;
+            }
+            let event = match e.pos {
+                KeyboardEventPos::Key(key_pos) => {
+                    if key_pos.row >= peripheral.rows || key_pos.col >= peripheral.cols {
+                        error!("Invalid peripheral row/col: {} {}", key_pos.row, key_pos.col);
+                        return;
+                    }
+                    KeyboardEvent::key(
+                        key_pos.row + peripheral.row_offset,
+                        key_pos.col + peripheral.col_offset,
+                        e.pressed,
+                    )
+                }
+                _ => e,
+            };
+            KEY_EVENT_CHANNEL.send(event).await;
+        }
+        SplitMessage::Event(event) => {
+            if !connected {
+                warn!("Event from peripheral is ignored because the connection is not established.");
+                return;
+            }
+            if EVENT_CHANNEL.is_full() {
+                let _ = EVENT_CHANNEL.receive().await;
+            }
+            EVENT_CHANNEL.send(event).await;
+        }
+        #[cfg(feature = "dfu")]
+        SplitMessage::DfuResponse(response) => crate::dfu::send_dfu_response(response),
+        _ => (),
+    }
+}
+
+/// Run the split peripheral on the split bus.
+///
+/// The peripheral responds to each request from the central with one buffered event.
+pub async fn run_bus_split_peripheral<T: SplitBusTarget>(mut target: T) {
+    CONNECTION_STATE.store(ConnectionState::Connected.into(), Ordering::Release);
+    let mut request = [0u8; SPLIT_BUS_FRAME_SIZE];
+    loop {
+        let n = match target.receive(&mut request).await {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Split bus receive error: {:?}", e);
+                continue;
+            }
+        };
+        match postcard::from_bytes::<SplitMessage>(&request[..n]) {
+            Ok(SplitMessage::ConnectionState(state)) => {
+                trace!("Received connection state update: {}", state);
+                CONNECTION_STATE.store(state, Ordering::Release);
+            }
+            #[cfg(feature = "dfu")]
+            Ok(SplitMessage::Dfu(_, request)) => crate::dfu::DFU_REQUEST_CHANNEL.send(request.into()).await,
+            Ok(_) => (),
+            Err(e) => error!("Postcard deserialize split message error: {}", e),
+        }
+
+        let message = next_peripheral_message();
+        let mut response = [0u8; SPLIT_BUS_FRAME_SIZE];
+        let len = match postcard::to_slice(&message, &mut response[1..]) {
+            Ok(bytes) => bytes.len(),
+            Err(e) => {
+                error!("Postcard serialize split message error: {}", e);
+                0
+            }
+        };
+        response[0] = len as u8;
+        if let Err(e) = target.respond(&response[..1 + len]).await {
+            error!("Split bus respond error: {:?}", e);
+        }
+    }
+}
+
+/// Get the next event to be sent to the central
+fn next_peripheral_message() -> SplitMessage {
+    #[cfg(feature = "dfu")]
+    if let Ok(response) = crate::dfu::DFU_RESPONSE_CHANNEL.try_receive() {
+        return SplitMessage::DfuResponse(response);
+    }
+    let connected = CONNECTION_STATE.load(Ordering::Acquire);
+    while let Ok(e) = KEY_EVENT_CHANNEL.try_receive() {
+        if connected {
+            return SplitMessage::Key(e);
+        }
+        debug!("Connection not established, skipping key event");
+    }
+    while let Ok(e) = EVENT_CHANNEL.try_receive() {
+        if connected {
+            return SplitMessage::Event(e);
+        }
+        debug!("Connection not established, skipping event");
+    }
+    SplitMessage::BusPoll
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::signal::Signal;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+
+    /// Bus with a present peripheral at address 1, other peripherals don't respond
+    struct MockBus {
+        /// Addresses, requests and times of all transfers
+        requests: Rc<RefCell<Vec<(u8, SplitMessage, Instant)>>>,
+        /// Responses of the present peripheral
+        responses: VecDeque<SplitMessage>,
+        /// Signaled when the peripheral at address 2 is polled twice
+        done: Rc<Signal<NoopRawMutex, ()>>,
+    }
+
+    impl SplitBusController for MockBus {
+        async fn transfer(&mut self, addr: u8, request: &[u8], response: &mut [u8]) -> Result<usize, SplitBusError> {
+            let mut requests = self.requests.borrow_mut();
+            requests.push((addr, postcard::from_bytes(request).unwrap(), Instant::now()));
+            if requests.iter().filter(|(a, _, _)| *a == 2).count() == 2 {
+                self.done.signal(());
+            }
+            if addr != 1 {
+                return Err(SplitBusError::NoResponse);
+            }
+            let message = self.responses.pop_front().unwrap_or(SplitMessage::BusPoll);
+            let len = postcard::to_slice(&message, &mut response[1..]).unwrap().len();
+            response[0] = len as u8;
+            Ok(len + 1)
+        }
+    }
+
+    /// Bus which always responds with the raw bytes
+    struct RawBus(Vec<u8>);
+
+    impl SplitBusController for RawBus {
+        async fn transfer(&mut self, _addr: u8, _request: &[u8], response: &mut [u8]) -> Result<usize, SplitBusError> {
+            response[..self.0.len()].copy_from_slice(&self.0);
+            Ok(self.0.len())
+        }
+    }
+
+    #[test]
+    fn test_poll_peripheral_corrupt_response() {
+        let poll = |raw: Vec<u8>| block_on(poll_peripheral(&mut RawBus(raw), 1, &SplitMessage::BusPoll));
+        // Empty response
+        assert_eq!(poll(vec![]).unwrap_err(), SplitBusError::Bus);
+        // Length prefix past the end of the response
+        assert_eq!(poll(vec![5, 1]).unwrap_err(), SplitBusError::Bus);
+        // Invalid message
+        assert_eq!(poll(vec![1, 0xFF]).unwrap_err(), SplitBusError::Bus);
+
+        let mut response = [0u8; SPLIT_BUS_FRAME_SIZE];
+        let len = postcard::to_slice(&SplitMessage::ConnectionState(true), &mut response[1..])
+            .unwrap()
+            .len();
+        response[0] = len as u8;
+        assert!(matches!(
+            poll(response[..1 + len].to_vec()),
+            Ok(SplitMessage::ConnectionState(true))
+        ));
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_bus_peripheral_manager() {
+            CONNECTION_STATE.store(true, Ordering::Release);
+            let requests = Rc::new(RefCell::new(Vec::new()));
+            let done = Rc::new(Signal::new());
+            let bus = MockBus {
+                requests: requests.clone(),
+                done: done.clone(),
+                responses: VecDeque::from([
+                    SplitMessage::Key(KeyboardEvent::key(1, 2, true)),
+                    // Out of the peripheral's matrix
+                    SplitMessage::Key(KeyboardEvent::key(2, 0, true)),
+                ]),
+            };
+            let peripheral = |addr| BusPeripheral {
+                addr,
+                rows: 2,
+                cols: 3,
+                row_offset: 4,
+                col_offset: 5,
+            };
+            // Run until the absent peripheral is polled again, instead of for a fixed time
+            block_on(select(
+                run_bus_peripheral_manager(bus, [peripheral(1), peripheral(2)]),
+                done.wait(),
+            ));
+
+            // The event is forwarded with the matrix offsets of the peripheral
+            assert_eq!(KEY_EVENT_CHANNEL.try_receive().unwrap(), KeyboardEvent::key(5, 7, true));
+            assert!(KEY_EVENT_CHANNEL.try_receive().is_err());
+
+            let requests = requests.borrow();
+            // The connection state is synced to all peripherals on start
+            assert!(matches!(requests[0], (1, SplitMessage::ConnectionState(true), _)));
+            assert!(matches!(requests[1], (2, SplitMessage::ConnectionState(true), _)));
+            // The absent peripheral is polled less frequently, the present one is polled in between
+            let next = requests.iter().rposition(|(a, _, _)| *a == 2).unwrap();
+            assert!(requests[next].2 - requests[1].2 >= ABSENT_POLL_INTERVAL);
+            assert!(requests[2..next].iter().filter(|(a, _, _)| *a == 1).count() >= 2);
+        }
+    }
+}
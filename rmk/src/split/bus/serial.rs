@@ -0,0 +1,298 @@
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read, Write};
+
+use super::{SPLIT_BUS_FRAME_SIZE, SplitBusController, SplitBusError, SplitBusTarget};
+
+/// Timeout of the peripheral's response
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(5);
+
+/// The highest bit of the address is set in responses, to distinguish them from requests
+const RESPONSE_FLAG: u8 = 0x80;
+
+/// Size of an encoded frame: the address, the COBS overhead and the sentinel
+const ENCODED_FRAME_SIZE: usize = SPLIT_BUS_FRAME_SIZE + 4;
+
+/// Split bus on a multi-drop serial line, such as RS-485, which is shared by the central and all peripherals.
+///
+/// Each frame is a COBS encoded address followed by the payload. For a half-duplex line, wrap the serial port
+/// with [`crate::split::serial::HalfDuplexSerial`] to switch the line direction.
+pub struct SerialBus<S: Read + Write> {
+    serial: S,
+    reader: FrameReader,
+}
+
+impl<S: Read + Write> SerialBus<S> {
+    pub fn new(serial: S) -> Self {
+        Self {
+            serial,
+            reader: FrameReader::new(),
+        }
+    }
+}
+
+impl<S: Read + Write> SplitBusController for SerialBus<S> {
+    async fn transfer(&mut self, addr: u8, request: &[u8], response: &mut [u8]) -> Result<usize, SplitBusError> {
+        write_frame(&mut self.serial, addr, request).await?;
+        // Skip the frames which aren't the response of the addressed peripheral, e.g. the echo of the request
+        with_timeout(RESPONSE_TIMEOUT, async {
+            loop {
+                let frame = self.reader.read_frame(&mut self.serial).await?;
+                if frame.first() == Some(&(addr | RESPONSE_FLAG)) {
+                    let len = (frame.len() - 1).min(response.len());
+                    response[..len].copy_from_slice(&frame[1..1 + len]);
+                    return Ok(len);
+                }
+            }
+        })
+        .await
+        .map_err(|_| SplitBusError::NoResponse)?
+    }
+}
+
+/// Peripheral side of [`SerialBus`], which only responds to the requests to its address
+pub struct SerialBusTarget<S: Read + Write> {
+    serial: S,
+    addr: u8,
+    reader: FrameReader,
+}
+
+impl<S: Read + Write> SerialBusTarget<S> {
+    pub fn new(serial: S, addr: u8) -> Self {
+        Self {
+            serial,
+            addr,
+            reader: FrameReader::new(),
+        }
+    }
+}
+
+impl<S: Read + Write> SplitBusTarget for SerialBusTarget<S> {
+    async fn receive(&mut self, request: &mut [u8]) -> Result<usize, SplitBusError> {
+        loop {
+            let frame = self.reader.read_frame(&mut self.serial).await?;
+            if frame.first() == Some(&self.addr) {
+                let len = (frame.len() - 1).min(request.len());
+                request[..len].copy_from_slice(&frame[1..1 + len]);
+                return Ok(len);
+            }
+        }
+    }
+
+    async fn respond(&mut self, response: &[u8]) -> Result<(), SplitBusError> {
+        write_frame(&mut self.serial, self.addr | RESPONSE_FLAG, response).await
+    }
+}
+
+async fn write_frame<S: Write>(serial: &mut S, addr: u8, payload: &[u8]) -> Result<(), SplitBusError> {
+    let mut raw = [0u8; SPLIT_BUS_FRAME_SIZE + 1];
+    if payload.len() >= raw.len() {
+        return Err(SplitBusError::Bus);
+    }
+    raw[0] = addr;
+    raw[1..1 + payload.len()].copy_from_slice(payload);
+    let mut frame = [0u8; ENCODED_FRAME_SIZE];
+    let n = cobs_encode(&raw[..1 + payload.len()], &mut frame);
+    frame[n] = 0;
+    serial
+        .write_all(&frame[..n + 1])
+        .await
+        .map_err(|_| SplitBusError::Bus)?;
+    serial.flush().await.map_err(|_| SplitBusError::Bus)
+}
+
+/// COBS encode `src` to `dst` without the sentinel, returns the encoded length
+fn cobs_encode(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut code_idx = 0;
+    let mut code = 1u8;
+    let mut n = 1;
+    for &b in src {
+        if b == 0 {
+            dst[code_idx] = code;
+            code_idx = n;
+            n += 1;
+            code = 1;
+        } else {
+            dst[n] = b;
+            n += 1;
+            code += 1;
+            if code == 0xFF {
+                dst[code_idx] = code;
+                code_idx = n;
+                n += 1;
+                code = 1;
+            }
+        }
+    }
+    dst[code_idx] = code;
+    n
+}
+
+/// COBS decode `buf` in place, returns the decoded length
+fn cobs_decode(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        if code == 0 || read + code > buf.len() {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            buf[write] = buf[read];
+            write += 1;
+            read += 1;
+        }
+        if code != 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+/// Read frames separated by the zero sentinel
+struct FrameReader {
+    buf: [u8; ENCODED_FRAME_SIZE],
+    len: usize,
+}
+
+impl FrameReader {
+    const fn new() -> Self {
+        Self {
+            buf: [0; ENCODED_FRAME_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Read the next valid frame, broken or oversized frames are dropped
+    async fn read_frame<S: Read>(&mut self, serial: &mut S) -> Result<&[u8], SplitBusError> {
+        let mut byte = [0u8; 1];
+        loop {
+            let n = serial.read(&mut byte).await.map_err(|_| SplitBusError::Bus)?;
+            if n == 0 {
+                continue;
+            }
+            if byte[0] != 0 {
+                if self.len < self.buf.len() {
+                    self.buf[self.len] = byte[0];
+                }
+                // Oversized frames are dropped at the sentinel
+                self.len = self.len.saturating_add(1);
+                continue;
+            }
+            let len = core::mem::take(&mut self.len);
+            if len == 0 || len > self.buf.len() {
+                continue;
+            }
+            if let Some(decoded) = cobs_decode(&mut self.buf[..len]) {
+                return Ok(&self.buf[..decoded]);
+            }
+            warn!("Dropped broken split bus frame");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embedded_io_async::{ErrorKind, ErrorType};
+
+    use super::*;
+
+    /// Serial port which receives the queued bytes
+    struct MockSerial {
+        rx: VecDeque<u8>,
+    }
+
+    impl ErrorType for MockSerial {
+        type Error = ErrorKind;
+    }
+
+    impl Read for MockSerial {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.rx.is_empty() {
+                core::future::pending::<()>().await;
+            }
+            let n = buf.len().min(self.rx.len());
+            for b in buf[..n].iter_mut() {
+                *b = self.rx.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    fn encode_frame(data: &[u8]) -> Vec<u8> {
+        let mut frame = [0u8; 300];
+        let n = cobs_encode(data, &mut frame);
+        let mut frame = frame[..n].to_vec();
+        frame.push(0);
+        frame
+    }
+
+    #[test]
+    fn test_cobs_round_trip() {
+        let long: Vec<u8> = (1..=255).chain(1..=20).collect();
+        let cases: [&[u8]; 7] = [&[], &[0], &[0, 0], &[1, 2, 3], &[1, 0, 2, 0], &[0, 0xFF, 0], &long];
+        for data in cases {
+            let mut encoded = [0u8; 300];
+            let n = cobs_encode(data, &mut encoded);
+            // No zero byte in the encoded frame, so the sentinel is unique
+            assert!(!encoded[..n].contains(&0), "{:?}", data);
+            assert_eq!(n, data.len() + 1 + data.len() / 254, "{:?}", data);
+            let decoded = cobs_decode(&mut encoded[..n]).unwrap();
+            assert_eq!(&encoded[..decoded], data);
+        }
+    }
+
+    #[test]
+    fn test_cobs_decode_corrupt() {
+        // Zero code
+        assert_eq!(cobs_decode(&mut [0, 1]), None);
+        // Code points past the end of the frame
+        assert_eq!(cobs_decode(&mut [5, 1, 2]), None);
+        assert_eq!(cobs_decode(&mut [2, 1, 3, 1]), None);
+    }
+
+    #[test]
+    fn test_frame_reader() {
+        let mut rx = VecDeque::new();
+        // Empty frame
+        rx.push_back(0);
+        // Broken frame
+        rx.extend([5, 1, 2, 0]);
+        // Oversized frame
+        rx.extend(core::iter::repeat_n(1, ENCODED_FRAME_SIZE + 1));
+        rx.push_back(0);
+        rx.extend(encode_frame(&[1, 0, 2]));
+        rx.extend(encode_frame(&[3]));
+        let mut serial = MockSerial { rx };
+
+        let mut reader = FrameReader::new();
+        assert_eq!(block_on(reader.read_frame(&mut serial)).unwrap(), [1, 0, 2]);
+        assert_eq!(block_on(reader.read_frame(&mut serial)).unwrap(), [3]);
+        assert!(serial.rx.is_empty());
+    }
+
+    #[test]
+    fn test_frame_reader_split_reads() {
+        // A frame arrives in several reads
+        let frame = encode_frame(&[7, 0, 8]);
+        let (first, second) = frame.split_at(2);
+        let mut serial = MockSerial {
+            rx: first.iter().copied().collect(),
+        };
+        let mut reader = FrameReader::new();
+        assert!(
+            block_on(embassy_time::with_timeout(
+                Duration::from_millis(5),
+                reader.read_frame(&mut serial)
+            ))
+            .is_err()
+        );
+        serial.rx.extend(second);
+        assert_eq!(block_on(reader.read_frame(&mut serial)).unwrap(), [7, 0, 8]);
+    }
+}
@@ -117,6 +117,13 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
                         }
                         _ => (),
                     }
+                    // DFU requests are sent to the target peripheral only
+                    #[cfg(feature = "dfu")]
+                    if let SplitMessage::Dfu(id, _) = split_message
+                        && id as usize != self.id
+                    {
+                        continue;
+                    }
                     debug!("Publishing split message {:?} to peripherals", split_message);
                     if let Err(e) = self.transceiver.write(&split_message).await {
                         match e {
@@ -186,6 +193,8 @@ impl<const ROW: usize, const COL: usize, const ROW_OFFSET: usize, const COL_OFFS
                         warn!("Event from peripheral is ignored because the connection is not established.");
                     }
                 }
+                #[cfg(feature = "dfu")]
+                Ok(SplitMessage::DfuResponse(response)) => crate::dfu::send_dfu_response(response),
                 Ok(_) => {
                     // Ignore other types of messages
                     debug!("Ignored non-event split message");
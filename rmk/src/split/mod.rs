@@ -5,6 +5,8 @@ use crate::event::{Event, KeyboardEvent};
 
 #[cfg(feature = "_ble")]
 pub mod ble;
+#[cfg(not(feature = "_ble"))]
+pub mod bus;
 pub mod central;
 /// Common abstraction layer of split driver
 pub(crate) mod driver;
@@ -34,4 +36,12 @@ pub(crate) enum SplitMessage {
     Address([u8; 6]),
     /// Clear the saved peer info
     ClearPeer,
+    /// Poll from central to a peripheral on the split bus, or the response of a peripheral which has nothing to send
+    BusPoll,
+    /// DFU request from central to the peripheral with the id
+    #[cfg(feature = "dfu")]
+    Dfu(u8, crate::dfu::SplitDfuRequest),
+    /// DFU response from peripheral to central
+    #[cfg(feature = "dfu")]
+    DfuResponse(crate::dfu::DfuResponse),
 }
@@ -1,6 +1,6 @@
 #[cfg(feature = "_ble")]
 use bt_hci::{cmd::le::LeSetPhy, controller::ControllerCmdAsync};
-use embassy_futures::select::select3;
+use embassy_futures::select::select4;
 #[cfg(not(feature = "_ble"))]
 use embedded_io_async::{Read, Write};
 #[cfg(all(feature = "_ble", feature = "storage"))]
@@ -40,6 +40,12 @@ pub async fn run_rmk_split_peripheral<
     #[cfg(feature = "_ble")] storage: &'b mut Storage<F, ROW, COL, NUM_LAYER, NUM_ENCODER>,
     #[cfg(not(feature = "_ble"))] serial: S,
 ) {
+    // Check the keys held at power-up
+    #[cfg(feature = "_ble")]
+    crate::bootmagic::run_bootmagic_with_storage(storage, true).await;
+    #[cfg(not(feature = "_ble"))]
+    crate::bootmagic::run_bootmagic().await;
+
     #[cfg(not(feature = "_ble"))]
     {
         let mut peripheral = SplitPeripheral::new(SerialSplitDriver::new(serial));
@@ -69,14 +75,15 @@ impl<S: SplitWriter + SplitReader> SplitPeripheral<S> {
     pub(crate) async fn run(&mut self) {
         CONNECTION_STATE.store(ConnectionState::Connected.into(), core::sync::atomic::Ordering::Release);
         loop {
-            match select3(
+            match select4(
                 self.split_driver.read(),
                 KEY_EVENT_CHANNEL.receive(),
                 EVENT_CHANNEL.receive(),
+                dfu_response(),
             )
             .await
             {
-                embassy_futures::select::Either3::First(m) => match m {
+                embassy_futures::select::Either4::First(m) => match m {
                     // Currently only handle the central state message
                     Ok(split_message) => match split_message {
                         SplitMessage::ConnectionState(state) => {
@@ -92,6 +99,8 @@ impl<S: SplitWriter + SplitReader> SplitPeripheral<S> {
                                 )))
                                 .await;
                         }
+                        #[cfg(feature = "dfu")]
+                        SplitMessage::Dfu(_, request) => crate::dfu::DFU_REQUEST_CHANNEL.send(request.into()).await,
                         _ => (),
                     },
                     Err(e) => {
@@ -104,7 +113,7 @@ impl<S: SplitWriter + SplitReader> SplitPeripheral<S> {
                         }
                     }
                 },
-                embassy_futures::select::Either3::Second(e) => {
+                embassy_futures::select::Either4::Second(e) => {
                     // Only send the key event if the connection is established
                     if CONNECTION_STATE.load(core::sync::atomic::Ordering::Acquire) {
                         debug!("Writing split key event to central");
@@ -113,7 +122,7 @@ impl<S: SplitWriter + SplitReader> SplitPeripheral<S> {
                         debug!("Connection not established, skipping key event");
                     }
                 }
-                embassy_futures::select::Either3::Third(e) => {
+                embassy_futures::select::Either4::Third(e) => {
                     if CONNECTION_STATE.load(core::sync::atomic::Ordering::Acquire) {
                         debug!("Writing split event to central: {:?}", e);
                         self.split_driver.write(&SplitMessage::Event(e)).await.ok();
@@ -121,7 +130,18 @@ impl<S: SplitWriter + SplitReader> SplitPeripheral<S> {
                         debug!("Connection not established, skipping event");
                     }
                 }
+                embassy_futures::select::Either4::Fourth(message) => {
+                    self.split_driver.write(&message).await.ok();
+                }
             }
         }
     }
 }
+
+/// Wait for the response of the DFU task, which is sent back to the central
+async fn dfu_response() -> SplitMessage {
+    #[cfg(feature = "dfu")]
+    return SplitMessage::DfuResponse(crate::dfu::DFU_RESPONSE_CHANNEL.receive().await);
+    #[cfg(not(feature = "dfu"))]
+    core::future::pending().await
+}
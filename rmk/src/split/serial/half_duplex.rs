@@ -0,0 +1,347 @@
+//! Half-duplex serial over a single wire
+//!
+//! Both sides transmit and receive on the same line, so a side can only transmit when the line is idle.
+//! [`HalfDuplexSerial`] waits for the bus turnaround before transmitting, switches the line direction
+//! by [`HalfDuplexDirection`], and checks the echo of the transmitted bytes to detect collisions.
+//! When a collision happens, the whole frame is re-transmitted after a backoff.
+//!
+//! [`HalfDuplexSerial`] implements `Read` and `Write` of `embedded-io-async`, so it can be used as the serial port
+//! of the serial split. The split driver writes each frame in a single `write` call, which makes the frame
+//! the unit of re-transmission.
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_hal::digital::OutputPin;
+use embedded_io_async::{Error, ErrorKind, ErrorType, Read, Write};
+
+use crate::split::SPLIT_MESSAGE_MAX_SIZE;
+
+/// Direction control of a half-duplex serial line
+pub trait HalfDuplexDirection {
+    /// Drive the line before transmitting
+    fn set_transmit(&mut self);
+
+    /// Release the line after transmitting, so that the other side can transmit
+    fn set_receive(&mut self);
+}
+
+/// The serial switches the direction by itself, such as the USART of STM32 in half-duplex mode
+pub struct AutoDirection;
+
+impl HalfDuplexDirection for AutoDirection {
+    fn set_transmit(&mut self) {}
+
+    fn set_receive(&mut self) {}
+}
+
+/// The direction is controlled by an output pin which is high when transmitting,
+/// such as the driver enable pin of a line driver
+pub struct DirectionPin<P: OutputPin>(pub P);
+
+impl<P: OutputPin> HalfDuplexDirection for DirectionPin<P> {
+    fn set_transmit(&mut self) {
+        let _ = self.0.set_high();
+    }
+
+    fn set_receive(&mut self) {
+        let _ = self.0.set_low();
+    }
+}
+
+/// Config of [`HalfDuplexSerial`]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HalfDuplexConfig {
+    /// Time that the line should be idle after receiving, before transmitting
+    pub turnaround: Duration,
+    /// Whether the transmitted bytes are received back, which is used to detect collisions
+    pub echo: bool,
+    /// Timeout of receiving the echo of each byte
+    pub echo_byte_timeout: Duration,
+    /// Backoff time after the first collision, it's doubled after each retry
+    pub backoff: Duration,
+    /// Max number of re-transmissions of a frame
+    pub max_retries: u8,
+}
+
+impl Default for HalfDuplexConfig {
+    fn default() -> Self {
+        // A byte takes about 87us at 115200 baud
+        Self {
+            turnaround: Duration::from_micros(200),
+            echo: true,
+            echo_byte_timeout: Duration::from_millis(1),
+            backoff: Duration::from_millis(1),
+            max_retries: 4,
+        }
+    }
+}
+
+/// Error of [`HalfDuplexSerial`]
+#[derive(Debug)]
+pub enum HalfDuplexError<E> {
+    /// Error of the underlying serial
+    Serial(E),
+    /// The frame still collides after all retries
+    Collision,
+}
+
+impl<E: Error> Error for HalfDuplexError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            HalfDuplexError::Serial(e) => e.kind(),
+            HalfDuplexError::Collision => ErrorKind::Other,
+        }
+    }
+}
+
+/// Half-duplex serial over a single wire
+pub struct HalfDuplexSerial<S: Read + Write, D: HalfDuplexDirection> {
+    serial: S,
+    direction: D,
+    config: HalfDuplexConfig,
+    /// Time of the last received byte
+    last_rx: Instant,
+    /// Bytes received when checking the echo, which may come from the other side
+    stash: [u8; SPLIT_MESSAGE_MAX_SIZE],
+    stash_start: usize,
+    stash_end: usize,
+}
+
+impl<S: Read + Write, D: HalfDuplexDirection> HalfDuplexSerial<S, D> {
+    /// Create a half-duplex serial, the line is released to receive
+    pub fn new(serial: S, mut direction: D, config: HalfDuplexConfig) -> Self {
+        direction.set_receive();
+        Self {
+            serial,
+            direction,
+            config,
+            last_rx: Instant::now(),
+            stash: [0; SPLIT_MESSAGE_MAX_SIZE],
+            stash_start: 0,
+            stash_end: 0,
+        }
+    }
+
+    /// Transmit a frame, the line is driven only during the transmission
+    async fn transmit(&mut self, frame: &[u8]) -> Result<(), S::Error> {
+        // Wait for the bus turnaround
+        Timer::at(self.last_rx + self.config.turnaround).await;
+        self.direction.set_transmit();
+        let result = match self.serial.write_all(frame).await {
+            Ok(()) => self.serial.flush().await,
+            Err(e) => Err(e),
+        };
+        self.direction.set_receive();
+        result
+    }
+
+    /// Receive the echo of the transmitted frame, returns false if the echo doesn't match.
+    ///
+    /// The mismatched bytes are stashed, because they might be a frame sent by the other side right before.
+    async fn check_echo(&mut self, frame: &[u8]) -> Result<bool, S::Error> {
+        let mut buf = [0u8; SPLIT_MESSAGE_MAX_SIZE];
+        let len = frame.len().min(buf.len());
+        let mut received = 0;
+        while received < len {
+            let timeout = self.config.echo_byte_timeout * (len - received) as u32;
+            match with_timeout(timeout, self.serial.read(&mut buf[received..len])).await {
+                Ok(Ok(0)) | Err(_) => break,
+                Ok(Ok(n)) => {
+                    received += n;
+                    self.last_rx = Instant::now();
+                }
+                Ok(Err(e)) => return Err(e),
+            }
+        }
+
+        if received == len && buf[..len] == frame[..len] {
+            return Ok(true);
+        }
+        self.stash_bytes(&buf[..received]);
+        Ok(false)
+    }
+
+    fn stash_bytes(&mut self, bytes: &[u8]) {
+        if self.stash_start == self.stash_end {
+            self.stash_start = 0;
+            self.stash_end = 0;
+        }
+        let n = bytes.len().min(self.stash.len() - self.stash_end);
+        if n < bytes.len() {
+            warn!(
+                "Half-duplex serial stash is full, drop {} received bytes",
+                bytes.len() - n
+            );
+        }
+        self.stash[self.stash_end..self.stash_end + n].copy_from_slice(&bytes[..n]);
+        self.stash_end += n;
+    }
+
+    /// Backoff time of a retry.
+    ///
+    /// The time is shifted by the content of the frame, so that the two sides are unlikely to collide again.
+    fn backoff(&self, frame: &[u8], retry: u8) -> Duration {
+        let jitter = frame.iter().fold(0u32, |acc, b| acc.wrapping_add(*b as u32)) % 4;
+        self.config.backoff * (1 << retry) + self.config.backoff * jitter / 4
+    }
+}
+
+impl<S: Read + Write, D: HalfDuplexDirection> ErrorType for HalfDuplexSerial<S, D> {
+    type Error = HalfDuplexError<S::Error>;
+}
+
+impl<S: Read + Write, D: HalfDuplexDirection> Read for HalfDuplexSerial<S, D> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.stash_start < self.stash_end {
+            let n = buf.len().min(self.stash_end - self.stash_start);
+            buf[..n].copy_from_slice(&self.stash[self.stash_start..self.stash_start + n]);
+            self.stash_start += n;
+            return Ok(n);
+        }
+        let n = self.serial.read(buf).await.map_err(HalfDuplexError::Serial)?;
+        self.last_rx = Instant::now();
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write, D: HalfDuplexDirection> Write for HalfDuplexSerial<S, D> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        for retry in 0..=self.config.max_retries {
+            self.transmit(buf).await.map_err(HalfDuplexError::Serial)?;
+            if !self.config.echo || self.check_echo(buf).await.map_err(HalfDuplexError::Serial)? {
+                return Ok(buf.len());
+            }
+            warn!("Half-duplex serial collision, retry: {}", retry);
+            Timer::after(self.backoff(buf, retry)).await;
+        }
+        error!("Half-duplex serial collision, drop the frame");
+        Err(HalfDuplexError::Collision)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // Frames are flushed when they are written
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// A single wire, the transmitted bytes are received back
+    #[derive(Default)]
+    struct MockWire {
+        /// Bytes on the line which are not received yet
+        rx: VecDeque<u8>,
+        /// Transmitted frames
+        frames: Vec<Vec<u8>>,
+        /// Number of the next frames which collide with the other side
+        collisions: usize,
+    }
+
+    #[derive(Debug)]
+    struct MockError;
+
+    impl Error for MockError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    impl ErrorType for MockWire {
+        type Error = MockError;
+    }
+
+    impl Read for MockWire {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.rx.is_empty() {
+                core::future::pending::<()>().await;
+            }
+            let n = buf.len().min(self.rx.len());
+            for b in buf[..n].iter_mut() {
+                *b = self.rx.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockWire {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.frames.push(buf.to_vec());
+            if self.collisions > 0 {
+                // Both sides transmit at the same time, the bytes on the line are corrupted
+                self.collisions -= 1;
+                self.rx.extend(buf.iter().map(|b| !b));
+            } else {
+                self.rx.extend(buf);
+            }
+            Ok(buf.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn half_duplex(collisions: usize) -> HalfDuplexSerial<MockWire, AutoDirection> {
+        let wire = MockWire {
+            collisions,
+            ..Default::default()
+        };
+        let config = HalfDuplexConfig {
+            backoff: Duration::from_millis(2),
+            max_retries: 2,
+            ..Default::default()
+        };
+        HalfDuplexSerial::new(wire, AutoDirection, config)
+    }
+
+    #[test]
+    fn test_echo_stripping() {
+        let mut serial = half_duplex(0);
+        assert_eq!(block_on(serial.write(&[1, 2, 3])).unwrap(), 3);
+        assert_eq!(serial.serial.frames, [[1, 2, 3]]);
+
+        // The echo is consumed, only the bytes from the other side are read
+        serial.serial.rx.extend([4, 5]);
+        let mut buf = [0; 4];
+        assert_eq!(block_on(serial.read(&mut buf)).unwrap(), 2);
+        assert_eq!(buf[..2], [4, 5]);
+    }
+
+    #[test]
+    fn test_collision() {
+        let mut serial = half_duplex(1);
+        assert_eq!(block_on(serial.write(&[1, 2, 3])).unwrap(), 3);
+        // The frame is re-transmitted after the collision
+        assert_eq!(serial.serial.frames, [[1, 2, 3], [1, 2, 3]]);
+
+        // The mismatched echo is stashed, it may be a frame from the other side
+        let mut buf = [0; 4];
+        assert_eq!(block_on(serial.read(&mut buf)).unwrap(), 3);
+        assert_eq!(buf[..3], [!1, !2, !3]);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut serial = half_duplex(usize::MAX);
+        let backoff = serial.config.backoff;
+        // The backoff is doubled after each retry, shifted by the content of the frame
+        assert_eq!(serial.backoff(&[4], 0), backoff);
+        assert_eq!(serial.backoff(&[4], 2), backoff * 4);
+        assert_eq!(serial.backoff(&[2], 1), backoff * 2 + backoff * 2 / 4);
+
+        // The frame is dropped after all retries
+        let start = Instant::now();
+        assert!(matches!(block_on(serial.write(&[4])), Err(HalfDuplexError::Collision)));
+        assert_eq!(serial.serial.frames.len(), 3);
+        assert!(start.elapsed() >= backoff * (1 + 2 + 4));
+    }
+}
@@ -4,6 +4,12 @@ use super::driver::SplitDriverError;
 use crate::split::driver::{PeripheralManager, SplitReader, SplitWriter};
 use crate::split::{SPLIT_MESSAGE_MAX_SIZE, SplitMessage};
 
+mod half_duplex;
+
+pub use half_duplex::{
+    AutoDirection, DirectionPin, HalfDuplexConfig, HalfDuplexDirection, HalfDuplexError, HalfDuplexSerial,
+};
+
 // Receive split message from peripheral via serial and process it
 ///
 /// Generic parameters:
@@ -3,14 +3,19 @@ use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 /// Current connection type:
 /// - 0: USB
 /// - 1: BLE
+/// - 2: Auto, USB when USB is connected, otherwise BLE
 /// - Other: reserved
 pub(crate) static CONNECTION_TYPE: AtomicU8 = AtomicU8::new(0);
 pub(crate) static CONNECTION_STATE: AtomicBool = AtomicBool::new(false);
 
 /// Current default connection type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ConnectionType {
     Usb = 0,
     Ble = 1,
+    /// Follow the USB connection, USB is used when it's connected, BLE is used otherwise
+    Auto = 2,
 }
 
 pub enum ConnectionState {
@@ -23,7 +28,9 @@ impl From<u8> for ConnectionType {
         match value {
             0 => ConnectionType::Usb,
             1 => ConnectionType::Ble,
-            _ => unreachable!("Invalid connection type"),
+            2 => ConnectionType::Auto,
+            // An unknown value, for example saved by a newer firmware or corrupted, falls back to USB
+            _ => ConnectionType::Usb,
         }
     }
 }
@@ -33,6 +40,7 @@ impl From<ConnectionType> for u8 {
         match conn_type {
             ConnectionType::Usb => 0,
             ConnectionType::Ble => 1,
+            ConnectionType::Auto => 2,
         }
     }
 }
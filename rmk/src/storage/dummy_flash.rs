@@ -75,3 +75,183 @@ impl embedded_storage_async::nor_flash::ReadNorFlash for DummyFlash {
         0
     }
 }
+
+/// A `NorFlash` in RAM, which can simulate a power loss in the middle of a write or an erase.
+///
+/// It's used for testing the storage and the firmware update. After `power_loss_after` writes and erases, the next write or erase is
+/// interrupted: only the first half of it is done, and all following operations fail until [`MemoryFlash::power_on`].
+#[derive(Clone)]
+pub struct MemoryFlash<const SIZE: usize, const ERASE_SIZE: usize> {
+    pub data: [u8; SIZE],
+    /// Number of writes and erases before the power is lost, `None` means the power is never lost
+    pub power_loss_after: Option<usize>,
+    /// Number of completed writes and erases
+    pub operations: usize,
+    power_lost: bool,
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> Default for MemoryFlash<SIZE, ERASE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> MemoryFlash<SIZE, ERASE_SIZE> {
+    /// Create an erased flash
+    pub fn new() -> Self {
+        Self {
+            data: [0xFF; SIZE],
+            power_loss_after: None,
+            operations: 0,
+            power_lost: false,
+        }
+    }
+
+    /// Whether the power has been lost
+    pub fn power_lost(&self) -> bool {
+        self.power_lost
+    }
+
+    /// Restore the power, the data is kept
+    pub fn power_on(&mut self) {
+        self.power_lost = false;
+        self.power_loss_after = None;
+    }
+
+    /// Check the power before an operation, returns the length of the operation which can be done
+    fn begin(&mut self, len: usize) -> Result<usize, MemoryFlashError> {
+        if self.power_lost {
+            return Err(MemoryFlashError::PowerLost);
+        }
+        if self.power_loss_after == Some(self.operations) {
+            self.power_lost = true;
+            return Ok(len / 2);
+        }
+        self.operations += 1;
+        Ok(len)
+    }
+}
+
+/// Error of [`MemoryFlash`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemoryFlashError {
+    /// The address is out of the flash or not aligned
+    OutOfBounds,
+    /// The power is lost, or it's lost during this operation
+    PowerLost,
+}
+
+impl embedded_storage_async::nor_flash::NorFlashError for MemoryFlashError {
+    fn kind(&self) -> embedded_storage_async::nor_flash::NorFlashErrorKind {
+        match self {
+            MemoryFlashError::OutOfBounds => embedded_storage_async::nor_flash::NorFlashErrorKind::OutOfBounds,
+            MemoryFlashError::PowerLost => embedded_storage_async::nor_flash::NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> embedded_storage_async::nor_flash::ErrorType
+    for MemoryFlash<SIZE, ERASE_SIZE>
+{
+    type Error = MemoryFlashError;
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> MemoryFlash<SIZE, ERASE_SIZE> {
+    fn read_data(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MemoryFlashError> {
+        let offset = offset as usize;
+        let data = self
+            .data
+            .get(offset..offset + bytes.len())
+            .ok_or(MemoryFlashError::OutOfBounds)?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn erase_data(&mut self, from: u32, to: u32) -> Result<(), MemoryFlashError> {
+        let (from, to) = (from as usize, to as usize);
+        if !from.is_multiple_of(ERASE_SIZE) || !to.is_multiple_of(ERASE_SIZE) || from > to || to > SIZE {
+            return Err(MemoryFlashError::OutOfBounds);
+        }
+        let len = self.begin(to - from)?;
+        self.data[from..from + len].fill(0xFF);
+        if self.power_lost {
+            return Err(MemoryFlashError::PowerLost);
+        }
+        Ok(())
+    }
+
+    fn write_data(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MemoryFlashError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) || offset + bytes.len() > SIZE {
+            return Err(MemoryFlashError::OutOfBounds);
+        }
+        let len = self.begin(bytes.len())?;
+        // NOR flash can only clear bits
+        for (d, b) in self.data[offset..offset + len].iter_mut().zip(bytes) {
+            *d &= b;
+        }
+        if self.power_lost {
+            return Err(MemoryFlashError::PowerLost);
+        }
+        Ok(())
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> embedded_storage_async::nor_flash::ReadNorFlash
+    for MemoryFlash<SIZE, ERASE_SIZE>
+{
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_data(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> embedded_storage_async::nor_flash::NorFlash
+    for MemoryFlash<SIZE, ERASE_SIZE>
+{
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_data(from, to)
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_data(offset, bytes)
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> embedded_storage::nor_flash::ReadNorFlash
+    for MemoryFlash<SIZE, ERASE_SIZE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_data(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize> embedded_storage::nor_flash::NorFlash
+    for MemoryFlash<SIZE, ERASE_SIZE>
+{
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_data(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_data(offset, bytes)
+    }
+}
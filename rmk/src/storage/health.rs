@@ -0,0 +1,346 @@
+//! Health of the flash storage
+//!
+//! The storage task scans the stored items when the storage hasn't been changed for a while, and records the usage of
+//! each category of items, the number of erased sectors and the last error. Items are never overwritten in place, so an outdated copy
+//! of an item occupies the flash until its sector is reclaimed. [`compact_storage`] reclaims all outdated copies.
+
+use core::cell::Cell;
+use core::ops::Range;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use sequential_storage::Error as SSError;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{SerializationError, Value, fetch_all_items, fetch_item, store_item};
+
+use super::{FlashOperationMessage, StorageData, StorageKeys};
+use crate::RawMutex;
+use crate::channel::FLASH_CHANNEL;
+
+/// Number of [`StorageCategory`]
+pub const STORAGE_CATEGORY_NUM: usize = 16;
+
+static STORAGE_HEALTH: Mutex<RawMutex, Cell<StorageHealth>> = Mutex::new(Cell::new(StorageHealth::new()));
+
+static STORAGE_USAGE: Mutex<RawMutex, Cell<[StorageUsage; STORAGE_CATEGORY_NUM]>> =
+    Mutex::new(Cell::new([StorageUsage::new(); STORAGE_CATEGORY_NUM]));
+
+/// Category of the stored items
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum StorageCategory {
+    /// Whether the storage is initialized, and the erase count
+    Config = 0,
+    Keymap = 1,
+    /// Layout options and the default layer
+    Layout = 2,
+    /// Behavior settings, lighting settings and the battery calibration
+    Behavior = 3,
+    Macro = 4,
+    Combo = 5,
+    ConnectionType = 6,
+    Encoder = 7,
+    Fork = 8,
+    Morse = 9,
+    AnalogCalibration = 10,
+    MouseKey = 11,
+    VialLock = 12,
+    /// Bonds, host names and the active profile of BLE
+    BleProfile = 13,
+    /// Addresses of the split peers
+    PeerAddress = 14,
+    /// Items which are not recognized, such as items written by another firmware
+    Unknown = 15,
+}
+
+impl StorageCategory {
+    /// Get the category of a stored item by its first byte, see [`StorageKeys`]
+    fn from_item(item: &[u8]) -> Self {
+        let Some(key) = item.first().and_then(|k| StorageKeys::from_u8(*k)) else {
+            return StorageCategory::Unknown;
+        };
+        match key {
+            StorageKeys::StorageConfig | StorageKeys::EraseCount => StorageCategory::Config,
+            StorageKeys::KeymapConfig => StorageCategory::Keymap,
+            StorageKeys::LayoutConfig => StorageCategory::Layout,
+            StorageKeys::BehaviorConfig | StorageKeys::LightingConfig | StorageKeys::BatteryCalibration => {
+                StorageCategory::Behavior
+            }
+            StorageKeys::MacroData => StorageCategory::Macro,
+            StorageKeys::ComboData => StorageCategory::Combo,
+            StorageKeys::ConnectionType => StorageCategory::ConnectionType,
+            StorageKeys::EncoderKeys => StorageCategory::Encoder,
+            StorageKeys::ForkData => StorageCategory::Fork,
+            StorageKeys::MorseData => StorageCategory::Morse,
+            StorageKeys::AnalogCalibration => StorageCategory::AnalogCalibration,
+            StorageKeys::MouseKeyConfig => StorageCategory::MouseKey,
+            StorageKeys::VialLockState => StorageCategory::VialLock,
+            #[cfg(feature = "_ble")]
+            StorageKeys::BleProfileMeta | StorageKeys::ActiveBleProfile | StorageKeys::BleBondInfo => {
+                StorageCategory::BleProfile
+            }
+            #[cfg(all(feature = "_ble", feature = "split"))]
+            StorageKeys::PeerAddress => StorageCategory::PeerAddress,
+        }
+    }
+}
+
+/// Flash usage of a category of stored items
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StorageUsage {
+    /// Number of stored items, including the outdated copies. Outdated copies of the storage config are not counted
+    pub items: u16,
+    /// Bytes occupied by the items, including the headers and the padding
+    pub bytes: u32,
+}
+
+impl StorageUsage {
+    const fn new() -> Self {
+        Self { items: 0, bytes: 0 }
+    }
+}
+
+/// Error of the flash storage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum StorageError {
+    /// The flash driver returns an error
+    Flash = 1,
+    /// There's no space for the new item, even after the outdated items are reclaimed
+    Full = 2,
+    /// The stored data is corrupted
+    Corrupted = 3,
+    /// Other errors, such as a malformed item
+    Other = 4,
+}
+
+impl<E> From<&SSError<E>> for StorageError {
+    fn from(e: &SSError<E>) -> Self {
+        match e {
+            SSError::Storage { .. } => StorageError::Flash,
+            SSError::FullStorage => StorageError::Full,
+            SSError::Corrupted { .. } => StorageError::Corrupted,
+            _ => StorageError::Other,
+        }
+    }
+}
+
+/// Health of the flash storage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StorageHealth {
+    /// Size of the storage region in bytes
+    pub capacity: u32,
+    /// Bytes occupied by the stored items, including the outdated copies except those of the storage config
+    pub used: u32,
+    /// Bytes left for new items. A sector is always kept empty for reclaiming the outdated items, so it's not counted
+    pub free: u32,
+    /// Number of erased sectors of the storage, which is saved in the storage
+    pub erase_count: u32,
+    /// The last error of the storage since power-up
+    pub last_error: Option<StorageError>,
+}
+
+impl StorageHealth {
+    const fn new() -> Self {
+        Self {
+            capacity: 0,
+            used: 0,
+            free: 0,
+            erase_count: 0,
+            last_error: None,
+        }
+    }
+
+    /// Percent of the usable space which is occupied by the stored items
+    pub fn used_percent(&self) -> u8 {
+        let total = self.used + self.free;
+        if total == 0 {
+            return 0;
+        }
+        (self.used as u64 * 100 / total as u64) as u8
+    }
+}
+
+/// Get the health of the storage, which is updated when the storage hasn't been changed for a while
+pub fn storage_health() -> StorageHealth {
+    STORAGE_HEALTH.lock(|h| h.get())
+}
+
+/// Get the flash usage of a category of stored items
+pub fn storage_usage(category: StorageCategory) -> StorageUsage {
+    STORAGE_USAGE.lock(|u| u.get()[category as usize])
+}
+
+/// Reclaim the space of all outdated items in the storage.
+///
+/// The storage is rewritten until every sector is erased once, so it takes a while and wears the flash. It's not needed
+/// in normal use, because the outdated items are reclaimed automatically when the storage is running out of space.
+/// The storage is compacted at most once an hour, more requests are ignored.
+pub async fn compact_storage() {
+    FLASH_CHANNEL.send(FlashOperationMessage::Compact).await;
+}
+
+/// Record an error of the storage
+pub(crate) fn set_last_error(error: StorageError) {
+    STORAGE_HEALTH.lock(|h| {
+        let mut health = h.get();
+        health.last_error = Some(error);
+        h.set(health);
+    });
+}
+
+/// Flash which counts the erased sectors
+pub(crate) struct HealthFlash<F> {
+    pub(crate) flash: F,
+    pub(crate) erase_count: u32,
+    /// The erase count which is saved in the storage
+    pub(crate) saved_erase_count: u32,
+}
+
+impl<F: NorFlash> HealthFlash<F> {
+    pub(crate) fn new(flash: F) -> Self {
+        Self {
+            flash,
+            erase_count: 0,
+            saved_erase_count: 0,
+        }
+    }
+}
+
+impl<F: NorFlash> ErrorType for HealthFlash<F> {
+    type Error = F::Error;
+}
+
+impl<F: NorFlash> ReadNorFlash for HealthFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for HealthFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_count += (to - from) / F::ERASE_SIZE as u32;
+        self.flash.erase(from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.flash.write(offset, bytes).await
+    }
+}
+
+/// A stored item which is not deserialized, only its category and length are kept
+struct RawItem {
+    category: StorageCategory,
+    len: usize,
+}
+
+impl<'a> Value<'a> for RawItem {
+    fn serialize_into(&self, _buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        Err(SerializationError::InvalidData)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        Ok(RawItem {
+            category: StorageCategory::from_item(buffer),
+            len: buffer.len(),
+        })
+    }
+}
+
+/// Scan all stored items, update the usage of each category and the storage health.
+pub(crate) async fn scan_storage<F: NorFlash>(
+    flash: &mut HealthFlash<F>,
+    range: Range<u32>,
+    buffer: &mut [u8],
+) -> Result<StorageHealth, SSError<F::Error>> {
+    let word_size = F::WRITE_SIZE.max(F::READ_SIZE) as u32;
+    let mut usage = [StorageUsage::new(); STORAGE_CATEGORY_NUM];
+    let mut config_size = None;
+    {
+        let mut cache = NoCache::new();
+        let mut items = fetch_all_items::<u32, _, _>(flash, range.clone(), &mut cache, buffer).await?;
+        while let Some((key, item)) = items.next::<RawItem>(buffer).await? {
+            // The data of an item is the key(u32) and the value, padded to the word size
+            let size =
+                sequential_storage::item_overhead_size::<F>() + (4 + item.len as u32).next_multiple_of(word_size);
+            if key == StorageKeys::StorageConfig as u32 {
+                // There's only one storage config, the outdated copies are mostly written by the compaction
+                config_size = Some(size);
+            } else {
+                let usage = &mut usage[item.category as usize];
+                usage.items = usage.items.saturating_add(1);
+                usage.bytes += size;
+            }
+        }
+    }
+    if let Some(size) = config_size {
+        let usage = &mut usage[StorageCategory::Config as usize];
+        usage.items += 1;
+        usage.bytes += size;
+    }
+
+    let used = usage.iter().map(|u| u.bytes).sum();
+    let capacity = range.end - range.start;
+    let sectors = capacity / F::ERASE_SIZE as u32;
+    // Each sector has a start and an end marker, and one sector is kept empty
+    let usable = sectors.saturating_sub(1) * (F::ERASE_SIZE as u32 - 2 * word_size);
+    STORAGE_USAGE.lock(|u| u.set(usage));
+    Ok(STORAGE_HEALTH.lock(|h| {
+        let health = StorageHealth {
+            capacity,
+            used,
+            free: usable.saturating_sub(used),
+            erase_count: flash.erase_count,
+            last_error: h.get().last_error,
+        };
+        h.set(health);
+        health
+    }))
+}
+
+/// Reclaim the space of all outdated items, returns the number of rewrites.
+///
+/// The storage config is rewritten as is, so that the storage moves forward, and `sequential-storage` moves the
+/// latest items out of the oldest sector before erasing it. It's done when every sector is erased once, then the only
+/// outdated items are the copies of the storage config, which are reclaimed automatically like any other outdated item.
+pub(crate) async fn compact<F: NorFlash>(
+    flash: &mut HealthFlash<F>,
+    range: Range<u32>,
+    buffer: &mut [u8],
+) -> Result<u32, SSError<F::Error>> {
+    let mut cache = NoCache::new();
+    let key = StorageKeys::StorageConfig as u32;
+    let Some(config) = fetch_item::<u32, StorageData, _>(flash, range.clone(), &mut cache, buffer, &key).await? else {
+        // The storage is empty
+        return Ok(0);
+    };
+
+    let capacity = range.end - range.start;
+    let sectors = capacity / F::ERASE_SIZE as u32;
+    // Each rewrite takes at least one word, so the storage has wrapped around twice before reaching the limit
+    let max_rewrites = 2 * capacity / F::WRITE_SIZE.max(F::READ_SIZE) as u32;
+    let start = flash.erase_count;
+    let mut rewrites = 0;
+    while flash.erase_count - start < sectors {
+        if rewrites >= max_rewrites {
+            warn!("Storage compaction stopped after {} rewrites", rewrites);
+            break;
+        }
+        store_item(flash, range.clone(), &mut cache, buffer, &key, &config).await?;
+        rewrites += 1;
+    }
+    Ok(rewrites)
+}
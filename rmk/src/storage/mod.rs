@@ -1,12 +1,15 @@
 pub mod dummy_flash;
+mod health;
 
 use core::fmt::Debug;
 use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use byteorder::{BigEndian, ByteOrder};
 use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_futures::select::{Either, select};
 use embassy_sync::signal::Signal;
-use embassy_time::Duration;
+use embassy_time::{Duration, Instant, Timer};
 use embedded_storage::nor_flash::NorFlash;
 use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
 use heapless::Vec;
@@ -16,7 +19,7 @@ use sequential_storage::map::{SerializationError, Value, fetch_all_items, fetch_
 #[cfg(feature = "_ble")]
 use {
     crate::ble::trouble::ble_server::CCCD_TABLE_SIZE,
-    crate::ble::trouble::profile::ProfileInfo,
+    crate::ble::trouble::profile::{HOST_NAME_MAX_LEN, ProfileInfo, ProfileMeta},
     trouble_host::{BondInformation, IdentityResolvingKey, LongTermKey, prelude::*},
 };
 
@@ -26,27 +29,53 @@ use crate::combo::Combo;
 use crate::config::{self, StorageConfig};
 use crate::fork::{Fork, StateBits};
 use crate::hid_state::{HidModifiers, HidMouseButtons};
-use crate::light::LedIndicator;
+use crate::input_device::adc::analog_matrix::KeyCalibration;
+use crate::input_device::battery::{BatteryCalibration, set_battery_calibration};
+use crate::light::{LedIndicator, LightingConfig, set_lighting_config};
 use crate::morse::{Morse, MorseMode, MorsePattern};
 #[cfg(all(feature = "_ble", feature = "split"))]
 use crate::split::ble::PeerAddress;
 use crate::via::keycode_convert::{from_via_keycode, to_via_keycode};
 use crate::{BUILD_HASH, COMBO_MAX_LENGTH, COMBO_MAX_NUM, FORK_MAX_NUM, MACRO_SPACE_SIZE, MORSE_MAX_NUM};
+#[cfg(feature = "controller")]
+use crate::{
+    channel::{CONTROLLER_CHANNEL, send_controller_event},
+    event::ControllerEvent,
+};
+use health::HealthFlash;
+pub use health::{
+    STORAGE_CATEGORY_NUM, StorageCategory, StorageError, StorageHealth, StorageUsage, compact_storage, storage_health,
+    storage_usage,
+};
+
+/// Delay of scanning the storage health after the last flash operation
+const HEALTH_SCAN_DELAY: Duration = Duration::from_secs(2);
+
+/// Minimum interval between two compactions of the storage, because a compaction erases every sector
+const MIN_COMPACTION_INTERVAL: Duration = Duration::from_secs(3600);
 
 /// Signal to synchronize the flash operation status, usually used outside of the flash task.
 /// True if the flash operation is finished correctly, false if the flash operation is finished with error.
 pub(crate) static FLASH_OPERATION_FINISHED: Signal<crate::RawMutex, bool> = Signal::new();
 
+/// Size of the storage region in bytes, it's 0 before the storage is initialized
+pub(crate) static STORAGE_SIZE: AtomicU32 = AtomicU32::new(0);
+
 // Message send from bonder to flash task, which will do saving or clearing operation
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub(crate) enum FlashOperationMessage {
+    // Reclaim the space of outdated items
+    Compact,
     #[cfg(feature = "_ble")]
     // BLE profile info to be saved
     ProfileInfo(ProfileInfo),
     #[cfg(feature = "_ble")]
     // Current active BLE profile number
     ActiveBleProfile(u8),
+    #[cfg(feature = "_ble")]
+    // Host name and host OS of a BLE profile
+    ProfileMeta(ProfileMeta),
     #[cfg(all(feature = "_ble", feature = "split"))]
     // Peer address
     PeerAddress(PeerAddress),
@@ -95,6 +124,20 @@ pub(crate) enum FlashOperationMessage {
     PriorIdleTime(u16),
     // Whether the unilateral tap is enabled
     UnilateralTap(bool),
+    // Calibration of an analog key
+    AnalogCalibration {
+        row: u8,
+        col: u8,
+        calibration: KeyCalibration,
+    },
+    // Mouse key config
+    MouseKeyConfig(config::MouseKeyConfig),
+    // Whether Vial is unlocked
+    VialLockState(bool),
+    // Lighting settings
+    LightingConfig(LightingConfig),
+    // Calibration of the battery level
+    BatteryCalibration(BatteryCalibration),
 }
 
 /// StorageKeys is the prefix digit stored in the flash, it's used to identify the type of the stored data.
@@ -113,6 +156,14 @@ pub(crate) enum StorageKeys {
     EncoderKeys = 7,
     ForkData = 8,
     MorseData = 9,
+    AnalogCalibration = 10,
+    MouseKeyConfig = 11,
+    VialLockState = 12,
+    LightingConfig = 13,
+    BatteryCalibration = 14,
+    EraseCount = 15,
+    #[cfg(feature = "_ble")]
+    BleProfileMeta = 0xEC,
     #[cfg(all(feature = "_ble", feature = "split"))]
     PeerAddress = 0xED,
     #[cfg(feature = "_ble")]
@@ -134,6 +185,14 @@ impl StorageKeys {
             7 => Some(StorageKeys::EncoderKeys),
             8 => Some(StorageKeys::ForkData),
             9 => Some(StorageKeys::MorseData),
+            10 => Some(StorageKeys::AnalogCalibration),
+            11 => Some(StorageKeys::MouseKeyConfig),
+            12 => Some(StorageKeys::VialLockState),
+            13 => Some(StorageKeys::LightingConfig),
+            14 => Some(StorageKeys::BatteryCalibration),
+            15 => Some(StorageKeys::EraseCount),
+            #[cfg(feature = "_ble")]
+            0xEC => Some(StorageKeys::BleProfileMeta),
             #[cfg(all(feature = "_ble", feature = "split"))]
             0xED => Some(StorageKeys::PeerAddress),
             #[cfg(feature = "_ble")]
@@ -158,12 +217,21 @@ pub(crate) enum StorageData {
     ConnectionType(u8),
     ForkData(ForkData),
     MorseData(Morse),
+    AnalogCalibration(AnalogCalibrationData),
+    MouseKeyConfig(config::MouseKeyConfig),
+    VialLockState(bool),
+    LightingConfig(LightingConfig),
+    BatteryCalibration(BatteryCalibration),
+    /// Number of erased sectors of the storage
+    EraseCount(u32),
     #[cfg(all(feature = "_ble", feature = "split"))]
     PeerAddress(PeerAddress),
     #[cfg(feature = "_ble")]
     BondInfo(ProfileInfo),
     #[cfg(feature = "_ble")]
     ActiveBleProfile(u8),
+    #[cfg(feature = "_ble")]
+    ProfileMeta(ProfileMeta),
 }
 
 /// Get the key to retrieve the keymap key from the storage.
@@ -180,6 +248,11 @@ pub(crate) fn get_bond_info_key(slot_num: u8) -> u32 {
     0x2000 + slot_num as u32
 }
 
+/// Get the key to retrieve the host name and host OS of a BLE profile from the storage.
+pub(crate) fn get_profile_meta_key(slot_num: u8) -> u32 {
+    0x2100 + slot_num as u32
+}
+
 /// Get the key to retrieve the combo from the storage.
 pub(crate) fn get_combo_key(idx: usize) -> u32 {
     0x3000 + idx as u32
@@ -204,6 +277,11 @@ pub(crate) fn get_morse_key(idx: u8) -> u32 {
     0x7000 + idx as u32
 }
 
+/// Get the key to retrieve the calibration of an analog key from the storage.
+pub(crate) fn get_analog_calibration_key(row: u8, col: u8) -> u32 {
+    0x8000 + ((row as u32) << 8 | col as u32)
+}
+
 // TODO: Move ser/de code to corresponding structs
 impl Value<'_> for StorageData {
     fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
@@ -356,6 +434,77 @@ impl Value<'_> for StorageData {
                 buffer[1] = *ty;
                 Ok(2)
             }
+            StorageData::VialLockState(unlocked) => {
+                buffer[0] = StorageKeys::VialLockState as u8;
+                buffer[1] = *unlocked as u8;
+                Ok(2)
+            }
+            StorageData::LightingConfig(c) => {
+                if buffer.len() < 3 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::LightingConfig as u8;
+                buffer[1] = c.indicators as u8;
+                buffer[2] = c.low_battery;
+                Ok(3)
+            }
+            StorageData::BatteryCalibration(c) => {
+                if buffer.len() < 5 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::BatteryCalibration as u8;
+                BigEndian::write_u16(&mut buffer[1..3], c.empty);
+                BigEndian::write_u16(&mut buffer[3..5], c.full);
+                Ok(5)
+            }
+            StorageData::EraseCount(count) => {
+                if buffer.len() < 5 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::EraseCount as u8;
+                BigEndian::write_u32(&mut buffer[1..5], *count);
+                Ok(5)
+            }
+            StorageData::AnalogCalibration(c) => {
+                if buffer.len() < 7 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::AnalogCalibration as u8;
+                buffer[1] = c.row;
+                buffer[2] = c.col;
+                BigEndian::write_u16(&mut buffer[3..5], c.calibration.rest);
+                BigEndian::write_u16(&mut buffer[5..7], c.calibration.bottom);
+                Ok(7)
+            }
+            StorageData::MouseKeyConfig(c) => {
+                if buffer.len() < 8 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::MouseKeyConfig as u8;
+                BigEndian::write_u16(&mut buffer[1..3], c.initial_delay_ms);
+                BigEndian::write_u16(&mut buffer[3..5], c.repeat_interval_ms);
+                buffer[5] = c.move_delta;
+                buffer[6] = c.max_speed;
+                buffer[7] = c.time_to_max;
+                Ok(8)
+            }
+            #[cfg(feature = "_ble")]
+            StorageData::ProfileMeta(m) => {
+                // Layout: key, slot, host name length, host name, settings.
+                // New settings are appended to the end, so that the meta saved by older firmware can still be read.
+                let name = m.host_name.as_bytes();
+                let settings = 3 + name.len();
+                if buffer.len() < settings + 2 {
+                    return Err(SerializationError::BufferTooSmall);
+                }
+                buffer[0] = StorageKeys::BleProfileMeta as u8;
+                buffer[1] = m.slot_num;
+                buffer[2] = name.len() as u8;
+                buffer[3..settings].copy_from_slice(name);
+                buffer[settings] = m.host_os as u8;
+                buffer[settings + 1] = m.latency_mode as u8;
+                Ok(settings + 2)
+            }
             #[cfg(all(feature = "_ble", feature = "split"))]
             StorageData::PeerAddress(p) => {
                 if buffer.len() < 9 {
@@ -504,6 +653,31 @@ impl Value<'_> for StorageData {
                     }))
                 }
                 StorageKeys::ConnectionType => Ok(StorageData::ConnectionType(buffer[1])),
+                StorageKeys::VialLockState => Ok(StorageData::VialLockState(buffer[1] == 1)),
+                StorageKeys::LightingConfig => {
+                    if buffer.len() < 3 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    Ok(StorageData::LightingConfig(LightingConfig {
+                        indicators: buffer[1] == 1,
+                        low_battery: buffer[2],
+                    }))
+                }
+                StorageKeys::BatteryCalibration => {
+                    if buffer.len() < 5 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    Ok(StorageData::BatteryCalibration(BatteryCalibration {
+                        empty: BigEndian::read_u16(&buffer[1..3]),
+                        full: BigEndian::read_u16(&buffer[3..5]),
+                    }))
+                }
+                StorageKeys::EraseCount => {
+                    if buffer.len() < 5 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    Ok(StorageData::EraseCount(BigEndian::read_u32(&buffer[1..5])))
+                }
                 StorageKeys::EncoderKeys => {
                     if buffer.len() < 7 {
                         return Err(SerializationError::BufferTooSmall);
@@ -589,6 +763,56 @@ impl Value<'_> for StorageData {
 
                     Ok(StorageData::MorseData(morse))
                 }
+                StorageKeys::AnalogCalibration => {
+                    if buffer.len() < 7 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    Ok(StorageData::AnalogCalibration(AnalogCalibrationData {
+                        row: buffer[1],
+                        col: buffer[2],
+                        calibration: KeyCalibration {
+                            rest: BigEndian::read_u16(&buffer[3..5]),
+                            bottom: BigEndian::read_u16(&buffer[5..7]),
+                        },
+                    }))
+                }
+                StorageKeys::MouseKeyConfig => {
+                    if buffer.len() < 8 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    // Only the settings which can be changed in VIA are saved
+                    Ok(StorageData::MouseKeyConfig(config::MouseKeyConfig {
+                        initial_delay_ms: BigEndian::read_u16(&buffer[1..3]),
+                        repeat_interval_ms: BigEndian::read_u16(&buffer[3..5]),
+                        move_delta: buffer[5],
+                        max_speed: buffer[6],
+                        time_to_max: buffer[7],
+                        ..Default::default()
+                    }))
+                }
+                #[cfg(feature = "_ble")]
+                StorageKeys::BleProfileMeta => {
+                    if buffer.len() < 3 {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    let len = buffer[2] as usize;
+                    if len > HOST_NAME_MAX_LEN || buffer.len() < 3 + len {
+                        return Err(SerializationError::InvalidData);
+                    }
+                    let host_name = core::str::from_utf8(&buffer[3..3 + len])
+                        .ok()
+                        .and_then(|name| heapless::String::try_from(name).ok())
+                        .ok_or(SerializationError::InvalidData)?;
+                    // Settings which are not saved take the default value
+                    let settings = &buffer[3 + len..];
+                    let setting = |i: usize| settings.get(i).copied().unwrap_or_default();
+                    Ok(StorageData::ProfileMeta(ProfileMeta {
+                        slot_num: buffer[1],
+                        host_os: setting(0).into(),
+                        latency_mode: setting(1).into(),
+                        host_name,
+                    }))
+                }
                 #[cfg(all(feature = "_ble", feature = "split"))]
                 StorageKeys::PeerAddress => {
                     if buffer.len() < 9 {
@@ -613,10 +837,23 @@ impl Value<'_> for StorageData {
                 }
                 #[cfg(feature = "_ble")]
                 StorageKeys::BleBondInfo => {
-                    if buffer.len() < 40 + CCCD_TABLE_SIZE * 4 {
+                    if buffer.len() < 40 {
                         return Err(SerializationError::BufferTooSmall);
                     }
                     let slot_num = buffer[1];
+                    // The CCCD table saved by other firmware has a different size when the GATT server is changed,
+                    // so the saved handles don't match the attributes anymore. Invalidate the bond, the host has to pair again
+                    if buffer.len() != 40 + CCCD_TABLE_SIZE * 4 {
+                        warn!(
+                            "The CCCD table of bond {} doesn't match the GATT server, remove it",
+                            slot_num
+                        );
+                        return Ok(StorageData::BondInfo(ProfileInfo {
+                            slot_num,
+                            removed: true,
+                            ..Default::default()
+                        }));
+                    }
                     let ltk = LongTermKey::from_le_bytes(buffer[2..18].try_into().unwrap());
                     let address = BdAddr::new(buffer[18..24].try_into().unwrap());
                     let irk = IdentityResolvingKey::from_le_bytes(buffer[24..40].try_into().unwrap());
@@ -676,18 +913,26 @@ impl StorageData {
                 panic!("To get combo key for ComboData, use `get_combo_key` instead");
             }
             StorageData::ConnectionType(_) => StorageKeys::ConnectionType as u32,
+            StorageData::VialLockState(_) => StorageKeys::VialLockState as u32,
+            StorageData::LightingConfig(_) => StorageKeys::LightingConfig as u32,
+            StorageData::BatteryCalibration(_) => StorageKeys::BatteryCalibration as u32,
+            StorageData::EraseCount(_) => StorageKeys::EraseCount as u32,
             StorageData::ForkData(_) => {
                 panic!("To get fork key for ForkData, use `get_fork_key` instead");
             }
             StorageData::MorseData(_) => {
                 panic!("To get morse key for MorseData, use `get_morse_key` instead");
             }
+            StorageData::AnalogCalibration(c) => get_analog_calibration_key(c.row, c.col),
+            StorageData::MouseKeyConfig(_) => StorageKeys::MouseKeyConfig as u32,
             #[cfg(all(feature = "_ble", feature = "split"))]
             StorageData::PeerAddress(p) => get_peer_address_key(p.peer_id),
             #[cfg(feature = "_ble")]
             StorageData::ActiveBleProfile(_) => StorageKeys::ActiveBleProfile as u32,
             #[cfg(feature = "_ble")]
             StorageData::BondInfo(b) => get_bond_info_key(b.slot_num),
+            #[cfg(feature = "_ble")]
+            StorageData::ProfileMeta(m) => get_profile_meta_key(m.slot_num),
         }
     }
 }
@@ -740,6 +985,14 @@ pub(crate) struct ForkData {
     pub(crate) fork: Fork,
 }
 
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct AnalogCalibrationData {
+    pub(crate) row: u8,
+    pub(crate) col: u8,
+    pub(crate) calibration: KeyCalibration,
+}
+
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub(crate) struct BehaviorConfig {
@@ -779,7 +1032,7 @@ pub struct Storage<
     const NUM_LAYER: usize,
     const NUM_ENCODER: usize = 0,
 > {
-    pub(crate) flash: F,
+    pub(crate) flash: HealthFlash<F>,
     pub(crate) storage_range: Range<u32>,
     pub(crate) buffer: [u8; get_buffer_size()],
 }
@@ -854,12 +1107,17 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             start_addr as u32..(start_addr + storage_config.num_sectors as usize * F::ERASE_SIZE) as u32
         };
 
+        STORAGE_SIZE.store(storage_range.end - storage_range.start, Ordering::Relaxed);
+
         let mut storage = Self {
-            flash,
+            flash: HealthFlash::new(flash),
             storage_range,
             buffer: [0; get_buffer_size()],
         };
 
+        // Restore the erase count before the storage is cleared, so that it's kept across clearing the storage
+        storage.read_erase_count().await;
+
         // Check whether keymap and configs have been storaged in flash
         if !storage.check_enable().await || storage_config.clear_storage {
             // Clear storage first
@@ -894,10 +1152,48 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
 
     pub(crate) async fn run(&mut self) {
         let mut storage_cache = NoCache::new();
+        #[cfg(feature = "controller")]
+        let mut controller_pub = unwrap!(CONTROLLER_CHANNEL.publisher());
+        let mut health = self.update_health().await;
+        #[cfg(feature = "controller")]
+        send_controller_event(&mut controller_pub, ControllerEvent::StorageHealth(health));
+        // Whether the storage is changed after the last scan of its health
+        let mut health_outdated = false;
+        let mut last_compaction: Option<Instant> = None;
         loop {
-            let info: FlashOperationMessage = FLASH_CHANNEL.receive().await;
+            let info: FlashOperationMessage = if health_outdated {
+                // Scan the storage when there's no flash operation for a while, instead of after every batch
+                match select(FLASH_CHANNEL.receive(), Timer::after(HEALTH_SCAN_DELAY)).await {
+                    Either::First(info) => info,
+                    Either::Second(_) => {
+                        health_outdated = false;
+                        let new_health = self.update_health().await;
+                        if new_health != health {
+                            health = new_health;
+                            #[cfg(feature = "controller")]
+                            send_controller_event(&mut controller_pub, ControllerEvent::StorageHealth(health));
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                FLASH_CHANNEL.receive().await
+            };
             debug!("Flash operation: {:?}", info);
+            health_outdated = true;
             match match info {
+                FlashOperationMessage::Compact => {
+                    if last_compaction.is_some_and(|t| t.elapsed() < MIN_COMPACTION_INTERVAL) {
+                        warn!("Storage was compacted recently, the compaction is skipped");
+                        Ok(())
+                    } else {
+                        info!("Compacting storage");
+                        last_compaction = Some(Instant::now());
+                        health::compact(&mut self.flash, self.storage_range.clone(), &mut self.buffer)
+                            .await
+                            .map(|rewrites| debug!("Storage compacted after {} rewrites", rewrites))
+                    }
+                }
                 FlashOperationMessage::LayoutOptions(layout_option) => {
                     // Read out layout options, update layer option and save back
                     update_storage_field!(
@@ -981,6 +1277,42 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                     )
                     .await
                 }
+                FlashOperationMessage::LightingConfig(c) => {
+                    let data = StorageData::LightingConfig(c);
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
+                FlashOperationMessage::BatteryCalibration(c) => {
+                    let data = StorageData::BatteryCalibration(c);
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
+                FlashOperationMessage::VialLockState(unlocked) => {
+                    let data = StorageData::VialLockState(unlocked);
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
                 FlashOperationMessage::EncoderKey { idx, layer, action } => {
                     let data = StorageData::EncoderConfig(EncoderConfig {
                         idx: idx as usize,
@@ -1022,6 +1354,30 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                     )
                     .await
                 }
+                FlashOperationMessage::MouseKeyConfig(c) => {
+                    let data = StorageData::MouseKeyConfig(c);
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
+                FlashOperationMessage::AnalogCalibration { row, col, calibration } => {
+                    let data = StorageData::AnalogCalibration(AnalogCalibrationData { row, col, calibration });
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
                 #[cfg(all(feature = "_ble", feature = "split"))]
                 FlashOperationMessage::PeerAddress(peer) => {
                     let key = get_peer_address_key(peer.peer_id);
@@ -1067,6 +1423,19 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                     .await
                 }
                 #[cfg(feature = "_ble")]
+                FlashOperationMessage::ProfileMeta(m) => {
+                    let data = StorageData::ProfileMeta(m);
+                    store_item::<u32, StorageData, _>(
+                        &mut self.flash,
+                        self.storage_range.clone(),
+                        &mut storage_cache,
+                        &mut self.buffer,
+                        &data.key(),
+                        &data,
+                    )
+                    .await
+                }
+                #[cfg(feature = "_ble")]
                 FlashOperationMessage::ProfileInfo(b) => {
                     debug!("Saving profile info: {:?}", b);
                     let data = StorageData::BondInfo(b);
@@ -1140,7 +1509,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 _ => Ok(()),
             } {
                 Err(e) => {
-                    print_storage_error::<F>(e);
+                    report_storage_error::<F>(e);
                     FLASH_OPERATION_FINISHED.signal(false);
                 }
                 _ => {
@@ -1150,6 +1519,50 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         }
     }
 
+    /// Read the erase count saved in the storage
+    async fn read_erase_count(&mut self) {
+        if let Ok(Some(StorageData::EraseCount(count))) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::EraseCount as u32),
+        )
+        .await
+        {
+            self.flash.erase_count = count;
+            self.flash.saved_erase_count = count;
+        }
+    }
+
+    /// Save the erase count and scan the storage to update its health
+    pub(crate) async fn update_health(&mut self) -> StorageHealth {
+        if self.flash.erase_count != self.flash.saved_erase_count {
+            let count = self.flash.erase_count;
+            match store_item(
+                &mut self.flash,
+                self.storage_range.clone(),
+                &mut NoCache::new(),
+                &mut self.buffer,
+                &(StorageKeys::EraseCount as u32),
+                &StorageData::EraseCount(count),
+            )
+            .await
+            {
+                // Sectors erased by saving the count are saved next time
+                Ok(_) => self.flash.saved_erase_count = count,
+                Err(e) => report_storage_error::<F>(e),
+            }
+        }
+        match health::scan_storage(&mut self.flash, self.storage_range.clone(), &mut self.buffer).await {
+            Ok(health) => health,
+            Err(e) => {
+                report_storage_error::<F>(e);
+                storage_health()
+            }
+        }
+    }
+
     pub(crate) async fn read_keymap(
         &mut self,
         keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
@@ -1164,13 +1577,13 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &mut self.buffer,
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         // Read all keymap keys and encoder configs
         while let Some((_key, item)) = key_iterator
             .next::<StorageData>(&mut self.buffer)
             .await
-            .map_err(|e| print_storage_error::<F>(e))?
+            .map_err(|e| report_storage_error::<F>(e))?
         {
             match item {
                 StorageData::KeymapKey(key) => {
@@ -1202,7 +1615,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &(StorageKeys::MacroData as u32),
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         if let Some(StorageData::MacroData(data)) = read_data {
             // Send data back
@@ -1223,7 +1636,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 &key,
             )
             .await
-            .map_err(|e| print_storage_error::<F>(e))?;
+            .map_err(|e| report_storage_error::<F>(e))?;
 
             if let Some(StorageData::ComboData(combo)) = read_data {
                 let mut actions: Vec<KeyAction, COMBO_MAX_LENGTH> = Vec::new();
@@ -1248,7 +1661,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 &key,
             )
             .await
-            .map_err(|e| print_storage_error::<F>(e))?;
+            .map_err(|e| report_storage_error::<F>(e))?;
 
             if let Some(StorageData::ForkData(fork)) = read_data {
                 *item = fork.fork;
@@ -1269,7 +1682,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                 &key,
             )
             .await
-            .map_err(|e| print_storage_error::<F>(e))?;
+            .map_err(|e| report_storage_error::<F>(e))?;
 
             if let Some(StorageData::MorseData(morse)) = read_data {
                 *item = morse;
@@ -1291,7 +1704,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &(StorageKeys::BehaviorConfig as u32),
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?
+        .map_err(|e| report_storage_error::<F>(e))?
         {
             behavior_config.tap_hold.timeout = Duration::from_millis(c.morse_timeout as u64);
             behavior_config.tap_hold.prior_idle_time = Duration::from_millis(c.prior_idle_time as u64);
@@ -1302,6 +1715,123 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             behavior_config.tap.tap_capslock_interval = c.tap_capslock_interval;
         }
 
+        if let Some(StorageData::MouseKeyConfig(c)) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::MouseKeyConfig as u32),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?
+        {
+            let mouse_key = &mut behavior_config.mouse_key;
+            mouse_key.initial_delay_ms = c.initial_delay_ms;
+            mouse_key.repeat_interval_ms = c.repeat_interval_ms;
+            mouse_key.move_delta = c.move_delta;
+            mouse_key.max_speed = c.max_speed;
+            mouse_key.time_to_max = c.time_to_max;
+        }
+
+        Ok(())
+    }
+
+    /// Read the lighting settings and the battery calibration
+    pub(crate) async fn read_lighting_and_battery(&mut self) -> Result<(), ()> {
+        if let Some(StorageData::LightingConfig(c)) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::LightingConfig as u32),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?
+        {
+            set_lighting_config(c);
+        }
+
+        if let Some(StorageData::BatteryCalibration(c)) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::BatteryCalibration as u32),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?
+        {
+            set_battery_calibration(c);
+        }
+
+        Ok(())
+    }
+
+    /// Read the Vial lock state, Vial is locked if the state isn't saved
+    pub(crate) async fn read_vial_lock_state(&mut self) -> Result<(), ()> {
+        #[cfg(feature = "vial_lock")]
+        if let Some(StorageData::VialLockState(unlocked)) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::VialLockState as u32),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?
+        {
+            crate::via::restore_lock_state(unlocked);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn read_layout_option(&mut self, layout_option: &mut u32) -> Result<(), ()> {
+        if let Some(StorageData::LayoutConfig(c)) = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(StorageKeys::LayoutConfig as u32),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?
+        {
+            *layout_option = c.layout_option;
+        }
+
+        Ok(())
+    }
+
+    /// Read calibration of analog keys, keys which are not calibrated are not changed
+    pub async fn read_analog_calibration<const MATRIX_ROW: usize, const MATRIX_COL: usize>(
+        &mut self,
+        calibration: &mut [[KeyCalibration; MATRIX_COL]; MATRIX_ROW],
+    ) -> Result<(), StorageError> {
+        let mut storage_cache = NoCache::new();
+        let mut key_iterator = fetch_all_items::<u32, _, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut storage_cache,
+            &mut self.buffer,
+        )
+        .await
+        .map_err(storage_error::<F>)?;
+
+        while let Some((_key, item)) = key_iterator
+            .next::<StorageData>(&mut self.buffer)
+            .await
+            .map_err(storage_error::<F>)?
+        {
+            if let StorageData::AnalogCalibration(c) = item
+                && let Some(key) = calibration
+                    .get_mut(c.row as usize)
+                    .and_then(|r| r.get_mut(c.col as usize))
+            {
+                *key = c.calibration;
+            }
+        }
+
         Ok(())
     }
 
@@ -1326,7 +1856,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &storage_config,
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         // Save layout config
         let layout_config = StorageData::LayoutConfig(LayoutConfig {
@@ -1342,7 +1872,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &layout_config,
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         // Save behavior config
         let behavior_config = StorageData::BehaviorConfig(BehaviorConfig {
@@ -1364,7 +1894,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &behavior_config,
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         for (layer, layer_data) in keymap.iter().enumerate() {
             for (row, row_data) in layer_data.iter().enumerate() {
@@ -1387,7 +1917,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         &item,
                     )
                     .await
-                    .map_err(|e| print_storage_error::<F>(e))?;
+                    .map_err(|e| report_storage_error::<F>(e))?;
                 }
             }
         }
@@ -1413,7 +1943,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
                         &item,
                     )
                     .await
-                    .map_err(|e| print_storage_error::<F>(e))?;
+                    .map_err(|e| report_storage_error::<F>(e))?;
                 }
             }
         }
@@ -1439,6 +1969,32 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         false
     }
 
+    /// Erase the whole storage
+    pub(crate) async fn erase_all(&mut self) -> Result<(), ()> {
+        sequential_storage::erase_all(&mut self.flash, self.storage_range.clone())
+            .await
+            .map_err(|e| report_storage_error::<F>(e))
+    }
+
+    /// Remove the bond info of a BLE profile
+    #[cfg(feature = "_ble")]
+    pub(crate) async fn remove_bond_info(&mut self, slot_num: u8) -> Result<(), ()> {
+        let mut empty = ProfileInfo::default();
+        empty.removed = true;
+        empty.slot_num = slot_num;
+        let data = StorageData::BondInfo(empty);
+        store_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &data.key(),
+            &data,
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))
+    }
+
     #[cfg(feature = "_ble")]
     pub(crate) async fn read_trouble_bond_info(&mut self, slot_num: u8) -> Result<Option<ProfileInfo>, ()> {
         let read_data = fetch_item::<u32, StorageData, _>(
@@ -1449,7 +2005,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &get_bond_info_key(slot_num),
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         if let Some(StorageData::BondInfo(info)) = read_data {
             Ok(Some(info))
@@ -1458,6 +2014,25 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
         }
     }
 
+    #[cfg(feature = "_ble")]
+    pub(crate) async fn read_profile_meta(&mut self, slot_num: u8) -> Result<Option<ProfileMeta>, ()> {
+        let read_data = fetch_item::<u32, StorageData, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &get_profile_meta_key(slot_num),
+        )
+        .await
+        .map_err(|e| report_storage_error::<F>(e))?;
+
+        if let Some(StorageData::ProfileMeta(meta)) = read_data {
+            Ok(Some(meta))
+        } else {
+            Ok(None)
+        }
+    }
+
     #[cfg(all(feature = "_ble", feature = "split"))]
     pub async fn read_peer_address(&mut self, peer_id: u8) -> Result<Option<PeerAddress>, ()> {
         let read_data = fetch_item::<u32, StorageData, _>(
@@ -1468,7 +2043,7 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &get_peer_address_key(peer_id),
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))?;
+        .map_err(|e| report_storage_error::<F>(e))?;
 
         if let Some(StorageData::PeerAddress(data)) = read_data {
             Ok(Some(data))
@@ -1491,11 +2066,20 @@ impl<F: AsyncNorFlash, const ROW: usize, const COL: usize, const NUM_LAYER: usiz
             &item,
         )
         .await
-        .map_err(|e| print_storage_error::<F>(e))
+        .map_err(|e| report_storage_error::<F>(e))
     }
 }
 
-fn print_storage_error<F: AsyncNorFlash>(e: SSError<F::Error>) {
+/// Print the storage error and record it as the last error
+/// Report the error like [`report_storage_error`], and convert it to a [`StorageError`] for the caller
+fn storage_error<F: AsyncNorFlash>(e: SSError<F::Error>) -> StorageError {
+    let error = StorageError::from(&e);
+    report_storage_error::<F>(e);
+    error
+}
+
+fn report_storage_error<F: AsyncNorFlash>(e: SSError<F::Error>) {
+    health::set_last_error((&e).into());
     match e {
         #[cfg(feature = "defmt")]
         SSError::Storage { value: e } => error!("Flash error: {:?}", defmt::Debug2Format(&e)),
@@ -1542,12 +2126,189 @@ macro_rules! read_storage {
 
 #[cfg(test)]
 mod tests {
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
     use sequential_storage::map::Value;
 
     use super::*;
     use crate::action::Action;
     use crate::keycode::KeyCode;
     use crate::morse::{HOLD, MorseMode, TAP};
+    use crate::storage::dummy_flash::{MemoryFlash, MemoryFlashError};
+
+    /// 8 sectors of 1KB, the storage uses the last 4 sectors
+    type TestFlash = MemoryFlash<8192, 1024>;
+    type TestStorage = Storage<TestFlash, 2, 2, 2>;
+
+    const TEST_STORAGE_CONFIG: StorageConfig = StorageConfig {
+        start_addr: 0,
+        num_sectors: 4,
+        clear_storage: false,
+    };
+
+    fn new_test_storage(flash: TestFlash) -> TestStorage {
+        let keymap = [[[KeyAction::No; 2]; 2]; 2];
+        block_on(Storage::new(
+            flash,
+            &keymap,
+            &None,
+            &TEST_STORAGE_CONFIG,
+            &config::BehaviorConfig::default(),
+        ))
+    }
+
+    /// Action of the n-th write, A to Z
+    fn nth_action(n: usize) -> KeyAction {
+        from_via_keycode(0x04 + (n % 26) as u16)
+    }
+
+    fn write_key(storage: &mut TestStorage, layer: usize, action: KeyAction) -> Result<(), SSError<MemoryFlashError>> {
+        let data = StorageData::KeymapKey(KeymapKey {
+            row: 0,
+            col: 1,
+            layer,
+            action,
+        });
+        block_on(store_item(
+            &mut storage.flash,
+            storage.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut storage.buffer,
+            &get_keymap_key::<2, 2, 2>(0, 1, layer),
+            &data,
+        ))
+    }
+
+    fn read_key(storage: &mut TestStorage, layer: usize) -> KeyAction {
+        let mut keymap = [[[KeyAction::No; 2]; 2]; 2];
+        block_on(storage.read_keymap(&mut keymap, &mut None)).expect("Failed to read keymap");
+        keymap[layer][0][1]
+    }
+
+    /// Run `operation` with the power lost at each flash operation, then check the storage after rebooting
+    fn check_power_loss(
+        base: &TestFlash,
+        operation: impl Fn(&mut TestStorage) -> usize,
+        check: impl Fn(&mut TestStorage, usize),
+    ) {
+        // Count the flash operations when the power is never lost
+        let mut storage = new_test_storage(base.clone());
+        let start = storage.flash.flash.operations;
+        operation(&mut storage);
+        let total = storage.flash.flash.operations - start;
+        assert!(total > 0);
+
+        for n in 0..total {
+            let mut storage = new_test_storage(base.clone());
+            storage.flash.flash.power_loss_after = Some(storage.flash.flash.operations + n);
+            let done = operation(&mut storage);
+            assert!(storage.flash.flash.power_lost(), "power is not lost at {}", n);
+
+            let mut flash = storage.flash.flash;
+            flash.power_on();
+            let mut storage = new_test_storage(flash);
+            check(&mut storage, done);
+            // The storage is still writable
+            write_key(&mut storage, 1, nth_action(7)).unwrap_or_else(|e| panic!("write after {}: {:?}", n, e));
+            assert_eq!(read_key(&mut storage, 1), nth_action(7));
+        }
+    }
+
+    #[test]
+    fn test_storage_power_loss_during_writes() {
+        let mut storage = new_test_storage(TestFlash::new());
+        write_key(&mut storage, 0, nth_action(0)).unwrap();
+        let base = storage.flash.flash.clone();
+
+        // Enough writes to wrap around the storage, so that sectors are reclaimed
+        const WRITES: usize = 120;
+        check_power_loss(
+            &base,
+            |storage| {
+                // Number of finished writes
+                (1..=WRITES)
+                    .take_while(|&i| write_key(storage, 0, nth_action(i)).is_ok())
+                    .count()
+            },
+            |storage, done| {
+                // The interrupted write is either lost or finished
+                let action = read_key(storage, 0);
+                assert!(
+                    action == nth_action(done) || action == nth_action(done + 1),
+                    "{:?} after {} writes",
+                    action,
+                    done
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn test_storage_power_loss_during_compaction() {
+        let mut storage = new_test_storage(TestFlash::new());
+        for i in 0..60 {
+            write_key(&mut storage, 0, nth_action(i)).unwrap();
+        }
+        let base = storage.flash.flash.clone();
+
+        check_power_loss(
+            &base,
+            |storage| {
+                let range = storage.storage_range.clone();
+                block_on(health::compact(&mut storage.flash, range, &mut storage.buffer)).map_or(0, |_| 1)
+            },
+            |storage, _| assert_eq!(read_key(storage, 0), nth_action(59)),
+        );
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_storage_health_and_compaction() {
+            let mut storage = new_test_storage(TestFlash::new());
+            let initial = block_on(storage.update_health());
+            assert_eq!(initial.capacity, 4096);
+            assert_eq!(initial.last_error, None);
+            assert_eq!(storage_usage(StorageCategory::Keymap).items, 8);
+            // The storage config and the erase count
+            assert_eq!(storage_usage(StorageCategory::Config).items, 2);
+            assert_eq!(storage_usage(StorageCategory::Macro).items, 0);
+            assert_eq!(
+                initial.used + initial.free,
+                // 3 usable sectors, each has a start and an end marker
+                3 * (1024 - 2 * 4)
+            );
+
+            // Outdated copies occupy the storage
+            for i in 0..60 {
+                write_key(&mut storage, 0, nth_action(i)).unwrap();
+            }
+            let written = block_on(storage.update_health());
+            assert!(written.used > initial.used);
+            assert!(storage_usage(StorageCategory::Keymap).items > 8);
+
+            let range = storage.storage_range.clone();
+            block_on(health::compact(&mut storage.flash, range, &mut storage.buffer)).unwrap();
+            let compacted = block_on(storage.update_health());
+            // Only the latest copies are left
+            assert_eq!(storage_usage(StorageCategory::Keymap).items, 8);
+            // The erase count is saved again after the compaction
+            assert_eq!(storage_usage(StorageCategory::Config).items, 3);
+            assert!(compacted.used < written.used);
+            assert!(compacted.erase_count >= written.erase_count + 4);
+            assert_eq!(read_key(&mut storage, 0), nth_action(59));
+            assert_eq!(compacted.used_percent(), (compacted.used * 100 / (compacted.used + compacted.free)) as u8);
+
+            // The erase count is kept after reboot
+            let rebooted = new_test_storage(storage.flash.flash.clone());
+            assert_eq!(rebooted.flash.erase_count, storage.flash.saved_erase_count);
+            assert!(rebooted.flash.erase_count >= written.erase_count + 4);
+
+            // Errors are recorded
+            storage.flash.flash.power_loss_after = Some(storage.flash.flash.operations);
+            report_storage_error::<TestFlash>(write_key(&mut storage, 0, nth_action(0)).unwrap_err());
+            assert_eq!(storage_health().last_error, Some(StorageError::Flash));
+        }
+    }
 
     #[test]
     fn test_morse_serialization_deserialization() {
@@ -1664,4 +2425,160 @@ mod tests {
             _ => panic!("Expected MorseData"),
         }
     }
+
+    #[test]
+    fn test_analog_calibration_serialization_deserialization() {
+        let data = StorageData::AnalogCalibration(AnalogCalibrationData {
+            row: 3,
+            col: 14,
+            calibration: KeyCalibration {
+                rest: 2048,
+                bottom: 1320,
+            },
+        });
+        assert_eq!(data.key(), 0x8000 + (3 << 8) + 14);
+
+        let mut buffer = [0u8; 7];
+        let serialized_size = Value::serialize_into(&data, &mut buffer).unwrap();
+        assert_eq!(serialized_size, 7);
+
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::AnalogCalibration(c) => {
+                assert_eq!((c.row, c.col), (3, 14));
+                assert_eq!(
+                    c.calibration,
+                    KeyCalibration {
+                        rest: 2048,
+                        bottom: 1320
+                    }
+                );
+            }
+            _ => panic!("Expected AnalogCalibration"),
+        }
+    }
+
+    #[cfg(feature = "_ble")]
+    #[test]
+    fn test_profile_meta_serialization_deserialization() {
+        use crate::ble::trouble::profile::{BleLatencyMode, HostOs};
+
+        let data = StorageData::ProfileMeta(ProfileMeta {
+            slot_num: 2,
+            host_os: HostOs::MacOs,
+            latency_mode: BleLatencyMode::PowerSaving,
+            host_name: heapless::String::try_from("MacBook").unwrap(),
+        });
+        let mut buffer = [0u8; 64];
+        let serialized_size = Value::serialize_into(&data, &mut buffer).unwrap();
+        assert_eq!(serialized_size, 3 + 7 + 2);
+
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::ProfileMeta(m) => {
+                assert_eq!(m.slot_num, 2);
+                assert_eq!(m.host_os, HostOs::MacOs);
+                assert_eq!(m.latency_mode, BleLatencyMode::PowerSaving);
+                assert_eq!(m.host_name.as_str(), "MacBook");
+            }
+            _ => panic!("Expected ProfileMeta"),
+        }
+
+        // The meta saved by older firmware has no latency mode, which takes the default value
+        match StorageData::deserialize_from(&buffer[..serialized_size - 1]).unwrap() {
+            StorageData::ProfileMeta(m) => {
+                assert_eq!(m.host_os, HostOs::MacOs);
+                assert_eq!(m.latency_mode, BleLatencyMode::default());
+            }
+            _ => panic!("Expected ProfileMeta"),
+        }
+    }
+
+    #[cfg(feature = "_ble")]
+    #[test]
+    fn test_bond_info_with_different_cccd_table() {
+        let data = StorageData::BondInfo(ProfileInfo {
+            slot_num: 1,
+            info: BondInformation::new(
+                Identity {
+                    bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+                    irk: None,
+                },
+                LongTermKey(0x1234),
+            ),
+            ..Default::default()
+        });
+        let mut buffer = [0u8; 128];
+        let serialized_size = Value::serialize_into(&data, &mut buffer).unwrap();
+        assert_eq!(serialized_size, 40 + CCCD_TABLE_SIZE * 4);
+
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::BondInfo(b) => {
+                assert_eq!(b.slot_num, 1);
+                assert!(!b.removed);
+                assert_eq!(b.info.ltk, LongTermKey(0x1234));
+            }
+            _ => panic!("Expected BondInfo"),
+        }
+
+        // Bonds saved with a smaller or larger CCCD table are invalidated
+        for size in [serialized_size - 4, serialized_size + 4] {
+            match StorageData::deserialize_from(&buffer[..size]).unwrap() {
+                StorageData::BondInfo(b) => {
+                    assert_eq!(b.slot_num, 1);
+                    assert!(b.removed);
+                }
+                _ => panic!("Expected BondInfo"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mouse_key_config_serialization_deserialization() {
+        let data = StorageData::MouseKeyConfig(config::MouseKeyConfig {
+            initial_delay_ms: 300,
+            repeat_interval_ms: 16,
+            move_delta: 8,
+            max_speed: 5,
+            time_to_max: 30,
+            ..Default::default()
+        });
+
+        let mut buffer = [0u8; 8];
+        let serialized_size = Value::serialize_into(&data, &mut buffer).unwrap();
+        assert_eq!(serialized_size, 8);
+
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::MouseKeyConfig(c) => {
+                assert_eq!((c.initial_delay_ms, c.repeat_interval_ms), (300, 16));
+                assert_eq!((c.move_delta, c.max_speed, c.time_to_max), (8, 5, 30));
+            }
+            _ => panic!("Expected MouseKeyConfig"),
+        }
+    }
+
+    #[test]
+    fn test_lighting_and_battery_serialization_deserialization() {
+        let lighting = LightingConfig {
+            indicators: false,
+            low_battery: 20,
+        };
+        let mut buffer = [0u8; 8];
+        let serialized_size = Value::serialize_into(&StorageData::LightingConfig(lighting), &mut buffer).unwrap();
+        assert_eq!(serialized_size, 3);
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::LightingConfig(c) => assert_eq!(c, lighting),
+            _ => panic!("Expected LightingConfig"),
+        }
+
+        let calibration = BatteryCalibration {
+            empty: 4000,
+            full: 4800,
+        };
+        let serialized_size =
+            Value::serialize_into(&StorageData::BatteryCalibration(calibration), &mut buffer).unwrap();
+        assert_eq!(serialized_size, 5);
+        match StorageData::deserialize_from(&buffer[..serialized_size]).unwrap() {
+            StorageData::BatteryCalibration(c) => assert_eq!(c, calibration),
+            _ => panic!("Expected BatteryCalibration"),
+        }
+    }
 }
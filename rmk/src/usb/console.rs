@@ -0,0 +1,347 @@
+//! Serial console over USB CDC-ACM
+//!
+//! The console provides a small shell for inspecting the keyboard state, open the serial port of the keyboard
+//! in any serial terminal and type `help` to list the commands.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_usb::driver::{Driver, EndpointError};
+
+use crate::keymap::KeyMap;
+use crate::via::keycode_convert::to_via_keycode;
+
+/// Max length of a command line
+const CONSOLE_LINE_SIZE: usize = 64;
+
+/// Max length of the output of a command, the output is truncated if it's longer
+const CONSOLE_OUTPUT_SIZE: usize = 1024;
+
+const PROMPT: &str = "rmk> ";
+
+const HELP: &str = "Commands:
+  help            Show this help
+  layers          Show the default layer and activated layers
+  keymap <layer>  Show the VIA keycodes of the layer
+  matrix          Show the pressed keys in the matrix, Vial must be unlocked
+  behavior        Show the behavior timings
+  storage         Show the storage usage
+  ble             Show the BLE profiles and bonds
+";
+
+pub(crate) type ConsoleOutput = heapless::String<CONSOLE_OUTPUT_SIZE>;
+
+/// Run the console, it never returns
+pub(crate) async fn run_console<
+    'd,
+    D: Driver<'d>,
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+>(
+    mut class: CdcAcmClass<'d, D>,
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+) {
+    loop {
+        class.wait_connection().await;
+        info!("USB console connected");
+        if let Err(e) = run_session(&mut class, keymap).await {
+            info!("USB console disconnected: {:?}", e);
+        }
+    }
+}
+
+/// Read command lines from the host and execute them, until the host is disconnected
+async fn run_session<
+    'd,
+    D: Driver<'d>,
+    const ROW: usize,
+    const COL: usize,
+    const NUM_LAYER: usize,
+    const NUM_ENCODER: usize,
+>(
+    class: &mut CdcAcmClass<'d, D>,
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+) -> Result<(), EndpointError> {
+    let mut line: heapless::String<CONSOLE_LINE_SIZE> = heapless::String::new();
+    let mut buf = [0u8; 64];
+    // Terminals may send `\r\n` for enter, the `\n` after `\r` is ignored
+    let mut last_cr = false;
+    write_output(class, PROMPT).await?;
+    loop {
+        let n = class.read_packet(&mut buf).await?;
+        for &byte in &buf[..n] {
+            match byte {
+                b'\n' if last_cr => {}
+                b'\r' | b'\n' => {
+                    write_output(class, "\n").await?;
+                    let mut output = ConsoleOutput::new();
+                    execute(line.trim(), keymap, &mut output);
+                    write_output(class, &output).await?;
+                    line.clear();
+                    write_output(class, PROMPT).await?;
+                }
+                // Backspace and delete
+                0x08 | 0x7F if !line.is_empty() => {
+                    line.pop();
+                    write_output(class, "\x08 \x08").await?;
+                }
+                0x20..=0x7E if line.len() < CONSOLE_LINE_SIZE => {
+                    let _ = line.push(byte as char);
+                    class.write_packet(&[byte]).await?;
+                }
+                _ => {}
+            }
+            last_cr = byte == b'\r';
+        }
+    }
+}
+
+/// Write the output to the host, `\n` is converted to `\r\n`
+async fn write_output<'d, D: Driver<'d>>(class: &mut CdcAcmClass<'d, D>, output: &str) -> Result<(), EndpointError> {
+    let max_packet_size = class.max_packet_size() as usize;
+    let mut packet: heapless::Vec<u8, 64> = heapless::Vec::new();
+    for &byte in output.as_bytes() {
+        if byte == b'\n' {
+            push_byte(class, &mut packet, max_packet_size, b'\r').await?;
+        }
+        push_byte(class, &mut packet, max_packet_size, byte).await?;
+    }
+    if !packet.is_empty() {
+        class.write_packet(&packet).await?;
+        // A full packet doesn't end the transfer, so a zero-length packet is required
+        if packet.len() == max_packet_size {
+            class.write_packet(&[]).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn push_byte<'d, D: Driver<'d>>(
+    class: &mut CdcAcmClass<'d, D>,
+    packet: &mut heapless::Vec<u8, 64>,
+    max_packet_size: usize,
+    byte: u8,
+) -> Result<(), EndpointError> {
+    if packet.len() >= max_packet_size {
+        class.write_packet(packet).await?;
+        packet.clear();
+    }
+    // The packet has space after flushing
+    let _ = packet.push(byte);
+    Ok(())
+}
+
+/// Execute a command line, the output is written to `out`
+pub(crate) fn execute<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    line: &str,
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    out: &mut impl Write,
+) {
+    let mut args = line.split_whitespace();
+    let result = match args.next() {
+        None => Ok(()),
+        Some("help") => out.write_str(HELP),
+        Some("layers") => print_layers(keymap, out),
+        Some("keymap") => match args.next().map(|arg| arg.parse::<usize>()) {
+            Some(Ok(layer)) if layer < NUM_LAYER => print_keymap(keymap, layer, out),
+            Some(_) => writeln!(out, "Invalid layer, the keyboard has {} layers", NUM_LAYER),
+            None => writeln!(out, "Usage: keymap <layer>"),
+        },
+        // The switch matrix is only readable by the unlocked Vial, like the Vial matrix tester
+        #[cfg(feature = "vial_lock")]
+        Some("matrix") if !crate::via::is_unlocked() => writeln!(out, "Vial is locked, unlock it to show the matrix"),
+        Some("matrix") => print_matrix(keymap, out),
+        Some("behavior") => print_behavior(keymap, out),
+        Some("storage") => print_storage(out),
+        Some("ble") => print_ble(out),
+        Some(command) => writeln!(out, "Unknown command `{}`, type `help` to list the commands", command),
+    };
+    if result.is_err() {
+        warn!("USB console output is truncated");
+    }
+}
+
+fn print_layers<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    let keymap = keymap.borrow();
+    writeln!(out, "Layers: {}", NUM_LAYER)?;
+    writeln!(out, "Default layer: {}", keymap.get_default_layer())?;
+    out.write_str("Activated layers:")?;
+    for layer in (0..NUM_LAYER).filter(|&layer| keymap.is_layer_activated(layer)) {
+        write!(out, " {}", layer)?;
+    }
+    out.write_str("\n")
+}
+
+fn print_keymap<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    layer: usize,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    let keymap = keymap.borrow();
+    for row in keymap.layers[layer].iter() {
+        for (col, action) in row.iter().enumerate() {
+            let separator = if col == 0 { "" } else { " " };
+            write!(out, "{}{:04X}", separator, to_via_keycode(*action))?;
+        }
+        out.write_str("\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "matrix_tester")]
+fn print_matrix<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    let keymap = keymap.borrow();
+    for row in 0..ROW {
+        for col in 0..COL {
+            let pressed = keymap.matrix_state.read(row as u8, col as u8);
+            out.write_char(if pressed { 'X' } else { '.' })?;
+        }
+        out.write_str("\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "matrix_tester"))]
+fn print_matrix<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    _keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    writeln!(out, "Matrix state requires the `matrix_tester` feature")
+}
+
+fn print_behavior<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+    keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    let keymap = keymap.borrow();
+    let behavior = &keymap.behavior;
+    writeln!(out, "Tap-hold timeout: {}ms", behavior.tap_hold.timeout.as_millis())?;
+    writeln!(
+        out,
+        "Prior idle time: {}ms",
+        behavior.tap_hold.prior_idle_time.as_millis()
+    )?;
+    writeln!(out, "Unilateral tap: {}", behavior.tap_hold.unilateral_tap)?;
+    writeln!(out, "Tap interval: {}ms", behavior.tap.tap_interval)?;
+    writeln!(out, "Capslock tap interval: {}ms", behavior.tap.tap_capslock_interval)?;
+    writeln!(out, "One shot timeout: {}ms", behavior.one_shot.timeout.as_millis())?;
+    writeln!(out, "Combo timeout: {}ms", behavior.combo.timeout.as_millis())?;
+    writeln!(
+        out,
+        "Mouse key: initial delay {}ms, repeat interval {}ms, delta {}, max speed {}",
+        behavior.mouse_key.initial_delay_ms,
+        behavior.mouse_key.repeat_interval_ms,
+        behavior.mouse_key.move_delta,
+        behavior.mouse_key.max_speed
+    )
+}
+
+#[cfg(feature = "storage")]
+fn print_storage(out: &mut impl Write) -> core::fmt::Result {
+    use core::sync::atomic::Ordering;
+
+    use crate::channel::FLASH_CHANNEL;
+    use crate::storage::STORAGE_SIZE;
+
+    writeln!(out, "Storage size: {} bytes", STORAGE_SIZE.load(Ordering::Relaxed))?;
+    writeln!(
+        out,
+        "Pending writes: {}/{}",
+        FLASH_CHANNEL.len(),
+        FLASH_CHANNEL.capacity()
+    )
+}
+
+#[cfg(not(feature = "storage"))]
+fn print_storage(out: &mut impl Write) -> core::fmt::Result {
+    writeln!(out, "Storage is not enabled")
+}
+
+#[cfg(feature = "_ble")]
+fn print_ble(out: &mut impl Write) -> core::fmt::Result {
+    use core::sync::atomic::Ordering;
+
+    use crate::NUM_BLE_PROFILE;
+    use crate::ble::trouble::profile::BONDED_PROFILES;
+    use crate::ble::trouble::{ACTIVE_PROFILE, get_host_os, get_latency_mode};
+
+    writeln!(out, "Connection: {:?}", crate::state::get_connection_type())?;
+    writeln!(out, "Active profile: {}", ACTIVE_PROFILE.load(Ordering::SeqCst))?;
+    writeln!(out, "Latency mode: {:?}", get_latency_mode())?;
+    writeln!(out, "Host OS: {:?}", get_host_os())?;
+    out.write_str("Bonded profiles:")?;
+    let bonded_profiles = BONDED_PROFILES.load(Ordering::Acquire);
+    for profile in (0..NUM_BLE_PROFILE).filter(|profile| bonded_profiles & (1 << profile) != 0) {
+        write!(out, " {}", profile)?;
+    }
+    out.write_str("\n")
+}
+
+#[cfg(not(feature = "_ble"))]
+fn print_ble(out: &mut impl Write) -> core::fmt::Result {
+    writeln!(out, "BLE is not enabled")
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::action::{EncoderAction, KeyAction};
+    use crate::config::BehaviorConfig;
+    use crate::k;
+
+    fn create_keymap() -> &'static RefCell<KeyMap<'static, 2, 2, 2, 0>> {
+        let layers = Box::leak(Box::new([
+            [[k!(A), k!(B)], [k!(C), k!(D)]],
+            [[KeyAction::No, KeyAction::Transparent], [k!(Kc1), k!(Kc2)]],
+        ]));
+        let behavior = Box::leak(Box::new(BehaviorConfig::default()));
+        Box::leak(Box::new(RefCell::new(block_on(KeyMap::new(
+            layers,
+            None::<&mut [[EncoderAction; 0]; 2]>,
+            behavior,
+        )))))
+    }
+
+    fn run(line: &str) -> ConsoleOutput {
+        let keymap = create_keymap();
+        let mut output = ConsoleOutput::new();
+        execute(line, keymap, &mut output);
+        output
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_console_commands() {
+            assert!(run("").is_empty());
+            assert!(run("help").starts_with("Commands:"));
+            assert_eq!(run("layers"), "Layers: 2\nDefault layer: 0\nActivated layers:\n");
+            assert_eq!(run("keymap 0"), "0004 0005\n0006 0007\n");
+            assert_eq!(run("  keymap   1 "), "0000 0001\n001E 001F\n");
+            assert_eq!(run("keymap 2"), "Invalid layer, the keyboard has 2 layers\n");
+            assert_eq!(run("keymap"), "Usage: keymap <layer>\n");
+            assert!(run("behavior").starts_with("Tap-hold timeout: "));
+            assert_eq!(run("reboot"), "Unknown command `reboot`, type `help` to list the commands\n");
+        }
+
+        #[test]
+        #[cfg(feature = "vial_lock")]
+        fn test_console_matrix_requires_unlock() {
+            crate::via::restore_lock_state(false);
+            assert_eq!(run("matrix"), "Vial is locked, unlock it to show the matrix\n");
+            crate::via::restore_lock_state(true);
+            assert_eq!(run("matrix"), "..\n..\n");
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU8, Ordering};
 
 use embassy_sync::signal::Signal;
 use embassy_usb::class::hid::{HidWriter, ReportId, RequestHandler};
@@ -10,11 +10,25 @@ use static_cell::StaticCell;
 
 use crate::channel::KEYBOARD_REPORT_CHANNEL;
 use crate::config::KeyboardUsbConfig;
+#[cfg(feature = "webusb")]
+use crate::config::WebUsbConfig;
 use crate::descriptor::CompositeReportType;
-use crate::hid::{HidError, HidWriterTrait, Report, RunnableHidWriter};
+#[cfg(feature = "digitizer")]
+use crate::descriptor::{DIGITIZER_MAX_CONTACTS, DigitizerReportType};
+#[cfg(feature = "digitizer")]
+use crate::hid::{DIGITIZER_FUNCTION_SWITCH, DIGITIZER_INPUT_MODE};
+use crate::hid::{HidError, HidWriterTrait, Report, RunnableHidWriter, ScrollAccumulator};
 use crate::state::ConnectionState;
 use crate::{CONNECTION_STATE, RawMutex};
 
+#[cfg(feature = "usb_console")]
+pub(crate) mod console;
+pub(crate) mod power;
+
+pub use power::{UsbPowerState, get_usb_power_state};
+#[cfg(feature = "webusb")]
+mod webusb;
+
 pub(crate) static USB_REMOTE_WAKEUP: Signal<RawMutex, ()> = Signal::new();
 
 /// USB state
@@ -40,15 +54,33 @@ impl From<u8> for UsbState {
     }
 }
 
+/// The resolution multiplier feature report set by the USB host, see [`ScrollAccumulator`]
+pub(crate) static USB_RESOLUTION_MULTIPLIER: AtomicU8 = AtomicU8::new(0);
+
 pub(crate) struct UsbKeyboardWriter<'a, 'd, D: Driver<'d>> {
     pub(crate) keyboard_writer: &'a mut HidWriter<'d, D, 8>,
-    pub(crate) other_writer: &'a mut HidWriter<'d, D, 9>,
+    pub(crate) other_writer: &'a mut HidWriter<'d, D, 10>,
+    #[cfg(feature = "digitizer")]
+    pub(crate) digitizer_writer: &'a mut HidWriter<'d, D, 35>,
+    #[cfg(feature = "gamepad")]
+    pub(crate) gamepad_writer: &'a mut HidWriter<'d, D, 17>,
+    scroll: ScrollAccumulator,
 }
 impl<'a, 'd, D: Driver<'d>> UsbKeyboardWriter<'a, 'd, D> {
-    pub(crate) fn new(keyboard_writer: &'a mut HidWriter<'d, D, 8>, other_writer: &'a mut HidWriter<'d, D, 9>) -> Self {
+    pub(crate) fn new(
+        keyboard_writer: &'a mut HidWriter<'d, D, 8>,
+        other_writer: &'a mut HidWriter<'d, D, 10>,
+        #[cfg(feature = "digitizer")] digitizer_writer: &'a mut HidWriter<'d, D, 35>,
+        #[cfg(feature = "gamepad")] gamepad_writer: &'a mut HidWriter<'d, D, 17>,
+    ) -> Self {
         Self {
             keyboard_writer,
             other_writer,
+            #[cfg(feature = "digitizer")]
+            digitizer_writer,
+            #[cfg(feature = "gamepad")]
+            gamepad_writer,
+            scroll: ScrollAccumulator::new(&USB_RESOLUTION_MULTIPLIER),
         }
     }
 }
@@ -73,7 +105,8 @@ impl<'d, D: Driver<'d>> HidWriterTrait for UsbKeyboardWriter<'_, 'd, D> {
                 Ok(8)
             }
             Report::MouseReport(mouse_report) => {
-                let mut buf: [u8; 9] = [0; 9];
+                let mouse_report = self.scroll.convert(mouse_report);
+                let mut buf: [u8; 10] = [0; 10];
                 buf[0] = CompositeReportType::Mouse as u8;
                 let n = serialize(&mut buf[1..], &mouse_report).map_err(|_| HidError::ReportSerializeError)?;
                 self.other_writer
@@ -102,11 +135,34 @@ impl<'d, D: Driver<'d>> HidWriterTrait for UsbKeyboardWriter<'_, 'd, D> {
                     .map_err(HidError::UsbEndpointError)?;
                 Ok(n)
             }
+            #[cfg(feature = "digitizer")]
+            Report::DigitizerReport(digitizer_report) => {
+                let mut buf: [u8; 35] = [0; 35];
+                buf[0] = DigitizerReportType::Touch as u8;
+                let n = serialize(&mut buf[1..], &digitizer_report).map_err(|_| HidError::ReportSerializeError)?;
+                self.digitizer_writer
+                    .write(&buf[0..n + 1])
+                    .await
+                    .map_err(HidError::UsbEndpointError)?;
+                Ok(n)
+            }
+            #[cfg(feature = "gamepad")]
+            Report::GamepadReport(gamepad_report) => {
+                self.gamepad_writer
+                    .write_serialize(&gamepad_report)
+                    .await
+                    .map_err(HidError::UsbEndpointError)?;
+                Ok(17)
+            }
         }
     }
 }
 
-pub(crate) fn new_usb_builder<'d, D: Driver<'d>>(driver: D, keyboard_config: KeyboardUsbConfig<'d>) -> Builder<'d, D> {
+pub(crate) fn new_usb_builder<'d, D: Driver<'d>>(
+    driver: D,
+    keyboard_config: KeyboardUsbConfig<'d>,
+    #[cfg(feature = "webusb")] webusb_config: WebUsbConfig,
+) -> Builder<'d, D> {
     // Create embassy-usb Config
     let mut usb_config = embassy_usb::Config::new(keyboard_config.vid, keyboard_config.pid);
     usb_config.manufacturer = Some(keyboard_config.manufacturer);
@@ -122,15 +178,25 @@ pub(crate) fn new_usb_builder<'d, D: Driver<'d>>(driver: D, keyboard_config: Key
     usb_config.device_protocol = 0x01;
     usb_config.composite_with_iads = true;
 
-    #[cfg(feature = "usb_log")]
+    #[cfg(any(feature = "usb_log", feature = "digitizer", feature = "gamepad"))]
     const USB_BUF_SIZE: usize = 256;
-    #[cfg(not(feature = "usb_log"))]
+    #[cfg(not(any(feature = "usb_log", feature = "digitizer", feature = "gamepad")))]
     const USB_BUF_SIZE: usize = 128;
 
+    // BOS and MS OS 2.0 descriptors are only used by WebUSB
+    #[cfg(feature = "webusb")]
+    const BOS_BUF_SIZE: usize = 64;
+    #[cfg(not(feature = "webusb"))]
+    const BOS_BUF_SIZE: usize = 16;
+    #[cfg(feature = "webusb")]
+    const MSOS_BUF_SIZE: usize = 256;
+    #[cfg(not(feature = "webusb"))]
+    const MSOS_BUF_SIZE: usize = 16;
+
     // Create embassy-usb DeviceBuilder using the driver and config.
     static CONFIG_DESC: StaticCell<[u8; USB_BUF_SIZE]> = StaticCell::new();
-    static BOS_DESC: StaticCell<[u8; 16]> = StaticCell::new();
-    static MSOS_DESC: StaticCell<[u8; 16]> = StaticCell::new();
+    static BOS_DESC: StaticCell<[u8; BOS_BUF_SIZE]> = StaticCell::new();
+    static MSOS_DESC: StaticCell<[u8; MSOS_BUF_SIZE]> = StaticCell::new();
     static CONTROL_BUF: StaticCell<[u8; USB_BUF_SIZE]> = StaticCell::new();
 
     // UsbDevice builder
@@ -138,14 +204,17 @@ pub(crate) fn new_usb_builder<'d, D: Driver<'d>>(driver: D, keyboard_config: Key
         driver,
         usb_config,
         &mut CONFIG_DESC.init([0; USB_BUF_SIZE])[..],
-        &mut BOS_DESC.init([0; 16])[..],
-        &mut MSOS_DESC.init([0; 16])[..],
+        &mut BOS_DESC.init([0; BOS_BUF_SIZE])[..],
+        &mut MSOS_DESC.init([0; MSOS_BUF_SIZE])[..],
         &mut CONTROL_BUF.init([0; USB_BUF_SIZE])[..],
     );
 
     static device_handler: StaticCell<UsbDeviceHandler> = StaticCell::new();
     builder.handler(device_handler.init(UsbDeviceHandler::new()));
 
+    #[cfg(feature = "webusb")]
+    webusb::add_webusb(&mut builder, webusb_config);
+
     builder
 }
 
@@ -162,6 +231,18 @@ macro_rules! add_usb_logger {
     }};
 }
 
+#[cfg(feature = "usb_console")]
+macro_rules! add_usb_console {
+    ($usb_builder:expr) => {{
+        use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+        use static_cell::StaticCell;
+
+        static CONSOLE_STATE: StaticCell<State> = StaticCell::new();
+        let state = CONSOLE_STATE.init(State::new());
+        CdcAcmClass::new($usb_builder, state, 64)
+    }};
+}
+
 macro_rules! add_usb_writer {
     ($usb_builder:expr, $descriptor:ty, $n:expr) => {{
         // Initialize hid writer
@@ -212,6 +293,8 @@ macro_rules! add_usb_reader_writer {
     }};
 }
 
+#[cfg(feature = "usb_console")]
+pub(crate) use add_usb_console;
 #[cfg(feature = "usb_log")]
 pub(crate) use add_usb_logger;
 pub(crate) use {add_usb_reader_writer, add_usb_writer};
@@ -219,8 +302,54 @@ pub(crate) use {add_usb_reader_writer, add_usb_writer};
 pub(crate) struct UsbRequestHandler {}
 
 impl RequestHandler for UsbRequestHandler {
+    fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        match id {
+            // The report id is the first byte of the feature report
+            ReportId::Feature(id) if id == CompositeReportType::ResolutionMultiplier as u8 && buf.len() >= 2 => {
+                buf[0] = id;
+                buf[1] = USB_RESOLUTION_MULTIPLIER.load(Ordering::Relaxed);
+                Some(2)
+            }
+            #[cfg(feature = "digitizer")]
+            ReportId::Feature(id) if id == DigitizerReportType::Capabilities as u8 && buf.len() >= 2 => {
+                // Pad type 2 (non-clickable) in the high nibble, maximum contact count in the low nibble
+                buf[0] = id;
+                buf[1] = (2 << 4) | DIGITIZER_MAX_CONTACTS as u8;
+                Some(2)
+            }
+            #[cfg(feature = "digitizer")]
+            ReportId::Feature(id) if id == DigitizerReportType::InputMode as u8 && buf.len() >= 2 => {
+                buf[0] = id;
+                buf[1] = DIGITIZER_INPUT_MODE.load(Ordering::Relaxed);
+                Some(2)
+            }
+            #[cfg(feature = "digitizer")]
+            ReportId::Feature(id) if id == DigitizerReportType::FunctionSwitch as u8 && buf.len() >= 2 => {
+                buf[0] = id;
+                buf[1] = DIGITIZER_FUNCTION_SWITCH.load(Ordering::Relaxed);
+                Some(2)
+            }
+            _ => None,
+        }
+    }
+
     fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
         info!("Set report for {:?}: {:?}", id, data);
+        match (id, data.last()) {
+            // The report id may be included in the data, the multiplier is always the last byte
+            (ReportId::Feature(id), Some(multiplier)) if id == CompositeReportType::ResolutionMultiplier as u8 => {
+                USB_RESOLUTION_MULTIPLIER.store(*multiplier, Ordering::Relaxed);
+            }
+            #[cfg(feature = "digitizer")]
+            (ReportId::Feature(id), Some(mode)) if id == DigitizerReportType::InputMode as u8 => {
+                DIGITIZER_INPUT_MODE.store(*mode, Ordering::Relaxed);
+            }
+            #[cfg(feature = "digitizer")]
+            (ReportId::Feature(id), Some(switch)) if id == DigitizerReportType::FunctionSwitch as u8 => {
+                DIGITIZER_FUNCTION_SWITCH.store(*switch, Ordering::Relaxed);
+            }
+            _ => (),
+        }
         OutResponse::Accepted
     }
 }
@@ -243,6 +372,7 @@ impl Handler for UsbDeviceHandler {
             USB_ENABLED.signal(());
         } else {
             info!("Device disabled");
+            power::reset();
             if USB_ENABLED.signaled() {
                 USB_ENABLED.reset();
                 USB_SUSPENDED.signal(());
@@ -252,6 +382,13 @@ impl Handler for UsbDeviceHandler {
 
     fn reset(&mut self) {
         info!("Bus reset, the Vbus current limit is 100mA");
+        power::reset();
+        USB_RESOLUTION_MULTIPLIER.store(0, Ordering::Relaxed);
+        #[cfg(feature = "digitizer")]
+        {
+            DIGITIZER_INPUT_MODE.store(0, Ordering::Relaxed);
+            DIGITIZER_FUNCTION_SWITCH.store(0b11, Ordering::Relaxed);
+        }
     }
 
     fn addressed(&mut self, addr: u8) {
@@ -266,6 +403,7 @@ impl Handler for UsbDeviceHandler {
         } else {
             info!("Device is no longer configured, the Vbus current limit is 100mA.");
         }
+        power::set_configured(configured);
     }
 
     fn suspended(&mut self, suspended: bool) {
@@ -280,9 +418,11 @@ impl Handler for UsbDeviceHandler {
             );
             USB_SUSPENDED.reset();
         }
+        power::set_suspended(suspended);
     }
 
     fn remote_wakeup_enabled(&mut self, enabled: bool) {
         info!("Remote wakeup enabled state: {}", enabled);
+        power::set_remote_wakeup_enabled(enabled);
     }
 }
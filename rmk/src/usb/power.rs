@@ -0,0 +1,472 @@
+//! USB power state machine
+//!
+//! The host suspends the USB bus when it sleeps. While the bus is suspended, the matrix is scanned slowly,
+//! lighting controllers are notified to turn off, and only the wake keys can wake up the host.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use embassy_usb::UsbDevice;
+use embassy_usb::driver::Driver;
+
+use super::USB_REMOTE_WAKEUP;
+use crate::RawMutex;
+use crate::event::{KeyboardEvent, KeyboardEventPos};
+
+/// Interval between two matrix scans when the USB host is suspended
+pub(crate) const SUSPENDED_SCAN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Maximum time to wait for the host to resume after the remote wakeup, before the wake key is dropped
+const WAKEUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Power state of the USB device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbPowerState {
+    /// The device isn't configured by the host
+    Disabled,
+    /// The device is configured, reports are sent to the host
+    Active,
+    /// The host suspended the bus
+    Suspended,
+}
+
+#[derive(Clone, Copy)]
+struct PowerState {
+    configured: bool,
+    suspended: bool,
+    /// Whether the host allows the device to wake it up
+    remote_wakeup_enabled: bool,
+}
+
+impl PowerState {
+    fn state(&self) -> UsbPowerState {
+        match (self.configured, self.suspended) {
+            (false, _) => UsbPowerState::Disabled,
+            (true, false) => UsbPowerState::Active,
+            (true, true) => UsbPowerState::Suspended,
+        }
+    }
+}
+
+static POWER_STATE: Mutex<RawMutex, Cell<PowerState>> = Mutex::new(Cell::new(PowerState {
+    configured: false,
+    suspended: false,
+    remote_wakeup_enabled: false,
+}));
+
+/// Matrix positions of the keys which wake up the host, any key wakes up the host if it's empty
+static WAKE_KEYS: Mutex<RawMutex, Cell<&'static [(u8, u8)]>> = Mutex::new(Cell::new(&[]));
+
+/// Whether the reports are sent by the USB keyboard, the USB suspend doesn't affect keys sent over BLE
+static USB_KEYBOARD_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Signalled when the suspended host is resumed
+static USB_RESUMED: Signal<RawMutex, ()> = Signal::new();
+
+/// Action for a key event while the USB host may be suspended, see [`on_key_event`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum KeyEventAction {
+    /// Process the event now
+    Process,
+    /// Drop the event
+    Drop,
+    /// The event is a wake key press, process it after the host is resumed, see [`wait_for_resume`]
+    ProcessAfterResume,
+}
+
+/// Marks the USB keyboard as the active writer until it's dropped
+pub(crate) struct ActiveUsbKeyboard;
+
+impl ActiveUsbKeyboard {
+    pub(crate) fn new() -> Self {
+        USB_KEYBOARD_ACTIVE.store(true, Ordering::Release);
+        Self
+    }
+}
+
+impl Drop for ActiveUsbKeyboard {
+    fn drop(&mut self) {
+        USB_KEYBOARD_ACTIVE.store(false, Ordering::Release);
+    }
+}
+
+/// Run the USB keyboard, key presses are gated by the USB suspend only while it's running
+pub(crate) async fn run_usb_keyboard<F: Future>(keyboard: F) -> F::Output {
+    let _active = ActiveUsbKeyboard::new();
+    keyboard.await
+}
+
+/// Get the power state of the USB device
+pub fn get_usb_power_state() -> UsbPowerState {
+    POWER_STATE.lock(|s| s.get().state())
+}
+
+/// Set the keys which wake up the suspended host, any key wakes up the host if it's empty
+pub(crate) fn set_wake_keys(wake_keys: &'static [(u8, u8)]) {
+    WAKE_KEYS.lock(|k| k.set(wake_keys));
+}
+
+/// Whether the USB keyboard is running and its host is suspended
+pub(crate) fn is_host_suspended() -> bool {
+    USB_KEYBOARD_ACTIVE.load(Ordering::Acquire) && get_usb_power_state() == UsbPowerState::Suspended
+}
+
+pub(crate) fn set_configured(configured: bool) {
+    update(|s| s.configured = configured);
+}
+
+pub(crate) fn set_suspended(suspended: bool) {
+    update(|s| s.suspended = suspended);
+}
+
+pub(crate) fn set_remote_wakeup_enabled(enabled: bool) {
+    update(|s| s.remote_wakeup_enabled = enabled);
+}
+
+/// The device is unconfigured after a bus reset, and the host should enable remote wakeup again
+pub(crate) fn reset() {
+    update(|s| {
+        *s = PowerState {
+            configured: false,
+            suspended: false,
+            remote_wakeup_enabled: false,
+        }
+    });
+}
+
+/// Update the power state, and notify the controllers when the host is suspended or resumed
+fn update(f: impl FnOnce(&mut PowerState)) {
+    let (old, new) = POWER_STATE.lock(|s| {
+        let mut state = s.get();
+        let old = state.state();
+        f(&mut state);
+        s.set(state);
+        (old, state.state())
+    });
+    if old == new {
+        return;
+    }
+    info!("USB power state changed: {:?} -> {:?}", old, new);
+
+    let suspended = new == UsbPowerState::Suspended;
+    if suspended {
+        // Drop the wakeup requested before the suspend
+        USB_REMOTE_WAKEUP.reset();
+        USB_RESUMED.reset();
+    } else if old == UsbPowerState::Suspended {
+        USB_RESUMED.signal(());
+    }
+    #[cfg(feature = "controller")]
+    if suspended || old == UsbPowerState::Suspended {
+        use crate::channel::CONTROLLER_CHANNEL;
+        use crate::event::ControllerEvent;
+
+        CONTROLLER_CHANNEL
+            .immediate_publisher()
+            .publish_immediate(ControllerEvent::UsbSuspended(suspended));
+    }
+}
+
+/// Check the key event when the USB host is suspended.
+///
+/// Key presses are dropped while the host is suspended. Pressing a wake key requests the remote wakeup,
+/// if the host enabled it, and the press is processed after the host is resumed, so that the first keystroke isn't lost.
+/// Key releases are always processed, so that no key is stuck after resuming.
+pub(crate) fn on_key_event(event: &KeyboardEvent) -> KeyEventAction {
+    if !event.pressed || !is_host_suspended() {
+        return KeyEventAction::Process;
+    }
+    let is_wake_key = match event.pos {
+        KeyboardEventPos::Key(pos) => WAKE_KEYS.lock(|k| {
+            let wake_keys = k.get();
+            wake_keys.is_empty() || wake_keys.contains(&(pos.row, pos.col))
+        }),
+        KeyboardEventPos::RotaryEncoder(_) => false,
+    };
+    if is_wake_key {
+        if POWER_STATE.lock(|s| s.get().remote_wakeup_enabled) {
+            info!("Wake key pressed, waking up the host");
+            USB_REMOTE_WAKEUP.signal(());
+            return KeyEventAction::ProcessAfterResume;
+        }
+        warn!("Wake key pressed, but remote wakeup is not enabled by the host");
+    }
+    KeyEventAction::Drop
+}
+
+/// Wait for the host to resume after the remote wakeup, returns false if it's not resumed within [`WAKEUP_TIMEOUT`]
+pub(crate) async fn wait_for_resume() -> bool {
+    if get_usb_power_state() == UsbPowerState::Active {
+        return true;
+    }
+    with_timeout(WAKEUP_TIMEOUT, USB_RESUMED.wait()).await.is_ok()
+}
+
+/// Run the USB device, the host is woken up when the remote wakeup is requested
+pub(crate) async fn run_usb_device<'d, D: Driver<'d>>(usb_device: &mut UsbDevice<'d, D>) -> ! {
+    loop {
+        usb_device.run_until_suspend().await;
+        // Suspended, wait resume or remote wakeup
+        match select(usb_device.wait_resume(), USB_REMOTE_WAKEUP.wait()).await {
+            Either::First(_) => continue,
+            Either::Second(_) => {
+                info!("USB wakeup remote");
+                if let Err(e) = usb_device.remote_wakeup().await {
+                    info!("USB wakeup remote error: {:?}", e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use embassy_sync::channel::Channel;
+    use embassy_time::Timer;
+    use embassy_usb::driver::{
+        Bus, ControlPipe, Endpoint, EndpointAddress, EndpointAllocError, EndpointError, EndpointIn, EndpointInfo,
+        EndpointOut, EndpointType, Event, Unsupported,
+    };
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::config::KeyboardUsbConfig;
+    use crate::usb::new_usb_builder;
+
+    /// Bus events sent by the mocked host
+    static BUS_EVENTS: Channel<RawMutex, Event, 4> = Channel::new();
+    /// Setup packets sent by the mocked host
+    static SETUP_PACKETS: Channel<RawMutex, [u8; 8], 4> = Channel::new();
+    /// Number of the remote wakeups signalled on the bus
+    static REMOTE_WAKEUPS: AtomicUsize = AtomicUsize::new(0);
+
+    const SET_CONFIGURATION: [u8; 8] = [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+    const SET_REMOTE_WAKEUP: [u8; 8] = [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    struct MockDriver;
+    struct MockBus;
+    struct MockControlPipe;
+    struct MockEndpoint(EndpointInfo);
+
+    impl Driver<'static> for MockDriver {
+        type EndpointOut = MockEndpoint;
+        type EndpointIn = MockEndpoint;
+        type ControlPipe = MockControlPipe;
+        type Bus = MockBus;
+
+        fn alloc_endpoint_out(
+            &mut self,
+            _ep_type: EndpointType,
+            _ep_addr: Option<EndpointAddress>,
+            _max_packet_size: u16,
+            _interval_ms: u8,
+        ) -> Result<Self::EndpointOut, EndpointAllocError> {
+            Err(EndpointAllocError)
+        }
+
+        fn alloc_endpoint_in(
+            &mut self,
+            _ep_type: EndpointType,
+            _ep_addr: Option<EndpointAddress>,
+            _max_packet_size: u16,
+            _interval_ms: u8,
+        ) -> Result<Self::EndpointIn, EndpointAllocError> {
+            Err(EndpointAllocError)
+        }
+
+        fn start(self, _control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+            (MockBus, MockControlPipe)
+        }
+    }
+
+    impl Bus for MockBus {
+        async fn enable(&mut self) {}
+
+        async fn disable(&mut self) {}
+
+        async fn poll(&mut self) -> Event {
+            BUS_EVENTS.receive().await
+        }
+
+        fn endpoint_set_enabled(&mut self, _ep_addr: EndpointAddress, _enabled: bool) {}
+
+        fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {}
+
+        fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+
+        async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+            REMOTE_WAKEUPS.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    impl ControlPipe for MockControlPipe {
+        fn max_packet_size(&self) -> usize {
+            64
+        }
+
+        async fn setup(&mut self) -> [u8; 8] {
+            SETUP_PACKETS.receive().await
+        }
+
+        async fn data_out(&mut self, _buf: &mut [u8], _first: bool, _last: bool) -> Result<usize, EndpointError> {
+            Ok(0)
+        }
+
+        async fn data_in(&mut self, _data: &[u8], _first: bool, _last: bool) -> Result<(), EndpointError> {
+            Ok(())
+        }
+
+        async fn accept(&mut self) {}
+
+        async fn reject(&mut self) {}
+
+        async fn accept_set_address(&mut self, _addr: u8) {}
+    }
+
+    impl Endpoint for MockEndpoint {
+        fn info(&self) -> &EndpointInfo {
+            &self.0
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointOut for MockEndpoint {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    impl EndpointIn for MockEndpoint {
+        async fn write(&mut self, _buf: &[u8]) -> Result<(), EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    /// Run the USB device with the mocked driver, while the host runs the script
+    fn run_with_host(script: impl Future<Output = ()>) {
+        embassy_futures::block_on(async {
+            let mut usb_device = new_usb_builder(
+                MockDriver,
+                KeyboardUsbConfig::default(),
+                #[cfg(feature = "webusb")]
+                crate::config::WebUsbConfig::default(),
+            )
+            .build();
+            select(run_usb_device(&mut usb_device), script).await;
+        });
+    }
+
+    /// Send the bus event or the setup packet, and wait for the device to handle it
+    async fn host_event(event: Event) {
+        BUS_EVENTS.send(event).await;
+        Timer::after_millis(10).await;
+    }
+
+    async fn host_setup(packet: [u8; 8]) {
+        SETUP_PACKETS.send(packet).await;
+        Timer::after_millis(10).await;
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_suspend_and_wake_key() {
+            run_with_host(async {
+                let _active = ActiveUsbKeyboard::new();
+                assert_eq!(get_usb_power_state(), UsbPowerState::Disabled);
+                host_event(Event::PowerDetected).await;
+                host_event(Event::Reset).await;
+                host_setup(SET_CONFIGURATION).await;
+                host_setup(SET_REMOTE_WAKEUP).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Active);
+                // Keys are processed normally when the host is active
+                assert_eq!(on_key_event(&KeyboardEvent::key(1, 1, true)), KeyEventAction::Process);
+
+                host_event(Event::Suspend).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+                assert!(is_host_suspended());
+
+                // Other keys are dropped without waking up the host, releases are still processed
+                set_wake_keys(&[(0, 0)]);
+                assert_eq!(on_key_event(&KeyboardEvent::key(1, 1, true)), KeyEventAction::Drop);
+                assert_eq!(on_key_event(&KeyboardEvent::key(1, 1, false)), KeyEventAction::Process);
+                Timer::after_millis(10).await;
+                assert_eq!(REMOTE_WAKEUPS.load(Ordering::Relaxed), 0);
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+
+                // The wake key wakes up the host, and it's processed after the host is resumed
+                assert_eq!(
+                    on_key_event(&KeyboardEvent::key(0, 0, true)),
+                    KeyEventAction::ProcessAfterResume
+                );
+                assert!(wait_for_resume().await);
+                assert_eq!(REMOTE_WAKEUPS.load(Ordering::Relaxed), 1);
+                assert_eq!(get_usb_power_state(), UsbPowerState::Active);
+
+                // Suspended and resumed by the host
+                host_event(Event::Suspend).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+                host_event(Event::Resume).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Active);
+                assert_eq!(REMOTE_WAKEUPS.load(Ordering::Relaxed), 1);
+            });
+        }
+
+        #[test]
+        fn test_remote_wakeup_disabled() {
+            run_with_host(async {
+                let _active = ActiveUsbKeyboard::new();
+                host_event(Event::PowerDetected).await;
+                host_event(Event::Reset).await;
+                host_setup(SET_CONFIGURATION).await;
+                host_event(Event::Suspend).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+
+                // Any key is a wake key, but the host doesn't allow the remote wakeup
+                assert_eq!(on_key_event(&KeyboardEvent::key(0, 0, true)), KeyEventAction::Drop);
+                Timer::after_millis(10).await;
+                assert_eq!(REMOTE_WAKEUPS.load(Ordering::Relaxed), 0);
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+                assert!(!wait_for_resume().await);
+
+                // Bus reset unconfigures the device
+                host_event(Event::Reset).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Disabled);
+                assert!(!is_host_suspended());
+            });
+        }
+
+        #[test]
+        fn test_suspend_without_usb_keyboard() {
+            run_with_host(async {
+                host_event(Event::PowerDetected).await;
+                host_event(Event::Reset).await;
+                host_setup(SET_CONFIGURATION).await;
+                host_event(Event::Suspend).await;
+                assert_eq!(get_usb_power_state(), UsbPowerState::Suspended);
+
+                // Keys are sent by another transport, such as BLE, they aren't affected by the USB suspend
+                assert!(!is_host_suspended());
+                assert_eq!(on_key_event(&KeyboardEvent::key(1, 1, true)), KeyEventAction::Process);
+
+                // Only affected while the USB keyboard is running
+                run_usb_keyboard(async {
+                    assert!(is_host_suspended());
+                    assert_eq!(on_key_event(&KeyboardEvent::key(1, 1, true)), KeyEventAction::Drop);
+                })
+                .await;
+                assert!(!is_host_suspended());
+            });
+        }
+    }
+}
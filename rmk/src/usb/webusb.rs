@@ -0,0 +1,136 @@
+//! WebUSB and MS OS 2.0 descriptors
+//!
+//! A vendor interface is added with the WebUSB platform capability, and the MS OS 2.0 descriptors bind WinUSB to it,
+//! so that web configurators can open the keyboard on Windows without installing drivers.
+
+use embassy_usb::control::{InResponse, Recipient, Request, RequestType};
+use embassy_usb::descriptor::capability_type;
+use embassy_usb::driver::Driver;
+use embassy_usb::msos::{self, windows_version};
+use embassy_usb::{Builder, Handler};
+use static_cell::StaticCell;
+
+use crate::config::WebUsbConfig;
+
+/// Vendor code of the WebUSB requests
+const WEBUSB_VENDOR_CODE: u8 = 0x01;
+/// Vendor code of the MS OS 2.0 requests
+const MSOS_VENDOR_CODE: u8 = 0x02;
+
+const WEBUSB_REQUEST_GET_URL: u16 = 0x02;
+const WEBUSB_DESCRIPTOR_TYPE_URL: u8 = 0x03;
+
+/// Add the WebUSB vendor interface and the MS OS 2.0 descriptors
+pub(crate) fn add_webusb<'d, D: Driver<'d>>(builder: &mut Builder<'d, D>, config: WebUsbConfig) {
+    static WEBUSB_HANDLER: StaticCell<WebUsbHandler> = StaticCell::new();
+    static DEVICE_INTERFACE_GUIDS: StaticCell<[&str; 1]> = StaticCell::new();
+
+    builder.msos_descriptor(windows_version::WIN8_1, MSOS_VENDOR_CODE);
+
+    let mut func = builder.function(0xFF, 0x00, 0x00);
+    func.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINUSB", ""));
+    let guids = DEVICE_INTERFACE_GUIDS.init([config.device_interface_guid]);
+    func.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+        "DeviceInterfaceGUIDs",
+        msos::PropertyData::RegMultiSz(guids),
+    ));
+    let mut iface = func.interface();
+    let mut alt = iface.alt_setting(0xFF, 0x00, 0x00, None);
+    alt.bos_capability(
+        capability_type::PLATFORM,
+        &[
+            // bReserved
+            0x00,
+            // WebUSB platform capability UUID {3408b638-09a9-47a0-8bfd-a0768815b665}
+            0x38,
+            0xB6,
+            0x08,
+            0x34,
+            0xA9,
+            0x09,
+            0xA0,
+            0x47,
+            0x8B,
+            0xFD,
+            0xA0,
+            0x76,
+            0x88,
+            0x15,
+            0xB6,
+            0x65,
+            // bcdVersion 1.0
+            0x00,
+            0x01,
+            WEBUSB_VENDOR_CODE,
+            // iLandingPage
+            config.landing_url.is_some() as u8,
+        ],
+    );
+    drop(func);
+
+    builder.handler(WEBUSB_HANDLER.init(WebUsbHandler {
+        landing_url: config.landing_url,
+        buf: [0; 255],
+    }));
+}
+
+/// Handle the WebUSB GET_URL request, which reads the landing page
+struct WebUsbHandler {
+    landing_url: Option<&'static str>,
+    buf: [u8; 255],
+}
+
+impl Handler for WebUsbHandler {
+    fn control_in(&mut self, req: Request, _data: &mut [u8]) -> Option<InResponse<'_>> {
+        if req.request_type != RequestType::Vendor
+            || req.recipient != Recipient::Device
+            || req.request != WEBUSB_VENDOR_CODE
+            || req.index != WEBUSB_REQUEST_GET_URL
+            || req.value != 1
+        {
+            return None;
+        }
+        let len = url_descriptor(self.landing_url?, &mut self.buf)?;
+        Some(InResponse::Accepted(&self.buf[..len]))
+    }
+}
+
+/// Encode the URL descriptor, returns the length of the descriptor, or `None` if the URL is too long
+fn url_descriptor(url: &str, buf: &mut [u8; 255]) -> Option<usize> {
+    let (scheme, url) = if let Some(url) = url.strip_prefix("https://") {
+        (1, url)
+    } else if let Some(url) = url.strip_prefix("http://") {
+        (0, url)
+    } else {
+        // The scheme is included in the URL
+        (255, url)
+    };
+    let len = url.len() + 3;
+    if len > buf.len() {
+        error!("WebUSB landing URL is too long");
+        return None;
+    }
+    buf[0] = len as u8;
+    buf[1] = WEBUSB_DESCRIPTOR_TYPE_URL;
+    buf[2] = scheme;
+    buf[3..len].copy_from_slice(url.as_bytes());
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_descriptor() {
+        let mut buf = [0; 255];
+        assert_eq!(url_descriptor("https://rmk.rs", &mut buf), Some(9));
+        assert_eq!(&buf[..9], &[9, 0x03, 1, b'r', b'm', b'k', b'.', b'r', b's']);
+        assert_eq!(url_descriptor("http://a", &mut buf), Some(4));
+        assert_eq!(&buf[..4], &[4, 0x03, 0, b'a']);
+        assert_eq!(url_descriptor("rmk.rs", &mut buf), Some(9));
+        assert_eq!(buf[2], 255);
+        let long_url = [b'a'; 253];
+        assert_eq!(url_descriptor(core::str::from_utf8(&long_url).unwrap(), &mut buf), None);
+    }
+}
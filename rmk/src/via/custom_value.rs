@@ -0,0 +1,319 @@
+//! VIA custom values, which expose RMK settings in the custom menus of VIA.
+//!
+//! The menus are generated into the keyboard definition by `rmk-config`, see `RMK_CUSTOM_MENUS` there.
+//! RMK settings use the custom channel 0, other channels can be served by registering a [`CustomValueHandler`].
+//!
+//! Packets of get/set value are `[command, channel, value id, value..]`, and the packet of save is `[command, channel]`.
+//! Following VIA, a value is 2 bytes(big endian) if the max of its range is larger than 255, otherwise it's 1 byte.
+
+use core::cell::RefCell;
+#[cfg(feature = "_ble")]
+use core::sync::atomic::Ordering;
+
+use embassy_sync::blocking_mutex::Mutex;
+use num_enum::TryFromPrimitive;
+#[cfg(feature = "controller")]
+use {crate::channel::CONTROLLER_CHANNEL, crate::event::ControllerEvent};
+
+use crate::RawMutex;
+use crate::input_device::battery::{battery_calibration, set_battery_calibration};
+use crate::keymap::KeyMap;
+use crate::light::{lighting_config, set_lighting_config};
+#[cfg(feature = "_ble")]
+use {
+    crate::NUM_BLE_PROFILE,
+    crate::ble::trouble::profile::BleProfileAction,
+    crate::ble::trouble::{ACTIVE_PROFILE, get_host_os, get_latency_mode, set_host_os, set_latency_mode},
+    crate::channel::BLE_PROFILE_CHANNEL,
+};
+#[cfg(feature = "storage")]
+use {
+    crate::channel::FLASH_CHANNEL,
+    crate::storage::{FlashOperationMessage, compact_storage, storage_health},
+};
+
+/// VIA custom channel of RMK settings
+pub(crate) const RMK_SETTINGS_CHANNEL: u8 = 0;
+
+/// Max number of the registered custom value handlers
+const MAX_CUSTOM_VALUE_HANDLERS: usize = 4;
+
+/// Handler of the values in a VIA custom channel
+pub trait CustomValueHandler: Sync {
+    /// Write the value of `id` to `data`, returns false if the value is not handled
+    fn get_value(&self, id: u8, data: &mut [u8]) -> bool;
+    /// Set the value of `id` from `data`, returns false if the value is not handled
+    fn set_value(&self, id: u8, data: &[u8]) -> bool;
+    /// Save all values of the channel, it's called when VIA closes the custom menu
+    fn save(&self) {}
+}
+
+type CustomValueHandlers = heapless::Vec<(u8, &'static dyn CustomValueHandler), MAX_CUSTOM_VALUE_HANDLERS>;
+
+static CUSTOM_VALUE_HANDLERS: Mutex<RawMutex, RefCell<CustomValueHandlers>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Register the handler of a VIA custom channel, the values should also be added to `menus` of `vial.json`.
+///
+/// Returns false if the channel is used by RMK or already registered, or there are too many handlers.
+pub fn register_custom_value_handler(channel: u8, handler: &'static dyn CustomValueHandler) -> bool {
+    if channel == RMK_SETTINGS_CHANNEL {
+        return false;
+    }
+    CUSTOM_VALUE_HANDLERS.lock(|handlers| {
+        let mut handlers = handlers.borrow_mut();
+        if handlers.iter().any(|(c, _)| *c == channel) {
+            return false;
+        }
+        handlers.push((channel, handler)).is_ok()
+    })
+}
+
+fn get_handler(channel: u8) -> Option<&'static dyn CustomValueHandler> {
+    CUSTOM_VALUE_HANDLERS.lock(|handlers| {
+        handlers
+            .borrow()
+            .iter()
+            .find(|(c, _)| *c == channel)
+            .map(|(_, handler)| *handler)
+    })
+}
+
+// `RmkSetting` and the sizes and ranges of its values, generated from `RMK_CUSTOM_MENUS` in `rmk-config`
+include!(concat!(env!("OUT_DIR"), "/rmk_settings.rs"));
+
+/// Serves the VIA custom values
+#[derive(Default)]
+pub(crate) struct CustomValues {
+    /// Mouse key settings are changed but not saved
+    mouse_key_unsaved: bool,
+    /// Lighting settings are changed but not saved
+    lighting_unsaved: bool,
+    /// Battery calibration is changed but not saved
+    battery_unsaved: bool,
+}
+
+impl CustomValues {
+    /// Process `CustomGetValue`, `data` is the packet without the command byte.
+    ///
+    /// Returns false if the value is not handled.
+    pub(crate) fn get_value<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &self,
+        data: &mut [u8],
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> bool {
+        let (channel, id) = (data[0], data[1]);
+        if channel != RMK_SETTINGS_CHANNEL {
+            return get_handler(channel).is_some_and(|handler| handler.get_value(id, &mut data[2..]));
+        }
+        let Ok(setting) = RmkSetting::try_from(id) else {
+            return false;
+        };
+        let Some(value) = Self::get_setting(setting, keymap) else {
+            return false;
+        };
+        match setting.size() {
+            2 => data[2..4].copy_from_slice(&value.to_be_bytes()),
+            _ => data[2] = value as u8,
+        }
+        true
+    }
+
+    /// Process `CustomSetValue`, `data` is the packet without the command byte.
+    ///
+    /// Returns false if the value is not handled.
+    pub(crate) async fn set_value<
+        const ROW: usize,
+        const COL: usize,
+        const NUM_LAYER: usize,
+        const NUM_ENCODER: usize,
+    >(
+        &mut self,
+        data: &[u8],
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> bool {
+        let (channel, id) = (data[0], data[1]);
+        if channel != RMK_SETTINGS_CHANNEL {
+            return get_handler(channel).is_some_and(|handler| handler.set_value(id, &data[2..]));
+        }
+        let Ok(setting) = RmkSetting::try_from(id) else {
+            return false;
+        };
+        let value = match setting.size() {
+            2 => u16::from_be_bytes([data[2], data[3]]),
+            _ => data[2] as u16,
+        };
+        debug!("Set RMK setting {:?} to {}", setting, value);
+        if !Self::set_setting(setting, value, keymap).await {
+            return false;
+        }
+        // BLE settings are saved by the BLE profile manager once they are changed
+        match setting {
+            RmkSetting::MouseInitialDelay
+            | RmkSetting::MouseRepeatInterval
+            | RmkSetting::MouseMoveDelta
+            | RmkSetting::MouseMaxSpeed
+            | RmkSetting::MouseTimeToMax => self.mouse_key_unsaved = true,
+            RmkSetting::LightingIndicators | RmkSetting::LightingLowBattery => self.lighting_unsaved = true,
+            RmkSetting::BatteryEmpty | RmkSetting::BatteryFull => self.battery_unsaved = true,
+            _ => {}
+        }
+        true
+    }
+
+    /// Process `CustomSave`, `data` is the packet without the command byte.
+    ///
+    /// Returns false if the channel is not handled.
+    pub(crate) async fn save<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        &mut self,
+        data: &[u8],
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> bool {
+        let channel = data[0];
+        if channel != RMK_SETTINGS_CHANNEL {
+            return match get_handler(channel) {
+                Some(handler) => {
+                    handler.save();
+                    true
+                }
+                None => false,
+            };
+        }
+
+        #[cfg(feature = "storage")]
+        {
+            if self.mouse_key_unsaved {
+                let mouse_key = keymap.borrow().behavior.mouse_key;
+                FLASH_CHANNEL
+                    .send(FlashOperationMessage::MouseKeyConfig(mouse_key))
+                    .await;
+            }
+            if self.lighting_unsaved {
+                FLASH_CHANNEL
+                    .send(FlashOperationMessage::LightingConfig(lighting_config()))
+                    .await;
+            }
+            if self.battery_unsaved {
+                FLASH_CHANNEL
+                    .send(FlashOperationMessage::BatteryCalibration(battery_calibration()))
+                    .await;
+            }
+        }
+        #[cfg(not(feature = "storage"))]
+        let _ = keymap;
+
+        *self = Self::default();
+        true
+    }
+
+    fn get_setting<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        setting: RmkSetting,
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> Option<u16> {
+        let keymap = keymap.borrow();
+        let mouse_key = &keymap.behavior.mouse_key;
+        let value = match setting {
+            RmkSetting::MouseInitialDelay => mouse_key.initial_delay_ms,
+            RmkSetting::MouseRepeatInterval => mouse_key.repeat_interval_ms,
+            RmkSetting::MouseMoveDelta => mouse_key.move_delta as u16,
+            RmkSetting::MouseMaxSpeed => mouse_key.max_speed as u16,
+            RmkSetting::MouseTimeToMax => mouse_key.time_to_max as u16,
+            RmkSetting::LightingIndicators => lighting_config().indicators as u16,
+            RmkSetting::LightingLowBattery => lighting_config().low_battery as u16,
+            RmkSetting::BatteryEmpty => battery_calibration().empty,
+            RmkSetting::BatteryFull => battery_calibration().full,
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleProfile => ACTIVE_PROFILE.load(Ordering::Relaxed) as u16,
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleLatencyMode => get_latency_mode() as u16,
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleHostOs => get_host_os() as u16,
+            #[cfg(not(feature = "_ble"))]
+            RmkSetting::BleProfile | RmkSetting::BleLatencyMode | RmkSetting::BleHostOs => return None,
+            #[cfg(feature = "storage")]
+            RmkSetting::StorageUsed => storage_health().used_percent() as u16,
+            #[cfg(feature = "storage")]
+            RmkSetting::StorageEraseCount => storage_health().erase_count.min(u16::MAX as u32) as u16,
+            #[cfg(feature = "storage")]
+            RmkSetting::StorageLastError => storage_health().last_error.map_or(0, |e| e as u16),
+            // Compaction is triggered by setting it, it's always off
+            #[cfg(feature = "storage")]
+            RmkSetting::StorageCompact => 0,
+            #[cfg(not(feature = "storage"))]
+            RmkSetting::StorageUsed
+            | RmkSetting::StorageEraseCount
+            | RmkSetting::StorageLastError
+            | RmkSetting::StorageCompact => return None,
+        };
+        Some(value)
+    }
+
+    /// Apply the setting, returns false if the value is invalid
+    async fn set_setting<const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>(
+        setting: RmkSetting,
+        value: u16,
+        keymap: &RefCell<KeyMap<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> bool {
+        // Values out of the range of the VIA control are rejected, e.g. mouse keys stop moving if the speed is 0
+        let (min, max) = setting.range();
+        if value < min || value > max {
+            return false;
+        }
+        let mut lighting = lighting_config();
+        let mut calibration = battery_calibration();
+        match setting {
+            RmkSetting::MouseInitialDelay => keymap.borrow_mut().behavior.mouse_key.initial_delay_ms = value,
+            RmkSetting::MouseRepeatInterval => keymap.borrow_mut().behavior.mouse_key.repeat_interval_ms = value,
+            RmkSetting::MouseMoveDelta => keymap.borrow_mut().behavior.mouse_key.move_delta = value as u8,
+            RmkSetting::MouseMaxSpeed => keymap.borrow_mut().behavior.mouse_key.max_speed = value as u8,
+            RmkSetting::MouseTimeToMax => keymap.borrow_mut().behavior.mouse_key.time_to_max = value as u8,
+            RmkSetting::LightingIndicators | RmkSetting::LightingLowBattery => {
+                if setting == RmkSetting::LightingIndicators {
+                    lighting.indicators = value != 0;
+                } else {
+                    lighting.low_battery = value as u8;
+                }
+                set_lighting_config(lighting);
+                // Notify the LED controllers to apply the settings
+                #[cfg(feature = "controller")]
+                CONTROLLER_CHANNEL
+                    .immediate_publisher()
+                    .publish_immediate(ControllerEvent::Lighting(lighting));
+            }
+            RmkSetting::BatteryEmpty | RmkSetting::BatteryFull => {
+                if setting == RmkSetting::BatteryEmpty {
+                    calibration.empty = value;
+                } else {
+                    calibration.full = value;
+                }
+                return set_battery_calibration(calibration);
+            }
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleProfile => {
+                if value as usize >= NUM_BLE_PROFILE {
+                    return false;
+                }
+                BLE_PROFILE_CHANNEL
+                    .send(BleProfileAction::SwitchProfile(value as u8))
+                    .await;
+            }
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleLatencyMode => set_latency_mode((value as u8).into()).await,
+            #[cfg(feature = "_ble")]
+            RmkSetting::BleHostOs => set_host_os((value as u8).into()).await,
+            #[cfg(not(feature = "_ble"))]
+            RmkSetting::BleProfile | RmkSetting::BleLatencyMode | RmkSetting::BleHostOs => return false,
+            #[cfg(feature = "storage")]
+            RmkSetting::StorageCompact => {
+                if value != 0 {
+                    compact_storage().await
+                }
+            }
+            // Storage health is read-only
+            RmkSetting::StorageUsed | RmkSetting::StorageEraseCount | RmkSetting::StorageLastError => return false,
+            #[cfg(not(feature = "storage"))]
+            RmkSetting::StorageCompact => return false,
+        }
+        true
+    }
+}
@@ -16,6 +16,11 @@ pub(crate) fn to_via_keycode(key_action: KeyAction) -> u16 {
                     k as u16 & 0xFF | 0x7700
                 } else if k.is_user() {
                     k as u16 & 0xF | 0x7E00
+                } else if k.is_joystick() {
+                    k as u16 & 0x1F | 0x7400
+                } else if k.is_joystick_hat() {
+                    // Joystick hat keycodes use QK_KB_32 ~ QK_KB_35, after the user keycodes
+                    k as u16 & 0x3 | 0x7E20
                 } else if k.is_combo() || k.is_boot() {
                     // is_rmk() 's subset
                     k as u16 & 0xFF | 0x7C00
@@ -151,6 +156,11 @@ pub(crate) fn from_via_keycode(via_keycode: u16) -> KeyAction {
             warn!("QMK functions {:#X} not supported", via_keycode);
             KeyAction::No
         }
+        0x7400..=0x741F => {
+            // Joystick buttons
+            let keycode = via_keycode & 0x1F | 0x400;
+            KeyAction::Single(Action::Key(KeyCode::from_primitive(keycode)))
+        }
         0x7700..=0x770F => {
             // Macro
             let keycode = via_keycode & 0xFF | 0x500;
@@ -183,6 +193,11 @@ pub(crate) fn from_via_keycode(via_keycode: u16) -> KeyAction {
             );
             KeyAction::No
         }
+        0x7E20..=0x7E23 => {
+            // Joystick hat
+            let keycode = via_keycode & 0x3 | 0x440;
+            KeyAction::Single(Action::Key(KeyCode::from_primitive(keycode)))
+        }
         0x7E00..=0x7E0F => {
             // QK_KB_N, aka UserN
             let keycode = via_keycode & 0xFF | 0x840;
@@ -579,6 +594,26 @@ mod test {
         // Morse(255)
         let via_keycode = 0x57FF;
         assert_eq!(KeyAction::Morse(255), from_via_keycode(via_keycode));
+
+        // JS_0
+        let via_keycode = 0x7400;
+        assert_eq!(
+            KeyAction::Single(Action::Key(KeyCode::JoystickButton0)),
+            from_via_keycode(via_keycode)
+        );
+
+        // JS_31
+        let via_keycode = 0x741F;
+        assert_eq!(
+            KeyAction::Single(Action::Key(KeyCode::JoystickButton31)),
+            from_via_keycode(via_keycode)
+        );
+        // Joystick hat right
+        let via_keycode = 0x7E21;
+        assert_eq!(
+            KeyAction::Single(Action::Key(KeyCode::JoystickHatRight)),
+            from_via_keycode(via_keycode)
+        );
     }
 
     #[test]
@@ -690,6 +725,16 @@ mod test {
 
         let a = KeyAction::Morse(255);
         assert_eq!(0x57FF, to_via_keycode(a));
+
+        // Joystick
+        let a = KeyAction::Single(Action::Key(KeyCode::JoystickButton5));
+        assert_eq!(0x7405, to_via_keycode(a));
+
+        let a = KeyAction::Single(Action::Key(KeyCode::JoystickHatUp));
+        assert_eq!(0x7E20, to_via_keycode(a));
+
+        let a = KeyAction::Single(Action::Key(KeyCode::JoystickHatLeft));
+        assert_eq!(0x7E23, to_via_keycode(a));
     }
 
     #[test]
@@ -12,19 +12,47 @@ use vial::process_vial;
 use crate::config::VialConfig;
 use crate::descriptor::ViaReport;
 use crate::event::KeyboardEventPos;
+use crate::firmware::{FIRMWARE_METADATA_SIZE, firmware_metadata};
 use crate::hid::{HidError, HidReaderTrait, HidWriterTrait};
+use crate::input_device::rotary_encoder::Direction;
+use crate::keyboard_macros::NUM_MACRO;
 use crate::keymap::KeyMap;
 use crate::state::ConnectionState;
 use crate::via::keycode_convert::{from_via_keycode, to_via_keycode};
 use crate::{CONNECTION_STATE, MACRO_SPACE_SIZE, boot};
 #[cfg(feature = "storage")]
-use crate::{channel::FLASH_CHANNEL, storage::FlashOperationMessage};
+use crate::{action::EncoderAction, channel::FLASH_CHANNEL, storage::FlashOperationMessage};
+#[cfg(feature = "controller")]
+use {
+    crate::RawMutex,
+    crate::channel::{CONTROLLER_CHANNEL, send_controller_event},
+    crate::event::ControllerEvent,
+    embassy_futures::select::{Either, select},
+    embassy_sync::signal::Signal,
+    embassy_time::Duration,
+};
+mod custom_value;
 pub(crate) mod keycode_convert;
 mod protocol;
 mod vial;
 #[cfg(feature = "vial_lock")]
 mod vial_lock;
 
+pub use custom_value::{CustomValueHandler, register_custom_value_handler};
+#[cfg(feature = "vial_lock")]
+pub(crate) use vial_lock::{is_unlocked, restore_lock_state};
+
+/// Number of toggles of the device indication, which blinks 3 times
+#[cfg(feature = "controller")]
+const DEVICE_INDICATION_TOGGLES: u8 = 6;
+/// Interval between the toggles of the device indication
+#[cfg(feature = "controller")]
+const DEVICE_INDICATION_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Signal of the device indication requested by VIA
+#[cfg(feature = "controller")]
+static DEVICE_INDICATION: Signal<RawMutex, ()> = Signal::new();
+
 pub(crate) struct VialService<
     'a,
     RW: HidWriterTrait<ReportType = ViaReport> + HidReaderTrait<ReportType = ViaReport>,
@@ -39,6 +67,9 @@ pub(crate) struct VialService<
     // Vial config
     vial_config: VialConfig<'static>,
 
+    // VIA custom values, aka RMK settings in the custom menus
+    custom_values: custom_value::CustomValues,
+
     // Vail lock instance
     #[cfg(feature = "vial_lock")]
     locker: vial_lock::VialLock<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>,
@@ -66,8 +97,13 @@ impl<
         Self {
             keymap,
             vial_config,
+            custom_values: custom_value::CustomValues::default(),
             #[cfg(feature = "vial_lock")]
-            locker: vial_lock::VialLock::<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>::new(vial_config.unlock_keys, keymap),
+            locker: vial_lock::VialLock::<'_, ROW, COL, NUM_LAYER, NUM_ENCODER>::new(
+                vial_config.unlock_keys,
+                vial_config.lock_timeout,
+                keymap,
+            ),
             reader_writer,
         }
     }
@@ -105,12 +141,38 @@ impl<
         &mut self,
         report: &mut ViaReport,
         keymap: &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) {
+        self.handle_via_packet(report, keymap).await;
+
+        // Save the lock state, which is changed by the command or relocked after inactivity
+        #[cfg(all(feature = "vial_lock", feature = "storage"))]
+        if let Some(unlocked) = vial_lock::take_unsaved_lock_state() {
+            FLASH_CHANNEL.send(FlashOperationMessage::VialLockState(unlocked)).await;
+        }
+    }
+
+    async fn handle_via_packet(
+        &mut self,
+        report: &mut ViaReport,
+        keymap: &RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
     ) {
         let command_id = report.output_data[0];
 
         // `report.input_data` is initialized using `report.output_data`
         report.input_data = report.output_data;
         let via_command = ViaCommand::from_primitive(command_id);
+
+        #[cfg(feature = "vial_lock")]
+        {
+            self.locker.on_command(Instant::now());
+            if !self.locker.is_unlocked()
+                && vial_lock::command_policy(&report.output_data) == vial_lock::CommandPolicy::RequiresUnlock
+            {
+                warn!("Vial is locked, command {} is rejected", command_id);
+                report.input_data[0] = ViaCommand::Unhandled as u8;
+                return;
+            }
+        }
         // debug!("Received via command: {}, report: {:02X?}", via_command, report.output_data);
         match via_command {
             ViaCommand::GetProtocolVersion => {
@@ -125,8 +187,7 @@ impl<
                             BigEndian::write_u32(&mut report.input_data[2..6], value);
                         }
                         ViaKeyboardInfo::LayoutOptions => {
-                            // TODO: retrieve layout option from storage
-                            let layout_option: u32 = 0;
+                            let layout_option = keymap.borrow().get_layout_option();
                             BigEndian::write_u32(&mut report.input_data[2..6], layout_option);
                         }
                         ViaKeyboardInfo::SwitchMatrixState => {
@@ -138,14 +199,23 @@ impl<
                                     error!("It is not sercure to use matrix tester without vial lock");
                                 }
 
+                                // Reading the matrix is rejected by the vial lock if it's locked
                                 #[cfg(feature = "vial_lock")]
-                                if self.locker.is_unlocked() {
-                                    self.keymap.borrow().matrix_state.read_all(&mut report.input_data[2..]);
-                                }
+                                self.keymap.borrow().matrix_state.read_all(&mut report.input_data[2..]);
                             }
                         }
                         ViaKeyboardInfo::FirmwareVersion => {
-                            BigEndian::write_u32(&mut report.input_data[2..6], VIA_FIRMWARE_VERSION);
+                            let version = firmware_metadata()
+                                .map(|m| m.version.to_via())
+                                .unwrap_or(VIA_FIRMWARE_VERSION);
+                            BigEndian::write_u32(&mut report.input_data[2..6], version);
+                        }
+                        ViaKeyboardInfo::FirmwareMetadata => {
+                            // Zeros if the metadata is not set
+                            if let Some(metadata) = firmware_metadata() {
+                                report.input_data[2..2 + FIRMWARE_METADATA_SIZE - 8]
+                                    .copy_from_slice(&metadata.to_bytes()[8..]);
+                            }
                         }
                         _ => (),
                     },
@@ -156,16 +226,44 @@ impl<
                 // Check the second u8
                 match ViaKeyboardInfo::try_from_primitive(report.output_data[1]) {
                     Ok(v) => match v {
-                        #[cfg(feature = "storage")]
                         ViaKeyboardInfo::LayoutOptions => {
                             let layout_option = BigEndian::read_u32(&report.output_data[2..6]);
-                            FLASH_CHANNEL
-                                .send(FlashOperationMessage::LayoutOptions(layout_option))
-                                .await;
+                            #[cfg(feature = "storage")]
+                            let previous = keymap.borrow().get_layout_option();
+                            keymap.borrow_mut().set_layout_option(layout_option);
+                            #[cfg(feature = "storage")]
+                            {
+                                FLASH_CHANNEL
+                                    .send(FlashOperationMessage::LayoutOptions(layout_option))
+                                    .await;
+                                // Save the keys which are reset by the newly selected or deselected layout option choices
+                                let layout_options = keymap.borrow().behavior.layout_options;
+                                for option in layout_options.changed(previous, layout_option) {
+                                    for &(layer, row, col, _) in option.keys {
+                                        if layer as usize >= NUM_LAYER || row as usize >= ROW || col as usize >= COL {
+                                            continue;
+                                        }
+                                        let action = keymap
+                                            .borrow()
+                                            .get_action_at(KeyboardEventPos::key_pos(col, row), layer as usize);
+                                        FLASH_CHANNEL
+                                            .send(FlashOperationMessage::KeymapKey {
+                                                layer,
+                                                col,
+                                                row,
+                                                action,
+                                            })
+                                            .await;
+                                    }
+                                }
+                            }
                         }
                         ViaKeyboardInfo::DeviceIndication => {
-                            let _device_indication = report.output_data[2];
-                            warn!("SetKeyboardValue - DeviceIndication")
+                            debug!("SetKeyboardValue - DeviceIndication: {}", report.output_data[2]);
+                            #[cfg(feature = "controller")]
+                            DEVICE_INDICATION.signal(());
+                            #[cfg(not(feature = "controller"))]
+                            warn!("Device indication requires the controller feature")
                         }
                         _ => (),
                     },
@@ -209,19 +307,88 @@ impl<
                     .await;
             }
             ViaCommand::DynamicKeymapReset => {
-                warn!("Dynamic keymap reset -- not supported")
+                if !keymap.borrow().has_default_keymap() {
+                    warn!("Default keymap isn't set, call `KeyMap::set_default_keymap` to support the keymap reset");
+                    report.input_data[0] = ViaCommand::Unhandled as u8;
+                    return;
+                }
+                info!("Resetting keymap to default");
+                for layer in 0..NUM_LAYER {
+                    for row in 0..ROW {
+                        for col in 0..COL {
+                            let pos = KeyboardEventPos::key_pos(col as u8, row as u8);
+                            let Some(action) = keymap.borrow().get_default_action_at(pos, layer) else {
+                                continue;
+                            };
+                            if keymap.borrow().get_action_at(pos, layer) == action {
+                                continue;
+                            }
+                            keymap.borrow_mut().set_action_at(pos, layer, action);
+                            #[cfg(feature = "storage")]
+                            FLASH_CHANNEL
+                                .send(FlashOperationMessage::KeymapKey {
+                                    layer: layer as u8,
+                                    col: col as u8,
+                                    row: row as u8,
+                                    action,
+                                })
+                                .await;
+                        }
+                    }
+                    for idx in 0..NUM_ENCODER {
+                        let clockwise_pos = KeyboardEventPos::rotary_encoder_pos(idx as u8, Direction::Clockwise);
+                        let counter_clockwise_pos =
+                            KeyboardEventPos::rotary_encoder_pos(idx as u8, Direction::CounterClockwise);
+                        // Encoders without a default map are kept
+                        let (Some(clockwise), Some(counter_clockwise)) = (
+                            keymap.borrow().get_default_action_at(clockwise_pos, layer),
+                            keymap.borrow().get_default_action_at(counter_clockwise_pos, layer),
+                        ) else {
+                            continue;
+                        };
+                        if keymap.borrow().get_action_at(clockwise_pos, layer) == clockwise
+                            && keymap.borrow().get_action_at(counter_clockwise_pos, layer) == counter_clockwise
+                        {
+                            continue;
+                        }
+                        keymap.borrow_mut().set_action_at(clockwise_pos, layer, clockwise);
+                        keymap
+                            .borrow_mut()
+                            .set_action_at(counter_clockwise_pos, layer, counter_clockwise);
+                        #[cfg(feature = "storage")]
+                        FLASH_CHANNEL
+                            .send(FlashOperationMessage::EncoderKey {
+                                idx: idx as u8,
+                                layer: layer as u8,
+                                action: EncoderAction::new(clockwise, counter_clockwise),
+                            })
+                            .await;
+                    }
+                }
             }
             ViaCommand::CustomSetValue => {
-                // backlight/rgblight/rgb matrix/led matrix/audio settings here
-                warn!("Custom set value -- not supported")
+                if !self.custom_values.set_value(&report.output_data[1..], keymap).await {
+                    warn!(
+                        "Custom set value -- unhandled value {} of channel {}",
+                        report.output_data[2], report.output_data[1]
+                    );
+                    report.input_data[0] = ViaCommand::Unhandled as u8;
+                }
             }
             ViaCommand::CustomGetValue => {
-                // backlight/rgblight/rgb matrix/led matrix/audio settings here
-                warn!("Custom get value -- not supported")
+                if !self.custom_values.get_value(&mut report.input_data[1..], keymap) {
+                    warn!(
+                        "Custom get value -- unhandled value {} of channel {}",
+                        report.output_data[2], report.output_data[1]
+                    );
+                    report.input_data[0] = ViaCommand::Unhandled as u8;
+                }
             }
             ViaCommand::CustomSave => {
-                // backlight/rgblight/rgb matrix/led matrix/audio settings here
-                warn!("Custom get value -- not supported")
+                if !self.custom_values.save(&report.output_data[1..], keymap).await {
+                    warn!("Custom save -- unhandled channel {}", report.output_data[1]);
+                    report.input_data[0] = ViaCommand::Unhandled as u8;
+                }
             }
             ViaCommand::EepromReset => {
                 warn!("Reseting storage..");
@@ -234,13 +401,10 @@ impl<
                 boot::jump_to_bootloader();
             }
             ViaCommand::DynamicKeymapMacroGetCount => {
-                report.input_data[1] = 32;
-                warn!("Macro get count -- to be implemented")
+                report.input_data[1] = NUM_MACRO;
             }
             ViaCommand::DynamicKeymapMacroGetBufferSize => {
-                report.input_data[1] = (MACRO_SPACE_SIZE as u16 >> 8) as u8;
-                report.input_data[2] = (MACRO_SPACE_SIZE & 0xFF) as u8;
-                warn!("Macro get buffer size -- to be implemented")
+                BigEndian::write_u16(&mut report.input_data[1..3], MACRO_SPACE_SIZE as u16);
             }
             ViaCommand::DynamicKeymapMacroGetBuffer => {
                 let offset = BigEndian::read_u16(&report.output_data[1..3]) as usize;
@@ -284,7 +448,12 @@ impl<
                 }
             }
             ViaCommand::DynamicKeymapMacroReset => {
-                warn!("Macro reset -- to be implemented")
+                info!("Resetting macros");
+                self.keymap.borrow_mut().behavior.keyboard_macros.macro_sequences = [0; MACRO_SPACE_SIZE];
+                #[cfg(feature = "storage")]
+                FLASH_CHANNEL
+                    .send(FlashOperationMessage::WriteMacro([0; MACRO_SPACE_SIZE]))
+                    .await;
             }
             ViaCommand::DynamicKeymapGetLayerCount => {
                 report.input_data[1] = NUM_LAYER as u8;
@@ -348,10 +517,52 @@ impl<
                     });
             }
             ViaCommand::DynamicKeymapGetEncoder => {
-                warn!("Keymap get encoder -- not supported");
+                let layer = report.output_data[1];
+                let idx = report.output_data[2];
+                let direction = encoder_direction(report.output_data[3]);
+                let keycode = if (layer as usize) < NUM_LAYER {
+                    let pos = KeyboardEventPos::rotary_encoder_pos(idx, direction);
+                    to_via_keycode(keymap.borrow().get_action_at(pos, layer as usize))
+                } else {
+                    0
+                };
+                debug!("Getting encoder: {:02X} of encoder {}, layer {}", keycode, idx, layer);
+                BigEndian::write_u16(&mut report.input_data[4..6], keycode);
             }
             ViaCommand::DynamicKeymapSetEncoder => {
-                warn!("Keymap set encoder -- not supported");
+                let layer = report.output_data[1];
+                let idx = report.output_data[2];
+                let direction = encoder_direction(report.output_data[3]);
+                let keycode = BigEndian::read_u16(&report.output_data[4..6]);
+                if layer as usize >= NUM_LAYER || idx as usize >= NUM_ENCODER {
+                    error!("Invalid encoder {} at layer {}", idx, layer);
+                    return;
+                }
+                let action = from_via_keycode(keycode);
+                info!(
+                    "Setting encoder: 0x{:02X} of encoder {}, layer {} as {:?}",
+                    keycode, idx, layer, action
+                );
+                let pos = KeyboardEventPos::rotary_encoder_pos(idx, direction);
+                keymap.borrow_mut().set_action_at(pos, layer as usize, action);
+                #[cfg(feature = "storage")]
+                {
+                    let clockwise = keymap.borrow().get_action_at(
+                        KeyboardEventPos::rotary_encoder_pos(idx, Direction::Clockwise),
+                        layer as usize,
+                    );
+                    let counter_clockwise = keymap.borrow().get_action_at(
+                        KeyboardEventPos::rotary_encoder_pos(idx, Direction::CounterClockwise),
+                        layer as usize,
+                    );
+                    FLASH_CHANNEL
+                        .send(FlashOperationMessage::EncoderKey {
+                            idx,
+                            layer,
+                            action: EncoderAction::new(clockwise, counter_clockwise),
+                        })
+                        .await;
+                }
             }
             ViaCommand::Vial => {
                 process_vial(
@@ -371,6 +582,36 @@ impl<
     }
 }
 
+/// Direction of the encoder in VIA commands, non-zero means clockwise
+fn encoder_direction(clockwise: u8) -> Direction {
+    if clockwise != 0 {
+        Direction::Clockwise
+    } else {
+        Direction::CounterClockwise
+    }
+}
+
+/// Run the device indication, which blinks when it's requested by VIA.
+///
+/// The blinking is published as [`ControllerEvent::DeviceIndication`], a new request restarts it.
+#[cfg(feature = "controller")]
+pub(crate) async fn run_device_indication() {
+    let mut controller_pub = unwrap!(CONTROLLER_CHANNEL.publisher());
+    loop {
+        DEVICE_INDICATION.wait().await;
+        let mut toggles = 0;
+        while toggles < DEVICE_INDICATION_TOGGLES {
+            // Start with on, and end with off
+            send_controller_event(&mut controller_pub, ControllerEvent::DeviceIndication(toggles % 2 == 0));
+            toggles += 1;
+            if let Either::Second(_) = select(Timer::after(DEVICE_INDICATION_INTERVAL), DEVICE_INDICATION.wait()).await
+            {
+                toggles = 0;
+            }
+        }
+    }
+}
+
 fn get_position_from_offset(offset: usize, max_row: usize, max_col: usize) -> (usize, usize, usize) {
     let layer = offset / (max_col * max_row);
     let current_layer_offset = offset % (max_col * max_row);
@@ -421,3 +662,313 @@ impl<'d, D: Driver<'d>> HidReaderTrait for UsbVialReaderWriter<'_, 'd, D> {
         Ok(read_report)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use rusty_fork::rusty_fork_test;
+
+    use super::*;
+    use crate::action::{EncoderAction, KeyAction};
+    use crate::config::BehaviorConfig;
+    use crate::k;
+
+    struct TestReaderWriter;
+
+    impl HidWriterTrait for TestReaderWriter {
+        type ReportType = ViaReport;
+
+        async fn write_report(&mut self, _report: Self::ReportType) -> Result<usize, HidError> {
+            Ok(32)
+        }
+    }
+
+    impl HidReaderTrait for TestReaderWriter {
+        type ReportType = ViaReport;
+
+        async fn read_report(&mut self) -> Result<ViaReport, HidError> {
+            core::future::pending().await
+        }
+    }
+
+    type TestService = VialService<'static, TestReaderWriter, 1, 2, 2, 1>;
+
+    fn create_service() -> TestService {
+        create_service_with_config(VialConfig::new(&[], &[], &[]))
+    }
+
+    fn create_service_with_config(vial_config: VialConfig<'static>) -> TestService {
+        let behavior_config = Box::leak(Box::new(BehaviorConfig::default()));
+        let layers = Box::leak(Box::new([[[k!(A), k!(B)]], [[k!(C), k!(D)]]]));
+        let encoders = Box::leak(Box::new([
+            [EncoderAction::new(k!(AudioVolUp), k!(AudioVolDown))],
+            [EncoderAction::new(KeyAction::No, KeyAction::No)],
+        ]));
+        let default_layers = Box::leak(Box::new(*layers));
+        let default_encoders = Box::leak(Box::new(*encoders));
+        let keymap = Box::leak(Box::new(RefCell::new(block_on(KeyMap::new(
+            layers,
+            Some(encoders),
+            behavior_config,
+        )))));
+        keymap
+            .borrow_mut()
+            .set_default_keymap(default_layers, Some(default_encoders));
+        // Commands which require unlock are tested in `test_vial_lock`
+        #[cfg(feature = "vial_lock")]
+        restore_lock_state(true);
+        VialService::new(keymap, vial_config, TestReaderWriter)
+    }
+
+    /// Send a raw VIA packet, returns the response
+    fn send_packet(service: &mut TestService, data: &[u8]) -> [u8; 32] {
+        let mut report = ViaReport::default();
+        report.output_data[..data.len()].copy_from_slice(data);
+        let keymap = service.keymap;
+        block_on(service.process_via_packet(&mut report, keymap));
+        report.input_data
+    }
+
+    #[cfg(feature = "storage")]
+    fn flash_messages() -> usize {
+        let mut n = 0;
+        while FLASH_CHANNEL.try_receive().is_ok() {
+            n += 1;
+        }
+        n
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_dynamic_keymap_reset() {
+            let mut service = create_service();
+            // Set (0,1) of layer 1 to `A`, and the clockwise action of encoder 0 at layer 0 to `B`
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetKeyCode as u8, 1, 0, 1, 0x00, 0x04]);
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetEncoder as u8, 0, 0, 1, 0x00, 0x05]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 2);
+
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetKeyCode as u8, 1, 0, 1]);
+            assert_eq!(response[4..6], [0x00, 0x04]);
+
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapReset as u8]);
+            // Only the changed key and encoder are saved
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 2);
+
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetKeyCode as u8, 1, 0, 1]);
+            assert_eq!(response[4..6], [0x00, 0x07]);
+            let keymap = service.keymap.borrow();
+            let pos = KeyboardEventPos::rotary_encoder_pos(0, Direction::Clockwise);
+            assert_eq!(keymap.get_action_at(pos, 0), k!(AudioVolUp));
+        }
+
+        #[test]
+        fn test_dynamic_keymap_reset_without_default() {
+            let behavior_config = Box::leak(Box::new(BehaviorConfig::default()));
+            let layers = Box::leak(Box::new([[[k!(A), k!(B)]], [[k!(C), k!(D)]]]));
+            let keymap = Box::leak(Box::new(RefCell::new(block_on(KeyMap::new(layers, None, behavior_config)))));
+            let mut service: TestService = VialService::new(keymap, VialConfig::new(&[], &[], &[]), TestReaderWriter);
+            #[cfg(feature = "vial_lock")]
+            restore_lock_state(true);
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetKeyCode as u8, 1, 0, 1, 0x00, 0x04]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 1);
+
+            // The reset fails instead of keeping the current keymap silently
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapReset as u8]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 0);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetKeyCode as u8, 1, 0, 1]);
+            assert_eq!(response[4..6], [0x00, 0x04]);
+        }
+
+        #[test]
+        fn test_dynamic_keymap_encoder() {
+            let mut service = create_service();
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetEncoder as u8, 0, 0, 0]);
+            assert_eq!(response[4..6], to_via_keycode(k!(AudioVolDown)).to_be_bytes());
+
+            // Set the counter-clockwise action of encoder 0 at layer 1
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetEncoder as u8, 1, 0, 0, 0x00, 0x04]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 1);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetEncoder as u8, 1, 0, 0]);
+            assert_eq!(response[..6], [ViaCommand::DynamicKeymapGetEncoder as u8, 1, 0, 0, 0x00, 0x04]);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetEncoder as u8, 1, 0, 1]);
+            assert_eq!(response[4..6], [0x00, 0x00]);
+
+            // Invalid encoders are ignored
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetEncoder as u8, 1, 1, 0, 0x00, 0x04]);
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapSetEncoder as u8, 2, 0, 0, 0x00, 0x04]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 0);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetEncoder as u8, 1, 1, 0]);
+            assert_eq!(response[4..6], [0x00, 0x00]);
+        }
+
+        #[test]
+        fn test_dynamic_keymap_macro() {
+            let mut service = create_service();
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroGetCount as u8]);
+            assert_eq!(response[1], 32);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroGetBufferSize as u8]);
+            assert_eq!(BigEndian::read_u16(&response[1..3]) as usize, MACRO_SPACE_SIZE);
+
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroSetBuffer as u8, 0, 0, 3, 0x61, 0x62, 0x00]);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroGetBuffer as u8, 0, 0, 3]);
+            assert_eq!(response[4..7], [0x61, 0x62, 0x00]);
+
+            send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroReset as u8]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 2);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroGetBuffer as u8, 0, 0, 3]);
+            assert_eq!(response[4..7], [0x00, 0x00, 0x00]);
+        }
+
+        #[test]
+        fn test_custom_rmk_settings() {
+            let mut service = create_service();
+            // Battery calibration is 2 bytes, the empty value must be lower than the full value
+            let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 0, 0x51]);
+            assert_eq!(response[..5], [ViaCommand::CustomGetValue as u8, 0, 0x51, 0x12, 0x93]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x50, 0x13, 0x00]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x50, 0x0F, 0xA0]);
+            assert_eq!(crate::input_device::battery::battery_calibration().empty, 4000);
+
+            // Lighting settings
+            send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x40, 0]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x41, 101]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            assert_eq!(
+                crate::light::lighting_config(),
+                crate::light::LightingConfig {
+                    indicators: false,
+                    low_battery: 10
+                }
+            );
+
+            // Mouse key move delta is 1 byte, 0 is rejected
+            send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x12, 10]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x12, 0]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 0, 0x12]);
+            assert_eq!(response[3], 10);
+
+            // Values out of the range are rejected, the max speed is 1..=10
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x13, 255]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x13, 11]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x13, 10]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 0, 0x13]);
+            assert_eq!(response[3], 10);
+
+            // Changed settings are saved on request, the settings of a menu are saved together
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 0);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSave as u8, 0]);
+            assert_eq!(response[0], ViaCommand::CustomSave as u8);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 3);
+            send_packet(&mut service, &[ViaCommand::CustomSave as u8, 0]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 0);
+
+            // Unknown values and channels
+            let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 0, 0x7F]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSave as u8, 1]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            #[cfg(not(feature = "_ble"))]
+            {
+                let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 0, 0x20]);
+                assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            }
+        }
+
+        #[test]
+        fn test_custom_value_handler() {
+            struct Brightness(core::sync::atomic::AtomicU8);
+
+            impl CustomValueHandler for Brightness {
+                fn get_value(&self, id: u8, data: &mut [u8]) -> bool {
+                    data[0] = self.0.load(Ordering::Relaxed);
+                    id == 1
+                }
+
+                fn set_value(&self, id: u8, data: &[u8]) -> bool {
+                    self.0.store(data[0], Ordering::Relaxed);
+                    id == 1
+                }
+            }
+
+            static BRIGHTNESS: Brightness = Brightness(core::sync::atomic::AtomicU8::new(0));
+            assert!(!register_custom_value_handler(0, &BRIGHTNESS));
+            assert!(register_custom_value_handler(2, &BRIGHTNESS));
+            assert!(!register_custom_value_handler(2, &BRIGHTNESS));
+
+            let mut service = create_service();
+            send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 2, 1, 128]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomGetValue as u8, 2, 1]);
+            assert_eq!(response[..4], [ViaCommand::CustomGetValue as u8, 2, 1, 128]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSave as u8, 2]);
+            assert_eq!(response[0], ViaCommand::CustomSave as u8);
+        }
+
+        #[cfg(feature = "vial_lock")]
+        #[test]
+        fn test_vial_lock() {
+            let mut config = VialConfig::new(&[], &[], &[]);
+            config.lock_timeout = Some(embassy_time::Duration::from_millis(50));
+            let mut service = create_service_with_config(config);
+            restore_lock_state(false);
+
+            // Dangerous commands are rejected when locked
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroReset as u8]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::GetKeyboardValue as u8, 0x03]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapSetKeyCode as u8, 0, 0, 0, 0x7C, 0x00]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapGetKeyCode as u8, 0, 0, 0]);
+            assert_eq!(response[4..6], [0x00, 0x04]);
+            let response = send_packet(&mut service, &[ViaCommand::CustomSetValue as u8, 0, 0x30, 1]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x0D, 0x04, 0]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x0B, 0x02, 0x00, 0x10, 0x00]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x0C]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+
+            // Reading the dynamic entries is allowed
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x0D, 0x00]);
+            assert_eq!(response[..2], [crate::MORSE_MAX_NUM as u8, crate::COMBO_MAX_NUM as u8]);
+
+            // Normal commands are always allowed
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapSetKeyCode as u8, 0, 0, 0, 0x00, 0x05]);
+            assert_eq!(response[0], ViaCommand::DynamicKeymapSetKeyCode as u8);
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x05]);
+            assert_eq!(response[..2], [0, 0]);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 1);
+
+            // The lock state is shared by all services
+            let _other = create_service();
+            let response = send_packet(&mut service, &[ViaCommand::GetKeyboardValue as u8, 0x03]);
+            assert_eq!(response[0], ViaCommand::GetKeyboardValue as u8);
+
+            // Relock after inactivity, the lock state is saved
+            service.locker.on_command(Instant::now() + embassy_time::Duration::from_millis(60));
+            let response = send_packet(&mut service, &[ViaCommand::DynamicKeymapMacroReset as u8]);
+            assert_eq!(response[0], ViaCommand::Unhandled as u8);
+            #[cfg(feature = "storage")]
+            assert_eq!(flash_messages(), 1);
+            let response = send_packet(&mut service, &[ViaCommand::Vial as u8, 0x05]);
+            assert_eq!(response[0], 0);
+        }
+    }
+}
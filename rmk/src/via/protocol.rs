@@ -42,4 +42,6 @@ pub(crate) enum ViaKeyboardInfo {
     SwitchMatrixState = 0x03,
     FirmwareVersion = 0x04,
     DeviceIndication = 0x05,
+    /// RMK specific, the firmware metadata block without the magic, see [`crate::firmware`]
+    FirmwareMetadata = 0x80,
 }
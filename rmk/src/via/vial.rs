@@ -156,9 +156,10 @@ pub(crate) async fn process_vial<
             #[cfg(feature = "vial_lock")]
             {
                 locker.unlocking();
+                let counter = locker.check_unlock();
                 report.input_data[0] = locker.is_unlocked() as u8;
                 report.input_data[1] = locker.is_unlocking() as u8;
-                report.input_data[2] = locker.check_unlock();
+                report.input_data[2] = counter;
             }
             #[cfg(not(feature = "vial_lock"))]
             error!("Vial lock feature is not enabled");
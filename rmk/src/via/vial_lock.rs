@@ -1,11 +1,141 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use num_enum::{FromPrimitive as _, TryFromPrimitive as _};
+
+use super::protocol::{ViaCommand, ViaKeyboardInfo};
+use super::vial::{VialCommand, VialDynamic};
+use crate::RawMutex;
 use crate::keymap::KeyMap;
 
-pub(crate) struct VialLock<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> {
+/// Lock state, it's shared by the Vial services of USB and BLE, so that they are always locked together
+#[derive(Clone, Copy)]
+struct LockState {
     unlocked: bool,
+    /// The lock state which is saved in the storage
+    saved: bool,
+    /// Time of the last VIA/Vial command, used for relocking after inactivity
+    last_activity: Instant,
+}
+
+static LOCK_STATE: Mutex<RawMutex, Cell<LockState>> = Mutex::new(Cell::new(LockState {
+    unlocked: false,
+    saved: false,
+    last_activity: Instant::MIN,
+}));
+
+/// Restore the lock state saved in the storage
+pub(crate) fn restore_lock_state(unlocked: bool) {
+    LOCK_STATE.lock(|s| {
+        s.set(LockState {
+            unlocked,
+            saved: unlocked,
+            last_activity: Instant::now(),
+        })
+    });
+}
+
+/// Whether Vial is unlocked, the other interfaces which expose the same data as the Vial commands check it too
+pub(crate) fn is_unlocked() -> bool {
+    LOCK_STATE.lock(|s| s.get().unlocked)
+}
+
+/// Get the lock state if it's changed since it's saved, it's marked as saved then
+pub(crate) fn take_unsaved_lock_state() -> Option<bool> {
+    LOCK_STATE.lock(|s| {
+        let state = s.get();
+        if state.unlocked == state.saved {
+            return None;
+        }
+        s.set(LockState {
+            saved: state.unlocked,
+            ..state
+        });
+        Some(state.unlocked)
+    })
+}
+
+/// Whether a VIA/Vial command can be executed when Vial is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CommandPolicy {
+    /// The command only reads the keyboard info or changes the keymap
+    Allowed,
+    /// The command can jump to the bootloader, erase the storage, read the switch matrix, type arbitrary text,
+    /// or change the settings and the dynamic entries
+    RequiresUnlock,
+}
+
+/// Classify the VIA/Vial command in the packet
+pub(crate) fn command_policy(data: &[u8]) -> CommandPolicy {
+    let via_command = ViaCommand::from_primitive(data[0]);
+    match via_command {
+        ViaCommand::GetProtocolVersion
+        | ViaCommand::SetKeyboardValue
+        | ViaCommand::DynamicKeymapGetKeyCode
+        | ViaCommand::CustomGetValue
+        | ViaCommand::CustomSave
+        | ViaCommand::DynamicKeymapMacroGetCount
+        | ViaCommand::DynamicKeymapMacroGetBufferSize
+        | ViaCommand::DynamicKeymapMacroGetBuffer
+        | ViaCommand::DynamicKeymapGetLayerCount
+        | ViaCommand::DynamicKeymapGetBuffer
+        | ViaCommand::DynamicKeymapGetEncoder
+        | ViaCommand::Unhandled => CommandPolicy::Allowed,
+        ViaCommand::GetKeyboardValue => match ViaKeyboardInfo::try_from_primitive(data[1]) {
+            Ok(ViaKeyboardInfo::SwitchMatrixState) => CommandPolicy::RequiresUnlock,
+            _ => CommandPolicy::Allowed,
+        },
+        // Keys which jump to the bootloader can only be set when unlocked
+        ViaCommand::DynamicKeymapSetKeyCode | ViaCommand::DynamicKeymapSetEncoder => keycode_policy(&data[4..6]),
+        ViaCommand::DynamicKeymapReset
+        | ViaCommand::EepromReset
+        | ViaCommand::BootloaderJump
+        | ViaCommand::DynamicKeymapMacroSetBuffer
+        | ViaCommand::DynamicKeymapMacroReset
+        | ViaCommand::DynamicKeymapSetBuffer
+        | ViaCommand::CustomSetValue => CommandPolicy::RequiresUnlock,
+        ViaCommand::Vial => match VialCommand::from_primitive(data[1]) {
+            VialCommand::GetKeyboardId
+            | VialCommand::GetSize
+            | VialCommand::GetKeyboardDef
+            | VialCommand::GetEncoder
+            | VialCommand::GetUnlockStatus
+            | VialCommand::UnlockStart
+            | VialCommand::UnlockPoll
+            | VialCommand::Lock
+            | VialCommand::BehaviorSettingQuery
+            | VialCommand::GetBehaviorSetting
+            | VialCommand::Unhandled => CommandPolicy::Allowed,
+            VialCommand::SetBehaviorSetting | VialCommand::QmkSettingsReset => CommandPolicy::RequiresUnlock,
+            // Reading the dynamic entries is allowed, only writing them requires unlock
+            VialCommand::DynamicEntryOp => match VialDynamic::from_primitive(data[2]) {
+                VialDynamic::DynamicVialGetNumberOfEntries
+                | VialDynamic::DynamicVialMorseGet
+                | VialDynamic::DynamicVialComboGet
+                | VialDynamic::DynamicVialKeyOverrideGet
+                | VialDynamic::Unhandled => CommandPolicy::Allowed,
+                VialDynamic::DynamicVialMorseSet
+                | VialDynamic::DynamicVialComboSet
+                | VialDynamic::DynamicVialKeyOverrideSet => CommandPolicy::RequiresUnlock,
+            },
+            VialCommand::SetEncoder => keycode_policy(&data[5..7]),
+        },
+    }
+}
+
+/// `QK_BOOT` and `QK_REBOOT` require unlock
+fn keycode_policy(keycode: &[u8]) -> CommandPolicy {
+    match u16::from_be_bytes([keycode[0], keycode[1]]) {
+        0x7C00 | 0x7C01 => CommandPolicy::RequiresUnlock,
+        _ => CommandPolicy::Allowed,
+    }
+}
+
+pub(crate) struct VialLock<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize> {
     unlocking: bool,
-    last_poll: embassy_time::Instant,
+    last_poll: Instant,
+    lock_timeout: Option<Duration>,
     unlock_keys: &'a [(u8, u8)],
     keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
 }
@@ -13,11 +143,15 @@ pub(crate) struct VialLock<'a, const ROW: usize, const COL: usize, const NUM_LAY
 impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_ENCODER: usize>
     VialLock<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>
 {
-    pub fn new(unlock_keys: &'a [(u8, u8)], keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+    pub fn new(
+        unlock_keys: &'a [(u8, u8)],
+        lock_timeout: Option<Duration>,
+        keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    ) -> Self {
         Self {
-            unlocked: false,
             unlocking: false,
-            last_poll: embassy_time::Instant::MIN,
+            last_poll: Instant::MIN,
+            lock_timeout,
             unlock_keys,
             keymap,
         }
@@ -27,20 +161,20 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         self.unlocking
     }
     pub fn is_unlocked(&self) -> bool {
-        self.unlocked
+        is_unlocked()
     }
     pub fn unlocking(&mut self) {
         self.unlocking = true;
-        self.last_poll = embassy_time::Instant::now();
+        self.last_poll = Instant::now();
     }
     pub fn unlock(&mut self) {
         if self.unlocking {
-            self.unlocked = true;
             self.unlocking = false;
+            self.set_unlocked(true);
         }
     }
     pub fn check_unlock(&mut self) -> u8 {
-        if self.unlock_keys.len() == 0 {
+        if self.unlock_keys.is_empty() {
             warn!("No unlock keys provided");
             1
         } else {
@@ -57,10 +191,40 @@ impl<'a, const ROW: usize, const COL: usize, const NUM_LAYER: usize, const NUM_E
         }
     }
     pub fn lock(&mut self) {
-        self.unlocked = false;
+        self.set_unlocked(false);
+    }
+    /// Record a VIA/Vial command received at `now`, Vial is relocked if no command is received within the lock timeout
+    pub fn on_command(&mut self, now: Instant) {
+        let state = LOCK_STATE.lock(|s| s.get());
+        let expired = self
+            .lock_timeout
+            .is_some_and(|timeout| now.saturating_duration_since(state.last_activity) > timeout);
+        if expired && state.unlocked {
+            info!("Vial is relocked after inactivity");
+            self.lock();
+        }
+        LOCK_STATE.lock(|s| {
+            s.set(LockState {
+                last_activity: now,
+                ..s.get()
+            })
+        });
+    }
+    /// Change the lock state, it's saved by the Vial service after the command is processed
+    fn set_unlocked(&mut self, unlocked: bool) {
+        if self.is_unlocked() == unlocked {
+            return;
+        }
+        LOCK_STATE.lock(|s| {
+            s.set(LockState {
+                unlocked,
+                last_activity: Instant::now(),
+                ..s.get()
+            })
+        });
     }
     fn update_unlocking_state(&mut self) {
-        if self.last_poll.elapsed() > embassy_time::Duration::from_millis(100) {
+        if self.last_poll.elapsed() > Duration::from_millis(100) {
             self.unlocking = false;
         }
     }